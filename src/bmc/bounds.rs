@@ -1,14 +1,17 @@
 use std::collections::HashMap;
 
+use serde::{Deserialize, Serialize};
 use z3::{ast, SatResult};
 
 use crate::bmc::encoding::BMCEncoding;
 use crate::bmc::vas_bmc::MAX_BMC_STEPS;
+use crate::model::serialization::Persistent;
 use crate::model::vas_model::VasValue;
 use crate::AbstractVas;
 use crate::*;
 
 /// Struct to hold the BMC encoding components
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BMCBounds {
 	pub lb_loose: HashMap<String, VasValue>,
 	pub lb_tight: HashMap<String, VasValue>,
@@ -45,6 +48,19 @@ impl<'a> BMCBounds {
 		// Initialize the Z3 solver and reset the unroller
 		let solver = z3::Solver::new(ctx);
 		let mut unroller = encoding.unroller.clone();
+		// The conserved moieties bound some variables before any search
+		let largest_value: VasValue = (1 << bits) - 1;
+		let upper_limit = |index: usize| match encoding.invariant_upper_bounds[index] {
+			Some(bound) if bound < largest_value => {
+				debug_message!(
+					"{} is at most {} by the conserved moieties",
+					model.variable_names[index],
+					bound
+				);
+				bound
+			}
+			_ => largest_value,
+		};
 		// Step 1: Loosest upper bounds
 		for variable_name in variable_names.iter() {
 			let state_var = &state_vars[variable_name];
@@ -54,8 +70,8 @@ impl<'a> BMCBounds {
 				.position(|x| x == variable_name)
 				.unwrap();
 			debug_message!("Checking loose upper bound for {}", variable_name);
-			let mut min_bound: VasValue = model.initial_states.clone()[0].vector[state_var_index];
-			let mut max_bound: VasValue = (1 << bits) - 1;
+			let mut min_bound: VasValue = model.initial_range(state_var_index).0;
+			let mut max_bound: VasValue = upper_limit(state_var_index);
 			let mut bound: VasValue = 0;
 			// This loop does a binary search for the loosest upper bound
 			loop {
@@ -99,9 +115,9 @@ impl<'a> BMCBounds {
 			let state_var = &state_vars[s];
 			let state_var_index = model.variable_names.iter().position(|x| x == s).unwrap();
 			debug_message!("Checking tight upper bound for {}", s);
-			let mut min_bound: VasValue = model.initial_states.clone()[0].vector[state_var_index];
-			let mut max_bound: VasValue = (1 << bits) - 1;
-			let mut bound: VasValue = (1 << bits) - 1;
+			let mut min_bound: VasValue = model.initial_range(state_var_index).0;
+			let mut max_bound: VasValue = upper_limit(state_var_index);
+			let mut bound: VasValue = max_bound;
 			// This loop does a binary search for the tightest upper bound
 			loop {
 				solver.reset();
@@ -144,8 +160,8 @@ impl<'a> BMCBounds {
 			let state_var_index = model.variable_names.iter().position(|x| x == s).unwrap();
 			debug_message!("Checking loose lower bound for {}", s);
 			let mut min_bound: VasValue = 0;
-			let mut max_bound: VasValue = model.initial_states[0].vector[state_var_index];
-			let mut bound: VasValue = model.initial_states[0].vector[state_var_index];
+			let mut max_bound: VasValue = model.initial_range(state_var_index).1;
+			let mut bound: VasValue = max_bound;
 
 			loop {
 				if max_bound == 0 {
@@ -193,7 +209,7 @@ impl<'a> BMCBounds {
 			let state_var_index = model.variable_names.iter().position(|x| x == s).unwrap();
 			debug_message!("Checking tight lower bound for {}", s);
 			let mut min_bound: VasValue = 0;
-			let mut max_bound: VasValue = model.initial_states[0].vector[state_var_index];
+			let mut max_bound: VasValue = model.initial_range(state_var_index).1;
 			let mut bound: VasValue = 0;
 			// This loop does a binary search for the tightest lower bound
 			loop {
@@ -236,25 +252,52 @@ impl<'a> BMCBounds {
 			);
 		}
 		// Print summary
-		debug_message!("Summary of Bounds");
 		debug_message!(
+			"Summary of Bounds\n{}",
+			variable_bounds.summary(&variable_names)
+		);
+		variable_bounds
+	}
+
+	/// Whether there are bounds for every variable, e.g., to check that cached bounds are
+	/// still for the same model
+	pub fn covers(&self, variable_names: &[String]) -> bool {
+		[
+			&self.lb_loose,
+			&self.lb_tight,
+			&self.ub_loose,
+			&self.ub_tight,
+		]
+		.iter()
+		.all(|bounds| variable_names.iter().all(|name| bounds.contains_key(name)))
+	}
+
+	/// A table of the bounds of each variable
+	pub fn summary(&self, variable_names: &[String]) -> String {
+		let mut table = format!(
 			"{:<20} {:<10} {:<10} {:<10} {:<10}",
-			"Variable",
-			"LB Loose",
-			"LB Tight",
-			"UB Loose",
-			"UB Tight"
+			"Variable", "LB Loose", "LB Tight", "UB Loose", "UB Tight"
 		);
 		for s in variable_names.iter() {
-			debug_message!(
-				"{:<20} {:<10} {:<10} {:<10} {:<10}",
-				s,
-				variable_bounds.lb_loose[s],
-				variable_bounds.lb_tight[s],
-				variable_bounds.ub_loose[s],
-				variable_bounds.ub_tight[s],
+			table += &format!(
+				"\n{:<20} {:<10} {:<10} {:<10} {:<10}",
+				s, self.lb_loose[s], self.lb_tight[s], self.ub_loose[s], self.ub_tight[s],
 			);
 		}
-		variable_bounds
+		table
+	}
+}
+
+/// The bounds are plain data, so they are saved as they are
+impl Persistent for BMCBounds {
+	const KIND: &'static str = "bmc-bounds";
+	type Data = BMCBounds;
+
+	fn to_data(&self) -> Result<Self::Data, String> {
+		Ok(self.clone())
+	}
+
+	fn from_data(data: Self::Data) -> Result<Self, String> {
+		Ok(data)
 	}
 }
@@ -5,13 +5,24 @@ use z3::{
 	Context, SatResult, Solver,
 };
 
-use crate::{bmc::unroller::Unroller, logging::messages::*, AbstractVas};
+use crate::{
+	bmc::unroller::Unroller,
+	invariant::p_invariant::{PInvariant, PInvariants},
+	logging::messages::*,
+	model::vas_model::{AllowedRelation, VasTarget, VasValue},
+	AbstractVas,
+};
 
 /// Struct to hold the BMC encoding components
 pub struct BMCEncoding<'a> {
 	pub init_formula: ast::Bool<'a>,
 	pub target_formula: ast::Bool<'a>,
 	pub transition_formula: ast::Bool<'a>,
+	/// The conservation laws of the initial state that a run starts in, which hold in
+	/// every state it reaches
+	pub invariant_formula: ast::Bool<'a>,
+	/// The upper bound on each variable that the conserved moieties imply, if any
+	pub invariant_upper_bounds: Vec<Option<VasValue>>,
 	pub unroller: Unroller<'a>,
 }
 
@@ -24,32 +35,180 @@ impl<'a> BMCEncoding<'a> {
 		let model_variables = model.variable_names.clone();
 		let mut bmc_current_variables = HashMap::new();
 		let mut bmc_next_variables = HashMap::new();
-		// Load the initial constraints
-		let model_init = model.initial_states.clone();
-		let mut bmc_init_constraints = Vec::new();
-		// Encode the Z3 bit-vector variables for initial constraints and state variables
+		// Encode the Z3 bit-vector variables for state variables
 		for i in 0..model_variables.len() {
 			let state_var = ast::BV::new_const(&ctx, model_variables[i].clone(), bits);
 			let next_var = ast::BV::new_const(&ctx, format!("{}_next", model_variables[i]), bits);
 			bmc_current_variables.insert(model_variables[i].clone(), state_var.clone());
 			bmc_next_variables.insert(model_variables[i].clone(), next_var.clone());
-			bmc_init_constraints.push(Ast::_eq(
-				&state_var,
-				&ast::BV::from_i64(&ctx, model_init[0].vector[i].try_into().unwrap(), bits),
-			));
 		}
 		debug_message!("Encoded variables for BMC:\n{:?}", bmc_current_variables);
-		// Build the initial formula by conjoining the constraints
-		let bmc_init_formula =
-			ast::Bool::and(&ctx, &bmc_init_constraints.iter().collect::<Vec<_>>());
-		debug_message!("Encoded initial state for BMC:\n{:?}", bmc_init_formula);
+		// One selector per initial state, which picks the state that a run starts in. The
+		// selectors are not state variables, so the unroller keeps them the same at every
+		// step, and the conservation laws of the chosen state hold all along the run.
+		let bmc_init_selectors = (0..model.initial_states.len())
+			.map(|i| ast::Bool::new_const(&ctx, format!("init_{}", i)))
+			.collect::<Vec<_>>();
+		// Build the initial formula: a disjunction over the initial states, each one its
+		// selector and the conjunction of its variable values
+		let bmc_init_states = model
+			.initial_states
+			.iter()
+			.map(|state| {
+				let constraints = model_variables
+					.iter()
+					.enumerate()
+					.map(|(i, name)| {
+						Ast::_eq(
+							&bmc_current_variables[name],
+							&ast::BV::from_i64(&ctx, state.vector[i].try_into().unwrap(), bits),
+						)
+					})
+					.collect::<Vec<_>>();
+				ast::Bool::and(&ctx, &constraints.iter().collect::<Vec<_>>())
+			})
+			.zip(bmc_init_selectors.iter())
+			.map(|(state, selector)| ast::Bool::and(&ctx, &[selector, &state]))
+			.collect::<Vec<_>>();
+		let bmc_init_formula = ast::Bool::or(&ctx, &bmc_init_states.iter().collect::<Vec<_>>());
+		debug_message!("Encoded initial states for BMC:\n{:?}", bmc_init_formula);
 
 		// Encode the target formula
-		let model_target = model.target.clone();
-		let bmc_target_formula = ast::Ast::_eq(
-			&bmc_current_variables[&model_variables[model_target.variable_index]],
-			&ast::BV::from_i64(&ctx, model_target.target_value.try_into().unwrap(), bits),
+		fn encode_target<'a>(
+			target: &VasTarget,
+			variables: &HashMap<String, ast::BV<'a>>,
+			model_variables: &[String],
+			ctx: &'a Context,
+			bits: u32,
+		) -> ast::Bool<'a> {
+			match target {
+				VasTarget::Relation(index, relation, value) => {
+					let variable = &variables[&model_variables[*index]];
+					let value = ast::BV::from_i64(ctx, (*value).try_into().unwrap(), bits);
+					match relation {
+						AllowedRelation::Equal => ast::Ast::_eq(variable, &value),
+						AllowedRelation::LessThan => variable.bvult(&value),
+						AllowedRelation::GreaterThan => variable.bvugt(&value),
+						AllowedRelation::LessOrEqual => variable.bvule(&value),
+						AllowedRelation::GreaterOrEqual => variable.bvuge(&value),
+					}
+				}
+				VasTarget::And(targets) | VasTarget::Or(targets) => {
+					let encoded = targets
+						.iter()
+						.map(|t| encode_target(t, variables, model_variables, ctx, bits))
+						.collect::<Vec<_>>();
+					let encoded = encoded.iter().collect::<Vec<_>>();
+					if let VasTarget::And(_) = target {
+						ast::Bool::and(ctx, &encoded)
+					} else {
+						ast::Bool::or(ctx, &encoded)
+					}
+				}
+			}
+		}
+		let bmc_target_formula = encode_target(
+			&model.target,
+			&bmc_current_variables,
+			&model_variables,
+			ctx,
+			bits,
 		);
+		debug_message!("Encoded target for BMC:\n{:?}", bmc_target_formula);
+
+		// A weighted sum of the variables in a bit-vector wide enough that neither the sum
+		// (and its sign) nor `constant` can overflow, along with that constant
+		fn encode_weighted_sum<'a>(
+			weights: &[VasValue],
+			constant: VasValue,
+			variables: &HashMap<String, ast::BV<'a>>,
+			model_variables: &[String],
+			ctx: &'a Context,
+			bits: u32,
+		) -> (ast::BV<'a>, ast::BV<'a>) {
+			let total_weight: VasValue = weights.iter().map(|w| w.abs()).sum();
+			let width = (bits + (VasValue::BITS - total_weight.leading_zeros()))
+				.max(VasValue::BITS - constant.abs().leading_zeros())
+				+ 1;
+			let sum = weights
+				.iter()
+				.zip(model_variables.iter())
+				.filter(|(weight, _)| **weight != 0)
+				.map(|(weight, name)| {
+					variables[name]
+						.zero_ext(width - bits)
+						.bvmul(&ast::BV::from_i64(
+							ctx,
+							(*weight).try_into().unwrap(),
+							width,
+						))
+				})
+				.reduce(|sum, term| sum.bvadd(&term))
+				.unwrap_or(ast::BV::from_i64(ctx, 0, width));
+			(
+				sum,
+				ast::BV::from_i64(ctx, constant.try_into().unwrap(), width),
+			)
+		}
+
+		// Encode the conservation laws (P-invariants). They follow from the initial states
+		// and the transitions, but they also rule out the states that only bit-vector
+		// overflow reaches, which tightens the bounds.
+		fn encode_invariant<'a>(
+			invariant: &PInvariant,
+			value: VasValue,
+			variables: &HashMap<String, ast::BV<'a>>,
+			model_variables: &[String],
+			ctx: &'a Context,
+			bits: u32,
+		) -> ast::Bool<'a> {
+			let (sum, value) = encode_weighted_sum(
+				&invariant.weights,
+				value,
+				variables,
+				model_variables,
+				ctx,
+				bits,
+			);
+			ast::Ast::_eq(&sum, &value)
+		}
+		let (invariants, invariant_upper_bounds) = match PInvariants::from_model(model) {
+			Ok(invariants) => {
+				let upper_bounds = invariants.upper_bounds(model);
+				(invariants.basis, upper_bounds)
+			}
+			Err(e) => {
+				warning!("Could not compute the P-invariants for BMC: {}", e);
+				(Vec::new(), vec![None; model_variables.len()])
+			}
+		};
+		let bmc_invariant_states = model
+			.initial_states
+			.iter()
+			.map(|state| {
+				let equations = invariants
+					.iter()
+					.map(|invariant| {
+						encode_invariant(
+							invariant,
+							invariant.value(&state.vector),
+							&bmc_current_variables,
+							&model_variables,
+							ctx,
+							bits,
+						)
+					})
+					.collect::<Vec<_>>();
+				ast::Bool::and(&ctx, &equations.iter().collect::<Vec<_>>())
+			})
+			.zip(bmc_init_selectors.iter())
+			.map(|(equations, selector)| selector.implies(&equations))
+			.collect::<Vec<_>>();
+		// The conservation laws of the selected initial state, rather than those of any
+		// initial state at each step
+		let bmc_invariant_formula =
+			ast::Bool::and(&ctx, &bmc_invariant_states.iter().collect::<Vec<_>>());
+		debug_message!("Encoded invariants for BMC:\n{:?}", bmc_invariant_formula);
 		// Encode the transitions one-by-one
 		let mut bmc_transition_constraints = Vec::new();
 		for transition_i in &model.transitions {
@@ -95,6 +254,19 @@ impl<'a> BMCEncoding<'a> {
 					)
 				});
 			}
+			// Encode the linear guards, e.g., those of a reduced model
+			for guard in transition_i.guards.iter() {
+				let weights = guard.weights.iter().copied().collect::<Vec<_>>();
+				let (sum, bound) = encode_weighted_sum(
+					&weights,
+					guard.bound,
+					&bmc_current_variables,
+					&model_variables,
+					ctx,
+					bits,
+				);
+				transition_i_constraints.push(sum.bvsge(&bound));
+			}
 			// Combine all constraints for this transition and add it to the transition constraints
 			bmc_transition_constraints.push(ast::Bool::and(
 				&ctx,
@@ -116,6 +288,8 @@ impl<'a> BMCEncoding<'a> {
 			init_formula: bmc_init_formula,
 			target_formula: bmc_target_formula,
 			transition_formula: bmc_transition_formula,
+			invariant_formula: bmc_invariant_formula,
+			invariant_upper_bounds,
 			unroller,
 		}
 	}
@@ -125,18 +299,26 @@ impl<'a> BMCEncoding<'a> {
 	/// `init_formula`: The initial formula representing the system
 	/// `transition_formula`: The transition formula modifying the system
 	/// `target_formula`: The formula identifying the target
+	/// `invariant_formula`: The conservation laws, asserted at every step
 	/// `unroller`: The unroller used
 	pub fn run_bmc(&self, ctx: &'a Context, max_steps: u32) -> (ast::Bool<'a>, u32) {
 		debug_message!("Bounded Model Checking to {} steps", max_steps);
-		let (init_formula, transition_formula, target_formula, unroller) = (
+		let (init_formula, transition_formula, target_formula, invariant_formula, unroller) = (
 			&self.init_formula,
 			&self.transition_formula,
 			&self.target_formula,
+			&self.invariant_formula,
 			&mut self.unroller.clone(),
 		);
 		// let ctx = init_formula.get_ctx();
 		let solver = Solver::new(&ctx);
-		let mut formula = unroller.at_time(init_formula, 0);
+		let mut formula = ast::Bool::and(
+			&ctx,
+			&[
+				&unroller.at_time(init_formula, 0),
+				&unroller.at_time(invariant_formula, 0),
+			],
+		);
 		let mut max_k = 0;
 		// Do the full unrolling to k steps
 		for k in 0..max_steps {
@@ -156,8 +338,14 @@ impl<'a> BMCEncoding<'a> {
 				break;
 			} else {
 				// println!("Status: UNSAT");
-				formula =
-					ast::Bool::and(&ctx, &[&formula, &unroller.at_time(&transition_formula, k)]);
+				formula = ast::Bool::and(
+					&ctx,
+					&[
+						&formula,
+						&unroller.at_time(&transition_formula, k),
+						&unroller.at_time(&invariant_formula, k + 1),
+					],
+				);
 			}
 		}
 		debug_message!("Finished BMC with actual step count of {}", max_k);
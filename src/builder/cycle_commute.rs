@@ -0,0 +1,80 @@
+use crate::{
+	builder::builder::Builder,
+	checker::result::ModelCheckingResult,
+	cycle_commute::commute::cycle_commute,
+	logging::messages::*,
+	model::vas_model::{AbstractVas, PrismVasModel},
+};
+
+/// Builds the state space of seed traces with cycle & commute: the traces, their commuted
+/// (parallel) versions and the cycles of the model, with every other successor leading
+/// to an absorbing sink
+pub(crate) struct CycleCommuteBuilder<'a> {
+	pub abstract_model: &'a AbstractVas,
+	pub model_built: bool,
+	/// The file of white-space separated transition names, one seed trace per line
+	pub trace_file: String,
+	/// Where to also write the PRISM-style explicit state space, without an extension
+	pub output_file: Option<String>,
+}
+
+impl<'a> CycleCommuteBuilder<'a> {
+	/// Creates a builder for the seed traces in `trace_file`
+	pub fn new(abstract_model: &'a AbstractVas, trace_file: &str) -> Self {
+		CycleCommuteBuilder {
+			abstract_model,
+			model_built: false,
+			trace_file: trace_file.to_string(),
+			output_file: None,
+		}
+	}
+}
+
+impl<'a> Builder for CycleCommuteBuilder<'a> {
+	type AbstractModelType = AbstractVas;
+	type ExplicitModelType = PrismVasModel;
+	type ResultType = ModelCheckingResult;
+
+	/// Whether or not this model builder builds an abstracted model. In our case, yes.
+	fn is_abstracted(&self) -> bool {
+		true
+	}
+
+	/// The sink counts as failure for a lower bound
+	fn creates_pmin(&self) -> bool {
+		true
+	}
+
+	/// The state space only follows the traces, so the sink holds most of the probability
+	/// and an upper bound would not be useful
+	fn creates_pmax(&self) -> bool {
+		false
+	}
+
+	/// We only build once, so we are finished once `build()` has been called
+	fn finished(&mut self, _result: &Self::ResultType) -> bool {
+		self.model_built
+	}
+
+	/// Gets the abstract model that we're working with
+	fn get_abstract_model(&self) -> &AbstractVas {
+		self.abstract_model
+	}
+
+	/// Builds the explicit state space from the seed traces
+	fn build(&mut self, explicit_model: &mut Self::ExplicitModelType) {
+		// Do not try to rebuild the model
+		if self.model_built {
+			return;
+		}
+		if let Err(e) = cycle_commute(
+			self.abstract_model,
+			&self.trace_file,
+			self.output_file.as_deref(),
+			explicit_model,
+		) {
+			error!("{}", e);
+		}
+		self.model_built = true;
+	}
+}
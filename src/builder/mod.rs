@@ -1,4 +1,5 @@
 pub mod builder;
-// pub mod stamina;
+pub mod cycle_commute;
+pub mod stamina;
 // pub mod wayfarer;
 pub mod ragtimer;
@@ -1,15 +1,17 @@
-use std::default;
+use std::{collections::HashMap, default};
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
 	builder::builder::Builder,
+	checker::result::ModelCheckingResult,
 	model::{
-		model::ProbabilityOrRate,
+		serialization::Persistent,
 		vas_model::{AbstractVas, PrismVasModel},
 	},
 };
 
 pub type RewardValue = f64;
-type LowerBound = Option<ProbabilityOrRate>;
 
 /// Magic numbers used for RL traces in Ragtimer.
 #[derive(Debug)]
@@ -22,6 +24,39 @@ pub struct MagicNumbers {
 	pub clamp: f64,
 }
 
+/// The rewards that reinforcement learning gives the transitions, by transition ID. The
+/// names of the transitions are kept so that they are not used on a different model.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct LearnedRewards {
+	pub(crate) transition_names: Vec<String>,
+	pub(crate) rewards: HashMap<usize, RewardValue>,
+}
+
+impl LearnedRewards {
+	/// Whether these rewards were learned on the transitions of `model`
+	pub fn matches(&self, model: &AbstractVas) -> bool {
+		self.transition_names.len() == model.transitions.len()
+			&& self
+				.transition_names
+				.iter()
+				.zip(model.transitions.iter())
+				.all(|(name, transition)| *name == transition.transition_name)
+	}
+}
+
+impl Persistent for LearnedRewards {
+	const KIND: &'static str = "ragtimer-rewards";
+	type Data = LearnedRewards;
+
+	fn to_data(&self) -> Result<Self::Data, String> {
+		Ok(self.clone())
+	}
+
+	fn from_data(data: Self::Data) -> Result<Self, String> {
+		Ok(data)
+	}
+}
+
 pub enum RagtimerMethod {
 	ReinforcementLearning(MagicNumbers),
 	DeterministicDependencyGraph,
@@ -31,12 +66,14 @@ pub(crate) struct RagtimerBuilder<'a> {
 	pub abstract_model: &'a AbstractVas,
 	pub model_built: bool,
 	pub method: RagtimerMethod,
+	/// A file to start the rewards from, if it exists, and to save the learned rewards to
+	pub rewards_file: Option<String>,
 }
 
 impl<'a> Builder for RagtimerBuilder<'a> {
 	type AbstractModelType = AbstractVas;
 	type ExplicitModelType = PrismVasModel;
-	type ResultType = LowerBound;
+	type ResultType = ModelCheckingResult;
 
 	/// Whether or not this model builder builds an abstracted model. In our case, yes.
 	fn is_abstracted(&self) -> bool {
@@ -97,6 +134,7 @@ impl<'a> RagtimerBuilder<'a> {
 			abstract_model,
 			model_built: false,
 			method: RagtimerMethod::DeterministicDependencyGraph,
+			rewards_file: None,
 		};
 
 		if let Some(m) = method {
@@ -5,12 +5,14 @@ use rand::{seq::SliceRandom, Rng};
 
 use crate::{
 	builder::ragtimer::ragtimer::{
-		MagicNumbers, RagtimerBuilder, RagtimerMethod::ReinforcementLearning, RewardValue,
+		LearnedRewards, MagicNumbers, RagtimerBuilder, RagtimerMethod::ReinforcementLearning,
+		RewardValue,
 	},
 	dependency::graph::{make_dependency_graph, DependencyGraph},
-	logging::messages::{debug_message, error, warning},
+	logging::messages::{debug_message, error, message, warning},
 	model::{
-		model::{ExplicitModel, ProbabilityOrRate},
+		model::{AbstractModel, ExplicitModel, ProbabilityOrRate},
+		serialization::Persistent,
 		vas_model::{
 			PrismVasModel, PrismVasState, PrismVasTransition, VasStateVector, VasTransition,
 			VasValue,
@@ -67,6 +69,51 @@ impl<'a> RagtimerBuilder<'a> {
 		rewards
 	}
 
+	/// Reads the rewards learned in an earlier run from the rewards file, if there is one
+	/// and it was learned on this model
+	fn load_rewards(&self) -> Option<HashMap<usize, RewardValue>> {
+		let filename = self.rewards_file.as_ref()?;
+		if !std::path::Path::new(filename).exists() {
+			return None;
+		}
+		match LearnedRewards::load(filename) {
+			Ok(learned) if learned.matches(self.abstract_model) => {
+				message!("Starting from the rewards learned in {}", filename);
+				Some(learned.rewards)
+			}
+			Ok(_) => {
+				warning!(
+					"The rewards in {} were learned on a different model, ignoring them.",
+					filename
+				);
+				None
+			}
+			Err(e) => {
+				warning!("Could not load the rewards: {}", e);
+				None
+			}
+		}
+	}
+
+	/// Writes the learned rewards to the rewards file, if there is one
+	fn save_rewards(&self, rewards: &HashMap<usize, RewardValue>) {
+		let Some(filename) = &self.rewards_file else {
+			return;
+		};
+		let learned = LearnedRewards {
+			transition_names: self
+				.abstract_model
+				.transitions
+				.iter()
+				.map(|transition| transition.transition_name.clone())
+				.collect(),
+			rewards: rewards.clone(),
+		};
+		if let Err(e) = learned.save(filename) {
+			warning!("Could not save the rewards: {}", e);
+		}
+	}
+
 	/// Updates the rewards based on the trace and its probability.
 	/// This function will be called multiple times to update the rewards for the RL traces method.
 	fn update_rewards(
@@ -152,38 +199,25 @@ impl<'a> RagtimerBuilder<'a> {
 
 	/// Returns a list of transition IDs that are enabled in the current state.
 	fn get_available_transitions(&self, current_state: &VasStateVector) -> Vec<usize> {
-		let x = self
-			.abstract_model
-			.transitions
-			.iter()
-			.filter(|t| {
-				t.enabled_bounds
-					.iter()
-					.zip(current_state.iter())
-					.all(|(bound, &val)| val >= (*bound).try_into().unwrap())
-			})
+		let state = self.abstract_model.state(current_state.clone());
+		self.abstract_model
+			.enabled_transitions(&state)
 			.map(|t| t.transition_id)
-			.collect();
-		// debug_message!("Available transitions: {:?}", x));
-		x
+			.collect()
 	}
 
 	/// Calculates the transition rate for a given transition in the context
-	/// of the current state under the SCK assumption for CRN models.
+	/// of the current state, using the model's propensity convention.
 	fn crn_transition_rate(
 		&self,
 		current_state: &VasStateVector,
 		transition: &VasTransition,
 	) -> ProbabilityOrRate {
-		let mut transition_rate = 0.0;
-		for (_, &current_value) in current_state.iter().enumerate() {
-			transition_rate += transition.rate_const * (current_value as ProbabilityOrRate);
-		}
-		transition_rate
+		self.abstract_model.rate_at(transition, current_state)
 	}
 
 	/// Calculates the transition probability for a given transition in the context
-	/// of the current state under the SCK assumption for CRN models.
+	/// of the current state using the model's propensity convention.
 	fn crn_transition_probability(
 		&self,
 		current_state: &VasStateVector,
@@ -197,30 +231,22 @@ impl<'a> RagtimerBuilder<'a> {
 		self.crn_transition_rate(current_state, transition) / total_outgoing_rate
 	}
 
-	/// Calculates the transition probability for a given transition in the context
-	/// of the current state under the SCK assumption for CRN models.
+	/// Calculates the total outgoing rate of the current state.
 	fn crn_total_outgoing_rate(&self, current_state: &VasStateVector) -> ProbabilityOrRate {
-		let mut total_outgoing_rate = 0.0;
-		let available_transitions = self.get_available_transitions(current_state);
-		for t in available_transitions {
-			if let Some(vas_transition) = self.abstract_model.get_transition_from_id(t) {
-				total_outgoing_rate += self.crn_transition_rate(current_state, vas_transition);
-			} else {
-				error!("Transition ID {} not found in model.", t);
-				return 0.0; // If the transition is not found, return 0 probability
-			}
-		}
-		// debug_message!(
-		//     "Total outgoing rate for state {:?} is {:.3e}",
-		//     current_state, total_outgoing_rate
-		// ));
-		total_outgoing_rate
+		self.abstract_model.exit_rate_at(current_state)
 	}
 
-	/// Stores the explicit trace in the explicit model.
-	fn store_explicit_trace(&self, explicit_model: &mut PrismVasModel, trace: &Vec<usize>) {
+	/// Stores the explicit trace, starting in the initial state `initial_index`, in the explicit model.
+	fn store_explicit_trace(
+		&self,
+		explicit_model: &mut PrismVasModel,
+		initial_index: usize,
+		trace: &Vec<usize>,
+	) {
 		// Start with the initial state
-		let mut current_state = self.abstract_model.initial_states[0].vector.clone();
+		let mut current_state = self.abstract_model.initial_states[initial_index]
+			.vector
+			.clone();
 		let mut next_state = current_state.clone();
 		let mut current_state_id: usize = 0; // Start with the initial state ID
 		let mut next_state_id: usize = 0;
@@ -241,10 +267,7 @@ impl<'a> RagtimerBuilder<'a> {
 					explicit_model.add_state(PrismVasState {
 						state_id: current_state_id,
 						vector: current_state.clone(),
-						label: if current_state.len() > self.abstract_model.target.variable_index
-							&& current_state[self.abstract_model.target.variable_index]
-								== self.abstract_model.target.target_value
-						{
+						label: if self.abstract_model.target.is_satisfied(&current_state) {
 							Some("target".to_string())
 						} else {
 							None
@@ -257,11 +280,6 @@ impl<'a> RagtimerBuilder<'a> {
 						to_state: 0,                 // Absorbing state
 						rate: current_outgoing_rate, // Start out by assuming every outgoing transition goes to absorbing state
 					});
-					explicit_model
-						.transition_map
-						.entry(current_state_id)
-						.or_insert_with(Vec::new)
-						.push((0, explicit_model.transitions.len() - 1));
 				}
 				// Find the next state after applying the transition
 				next_state = current_state.clone() + vas_transition.update_vector.clone();
@@ -277,10 +295,7 @@ impl<'a> RagtimerBuilder<'a> {
 					explicit_model.add_state(PrismVasState {
 						state_id: next_state_id,
 						vector: next_state.clone(),
-						label: if next_state.len() > self.abstract_model.target.variable_index
-							&& next_state[self.abstract_model.target.variable_index]
-								== self.abstract_model.target.target_value
-						{
+						label: if self.abstract_model.target.is_satisfied(&next_state) {
 							Some("target".to_string())
 						} else {
 							None
@@ -293,11 +308,6 @@ impl<'a> RagtimerBuilder<'a> {
 						to_state: 0,              // Absorbing state
 						rate: next_outgoing_rate, // Start out by assuming every outgoing transition goes to absorbing state
 					});
-					explicit_model
-						.transition_map
-						.entry(next_state_id)
-						.or_insert_with(Vec::new)
-						.push((0, explicit_model.transitions.len() - 1));
 				}
 			} else {
 				error!("Transition ID {} not found in model.", transition_id);
@@ -326,27 +336,18 @@ impl<'a> RagtimerBuilder<'a> {
 					to_state: next_state_id,
 					rate: transition_rate,
 				});
-				// Update the transition map
-				explicit_model
-					.transition_map
-					.entry(current_state_id)
-					.or_insert_with(Vec::new)
-					.push((next_state_id, explicit_model.transitions.len() - 1));
 
-				// Update the absorbing state transition of the current state to account for the new transition
-				if let Some(outgoing_transitions) =
-					explicit_model.transition_map.get_mut(&current_state_id)
-				{
-					// Find the index of the absorbing transition (to_state == 0)
-					if let Some((absorbing_index, _)) = outgoing_transitions
-						.iter()
-						.find(|(to_state, _)| *to_state == 0)
-					{
-						// Update the rate of the absorbing transition
-						let absorbing_transition =
-							&mut explicit_model.transitions[*absorbing_index];
-						absorbing_transition.rate -= transition_rate;
-					}
+				// Move the rate of the new transition off the absorbing transition of the
+				// current state, which still holds every rate that is not explored yet
+				let absorbing_index = explicit_model
+					.transition_map
+					.get(&current_state_id)
+					.and_then(|targets| targets.iter().find(|(to_state, _)| *to_state == 0))
+					.map(|(_, transition_index)| *transition_index);
+				if let Some(absorbing_index) = absorbing_index {
+					let absorbing_transition = &mut explicit_model.transitions[absorbing_index];
+					absorbing_transition.rate =
+						(absorbing_transition.rate - transition_rate).max(0.0);
 				} else {
 					error!("No outgoing transitions found for state ID {}. Something probably went wrong with its absorbing state.", current_state_id);
 				}
@@ -355,30 +356,26 @@ impl<'a> RagtimerBuilder<'a> {
 		}
 	}
 
-	/// Generates a single trace based on the rewards and magic numbers.
+	/// Generates a single trace based on the rewards and magic numbers, from an initial state
+	/// sampled by its probability. Returns the index of that initial state along with the trace.
 	/// This function will be called multiple times to generate traces for the RL traces method.
 	fn generate_single_trace(
 		&mut self,
 		rewards: &HashMap<usize, RewardValue>,
-	) -> (Vec<usize>, ProbabilityOrRate) {
+	) -> (usize, Vec<usize>, ProbabilityOrRate) {
 		let mut trace = Vec::new();
-		let mut trace_probability = 1.0;
+		let initial_index = self.abstract_model.sample_initial_index(&mut rand::rng());
+		let mut trace_probability = self.abstract_model.initial_probabilities[initial_index];
 		let vas_target = &self.abstract_model.target;
 
 		// Starting in the initial state, generate a trace
-		let mut current_state = self.abstract_model.initial_states[0].vector.clone();
+		let mut current_state = self.abstract_model.initial_states[initial_index]
+			.vector
+			.clone();
 		while trace.len() < MAX_TRACE_LENGTH {
 			// Check if we have reached the target state
-			if current_state.len() > vas_target.variable_index {
-				if current_state[vas_target.variable_index] == vas_target.target_value {
-					break;
-				}
-			} else {
-				error!(
-					"Current state length {} is less than target variable index {}",
-					current_state.len(),
-					vas_target.variable_index
-				);
+			if vas_target.is_satisfied(&current_state) {
+				break;
 			}
 			// Get available transitions
 			let available_transitions = self.get_available_transitions(&current_state);
@@ -429,7 +426,7 @@ impl<'a> RagtimerBuilder<'a> {
 			}
 		}
 
-		(trace, trace_probability)
+		(initial_index, trace, trace_probability)
 	}
 
 	/// High-level function that builds the explicit state space with RL traces.
@@ -448,29 +445,45 @@ impl<'a> RagtimerBuilder<'a> {
 
 		// Set up state space storage structures
 		explicit_model.state_trie = VasTrieNode::new();
-		let current_state_id = 1;
-		let current_state = self.abstract_model.initial_states[0].vector.clone();
-		explicit_model
-			.state_trie
-			.insert_if_not_exists(&current_state, current_state_id);
-		explicit_model.add_state(PrismVasState {
-			state_id: current_state_id,
-			vector: current_state.clone(),
-			label: Some("init".to_string()),
-			total_outgoing_rate: self.crn_total_outgoing_rate(&current_state),
-		});
-		let absorbing_state = DVector::from_element(current_state.len(), -1);
+		// The initial states take the IDs after the absorbing state
+		for (index, initial_state) in self.abstract_model.initial_states.iter().enumerate() {
+			let current_state_id = index + 1;
+			let current_state = initial_state.vector.clone();
+			let current_outgoing_rate = self.crn_total_outgoing_rate(&current_state);
+			explicit_model
+				.state_trie
+				.insert_if_not_exists(&current_state, current_state_id);
+			explicit_model.add_state(PrismVasState {
+				state_id: current_state_id,
+				vector: current_state.clone(),
+				label: Some("init".to_string()),
+				total_outgoing_rate: current_outgoing_rate,
+			});
+			// Like every other state, it starts out with all of its rate going to the
+			// absorbing state
+			explicit_model.add_transition(PrismVasTransition {
+				transition_id: usize::MAX,
+				from_state: current_state_id,
+				to_state: 0,
+				rate: current_outgoing_rate,
+			});
+		}
+		let absorbing_state = DVector::from_element(self.abstract_model.variable_names.len(), -1);
 		let absorbing_state_id = 0;
 		explicit_model
 			.state_trie
 			.insert_if_not_exists(&absorbing_state, absorbing_state_id);
+		// The probabilities of a DTMC leave every state, so the absorbing state loops on itself
+		let discrete_time = explicit_model.model_type().is_discrete_time();
 		explicit_model.add_state(PrismVasState {
 			state_id: absorbing_state_id,
 			vector: absorbing_state,
 			label: Some("sink".to_string()),
-			total_outgoing_rate: 0.0,
+			total_outgoing_rate: if discrete_time { 1.0 } else { 0.0 },
 		});
-
+		if discrete_time {
+			explicit_model.add_entry(absorbing_state_id, absorbing_state_id, 1.0);
+		}
 		// If the dependency graph is not provided, we try to construct it from the abstract model.
 		let mut owned_dep_graph = None;
 		let dependency_graph_ref: &DependencyGraph = match dependency_graph {
@@ -493,16 +506,22 @@ impl<'a> RagtimerBuilder<'a> {
 				}
 			}
 		};
-		let mut rewards = self.initialize_rewards(dependency_graph_ref);
+		let mut rewards = self
+			.load_rewards()
+			.unwrap_or_else(|| self.initialize_rewards(dependency_graph_ref));
 		// Generate the traces one-by-one, repeating if the trace is not unique
 		for i in 0..magic_numbers.num_traces {
+			let mut initial_index;
 			let mut trace;
 			let mut trace_probability;
 			loop {
 				// Generate a single trace
-				(trace, trace_probability) = self.generate_single_trace(&rewards);
+				(initial_index, trace, trace_probability) = self.generate_single_trace(&rewards);
+				// The same transitions from another initial state make a different trace
+				let mut trace_key = vec![initial_index];
+				trace_key.extend(trace.iter());
 				// If the trace already exists or is empty, we try to generate a new one.
-				if !trace_trie.exists_or_insert(&trace) && !trace.is_empty() {
+				if !trace_trie.exists_or_insert(&trace_key) && !trace.is_empty() {
 					break;
 				}
 				debug_message!("Trace {} already exists, generating a new one.", i);
@@ -515,10 +534,11 @@ impl<'a> RagtimerBuilder<'a> {
 			);
 			trace_probability_history.push(trace_probability);
 			// Store explicit prism states and transitions for this trace
-			self.store_explicit_trace(explicit_model, &trace);
+			self.store_explicit_trace(explicit_model, initial_index, &trace);
 			// Update the rewards based on the trace
 			self.update_rewards(&mut rewards, &trace, &trace_probability_history);
 			self.maintain_rewards(&mut rewards, dependency_graph_ref);
 		}
+		self.save_rewards(&rewards);
 	}
 }
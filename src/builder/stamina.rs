@@ -1,99 +1,176 @@
+use std::collections::VecDeque;
+
 use metaverify::*;
+use nalgebra::DVector;
 
-use crate::*;
+use crate::{
+	builder::builder::Builder,
+	checker::result::ModelCheckingResult,
+	logging::messages::*,
+	model::{
+		model::{ExplicitModel, ProbabilityOrRate},
+		vas_model::{AbstractVas, PrismVasModel, PrismVasState, VasStateVector},
+	},
+	property::property::{Property, PropertyQuery, StateFormula},
+	solver::explicit::SINK_LABEL,
+};
 
-use builder::*;
-use model::*;
+/// The reachability threshold of the first round, as in STAMINA
+pub(crate) const DEFAULT_KAPPA: ProbabilityOrRate = 1.0;
+/// How much the threshold is divided by after each round
+pub(crate) const DEFAULT_KAPPA_REDUCTION: ProbabilityOrRate = 1000.0;
+/// The widest gap between Pmin and Pmax that the builder stops at
+pub(crate) const DEFAULT_WINDOW: ProbabilityOrRate = 1e-3;
+/// The most rounds of refinement
+pub(crate) const DEFAULT_MAX_ITERS: u16 = 10;
+
+/// How far Pmin may be above Pmax from rounding alone
+const BOUND_TOLERANCE: ProbabilityOrRate = 1e-9;
+
+/// The index of the absorbing state that the unexplored states lead to
+const SINK_INDEX: usize = 0;
 
-use std::collections::VecDeque;
-#[trusted]
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum RangeResult {
-	NoResult,
-	Range(f64, f64),
-}
-#[trusted]
-impl Default for RangeResult {
-	#[trusted]
-	fn default() -> Self {
-		Self::NoResult
-	}
-}
 #[trusted]
 struct StateProbability {
-	state_id: usize,
-	// reachability probability
-	probability: f64,
+	vector: VasStateVector,
+	// estimated reachability probability
+	probability: ProbabilityOrRate,
+	// whether the successors have not been explored yet
 	terminal: bool,
-	new: bool,
-}
-#[trusted]
-impl Default for StateProbability {
-	#[trusted]
-	fn default() -> Self {
-		Self {
-			state_id: 0,
-			probability: 0.0,
-			terminal: true,
-			new: true,
-		}
-	}
 }
 
+/// Builds the state space by STAMINA's method: states are explored in breadth-first
+/// order while the estimated probability of reaching them is at least `kappa`, and the
+/// states left unexplored (terminal states) lead to an absorbing sink. The result gives
+/// Pmin (the sink as failure) and Pmax (the sink as success), and each round that leaves
+/// them further apart than `window` lowers `kappa` and explores further.
 #[trusted]
-pub(crate) struct StaminaBuilder<AbstractModelType, ExplicitModelType>
-	where
-		AbstractModelType: AbstractModel,
-		ExplicitModelType: ExplicitModel,
-{
-
-	abstract_model: Arc<AbstractModelType>,
-	window: f64,
-	kappa: f64, // probability threshold
-	max_iters: u16,
+pub(crate) struct StaminaBuilder<'a> {
+	abstract_model: &'a AbstractVas,
+	pub(crate) window: ProbabilityOrRate,
+	pub(crate) kappa: ProbabilityOrRate, // probability threshold
+	pub(crate) kappa_reduction: ProbabilityOrRate,
+	pub(crate) max_iters: u16,
 	cur_iters: u16,
 	// If this is provided then we make any state satisfying the right, or not satisfying
 	// the left formula, absorbing
 	state_formulae: Option<(StateFormula, StateFormula)>,
+	// What we know about each state, by its index in the explicit model
+	states: Vec<StateProbability>,
 }
 
 #[trusted]
-impl StaminaBuilder<AbstractModelType, ExplicitModelType> {
-	type StateType = AbstractModelType::StateType;
-	/// Maps a state ID to that state's valuation and probabilistic information
-	/// about it (i.e., what we currently think its reachability is).
+impl<'a> StaminaBuilder<'a> {
+	/// Creates a builder with STAMINA's default settings
 	#[trusted]
-	fn id_to_state(&self, id: usize) -> (StateType, Arc<StateProbability>) {
-		// TODO
-		unimplemented!();
+	pub fn new(abstract_model: &'a AbstractVas) -> Self {
+		Self {
+			abstract_model,
+			window: DEFAULT_WINDOW,
+			kappa: DEFAULT_KAPPA,
+			kappa_reduction: DEFAULT_KAPPA_REDUCTION,
+			max_iters: DEFAULT_MAX_ITERS,
+			cur_iters: 0,
+			state_formulae: None,
+			states: Vec::new(),
+		}
 	}
 
-	/// This 
+	/// Makes the states that decide a path property absorbing, since what happens after
+	/// them does not change its probability. Steady-state queries need every state.
 	#[trusted]
-	fn reserve_state_index(&mut self, state: &StateType, index: usize) {
-		// TODO
-		unimplemented!();
+	pub fn set_property(&mut self, query: &PropertyQuery) {
+		self.state_formulae = match query {
+			PropertyQuery::Probability(property)
+			| PropertyQuery::MinProbability(property)
+			| PropertyQuery::MaxProbability(property) => Some(match property {
+				Property::Finally(goal, _) => (StateFormula::True, goal.clone()),
+				Property::Until(safe, goal, _) => (safe.clone(), goal.clone()),
+				Property::Globally(invariant) => (
+					StateFormula::True,
+					StateFormula::Not(Box::new(invariant.clone())),
+				),
+			}),
+			PropertyQuery::SteadyState(_) => None,
+		};
 	}
 
+	/// Gets the index of a state in the explicit model, adding it as a terminal state if
+	/// it is new
 	#[trusted]
-	fn find_or_create_sp(&mut self, state: &StateType) -> Arc<StateProbability> {
-		unimplemented!();
+	fn find_or_create_sp(
+		&mut self,
+		explicit_model: &mut PrismVasModel,
+		vector: &VasStateVector,
+		label: Option<String>,
+	) -> usize {
+		if let Some(index) = explicit_model.state_trie.get(vector) {
+			return index;
+		}
+		let index = self.states.len();
+		explicit_model.add_state(PrismVasState {
+			state_id: index,
+			vector: vector.clone(),
+			label,
+			total_outgoing_rate: self.abstract_model.exit_rate_at(vector),
+		});
+		self.states.push(StateProbability {
+			vector: vector.clone(),
+			probability: 0.0,
+			terminal: true,
+		});
+		index
 	}
 
 	#[trusted]
-	fn can_preterminate(&self, state: &StateType) -> bool {
-		if let Some(left_formula, right_formula) = self.state_formulae {
-			!left_formula.satisfied(state) || right_formula.satisfied(state)
+	fn can_preterminate(&self, vector: &VasStateVector) -> bool {
+		if let Some((left_formula, right_formula)) = &self.state_formulae {
+			let state = self.abstract_model.state(vector.clone());
+			!self.abstract_model.satisfies(left_formula, &state)
+				|| self.abstract_model.satisfies(right_formula, &state)
 		} else {
 			false
 		}
 	}
+
+	/// Adds the sink and the initial states, which start with their initial probabilities
+	#[trusted]
+	fn add_initial_states(&mut self, explicit_model: &mut PrismVasModel) {
+		let model = self.abstract_model;
+		let sink = DVector::from_element(model.variable_names.len(), -1);
+		// The probabilities of a DTMC leave every state, so the sink loops on itself
+		let discrete_time = model.m_type.is_discrete_time();
+		explicit_model.add_state(PrismVasState {
+			state_id: SINK_INDEX,
+			vector: sink.clone(),
+			label: Some(SINK_LABEL.to_string()),
+			total_outgoing_rate: if discrete_time { 1.0 } else { 0.0 },
+		});
+		if discrete_time {
+			explicit_model.add_entry(SINK_INDEX, SINK_INDEX, 1.0);
+		}
+		self.states.push(StateProbability {
+			vector: sink,
+			probability: 0.0,
+			terminal: false,
+		});
+		for (state, probability) in model
+			.initial_states
+			.iter()
+			.zip(model.initial_probabilities.iter())
+		{
+			let index =
+				self.find_or_create_sp(explicit_model, &state.vector, Some("init".to_string()));
+			self.states[index].probability += probability;
+		}
+	}
 }
+
 #[trusted]
-impl<AbstractModelType, ExplicitModelType> Builder for StaminaBuilder<AbstractModelType, ExplicitModelType> {
-	type AbstractModelType = StaminaBuilder::AbstractModelType;
-	type ExplicitModelType = StaminaBuilder::ExplicitModelType;
-	type ResultType = RangeResult;
+impl<'a> Builder for StaminaBuilder<'a> {
+	type AbstractModelType = AbstractVas;
+	type ExplicitModelType = PrismVasModel;
+	type ResultType = ModelCheckingResult;
 
 	/// Because we have an absorbing state, this is an abstracted model
 	#[trusted]
@@ -110,77 +187,117 @@ impl<AbstractModelType, ExplicitModelType> Builder for StaminaBuilder<AbstractMo
 		true
 	}
 
+	/// We are finished once Pmin and Pmax are within the window of each other, every
+	/// state is explored or we run out of iterations. Otherwise `kappa` is lowered for
+	/// the next round.
 	#[trusted]
-	fn finished(&mut self, result: &ResultType) -> bool {
-		match result {
-			RangeResult::NoResult => {
-				// TODO: other processing
-				// We're not done
-				false
-			},
-			Range(p_min, p_max) => {
-				if p_min > p_max {
-					panic!("Got invalid Pmin/Pmax pair! ({}/{})", p_min, p_max);
+	fn finished(&mut self, result: &ModelCheckingResult) -> bool {
+		self.cur_iters += 1;
+		let converged = match *result {
+			ModelCheckingResult::ProbabilityRange(p_min, p_max) => {
+				// Rounding can take Pmin just above Pmax, which only means that they met
+				if p_min > p_max + BOUND_TOLERANCE {
+					warning!(
+						"Got Pmin {} above Pmax {}, so the bounds are taken as converged",
+						p_min,
+						p_max
+					);
 				}
-				self.cur_iters += 1;
-				self.cur_iters >= self.max_iters || p_max - p_min <= self.window
+				p_max - p_min <= self.window
 			}
+			ModelCheckingResult::ExactProbability(_) => true,
+			// TODO: other processing
+			_ => false,
+		};
+		let explored = self.states.iter().all(|state| !state.terminal);
+		if converged || explored || self.cur_iters >= self.max_iters {
+			return true;
 		}
+		self.kappa /= self.kappa_reduction;
+		debug_message!("Lowering kappa to {:e}", self.kappa);
+		false
 	}
 
 	#[trusted]
-	fn get_abstract_model(&self) -> Arc<AbstractModelType> {
-		self.abstract_model.clone()
+	fn get_abstract_model(&self) -> &AbstractVas {
+		self.abstract_model
 	}
 
 	#[trusted]
-	fn build(&mut self, explicit_model: &mut ExplicitModelType) {
+	fn build(&mut self, explicit_model: &mut PrismVasModel) {
 		let model = self.abstract_model;
-		if self.cur_iters == 0 {
-			// For the artificial absorbing state
-			// TODO: should this be in the ExplicitModel trait?
-			explicit_model.reserve_index(0);
+		if self.states.is_empty() {
+			self.add_initial_states(explicit_model);
 		}
-		// Get the initial states and put them into a queue
-		let mut queue = model.initial_states()
-			.map(|(state, id)| {
-				self.reserve_state_index(state, id);
-				(state, self.find_or_create_sp(state))
+		let discrete_time = model.m_type.is_discrete_time();
+		// Explore from the terminal states that are likely enough to reach
+		let mut queue = (0..self.states.len())
+			.filter(|&index| {
+				self.states[index].terminal && self.states[index].probability >= self.kappa
 			})
 			.collect::<VecDeque<_>>();
-		// Explore until the queue is empty
-		while queue.len() > 0 {
-			let (cur_state, sp) = queue.pop_front();
+		while let Some(index) = queue.pop_front() {
+			// The state leads to its successors now, rather than to the sink
+			self.states[index].terminal = false;
+			explicit_model.remove_entry(index, SINK_INDEX);
+			let vector = self.states[index].vector.clone();
 			// Optimization. If we can preterminate this state, then we don't need
 			// to explore its successors.
-			if self.can_preterminate(&cur_state) {
-				sp.terminal = true;
-				explicit_model.add_entry(sp.state_id, sp.state_id, 1.0);
+			let exit_rate = model.exit_rate_at(&vector);
+			if self.can_preterminate(&vector) || exit_rate <= 0.0 {
+				if discrete_time {
+					explicit_model.add_entry(index, index, 1.0);
+				}
 				continue;
 			}
-			// Terminate if our threshold is low enough
-			if sp.terminal && sp.probability < self.kappa {
-				explicit_model.add_entry(sp.state_id, 0, model.exit_rate(cur_state));
+			let probability = self.states[index].probability;
+			for transition in model
+				.transitions
+				.iter()
+				.filter(|transition| transition.enabled_vector(&vector))
+			{
+				let rate = model.rate_at(transition, &vector);
+				if rate <= 0.0 {
+					continue;
+				}
+				let next_vector = &vector + &transition.update_vector;
+				let next_index = self.find_or_create_sp(explicit_model, &next_vector, None);
+				explicit_model.add_entry(index, next_index, rate);
+				// Pass the probability on to the successors that are not explored yet
+				let next_state = &mut self.states[next_index];
+				if next_state.terminal {
+					let was_below = next_state.probability < self.kappa;
+					next_state.probability += probability * rate / exit_rate;
+					if was_below && next_state.probability >= self.kappa {
+						queue.push_back(next_index);
+					}
+				}
+			}
+		}
+		// The terminal states lead to the sink, apart from the deadlocks, which have
+		// nothing left to explore
+		let mut terminal = 0;
+		for index in 0..self.states.len() {
+			if !self.states[index].terminal {
 				continue;
 			}
-			// Enqueue successors to this state and keep exploring
-			let enqueue_all = sp.probability == 0;
-			let exit_rate = if enqueue_all { 1.0 } else { model.exit_rate(cur_state) };
-			for (rate, next_state) in model.next_states(cur_state) {
-				let mut next_sp = self.find_or_create_sp(next_state);
-				// Handle enqueuing for exploration
-				if enqueue_all {
-					// If the current reachability is zero then we can enqueue without
-					// updating the successors' reachability
-					queue.push_back((next_state, next_sp));
-				} else {
-					next_sp.probability += (rate / exit_rate) * sp.probability;
-				}
-				// Handle adding to the sparse matrix
-				if sp.new {
-					// TODO
+			let exit_rate = model.exit_rate_at(&self.states[index].vector);
+			if exit_rate <= 0.0 {
+				self.states[index].terminal = false;
+				if discrete_time {
+					explicit_model.add_entry(index, index, 1.0);
 				}
+				continue;
 			}
+			terminal += 1;
+			explicit_model.remove_entry(index, SINK_INDEX);
+			explicit_model.add_entry(index, SINK_INDEX, exit_rate);
 		}
+		debug_message!(
+			"Explored {} states with kappa {:e}, {} of them terminal",
+			self.states.len() - 1,
+			self.kappa,
+			terminal
+		);
 	}
 }
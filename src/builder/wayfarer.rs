@@ -8,7 +8,9 @@ use model::*;
 type LowerBound = Option<ProbabilityOrRate>;
 
 struct AffineSpace {
-	// TODO
+	// TODO: Wayfarer does not build anything yet. Once it does, the minimal cycles of
+	// `TInvariants::from_model` (see `invariant::t_invariant`) are the directions that
+	// move within the space without changing the state.
 }
 
 pub(crate) struct WayfarerBuilder {
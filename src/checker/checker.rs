@@ -1,36 +1,146 @@
+use std::time::{Duration, Instant};
+
 use metaverify::*;
 
-use crate::*;
+use crate::{
+	builder::builder::Builder,
+	checker::result::ModelCheckingResult,
+	logging::messages::*,
+	model::{
+		model::{AbstractModel, ExplicitModel},
+		vas_model::{AbstractVas, PrismVasModel},
+	},
+	property::property::{Property, PropertyQuery, StateFormula},
+	solver::{
+		interval::check_interval,
+		iterative::{check_steady_state, SolverOptions},
+		transient::{check_bounded, DEFAULT_EPSILON},
+	},
+};
+
+/// The default number of rounds of building and checking
+pub(crate) const DEFAULT_MAX_ROUNDS: usize = 100;
 
-use model::*;
-use builder::*;
+/// How long a checker may keep refining its model
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Budget {
+	/// The most rounds of building and checking
+	pub(crate) max_rounds: usize,
+	/// The most time to spend, which is checked after each round
+	pub(crate) time_limit: Option<Duration>,
+}
+
+impl Default for Budget {
+	fn default() -> Self {
+		Self {
+			max_rounds: DEFAULT_MAX_ROUNDS,
+			time_limit: None,
+		}
+	}
+}
 
 #[trusted]
 pub(crate) trait Checker {
 	type AbstractModelType: AbstractModel;
 	type ExplicitModelType: ExplicitModel;
-	type BuilderType: Builder;
 	type ResultType: Clone + Copy + PartialEq + Default;
+	type BuilderType: Builder<
+		AbstractModelType = Self::AbstractModelType,
+		ExplicitModelType = Self::ExplicitModelType,
+		ResultType = Self::ResultType,
+	>;
 
-	// TODO
+	/// The builder that the explicit model is refined with
 	#[trusted]
-	fn builder(&self) -> &BuilderType;
+	fn builder(&self) -> &Self::BuilderType;
 	#[trusted]
-	fn builder_mut(&self) -> &mut BuilderType;
+	fn builder_mut(&mut self) -> &mut Self::BuilderType;
 	/// Checks the explicit model and returns a result
 	#[trusted]
-	fn check(&mut self, model: &ExplicitModelType) -> ResultType;
-	
-	/// Builds the model and checks it
+	fn check(&mut self, model: &Self::ExplicitModelType) -> Self::ResultType;
+
+	/// How long the model may be refined for. There is no time limit by default.
 	#[trusted]
-	fn build_and_check(&mut self) -> ResultType {
-		let mut bldr = self.builder_mut();
-		
-		let mut explicit_model: ExplicitModelType::default();
-		let mut finished = false;
-		while !finished {
-			// TODO	
+	fn budget(&self) -> Budget {
+		Budget::default()
+	}
+
+	/// Builds the model and checks it, round after round, feeding each result back to the
+	/// builder until it is finished or the budget runs out. Gives the last result.
+	#[trusted]
+	fn build_and_check(
+		&mut self,
+		explicit_model: &mut Self::ExplicitModelType,
+	) -> Self::ResultType {
+		let budget = self.budget();
+		let start = Instant::now();
+		let mut result = Self::ResultType::default();
+		for round in 1..=budget.max_rounds {
+			self.builder_mut().build(explicit_model);
+			result = self.check(explicit_model);
+			if self.builder_mut().finished(&result) {
+				debug_message!("Finished after {} rounds", round);
+				return result;
+			}
+			if budget
+				.time_limit
+				.is_some_and(|limit| start.elapsed() >= limit)
+			{
+				warning!("Ran out of time after {} rounds", round);
+				return result;
+			}
 		}
+		warning!(
+			"Stopped after {} rounds without the builder finishing",
+			budget.max_rounds
+		);
+		result
 	}
+}
 
+/// The queries of a VAS model, or the probability of eventually reaching its target if it
+/// has none (its minimum and maximum, for an MDP or CTMDP)
+pub(crate) fn model_queries(abstract_model: &AbstractVas) -> Vec<PropertyQuery> {
+	if !abstract_model.properties.is_empty() {
+		return abstract_model.properties.clone();
+	}
+	let reach_target = Property::Finally(
+		StateFormula::from_target(&abstract_model.target, &abstract_model.variable_names),
+		None,
+	);
+	if abstract_model.m_type.is_nondeterministic() {
+		vec![
+			PropertyQuery::MinProbability(reach_target.clone()),
+			PropertyQuery::MaxProbability(reach_target),
+		]
+	} else {
+		vec![PropertyQuery::Probability(reach_target)]
+	}
+}
+
+/// Checks a query of `abstract_model` on an explicit model built from it with the
+/// built-in solvers: uniformization for time-bounded queries, interval iteration for the
+/// other probabilities, and the linear solvers for steady-state queries
+pub(crate) fn check_explicit(
+	abstract_model: &AbstractVas,
+	explicit_model: &PrismVasModel,
+	query: &PropertyQuery,
+	options: &SolverOptions,
+) -> Result<ModelCheckingResult, String> {
+	match query {
+		PropertyQuery::Probability(Property::Finally(_, Some(_)))
+		| PropertyQuery::Probability(Property::Until(_, _, Some(_))) => {
+			let transient = check_bounded(abstract_model, explicit_model, query, DEFAULT_EPSILON)?;
+			debug_message!(
+				"Error bound {:e}, {} steps",
+				transient.error_bound,
+				transient.steps
+			);
+			Ok(transient.result)
+		}
+		PropertyQuery::SteadyState(_) => {
+			check_steady_state(abstract_model, explicit_model, query, options)
+		}
+		_ => check_interval(abstract_model, explicit_model, query, options),
+	}
 }
@@ -1,2 +1,4 @@
 pub(crate) mod checker;
+pub(crate) mod report;
 pub(crate) mod result;
+pub(crate) mod vas_checker;
@@ -0,0 +1,252 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	checker::result::{ModelCheckingResult, CONSISTENCY_TOLERANCE},
+	logging::messages::*,
+	model::serialization::Persistent,
+	property::property::PropertyQuery,
+};
+
+/// The result of one method (e.g., `ragtimer` or `stamina`) on a query
+#[derive(Clone, Debug)]
+pub(crate) struct MethodResult {
+	pub(crate) method: String,
+	pub(crate) result: ModelCheckingResult,
+}
+
+/// The results of several methods on one query, and the tightest result that they agree on
+#[derive(Clone, Debug)]
+pub(crate) struct QueryReport {
+	/// The query, as it is written in a `.prop` file
+	pub(crate) query: String,
+	pub(crate) results: Vec<MethodResult>,
+	pub(crate) merged: ModelCheckingResult,
+	/// The results that contradict the ones before them, which are left out of `merged`
+	pub(crate) inconsistencies: Vec<String>,
+}
+
+impl QueryReport {
+	fn new(query: String) -> Self {
+		Self {
+			query,
+			results: Vec::new(),
+			merged: ModelCheckingResult::new(),
+			inconsistencies: Vec::new(),
+		}
+	}
+
+	/// Adds the result of a method and merges it into the tightest result so far
+	fn add(&mut self, method: &str, result: ModelCheckingResult) {
+		match self.merged.merge(&result, CONSISTENCY_TOLERANCE) {
+			Ok(merged) => self.merged = merged,
+			Err(e) => {
+				let inconsistency = format!("{} on {}: {}", method, self.query, e);
+				warning!("{}", inconsistency);
+				self.inconsistencies.push(inconsistency);
+			}
+		}
+		self.results.push(MethodResult {
+			method: method.to_string(),
+			result,
+		});
+	}
+}
+
+/// The results of the methods run on a model, by query. Reports of different runs on the
+/// same model can be merged, so that the methods are compared query by query and their
+/// bounds combined into the tightest range.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Report {
+	/// The model file the results are for
+	pub(crate) model: String,
+	pub(crate) queries: Vec<QueryReport>,
+}
+
+impl Report {
+	/// Creates an empty report for a model file
+	pub fn new(model: &str) -> Self {
+		Self {
+			model: model.to_string(),
+			queries: Vec::new(),
+		}
+	}
+
+	/// Adds the result of a method on a query
+	pub fn add(&mut self, method: &str, query: &PropertyQuery, result: ModelCheckingResult) {
+		self.add_to_query(method, query.to_string(), result);
+	}
+
+	/// Adds the results of a method on several queries. A query that could not be checked
+	/// is reported as an error and gets no result.
+	pub fn add_results(
+		&mut self,
+		method: &str,
+		results: &[(PropertyQuery, Result<ModelCheckingResult, String>)],
+	) {
+		for (query, result) in results.iter() {
+			let result = result.clone().unwrap_or_else(|e| {
+				error!("{} could not check {}: {}", method, query, e);
+				ModelCheckingResult::NoResult
+			});
+			self.add(method, query, result);
+		}
+	}
+
+	fn add_to_query(&mut self, method: &str, query: String, result: ModelCheckingResult) {
+		let index = match self.queries.iter().position(|report| report.query == query) {
+			Some(index) => index,
+			None => {
+				self.queries.push(QueryReport::new(query));
+				self.queries.len() - 1
+			}
+		};
+		self.queries[index].add(method, result);
+	}
+
+	/// Adds every result of another report on the same model
+	pub fn merge(&mut self, other: &Report) -> Result<(), String> {
+		if other.model != self.model {
+			return Err(format!(
+				"Cannot merge the results for {} with those for {}",
+				other.model, self.model
+			));
+		}
+		for query in other.queries.iter() {
+			for result in query.results.iter() {
+				self.add_to_query(&result.method, query.query.clone(), result.result);
+			}
+		}
+		Ok(())
+	}
+
+	/// Whether no result contradicts another
+	pub fn consistent(&self) -> bool {
+		self.queries
+			.iter()
+			.all(|query| query.inconsistencies.is_empty())
+	}
+}
+
+/// The data of a result: its bounds on the probability, or its value
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ResultData {
+	pub(crate) lower: Option<f64>,
+	pub(crate) upper: Option<f64>,
+	pub(crate) value: Option<i64>,
+}
+
+impl ResultData {
+	fn new(result: &ModelCheckingResult) -> Self {
+		Self {
+			lower: result.lower(),
+			upper: result.upper(),
+			value: match *result {
+				ModelCheckingResult::VariableValueResult(value) => Some(value),
+				_ => None,
+			},
+		}
+	}
+
+	fn to_result(&self) -> ModelCheckingResult {
+		match self.value {
+			Some(value) => ModelCheckingResult::VariableValueResult(value),
+			None => ModelCheckingResult::from_bounds(self.lower, self.upper),
+		}
+	}
+}
+
+/// The data of the result of a method
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct MethodResultData {
+	pub(crate) method: String,
+	pub(crate) result: ResultData,
+}
+
+/// The data of the results on a query. The merged result and the inconsistencies are
+/// saved for people and other tools to read, and recomputed when the report is loaded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct QueryReportData {
+	pub(crate) query: String,
+	pub(crate) results: Vec<MethodResultData>,
+	pub(crate) merged: ResultData,
+	pub(crate) inconsistencies: Vec<String>,
+}
+
+/// The data of a `Report`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ReportData {
+	pub(crate) model: String,
+	pub(crate) queries: Vec<QueryReportData>,
+}
+
+impl Persistent for Report {
+	const KIND: &'static str = "report";
+	type Data = ReportData;
+
+	fn to_data(&self) -> Result<Self::Data, String> {
+		Ok(ReportData {
+			model: self.model.clone(),
+			queries: self
+				.queries
+				.iter()
+				.map(|query| QueryReportData {
+					query: query.query.clone(),
+					results: query
+						.results
+						.iter()
+						.map(|result| MethodResultData {
+							method: result.method.clone(),
+							result: ResultData::new(&result.result),
+						})
+						.collect(),
+					merged: ResultData::new(&query.merged),
+					inconsistencies: query.inconsistencies.clone(),
+				})
+				.collect(),
+		})
+	}
+
+	fn from_data(data: Self::Data) -> Result<Self, String> {
+		let mut report = Report::new(&data.model);
+		for query in data.queries {
+			for result in query.results.iter() {
+				report.add_to_query(
+					&result.method,
+					query.query.clone(),
+					result.result.to_result(),
+				);
+			}
+		}
+		Ok(report)
+	}
+}
+
+/// Prints the report for people: each query with the result of each method and the
+/// merged result, if there is more than one method
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Results for {}", self.model)?;
+		for query in self.queries.iter() {
+			write!(f, "\n{}", query.query)?;
+			let width = query
+				.results
+				.iter()
+				.map(|result| result.method.len())
+				.max()
+				.unwrap_or(0)
+				.max("merged".len());
+			for result in query.results.iter() {
+				write!(f, "\n\t{:width$}  {}", result.method, result.result)?;
+			}
+			if query.results.len() > 1 {
+				write!(f, "\n\t{:width$}  {}", "merged", query.merged)?;
+			}
+			for inconsistency in query.inconsistencies.iter() {
+				write!(f, "\n\tINCONSISTENT: {}", inconsistency)?;
+			}
+		}
+		Ok(())
+	}
+}
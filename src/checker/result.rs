@@ -1,3 +1,12 @@
+use std::fmt;
+
+use metaverify::*;
+
+/// How far the bounds from different methods may cross before they are inconsistent,
+/// which allows for the tolerance of the numerical methods
+pub(crate) const CONSISTENCY_TOLERANCE: f64 = 1e-6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) enum ModelCheckingResult {
 	NoResult, // checking has not yet been performed or was unable to occur
 	LowerBound(f64), // A lower bound (Pmin)
@@ -6,16 +15,21 @@ pub(crate) enum ModelCheckingResult {
 	ProbabilityRange(f64, f64), // A probabilistic range of Pmin to Pmax
 	VariableValueResult(i64), // A result representing a variable value
 }
+impl Default for ModelCheckingResult {
+	fn default() -> Self {
+		Self::new()
+	}
+}
 impl ModelCheckingResult {
-	// TODO: I would like to have these perform the model checking
-	// but idk if that makes sense
+	/// A result before any checking has been performed
 	#[trusted]
-	fn new() -> Self {
-		unimplemented!();
+	pub(crate) fn new() -> Self {
+		ModelCheckingResult::NoResult
 	}
 	#[trusted]
-	fn valid(&self) -> bool {
-		match self {
+	pub(crate) fn valid(&self) -> bool {
+		use ModelCheckingResult::*;
+		match *self {
 			NoResult => true,
 			// Probabilities must be in the range of 0.0-1.0
 			LowerBound(pmin) => pmin >= 0.0 && pmin <= 1.0,
@@ -30,4 +44,118 @@ impl ModelCheckingResult {
 			_ => true
 		}
 	}
+
+	/// The lower bound on the probability that this result gives, if any
+	#[trusted]
+	pub(crate) fn lower(&self) -> Option<f64> {
+		use ModelCheckingResult::*;
+		match *self {
+			LowerBound(pmin) | ProbabilityRange(pmin, _) | ExactProbability(pmin) => Some(pmin),
+			_ => None,
+		}
+	}
+
+	/// The upper bound on the probability that this result gives, if any
+	#[trusted]
+	pub(crate) fn upper(&self) -> Option<f64> {
+		use ModelCheckingResult::*;
+		match *self {
+			UpperBound(pmax) | ProbabilityRange(_, pmax) | ExactProbability(pmax) => Some(pmax),
+			_ => None,
+		}
+	}
+
+	/// Whether this result says anything about a probability
+	#[trusted]
+	pub(crate) fn is_probability(&self) -> bool {
+		self.lower().is_some() || self.upper().is_some()
+	}
+
+	/// The result from the given bounds: a range if both are known, and a single bound or
+	/// no result otherwise
+	#[trusted]
+	pub(crate) fn from_bounds(lower: Option<f64>, upper: Option<f64>) -> Self {
+		use ModelCheckingResult::*;
+		match (lower, upper) {
+			(Some(pmin), Some(pmax)) if pmin == pmax => ExactProbability(pmin),
+			(Some(pmin), Some(pmax)) => ProbabilityRange(pmin, pmax),
+			(Some(pmin), None) => LowerBound(pmin),
+			(None, Some(pmax)) => UpperBound(pmax),
+			(None, None) => NoResult,
+		}
+	}
+
+	/// Keeps only the bounds that the model this result was checked on is meant to give,
+	/// e.g., only the lower bound of a model whose builder only creates $P_{min}$
+	#[trusted]
+	pub(crate) fn keep_bounds(&self, pmin: bool, pmax: bool) -> Self {
+		if !self.is_probability() {
+			return *self;
+		}
+		Self::from_bounds(self.lower().filter(|_| pmin), self.upper().filter(|_| pmax))
+	}
+
+	/// The tightest result that both results allow: the largest lower bound and the
+	/// smallest upper bound. Gives an error if the results are invalid or contradict each
+	/// other, i.e., a lower bound is above an upper bound by more than `tolerance`. Bounds
+	/// that cross by less than that are taken as a range between them.
+	#[trusted]
+	pub(crate) fn merge(&self, other: &Self, tolerance: f64) -> Result<Self, String> {
+		use ModelCheckingResult::*;
+		for result in [self, other] {
+			if !result.valid() {
+				return Err(format!("{} is not a valid result", result));
+			}
+		}
+		match (*self, *other) {
+			(NoResult, result) | (result, NoResult) => return Ok(result),
+			(VariableValueResult(a), VariableValueResult(b)) => {
+				return if a == b {
+					Ok(*self)
+				} else {
+					Err(format!("The values {} and {} differ", a, b))
+				}
+			}
+			(VariableValueResult(_), _) | (_, VariableValueResult(_)) => {
+				return Err(format!("Cannot merge {} with {}", self, other))
+			}
+			_ => {}
+		}
+		let lower = self
+			.lower()
+			.into_iter()
+			.chain(other.lower())
+			.reduce(f64::max);
+		let upper = self
+			.upper()
+			.into_iter()
+			.chain(other.upper())
+			.reduce(f64::min);
+		match (lower, upper) {
+			(Some(pmin), Some(pmax)) if pmin > pmax + tolerance => Err(format!(
+				"{} and {} are inconsistent: the lower bound {} is above the upper bound {}",
+				self, other, pmin, pmax
+			)),
+			(Some(pmin), Some(pmax)) if pmin > pmax => {
+				Ok(Self::from_bounds(Some(pmax), Some(pmin)))
+			}
+			_ => Ok(Self::from_bounds(lower, upper)),
+		}
+	}
+}
+
+/// Prints the result for people, e.g., `>= 0.25` for a lower bound and `[0.25, 0.5]` for a
+/// range
+impl fmt::Display for ModelCheckingResult {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		use ModelCheckingResult::*;
+		match *self {
+			NoResult => write!(f, "no result"),
+			LowerBound(pmin) => write!(f, ">= {}", pmin),
+			UpperBound(pmax) => write!(f, "<= {}", pmax),
+			ExactProbability(p) => write!(f, "{}", p),
+			ProbabilityRange(pmin, pmax) => write!(f, "[{}, {}]", pmin, pmax),
+			VariableValueResult(value) => write!(f, "{}", value),
+		}
+	}
 }
@@ -0,0 +1,141 @@
+use crate::{
+	builder::{
+		builder::Builder, cycle_commute::CycleCommuteBuilder, ragtimer::ragtimer::RagtimerBuilder,
+		stamina::StaminaBuilder,
+	},
+	checker::{
+		checker::{check_explicit, Budget, Checker},
+		result::ModelCheckingResult,
+	},
+	logging::messages::*,
+	model::vas_model::{AbstractVas, PrismVasModel},
+	property::property::PropertyQuery,
+	solver::iterative::SolverOptions,
+};
+
+/// Checks the queries of a VAS model on the explicit models that one of its builders
+/// makes, with the built-in solvers. The first query drives the refinement and the others
+/// are checked on the final model.
+pub(crate) struct VasChecker<B> {
+	builder: B,
+	queries: Vec<PropertyQuery>,
+	options: SolverOptions,
+	budget: Budget,
+}
+
+/// Ragtimer's traces, checked by the built-in solvers
+pub(crate) type RagtimerChecker<'a> = VasChecker<RagtimerBuilder<'a>>;
+/// STAMINA's truncated state spaces, checked by the built-in solvers
+pub(crate) type StaminaChecker<'a> = VasChecker<StaminaBuilder<'a>>;
+/// The state spaces from cycle & commute, checked by the built-in solvers
+pub(crate) type CycleCommuteChecker<'a> = VasChecker<CycleCommuteBuilder<'a>>;
+
+impl<B> VasChecker<B>
+where
+	B: Builder<
+		AbstractModelType = AbstractVas,
+		ExplicitModelType = PrismVasModel,
+		ResultType = ModelCheckingResult,
+	>,
+{
+	/// Creates a checker for `queries`, which must not be empty
+	pub fn new(
+		builder: B,
+		queries: Vec<PropertyQuery>,
+		options: SolverOptions,
+	) -> Result<Self, String> {
+		if queries.is_empty() {
+			return Err("The model has no target or query to check".to_string());
+		}
+		Ok(Self {
+			builder,
+			queries,
+			options,
+			budget: Budget::default(),
+		})
+	}
+
+	/// Sets how long the model may be refined for
+	pub fn set_budget(&mut self, budget: Budget) {
+		self.budget = budget;
+	}
+
+	/// Checks each query on an explicit model built by this checker's builder, keeping
+	/// only the bounds that the builder is meant to give
+	pub fn check_all(
+		&self,
+		explicit_model: &PrismVasModel,
+	) -> Vec<(PropertyQuery, Result<ModelCheckingResult, String>)> {
+		self.queries
+			.iter()
+			.map(|query| {
+				let result = check_explicit(
+					self.builder.get_abstract_model(),
+					explicit_model,
+					query,
+					&self.options,
+				)
+				.map(|result| {
+					result.keep_bounds(self.builder.creates_pmin(), self.builder.creates_pmax())
+				});
+				(query.clone(), result)
+			})
+			.collect()
+	}
+
+	/// Builds the model, refining it for the first query, and then checks every query
+	pub fn build_and_check_all(
+		&mut self,
+		explicit_model: &mut PrismVasModel,
+	) -> Vec<(PropertyQuery, Result<ModelCheckingResult, String>)> {
+		self.build_and_check(explicit_model);
+		self.check_all(explicit_model)
+	}
+}
+
+impl<B> Checker for VasChecker<B>
+where
+	B: Builder<
+		AbstractModelType = AbstractVas,
+		ExplicitModelType = PrismVasModel,
+		ResultType = ModelCheckingResult,
+	>,
+{
+	type AbstractModelType = AbstractVas;
+	type ExplicitModelType = PrismVasModel;
+	type ResultType = ModelCheckingResult;
+	type BuilderType = B;
+
+	fn builder(&self) -> &B {
+		&self.builder
+	}
+
+	fn builder_mut(&mut self) -> &mut B {
+		&mut self.builder
+	}
+
+	/// Checks the first query. An error is reported and gives no result, which the
+	/// builders take as a reason to keep refining.
+	fn check(&mut self, model: &PrismVasModel) -> ModelCheckingResult {
+		let query = &self.queries[0];
+		match check_explicit(
+			self.builder.get_abstract_model(),
+			model,
+			query,
+			&self.options,
+		) {
+			Ok(result) => {
+				debug_message!("{}: {}", query, result);
+				result
+			}
+			Err(e) => {
+				error!("Could not check {}: {}", query, e);
+				ModelCheckingResult::NoResult
+			}
+		}
+	}
+
+	fn budget(&self) -> Budget {
+		self.budget
+	}
+}
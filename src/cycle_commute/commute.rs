@@ -2,6 +2,7 @@
 /// It generates a PRISM-compatible state space from a given trace file.
 /// It then uses the trace to build a highly-concurrent and cyclical state space of the VAS model
 use std::{
+	collections::BTreeMap,
 	fs::File,
 	io::{BufRead, BufReader},
 };
@@ -9,19 +10,23 @@ use std::{
 use nalgebra::DVector;
 
 use crate::{
+	invariant::t_invariant::{TInvariant, TInvariants},
 	model::{
-		model::ProbabilityOrRate,
-		vas_model::{AbstractVas, VasStateVector, VasTransition, VasValue},
+		model::{ExplicitModel, ProbabilityOrRate},
+		vas_model::{
+			AbstractVas, PrismVasModel, PrismVasState, VasStateVector, VasTransition, VasValue,
+		},
 		vas_trie,
 	},
+	solver::explicit::SINK_LABEL,
 	*,
 };
-use itertools::Itertools;
 use std::io::Write;
 
 /// Temporary constant max depth for the cycle commute algorithm.
 const MAX_DEPTH: usize = 2;
-const MAX_CYCLE_LENGTH: usize = 2;
+/// The most orders in which each cycle is added from each state
+const MAX_CYCLE_ORDERINGS: usize = 8;
 
 /// PrismStyleExplicitState represents a state in the PRISM-style explicit state space as described at
 /// <https://www.prismmodelchecker.org/manual/RunningPRISM/ExplicitModelImport>
@@ -33,8 +38,9 @@ struct PrismStyleExplicitState {
 	total_rate: ProbabilityOrRate,
 	/// Label for the state, currently unused
 	label: String,
-	/// Vector of next states, here only for convenience in lookup while building the state space.
-	next_states: Vec<usize>,
+	/// The (next state, model transition ID) pairs of the outgoing transitions, here only for
+	/// convenience in lookup while building the state space.
+	next_states: Vec<(usize, usize)>,
 }
 
 impl PrismStyleExplicitState {
@@ -43,7 +49,7 @@ impl PrismStyleExplicitState {
 		state_vector: VasStateVector,
 		total_rate: ProbabilityOrRate,
 		label: String,
-		next_states: Vec<usize>,
+		next_states: Vec<(usize, usize)>,
 	) -> Self {
 		PrismStyleExplicitState {
 			state_vector,
@@ -66,23 +72,33 @@ struct PrismStyleExplicitTransition {
 	rate: ProbabilityOrRate,
 }
 
-/// This function calculates the outgoing rate of a transition.
-/// It currently assumes the SCK assumption that the rate
-/// depends on the product of the enabled bounds.
-impl VasTransition {
-	/// Calculates the SCK rate of the transition.
-	/// This function is temporary and intended only for quick C&C result generation ---
-	/// it will eventually be replaced by a system-wide more-powerful rate calculation
-	/// that allows for more complex rate calculations.
-	fn get_sck_rate(&self) -> ProbabilityOrRate {
-		self.rate_const
-			* self
-				.enabled_bounds
-				.iter()
-				.filter(|&&r| r != 0)
-				.map(|&r| (r as ProbabilityOrRate))
-				.product::<ProbabilityOrRate>()
+/// Adds the transition of the model with ID `transition_id` from `from_state` to
+/// `to_state`, unless it is already there, and gives it if it is new. Transitions of the
+/// model that lead to the same state are each kept, so their rates add up.
+fn add_transition(
+	prism_states: &mut [PrismStyleExplicitState],
+	prism_transitions: &mut Vec<PrismStyleExplicitTransition>,
+	from_state: usize,
+	to_state: usize,
+	transition_id: usize,
+	rate: ProbabilityOrRate,
+) -> Option<PrismStyleExplicitTransition> {
+	if prism_states[from_state]
+		.next_states
+		.contains(&(to_state, transition_id))
+	{
+		return None;
 	}
+	let transition = PrismStyleExplicitTransition {
+		from_state,
+		to_state,
+		rate,
+	};
+	prism_states[from_state]
+		.next_states
+		.push((to_state, transition_id));
+	prism_transitions.push(transition.clone());
+	Some(transition)
 }
 
 /// This function prints the PRISM-style explicit state space to .sta and .tra files.
@@ -123,13 +139,18 @@ fn print_prism_files(
 			return;
 		}
 	};
+	// Transitions of the model between the same states are one entry
+	let mut entries = BTreeMap::<(usize, usize), ProbabilityOrRate>::new();
+	for t in prism_transitions.iter() {
+		*entries.entry((t.from_state, t.to_state)).or_insert(0.0) += t.rate;
+	}
 	// header
 	let num_states = prism_states.len();
-	let num_transitions = prism_transitions.len();
+	let num_transitions = entries.len();
 	writeln!(tra_file, "{} {}", num_states, num_transitions).unwrap();
 	// transitions
-	for t in prism_transitions.iter() {
-		writeln!(tra_file, "{} {} {}", t.from_state, t.to_state, t.rate).unwrap();
+	for ((from_state, to_state), rate) in entries.iter() {
+		writeln!(tra_file, "{} {} {}", from_state, to_state, rate).unwrap();
 	}
 	// Output results to the specified output file
 	message!(
@@ -138,36 +159,73 @@ fn print_prism_files(
 	);
 	message!(
 		"Check this with the following command:\n
-		prism -importtrans {}.tra -importstates {}.sta -ctmc",
+		prism -importtrans {}.tra -importstates {}.sta -{}",
 		output_file,
-		output_file
+		output_file,
+		model.m_type
 	);
 }
 
 /// This is the main function that implements the cycle & commute algorithm.
 /// It reads a trace file, builds the state space from the trace,
 /// builds the user-specified set of concurrent and cyclical transitions,
-/// and generates the PRISM-style explicit state space files (.sta and .tra).
-pub fn cycle_commute(model: &AbstractVas, trace_file: &str, output_file: &str) {
+/// and adds it to `explicit_model`. If `output_file` is given, it also generates the
+/// PRISM-style explicit state space files (.sta and .tra) and a Graphviz .dot file.
+pub fn cycle_commute(
+	model: &AbstractVas,
+	trace_file: &str,
+	output_file: Option<&str>,
+	explicit_model: &mut PrismVasModel,
+) -> Result<(), String> {
+	let (prism_states, prism_transitions) = build_state_space(model, trace_file)?;
+	if let Some(output_file) = output_file {
+		print_prism_files(model, &prism_states, &prism_transitions, output_file);
+		visualize_prism_state_space(&prism_states, &prism_transitions, output_file);
+	}
+	for (state_id, state) in prism_states.iter().enumerate() {
+		// Only the sink and the initial states keep their labels
+		let label = if state_id == 0 {
+			Some(SINK_LABEL.to_string())
+		} else if state.label == "init" {
+			Some(state.label.clone())
+		} else {
+			None
+		};
+		explicit_model.add_state(PrismVasState {
+			state_id,
+			vector: state.state_vector.clone(),
+			label,
+			total_outgoing_rate: state.total_rate,
+		});
+	}
+	for t in prism_transitions.iter() {
+		explicit_model.add_entry(t.from_state, t.to_state, t.rate);
+	}
+	Ok(())
+}
+
+/// Reads a trace file and builds the state space of the traces, their commuted
+/// (parallel) versions and the cycles of the model, with an absorbing state at index 0.
+fn build_state_space(
+	model: &AbstractVas,
+	trace_file: &str,
+) -> Result<
+	(
+		Vec<PrismStyleExplicitState>,
+		Vec<PrismStyleExplicitTransition>,
+	),
+	String,
+> {
 	// Read the trace list
-	let trace_file = match File::open(trace_file) {
-		Ok(f) => f,
-		Err(e) => {
-			error!("Error opening trace file: {}", e);
-			return;
-		}
-	};
+	let trace_file =
+		File::open(trace_file).map_err(|e| format!("Error opening trace file: {}", e))?;
 	// Inititalize the bookkeeping things
-	let mut current_state = model.initial_states[0].vector.clone();
-	let mut current_state_id = 1;
 	let mut prism_states: Vec<PrismStyleExplicitState> = Vec::new();
 	let mut prism_transitions: Vec<PrismStyleExplicitTransition> = Vec::new();
-	let mut seed_trace: Vec<PrismStyleExplicitTransition> = Vec::new();
 	// State trie for super quick lookups
 	let mut state_trie = vas_trie::VasTrieNode::new();
-	state_trie.insert_if_not_exists(&current_state, current_state_id);
 	// Create the absorbing state
-	let absorbing_state = DVector::from_element(current_state.len(), -1);
+	let absorbing_state = DVector::from_element(model.variable_names.len(), -1);
 	let absorbing_state_id = 0;
 	// Add the absorbing state to the prism states
 	prism_states.insert(
@@ -179,6 +237,26 @@ pub fn cycle_commute(model: &AbstractVas, trace_file: &str, output_file: &str) {
 			next_states: Vec::new(),
 		},
 	);
+	// Add the initial states, which each trace is replayed from
+	let mut initial_state_ids = Vec::with_capacity(model.initial_states.len());
+	for initial_state in model.initial_states.iter() {
+		let state_id = prism_states.len();
+		match state_trie.insert_if_not_exists(&initial_state.vector, state_id) {
+			Some(existing_id) => initial_state_ids.push(existing_id),
+			None => {
+				prism_states.push(PrismStyleExplicitState::from_state(
+					initial_state.vector.clone(),
+					model.exit_rate_at(&initial_state.vector),
+					"init".to_string(),
+					Vec::new(),
+				));
+				initial_state_ids.push(state_id);
+			}
+		}
+	}
+	// One seed trace per initial state
+	let mut seed_traces: Vec<Vec<PrismStyleExplicitTransition>> =
+		vec![Vec::new(); initial_state_ids.len()];
 	// Read the trace file line by line (traces are line-separated)
 	let trace_reader = BufReader::new(trace_file);
 	for trace in trace_reader.lines() {
@@ -189,106 +267,119 @@ pub fn cycle_commute(model: &AbstractVas, trace_file: &str, output_file: &str) {
 				continue;
 			}
 		};
-		// Reset current state for each trace
-		current_state = model.initial_states[0].vector.clone();
-		current_state_id = 1;
-		// Build the state space from the original trace
 		let transitions: Vec<&str> = trace.split_whitespace().collect();
-		for transition_name in transitions {
-			// Apply the transition to the current state
-			let transition = model.get_transition_from_name(transition_name);
-			if let Some(t) = transition {
-				// Update the current state based on the transition
-				let next_state =
-					(current_state.clone().cast::<VasValue>() + t.update_vector.clone()).clone();
-				let mut next_state_id = current_state_id + 1;
-				if next_state.iter().any(|&x| x < 0) {
-					error!(
-						"ERROR: Next state contains non-positive values: {:?}",
-						next_state
-					);
-					return;
-				}
-				// Add the new state to the trie if it doesn't already exist
-				let potential_id = state_trie.insert_if_not_exists(&next_state, next_state_id);
-				if potential_id.is_some() {
-					next_state_id = potential_id.unwrap();
+		for (seed_trace, &initial_state_id) in seed_traces.iter_mut().zip(initial_state_ids.iter())
+		{
+			// Reset current state for each trace
+			let mut current_state = prism_states[initial_state_id].state_vector.clone();
+			let mut current_state_id = initial_state_id;
+			// Build the state space from the original trace
+			for transition_name in transitions.iter() {
+				// Apply the transition to the current state
+				let transition = model.get_transition_from_name(transition_name);
+				if let Some(t) = transition {
+					// Update the current state based on the transition
+					let next_state = (current_state.clone().cast::<VasValue>()
+						+ t.update_vector.clone())
+					.clone();
+					let mut next_state_id = prism_states.len();
+					if next_state.iter().any(|&x| x < 0) {
+						// A trace need not be valid from every initial state
+						warning!(
+							"Trace cannot be replayed from initial state {}, next state contains non-positive values: {:?}",
+							initial_state_id,
+							next_state
+						);
+						break;
+					}
+					// Add the new state to the trie if it doesn't already exist
+					let potential_id = state_trie.insert_if_not_exists(&next_state, next_state_id);
+					if potential_id.is_some() {
+						next_state_id = potential_id.unwrap();
+					} else {
+						let rate_sum = model.exit_rate_at(&next_state);
+						prism_states.push(PrismStyleExplicitState::from_state(
+							next_state.clone(),
+							rate_sum,
+							format!("State {}", current_state_id),
+							Vec::new(),
+						));
+					}
+					// Add the transition to the current state's outgoing transitions, if it is new
+					if let Some(this_transition) = add_transition(
+						&mut prism_states,
+						&mut prism_transitions,
+						current_state_id,
+						next_state_id,
+						t.transition_id,
+						model.rate_at(t, &current_state),
+					) {
+						seed_trace.push(this_transition);
+					}
+					// Move along the state space
+					current_state = next_state.clone();
+					current_state_id = next_state_id;
 				} else {
-					// TODO: This only works for CRNs right now. Need to generalize for VAS with custom formulas.
-					let rate_sum = model
-						.transitions
-						.iter()
-						.map(|trans| trans.get_sck_rate())
-						.sum();
-					prism_states.push(PrismStyleExplicitState::from_state(
-						next_state.clone(),
-						rate_sum,
-						format!("State {}", current_state_id),
-						Vec::new(),
-					));
+					return Err(format!("Transition {} not found in model", transition_name));
 				}
-				// Check if the transition is already in the current state's outgoing transitions
-				if prism_states.get(current_state_id).map_or(true, |s| {
-					!s.next_states.iter().any(|tr| *tr == next_state_id)
-				}) {
-					// Add the transition to the current state's outgoing transitions
-					let this_transition = PrismStyleExplicitTransition {
-						from_state: current_state_id,
-						to_state: next_state_id,
-						rate: t.get_sck_rate(),
-					};
-					prism_states[current_state_id]
-						.next_states
-						.push(next_state_id);
-					prism_transitions.push(this_transition.clone());
-					seed_trace.push(this_transition.clone());
-				}
-				// Move along the state space
-				current_state = next_state.clone();
-				current_state_id = next_state_id;
-			} else {
-				error!("ERROR: Transition {} not found in model", transition_name);
-				return;
 			}
 		}
 	}
-	// Add commuted/parallel traces
-	commute(
-		&model,
-		&mut prism_states,
-		&mut state_trie,
-		&mut prism_transitions,
-		&seed_trace,
-		0,
-		MAX_DEPTH,
-	);
+	// Add commuted/parallel traces, from each initial state
+	for seed_trace in seed_traces.iter().filter(|trace| !trace.is_empty()) {
+		commute(
+			&model,
+			&mut prism_states,
+			&mut state_trie,
+			&mut prism_transitions,
+			seed_trace,
+			0,
+			MAX_DEPTH,
+		);
+	}
 	// Add cycles to the state space
-	add_cycles(
-		&model,
-		&mut prism_states,
-		&mut state_trie,
-		&mut prism_transitions,
-		MAX_CYCLE_LENGTH,
-	);
-	// Add transitions to the absorbing state
+	match TInvariants::from_model(&model) {
+		Ok(invariants) => add_cycles(
+			&model,
+			&mut prism_states,
+			&mut state_trie,
+			&mut prism_transitions,
+			&invariants.minimal,
+			MAX_CYCLE_ORDERINGS,
+		),
+		Err(e) => warning!("Could not find the cycles of the model: {}", e),
+	}
+	// Add transitions to the absorbing state, with the rate of each state that is not
+	// explored
+	let mut explored_rates = vec![0.0; prism_states.len()];
+	for tr in prism_transitions.iter() {
+		explored_rates[tr.from_state] += tr.rate;
+	}
 	for i in 1..prism_states.len() {
-		let transition_to_absorbing = PrismStyleExplicitTransition {
+		let rate = prism_states[i].total_rate - explored_rates[i];
+		// Each transition of the model is added at most once from each state, so only
+		// rounding takes the rate below zero
+		debug_assert!(
+			rate > -1e-9 * prism_states[i].total_rate.max(1.0),
+			"State {} has a negative rate {} to the absorbing state",
+			i,
+			rate
+		);
+		prism_transitions.push(PrismStyleExplicitTransition {
 			from_state: i,
 			to_state: absorbing_state_id,
-			rate: prism_states[i].total_rate
-				- prism_transitions
-					.iter()
-					.filter(|tr| {
-						tr.to_state != absorbing_state_id
-							&& prism_states[i].next_states.contains(&tr.to_state)
-					})
-					.map(|tr| tr.rate)
-					.sum::<ProbabilityOrRate>(),
-		};
-		prism_transitions.push(transition_to_absorbing);
+			rate: rate.max(0.0),
+		});
 	}
-	print_prism_files(model, &prism_states, &prism_transitions, output_file);
-	visualize_prism_state_space(&prism_states, &prism_transitions, output_file);
+	// The probabilities of a DTMC leave every state, so the absorbing state loops on itself
+	if model.m_type.is_discrete_time() {
+		prism_transitions.push(PrismStyleExplicitTransition {
+			from_state: absorbing_state_id,
+			to_state: absorbing_state_id,
+			rate: 1.0,
+		});
+	}
+	Ok((prism_states, prism_transitions))
 }
 
 /// Recursively takes the model and existing state space and generates
@@ -354,11 +445,7 @@ fn commute(
 				next_state_id = existing_id;
 			} else {
 				// Compute total outgoing rate for the new state
-				let rate_sum = model
-					.transitions
-					.iter()
-					.map(|trans| trans.get_sck_rate())
-					.sum();
+				let rate_sum = model.exit_rate_at(&next_state);
 				prism_states.push(PrismStyleExplicitState::from_state(
 					next_state.clone(),
 					rate_sum,
@@ -366,15 +453,15 @@ fn commute(
 					Vec::new(),
 				));
 			}
-			// Check if this transition already exists
-			if !prism_states[state_id].next_states.contains(&next_state_id) {
-				let new_transition = PrismStyleExplicitTransition {
-					from_state: state_id,
-					to_state: next_state_id,
-					rate: transition.get_sck_rate(),
-				};
-				prism_states[state_id].next_states.push(next_state_id);
-				prism_transitions.push(new_transition.clone());
+			// Add the transition, if it is new
+			if let Some(new_transition) = add_transition(
+				prism_states,
+				prism_transitions,
+				state_id,
+				next_state_id,
+				transition.transition_id,
+				model.rate_at(transition, &state_vector),
+			) {
 				// Step 2. For each new state, create a new trace with the transition added
 				let mut new_trace = trace[..i + 1].to_vec();
 				new_trace.push(new_transition);
@@ -392,119 +479,56 @@ fn commute(
 	}
 }
 
-/// This function combinatorially finds cycles of transitions (i.e., update vectors add to 0)
-/// and adds them to every where they are enabled.
+/// This function adds the cycles of the model (its minimal T-invariants) to every state where
+/// they can fire, in up to `max_orderings` orders each.
 fn add_cycles(
 	model: &AbstractVas,
 	prism_states: &mut Vec<PrismStyleExplicitState>,
 	state_trie: &mut vas_trie::VasTrieNode,
 	prism_transitions: &mut Vec<PrismStyleExplicitTransition>,
-	max_cycle_length: usize,
+	cycles: &[TInvariant],
+	max_orderings: usize,
 ) {
-	// Collect all transition indices for easier cycle enumeration
-	let transition_indices: Vec<usize> = (0..model.transitions.len()).collect();
-	// For all cycle lengths from 2 up to max_cycle_length
-	for cycle_len in 2..=max_cycle_length {
-		// Generate all possible multisets (with repetition) of transitions
-		for cycle in Itertools::combinations_with_replacement(transition_indices.iter(), cycle_len)
-		{
-			// For each multiset, check if the sum of update vectors is zero
-			let mut sum_update = model.transitions[*cycle[0]].update_vector.clone();
-			for &idx in &cycle[1..] {
-				sum_update += model.transitions[*idx].update_vector.clone();
-			}
-			if sum_update.iter().all(|&x| x == 0) {
-				// This is a cycle
-				debug_message!("Found cycle: {:?}", cycle);
-				// Get every permutation of the cycle
-				let mut cycle_permutations = Vec::new();
-				let mut cycle_indices = cycle.clone();
-				cycle_indices.sort(); // Ensure canonical order for deduplication
-				for perm in cycle_indices
-					.iter()
-					.permutations(cycle_indices.len())
-					.unique()
-				{
-					cycle_permutations.push(perm.into_iter().copied().collect::<Vec<_>>());
-				}
-				// Add the cycle to all states where it is enabled (i.e., where the current state + min_vector is non-negative)
-				// Right now, 1 is the index of the first real initial state. Eventually, maybe we make this safer by including
-				// the absorbing state ID in the calculation
-				for state_id in 1..prism_states.len() {
-					let state_vector = prism_states[state_id].state_vector.clone();
-					// Check if the cycle is enabled at this state (state_vector + min_vector >= 0)
-					// For each permutation of the cycle, try to fire the transitions in order
-					for perm in &cycle_permutations {
-						// For each permutation, find the min possible value for each values
-						let mut min_vector = model.transitions[*cycle[0]].update_vector.clone();
-						let mut running_sum = min_vector.clone();
-						for &idx in &cycle[1..] {
-							running_sum += model.transitions[*idx].update_vector.clone();
-							for i in 0..min_vector.len() {
-								if running_sum[i] < min_vector[i] {
-									min_vector[i] = running_sum[i];
-								}
-							}
-						}
-						let enabled = state_vector
-							.iter()
-							.zip(min_vector.iter())
-							.all(|(&s, &m)| (s) + m >= 0);
-						if !enabled {
-							continue;
-						}
-						let mut current_state = state_vector.clone();
-						let mut prev_state_id = state_id;
-						// Try to apply each transition in the permutation
-						for &&idx in perm {
-							let transition = &model.transitions[idx];
-							// Check if enabled: min_vector + update_vector must be non-negative
-							if (current_state.clone() + transition.update_vector.clone())
-								.iter()
-								.any(|&x| x < 0)
-							{
-								break;
-							}
-							// Compute next state
-							let next_state =
-								current_state.clone() + transition.update_vector.clone();
-							// Insert or get the state ID
-							let mut next_state_id = prism_states.len();
-							if let Some(existing_id) =
-								state_trie.insert_if_not_exists(&next_state, next_state_id)
-							{
-								next_state_id = existing_id;
-							} else {
-								// Compute total outgoing rate for the new state
-								let rate_sum = model
-									.transitions
-									.iter()
-									.map(|trans| trans.get_sck_rate())
-									.sum();
-								prism_states.push(PrismStyleExplicitState::from_state(
-									next_state.clone(),
-									rate_sum,
-									format!("State {}", next_state_id),
-									Vec::new(),
-								));
-							}
-							// Add transition if not already present
-							if !prism_states[prev_state_id]
-								.next_states
-								.contains(&next_state_id)
-							{
-								let new_transition = PrismStyleExplicitTransition {
-									from_state: prev_state_id,
-									to_state: next_state_id,
-									rate: transition.get_sck_rate(),
-								};
-								prism_states[prev_state_id].next_states.push(next_state_id);
-								prism_transitions.push(new_transition);
-							}
-							current_state = next_state;
-							prev_state_id = next_state_id;
-						}
+	for cycle in cycles.iter() {
+		debug_message!("Adding cycle: {}", cycle.to_string_with_names(model));
+		// Only the states that are already in the state space get cycles, not the states
+		// that the cycles add. Index 0 is the absorbing state.
+		for state_id in 1..prism_states.len() {
+			let state_vector = prism_states[state_id].state_vector.clone();
+			for ordering in cycle.firing_sequences(model, &state_vector, max_orderings) {
+				let mut current_state = state_vector.clone();
+				let mut prev_state_id = state_id;
+				for idx in ordering {
+					let transition = &model.transitions[idx];
+					// Compute next state
+					let next_state = current_state.clone() + transition.update_vector.clone();
+					// Insert or get the state ID
+					let mut next_state_id = prism_states.len();
+					if let Some(existing_id) =
+						state_trie.insert_if_not_exists(&next_state, next_state_id)
+					{
+						next_state_id = existing_id;
+					} else {
+						// Compute total outgoing rate for the new state
+						let rate_sum = model.exit_rate_at(&next_state);
+						prism_states.push(PrismStyleExplicitState::from_state(
+							next_state.clone(),
+							rate_sum,
+							format!("State {}", next_state_id),
+							Vec::new(),
+						));
 					}
+					// Add transition if not already present
+					add_transition(
+						prism_states,
+						prism_transitions,
+						prev_state_id,
+						next_state_id,
+						transition.transition_id,
+						model.rate_at(transition, &current_state),
+					);
+					current_state = next_state;
+					prev_state_id = next_state_id;
 				}
 			}
 		}
@@ -1,6 +1,8 @@
+use crate::bmc::bounds::BMCBounds;
 use crate::bmc::vas_bmc::AbstractVasBmc;
 use crate::dependency;
 use crate::dependency::graph::make_dependency_graph;
+use crate::model::serialization::{is_fresh, Persistent};
 use crate::model::vas_model::AbstractVas;
 use crate::*;
 
@@ -62,10 +64,38 @@ pub fn bmc_demo(crn_model_directory: &Path) {
 					dependency_graph.nice_print(&model)
 				);
 
-				model.setup_z3();
-				let bmc_encoding = model.bmc_encoding();
-				let _ = model.variable_bounds(&bmc_encoding);
-				message!("Bounding completed successfully on original model.");
+				// Reuse the bounds of the original model unless the model changed since
+				let model_file = model_path.to_str().unwrap();
+				let bounds_file = model_path.with_extension("bounds.json");
+				let bounds_file = bounds_file.to_str().unwrap();
+				let cached = if is_fresh(bounds_file, model_file) {
+					BMCBounds::load(bounds_file)
+						.inspect_err(|e| warning!("Ignoring cached bounds: {}", e))
+						.ok()
+						.filter(|bounds| bounds.covers(&model.variable_names))
+				} else {
+					None
+				};
+				let bounds = match cached {
+					Some(bounds) => {
+						message!("Using the bounds cached in {}", bounds_file);
+						bounds
+					}
+					None => {
+						model.setup_z3();
+						let bmc_encoding = model.bmc_encoding();
+						let bounds = model.variable_bounds(&bmc_encoding);
+						message!("Bounding completed successfully on original model.");
+						if let Err(e) = bounds.save(bounds_file) {
+							warning!("Could not cache the bounds: {}", e);
+						}
+						bounds
+					}
+				};
+				message!(
+					"Bounds of the original model:\n{}",
+					bounds.summary(&model.variable_names)
+				);
 
 				// Trim the model using the dependency graph
 				let mut trimmed_model =
@@ -1,17 +1,44 @@
-use crate::model::vas_model::AbstractVas;
+use crate::builder::cycle_commute::CycleCommuteBuilder;
+use crate::checker::{checker::model_queries, report::Report, vas_checker::CycleCommuteChecker};
+use crate::model::vas_model::{AbstractVas, PrismVasModel};
+use crate::solver::iterative::SolverOptions;
 use crate::*;
 
 /// This function runs the cycle commute demo for a given model and trace file.
-/// It reads the model from the specified file, processes the trace file,
-/// and writes the output to the specified output file.
+/// It processes the trace file on the model read from `model_file`,
+/// checks the queries of the model on the state space it builds, and writes the state
+/// space to the output file if one is given. It gives the report of the results, if the
+/// model could be checked.
 /// It is not meant to be used by an end user, but rather as a demo or proof of concept for the cycle commute functionality.
 /// For now, run this demo with
 /// cargo run -- cycle-commute -d models/ModifiedYeastPolarization/ModifiedYeastPolarization.crn -t models/ModifiedYeastPolarization/MYP_Trace.txt
-pub fn cycle_commute_demo(model_file: &str, trace_file: &str, output_file: &str) {
-	if let Ok(model) = AbstractVas::from_file(model_file) {
-		debug_message!("Model Parsed");
-		crate::cycle_commute::commute::cycle_commute(&model, trace_file, output_file);
-	} else {
-		error!("Could not parse model");
+pub fn cycle_commute_demo(
+	model_file: &str,
+	model: &AbstractVas,
+	trace_file: &str,
+	output_file: Option<&str>,
+	solver_options: &SolverOptions,
+) -> Option<Report> {
+	if model.m_type.is_nondeterministic() {
+		error!(
+			"Cycle & commute needs a Markov chain, but {} is a {}. Check it with the `mdp` subcommand instead.",
+			model_file, model.m_type
+		);
+		return None;
 	}
+	let mut builder = CycleCommuteBuilder::new(model, trace_file);
+	builder.output_file = output_file.map(str::to_string);
+	let queries = model_queries(model);
+	let mut checker = match CycleCommuteChecker::new(builder, queries, *solver_options) {
+		Ok(checker) => checker,
+		Err(e) => {
+			error!("{}", e);
+			return None;
+		}
+	};
+	let mut explicit_model = PrismVasModel::from_abstract_model(model);
+	let results = checker.build_and_check_all(&mut explicit_model);
+	let mut report = Report::new(model_file);
+	report.add_results("cycle-commute", &results);
+	Some(report)
 }
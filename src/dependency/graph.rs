@@ -1,8 +1,15 @@
 use nalgebra::DVector;
 
+use serde::{Deserialize, Serialize};
+
 use crate::{
 	logging::messages::*,
-	model::vas_model::{self, AbstractVas, VasProperty, VasState, VasTransition, VasValue},
+	model::{
+		serialization::{Persistent, VasTransitionData},
+		vas_model::{
+			self, AbstractVas, AllowedRelation, VasProperty, VasState, VasTransition, VasValue,
+		},
+	},
 };
 
 /// Temporary constants for debugging.
@@ -53,9 +60,9 @@ impl GraphNode {
 		}
 		// Create a new "initial state" for the child nodes.
 		// This is the state after the child's parents have been applied to the model's initial state.
-		let mut child_init = VasState::new(
-			&self.node_init.vector + (&self.transition.update_vector * self.executions),
-		);
+		let mut child_init = self
+			.node_init
+			.successor(&self.node_init.vector + (&self.transition.update_vector * self.executions));
 		// Compute the adjustment vector: if update_vector[i] + enabled_bounds[i] != 0, subtract enabled_bounds[i] from child_init.vector[i]
 		let adjustment = self
 			.transition
@@ -265,19 +272,88 @@ impl GraphNode {
 	}
 }
 
-/// Checks if a given property is satisfied in the current state.
-fn property_sat(prop: &VasProperty, state: &VasState) -> Result<bool, String> {
-	if state.vector.len() < prop.variable_index {
-		return Err(format!(
-			"Error: Index out of bounds for state vector: {} >= {}",
-			prop.variable_index,
-			state.vector.len()
-		));
+/// The change a variable needs to satisfy a relational atom from a given value, i.e.,
+/// a positive amount to produce or a negative amount to consume. Zero if it is satisfied.
+fn required_change(relation: AllowedRelation, current: VasValue, value: VasValue) -> VasValue {
+	if relation.holds(current, value) {
+		return 0;
 	}
-	if state.vector[prop.variable_index] == prop.target_value {
-		return Ok(true);
+	match relation {
+		AllowedRelation::Equal => value - current,
+		AllowedRelation::GreaterOrEqual => value - current,
+		AllowedRelation::GreaterThan => value + 1 - current,
+		AllowedRelation::LessOrEqual => value - current,
+		AllowedRelation::LessThan => value - 1 - current,
 	}
-	return Ok(false);
+}
+
+/// The transition of an artificial node, which is not part of the model
+fn artificial_transition(vas: &AbstractVas) -> VasTransition {
+	VasTransition {
+		transition_id: usize::MAX,
+		transition_name: "ARTIFICIAL".to_string(),
+		update_vector: DVector::zeros(vas.variable_names.len()),
+		enabled_bounds: DVector::zeros(vas.variable_names.len()),
+		guards: Vec::new(),
+		rate_const: 0.0,
+		kinetics: Default::default(),
+		action: None,
+		custom_rate_fn: None, // make the artificial transition here
+	}
+}
+
+/// Builds the graph of what has to fire to reach the target from one initial state, under
+/// an artificial node whose targets are the changes the target needs
+fn initial_node(vas: &AbstractVas, initial_state: VasState) -> GraphNode {
+	// The graph can only follow one conjunction of atoms, so for a disjunction we use the
+	// alternative that needs the fewest changes from the initial state.
+	let root_targets: Vec<VasProperty> = vas
+		.target
+		.disjuncts()
+		.iter()
+		.map(|conjunction| {
+			conjunction
+				.iter()
+				.filter_map(|(index, relation, value)| {
+					let change = required_change(*relation, initial_state.vector[*index], *value);
+					if change != 0 {
+						Some(VasProperty {
+							variable_index: *index,
+							target_value: change,
+						})
+					} else {
+						None
+					}
+				})
+				.collect::<Vec<_>>()
+		})
+		.min_by_key(|targets| targets.iter().map(|t| t.target_value.abs()).sum::<VasValue>())
+		.unwrap_or_default();
+	// figure out the executions on the artificial root node
+	let target_difference = root_targets
+		.iter()
+		.map(|t| t.target_value.abs())
+		.max()
+		.unwrap_or(0);
+	let decrement = !root_targets.is_empty() && root_targets.iter().all(|t| t.target_value < 0);
+	// TODO: Handle stoichiometry greater than one.
+	debug_message!("Target Executions: {}", target_difference);
+	debug_message!("decrement? {}", decrement);
+	// Build a new root (abstract transition) node. Its targets are positive to produce a
+	// variable and negative to consume it.
+	let mut node = GraphNode {
+		transition: artificial_transition(vas),
+		children: Vec::new(),
+		parents: Vec::new(),
+		executions: target_difference,
+		enabled: false,
+		node_init: initial_state,
+		node_target: root_targets,
+		decrement,
+	};
+	// Start building the graph from the root node.
+	let _ = node.rec_build_graph(vas, 1);
+	node
 }
 
 /// Top-level function to create a dependency graph from an abstract VAS model.
@@ -285,64 +361,57 @@ pub fn make_dependency_graph(
 	vas: &vas_model::AbstractVas,
 ) -> Result<Option<DependencyGraph>, String> {
 	debug_message!("Building a dependency graph.");
-	// check if target is satisfied in the initial state; if not, build a root node.
-	let initial_state = VasState::new(vas.initial_states[0].vector.clone());
-	let initially_sat = property_sat(&vas.target, &initial_state);
-	if initially_sat == Ok(true) {
-		return Err(String::from("Error: Initial state satisfies the target property. Probability is 1 and this analysis is pointless."));
-	} else if initially_sat.is_err() {
+	// check if target is satisfied in the initial states; the graph is built from every
+	// initial state that does not satisfy it.
+	let initial_states = vas
+		.initial_states
+		.iter()
+		.filter(|state| !vas.target.is_satisfied(&state.vector))
+		.map(|state| vas.state(state.vector.clone()))
+		.collect::<Vec<_>>();
+	if initial_states.is_empty() {
+		return Err(String::from("Error: Every initial state satisfies the target property. Probability is 1 and this analysis is pointless."));
+	}
+	if vas
+		.target
+		.atoms()
+		.iter()
+		.any(|(index, _, _)| *index >= vas.variable_names.len())
+	{
 		return Err(String::from(
 			"Error: Cannot check initial state against target property.",
 		));
 	}
-	// figure out the executions on the artificial root node
-	let target_variable = vas.target.variable_index;
-	let initial_value = vas.initial_states[0].vector[target_variable];
-	let target_value = vas.target.target_value;
-	let target_difference = if (initial_value) < target_value {
-		target_value - (initial_value)
+	let disjuncts = vas.target.disjuncts().len();
+	if disjuncts > 1 {
+		warning!(
+			"The target has {} alternatives, the dependency graph only follows the closest one.",
+			disjuncts
+		);
+	}
+	let mut roots = initial_states
+		.into_iter()
+		.map(|initial_state| initial_node(vas, initial_state))
+		.collect::<Vec<_>>();
+	let root = if roots.len() == 1 {
+		roots.pop().unwrap()
 	} else {
-		(initial_value) - target_value
-	};
-	let decrement = (initial_value) > target_value;
-	// TODO: Handle stoichiometry greater than one.
-	debug_message!("Target Executions: {}", target_difference);
-	// Build a new root (abstract transition) node
-	let mut dependency_graph = DependencyGraph {
-		root: {
-			Box::new(GraphNode {
-				transition: VasTransition {
-					transition_id: usize::MAX,
-					transition_name: "ARTIFICIAL".to_string(),
-					update_vector: DVector::zeros(vas.variable_names.len()),
-					enabled_bounds: DVector::zeros(vas.variable_names.len()),
-					rate_const: 0.0,
-					custom_rate_fn: None, // make the artificial transition here
-				},
-				children: Vec::new(),
-				parents: Vec::new(),
-				executions: target_difference,
-				enabled: false,
-				node_init: initial_state.clone(),
-				node_target: vec![VasProperty {
-					variable_index: target_variable,
-					target_value: target_difference,
-				}],
-				decrement,
-			})
-		},
-	};
-	// handle the case where it is desired to decrease a value to reach a target
-	if dependency_graph.root.decrement {
-		if let Some(first_target) = dependency_graph.root.node_target.first_mut() {
-			first_target.target_value -=
-				dependency_graph.root.node_init.vector[first_target.variable_index];
+		// A virtual root leads to the graph of each initial state
+		debug_message!("Building the graphs of {} initial states.", roots.len());
+		GraphNode {
+			transition: artificial_transition(vas),
+			node_init: roots[0].node_init.clone(),
+			children: roots.into_iter().map(Box::new).collect(),
+			parents: Vec::new(),
+			executions: 0,
+			enabled: true,
+			node_target: Vec::new(),
+			decrement: false,
 		}
-	}
-	debug_message!("decrement? {}", dependency_graph.root.decrement);
-	// Start building the graph from the root node.
-	let _ = dependency_graph.root.rec_build_graph(vas, 1);
-	Ok(Some(dependency_graph))
+	};
+	Ok(Some(DependencyGraph {
+		root: Box::new(root),
+	}))
 }
 
 /// These methods provide functionality to print the dependency graph in various formats.
@@ -468,11 +537,16 @@ impl DependencyGraph {
 		output
 	}
 
-	/// Gives a vector of all the transitions in the dependency graph.
+	/// Gives a vector of all the transitions in the dependency graph, each once, even if
+	/// several nodes (e.g., of different initial states) use it.
 	pub fn get_transitions(&self) -> Vec<VasTransition> {
 		let mut transitions = Vec::new();
 		fn traverse(node: &GraphNode, transitions: &mut Vec<VasTransition>) {
-			if node.transition.transition_name != "ARTIFICIAL" {
+			if node.transition.transition_name != "ARTIFICIAL"
+				&& !transitions
+					.iter()
+					.any(|t| t.transition_id == node.transition.transition_id)
+			{
 				transitions.push(node.transition.clone());
 			}
 			for child in &node.children {
@@ -483,3 +557,84 @@ impl DependencyGraph {
 		transitions
 	}
 }
+
+/// The data of a node of a dependency graph and its children. The rate laws of the
+/// transitions are not kept, since the graph does not use them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct GraphNodeData {
+	transition: VasTransitionData,
+	children: Vec<GraphNodeData>,
+	parents: Vec<VasTransitionData>,
+	executions: VasValue,
+	enabled: bool,
+	node_init: Vec<VasValue>,
+	/// The variable index and required change of each target
+	node_target: Vec<(usize, VasValue)>,
+	decrement: bool,
+}
+
+impl GraphNode {
+	fn to_data(&self) -> GraphNodeData {
+		let transition_data = |transition: &VasTransition| {
+			// Converting a transition cannot fail
+			transition.to_data().unwrap()
+		};
+		GraphNodeData {
+			transition: transition_data(&self.transition),
+			children: self.children.iter().map(|child| child.to_data()).collect(),
+			parents: self.parents.iter().map(transition_data).collect(),
+			executions: self.executions,
+			enabled: self.enabled,
+			node_init: self.node_init.vector.iter().copied().collect(),
+			node_target: self
+				.node_target
+				.iter()
+				.map(|target| (target.variable_index, target.target_value))
+				.collect(),
+			decrement: self.decrement,
+		}
+	}
+
+	fn from_data(data: GraphNodeData) -> Result<Self, String> {
+		Ok(GraphNode {
+			transition: data.transition.to_transition()?,
+			children: data
+				.children
+				.into_iter()
+				.map(|child| GraphNode::from_data(child).map(Box::new))
+				.collect::<Result<_, _>>()?,
+			parents: data
+				.parents
+				.iter()
+				.map(|parent| parent.to_transition())
+				.collect::<Result<_, _>>()?,
+			executions: data.executions,
+			enabled: data.enabled,
+			node_init: VasState::new(DVector::from_vec(data.node_init)),
+			node_target: data
+				.node_target
+				.into_iter()
+				.map(|(variable_index, target_value)| VasProperty {
+					variable_index,
+					target_value,
+				})
+				.collect(),
+			decrement: data.decrement,
+		})
+	}
+}
+
+impl Persistent for DependencyGraph {
+	const KIND: &'static str = "dependency-graph";
+	type Data = GraphNodeData;
+
+	fn to_data(&self) -> Result<Self::Data, String> {
+		Ok(self.root.to_data())
+	}
+
+	fn from_data(data: Self::Data) -> Result<Self, String> {
+		Ok(DependencyGraph {
+			root: Box::new(GraphNode::from_data(data)?),
+		})
+	}
+}
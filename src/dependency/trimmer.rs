@@ -1,8 +1,13 @@
+use std::collections::HashMap;
+
 use nalgebra::DVector;
 
 use crate::{
 	logging::messages::*,
-	model::vas_model::{AbstractVas, VasProperty, VasState, VasTransition, VasValue},
+	model::{
+		model::ProbabilityOrRate,
+		vas_model::{variable_index, AbstractVas, LinearGuard, VasState, VasTransition},
+	},
 };
 
 use super::graph::DependencyGraph;
@@ -11,7 +16,7 @@ use super::graph::DependencyGraph;
 /// determined by the dependency graph.
 pub fn trim_model(model: &AbstractVas, dg: DependencyGraph) -> AbstractVas {
 	let mut variable_names = Vec::<String>::new();
-	let mut initial_state = Vec::<VasValue>::new();
+	let mut used_indices = Vec::<usize>::new();
 	let mut transitions = Vec::<VasTransition>::new();
 	let dg_transitions = dg.get_transitions();
 	// Collect exactly the set of variables that are used in the dependency graph
@@ -19,7 +24,10 @@ pub fn trim_model(model: &AbstractVas, dg: DependencyGraph) -> AbstractVas {
 		let mut is_used = false;
 		debug_message!("{}: ", model.variable_names[i]);
 		for t in dg_transitions.iter() {
-			if t.update_vector[i] != 0 || t.enabled_bounds[i] != 0 {
+			if t.update_vector[i] != 0
+				|| t.enabled_bounds[i] != 0
+				|| t.guards.iter().any(|guard| guard.weights[i] != 0)
+			{
 				is_used = true;
 				debug_message!("used by transition {}", t.transition_name);
 				break;
@@ -27,7 +35,7 @@ pub fn trim_model(model: &AbstractVas, dg: DependencyGraph) -> AbstractVas {
 		}
 		if is_used {
 			variable_names.push(model.variable_names[i].clone());
-			initial_state.push(model.initial_states[0].vector[i].try_into().unwrap());
+			used_indices.push(i);
 		} else {
 			debug_message!("unused");
 		}
@@ -64,26 +72,69 @@ pub fn trim_model(model: &AbstractVas, dg: DependencyGraph) -> AbstractVas {
 				})
 				.collect::<Vec<_>>()
 				.into(),
+			guards: t
+				.guards
+				.iter()
+				.map(|guard| LinearGuard {
+					weights: DVector::from_iterator(
+						used_indices.len(),
+						used_indices.iter().map(|&i| guard.weights[i]),
+					),
+					bound: guard.bound,
+				})
+				.collect(),
 			rate_const: t.rate_const,
+			kinetics: t.kinetics,
+			action: t.action,
 			custom_rate_fn: t.custom_rate_fn,
 		});
 	}
 	// Update the target property to match the trimmed model
-	let target_index = variable_names
+	let target = model
+		.target
+		.map_variables(&|i| {
+			variable_names
+				.iter()
+				.position(|x| x == &model.variable_names[i])
+		})
+		.unwrap_or_else(|| {
+			warning!("The trimmed model does not contain every target variable.");
+			model.target.clone()
+		});
+	// Project the initial states onto the used variables. Initial states that only differ
+	// in unused variables become one, with their probabilities added up.
+	let variable_index = variable_index(&variable_names);
+	let mut initial_states = Vec::<VasState>::new();
+	let mut initial_probabilities = Vec::<ProbabilityOrRate>::new();
+	for (state, probability) in model
+		.initial_states
 		.iter()
-		.position(|x| x == &model.variable_names[model.target.variable_index])
-		.unwrap_or(0);
-	let target = VasProperty {
-		variable_index: target_index,
-		target_value: model.target.target_value,
-	};
-	// Create the trimmed model with the collected variables, initial state, and transitions
+		.zip(model.initial_probabilities.iter())
+	{
+		let vector = DVector::from_iterator(
+			used_indices.len(),
+			used_indices.iter().map(|&i| state.vector[i]),
+		);
+		match initial_states.iter().position(|s| s.vector == vector) {
+			Some(index) => initial_probabilities[index] += probability,
+			None => {
+				initial_states.push(VasState::with_names(vector, variable_index.clone()));
+				initial_probabilities.push(*probability);
+			}
+		}
+	}
+	// Create the trimmed model with the collected variables, initial states, and transitions
 	let trimmed_model = AbstractVas {
 		variable_names: variable_names.into_boxed_slice(),
-		initial_states: vec![VasState::new(DVector::from_vec(initial_state))],
+		initial_states,
+		initial_probabilities,
+		variable_index,
 		transitions: transitions,
 		m_type: model.m_type,
 		target: target,
+		// The properties refer to the variables of the untrimmed model
+		properties: Vec::new(),
+		labels: HashMap::new(),
 		z3_context: None,
 	};
 	trimmed_model
@@ -0,0 +1,186 @@
+/// Exact integer linear algebra for the invariants of a VAS: null spaces of the
+/// stoichiometry matrix and its minimal semi-positive solutions (semiflows).
+use crate::model::vas_model::VasValue;
+
+/// The greatest common divisor of two integers, which is never negative
+pub(crate) fn gcd(a: VasValue, b: VasValue) -> VasValue {
+	let (mut a, mut b) = (a.abs(), b.abs());
+	while b != 0 {
+		(a, b) = (b, a % b);
+	}
+	a
+}
+
+/// Divides a vector by the greatest common divisor of its entries
+pub(crate) fn divide_by_gcd(vector: &mut [VasValue]) {
+	let divisor = vector.iter().fold(0, |divisor, value| gcd(divisor, *value));
+	if divisor > 1 {
+		vector.iter_mut().for_each(|value| *value /= divisor);
+	}
+}
+
+/// Divides a vector by the greatest common divisor of its entries and makes its first
+/// nonzero entry positive
+pub(crate) fn normalize(vector: &mut [VasValue]) {
+	divide_by_gcd(vector);
+	if vector
+		.iter()
+		.find(|value| **value != 0)
+		.is_some_and(|value| *value < 0)
+	{
+		vector.iter_mut().for_each(|value| *value = -*value);
+	}
+}
+
+/// The linear combination `a * x + b * y`, or `None` if it overflows
+fn combine(a: VasValue, x: &[VasValue], b: VasValue, y: &[VasValue]) -> Option<Vec<VasValue>> {
+	x.iter()
+		.zip(y.iter())
+		.map(|(x, y)| a.checked_mul(*x)?.checked_add(b.checked_mul(*y)?))
+		.collect()
+}
+
+/// Brings the rows of a matrix into reduced row echelon form with integer entries (every
+/// pivot column is zero outside of its pivot row), dropping the rows that become zero.
+/// Columns are eliminated in the order given by `column_order`. Returns the pivot column
+/// of each remaining row.
+pub(crate) fn integer_row_echelon(
+	rows: &mut Vec<Vec<VasValue>>,
+	column_order: &[usize],
+) -> Result<Vec<usize>, String> {
+	let mut pivots = Vec::new();
+	for &column in column_order.iter() {
+		let Some(pivot_row) = (pivots.len()..rows.len()).find(|&row| rows[row][column] != 0) else {
+			continue;
+		};
+		rows.swap(pivots.len(), pivot_row);
+		let pivot = rows[pivots.len()].clone();
+		for (index, row) in rows.iter_mut().enumerate() {
+			if index == pivots.len() || row[column] == 0 {
+				continue;
+			}
+			let factor = gcd(pivot[column], row[column]);
+			*row = combine(pivot[column] / factor, row, -row[column] / factor, &pivot)
+				.ok_or("Integer overflow in row reduction")?;
+			normalize(row);
+		}
+		pivots.push(column);
+	}
+	rows.truncate(pivots.len());
+	// Keep the pivots positive
+	for (row, &column) in rows.iter_mut().zip(pivots.iter()) {
+		if row[column] < 0 {
+			row.iter_mut().for_each(|value| *value = -*value);
+		}
+	}
+	Ok(pivots)
+}
+
+/// An integer basis of the null space `{ x : A x = 0 }` of a matrix with `columns`
+/// columns, one vector per free column
+pub(crate) fn integer_null_space(
+	matrix: &[Vec<VasValue>],
+	columns: usize,
+) -> Result<Vec<Vec<VasValue>>, String> {
+	let mut rows = matrix.to_vec();
+	let pivots = integer_row_echelon(&mut rows, &(0..columns).collect::<Vec<_>>())?;
+	let mut basis = Vec::new();
+	for free in (0..columns).filter(|column| !pivots.contains(column)) {
+		// Scale the free variable so that every pivot variable is a whole number
+		let scale = rows
+			.iter()
+			.zip(pivots.iter())
+			.filter(|(row, _)| row[free] != 0)
+			.try_fold(1 as VasValue, |scale, (row, &pivot)| {
+				(scale / gcd(scale, row[pivot])).checked_mul(row[pivot])
+			})
+			.ok_or("Integer overflow in the null space")?;
+		let mut vector = vec![0; columns];
+		vector[free] = scale;
+		for (row, &pivot) in rows.iter().zip(pivots.iter()) {
+			vector[pivot] = -row[free] * (scale / row[pivot]);
+		}
+		normalize(&mut vector);
+		basis.push(vector);
+	}
+	Ok(basis)
+}
+
+/// Whether the support (nonzero entries) of `a` is a subset of the support of `b`
+fn support_subset(a: &[VasValue], b: &[VasValue]) -> bool {
+	a.iter().zip(b.iter()).all(|(a, b)| *a == 0 || *b != 0)
+}
+
+/// The minimal semi-positive integer solutions `y >= 0` of `y^T A = 0`, by the Farkas
+/// algorithm. These are the generators of every semi-positive solution. The number of
+/// candidates can grow exponentially, so the computation stops with an error once there
+/// are more than `limit` of them.
+pub(crate) fn semi_positive_solutions(
+	matrix: &[Vec<VasValue>],
+	limit: usize,
+) -> Result<Vec<Vec<VasValue>>, String> {
+	let size = matrix.len();
+	let columns = matrix.first().map_or(0, |row| row.len());
+	// Each candidate is a row of A followed by the combination of rows it came from
+	let mut candidates: Vec<Vec<VasValue>> = matrix
+		.iter()
+		.enumerate()
+		.map(|(index, row)| {
+			let mut candidate = row.clone();
+			candidate.extend((0..size).map(|i| (i == index) as VasValue));
+			candidate
+		})
+		.collect();
+	for column in 0..columns {
+		let (zero, nonzero): (Vec<_>, Vec<_>) = candidates
+			.into_iter()
+			.partition(|candidate| candidate[column] == 0);
+		let mut next = zero;
+		for positive in nonzero.iter().filter(|candidate| candidate[column] > 0) {
+			for negative in nonzero.iter().filter(|candidate| candidate[column] < 0) {
+				let factor = gcd(positive[column], negative[column]);
+				let mut combined = combine(
+					-negative[column] / factor,
+					positive,
+					positive[column] / factor,
+					negative,
+				)
+				.ok_or("Integer overflow in the Farkas algorithm")?;
+				divide_by_gcd(&mut combined);
+				next.push(combined);
+			}
+		}
+		// Keep only the candidates with a minimal support (and one of each support)
+		let mut minimal: Vec<Vec<VasValue>> = Vec::with_capacity(next.len());
+		for (index, candidate) in next.iter().enumerate() {
+			let combination = &candidate[columns..];
+			let dominated = next.iter().enumerate().any(|(other_index, other)| {
+				let other = &other[columns..];
+				other_index != index
+					&& support_subset(other, combination)
+					&& (!support_subset(combination, other) || other_index < index)
+			});
+			if !dominated {
+				minimal.push(candidate.clone());
+			}
+		}
+		if minimal.len() > limit {
+			return Err(format!(
+				"More than {} candidate semiflows, giving up",
+				limit
+			));
+		}
+		candidates = minimal;
+	}
+	Ok(candidates
+		.into_iter()
+		.map(|candidate| candidate[columns..].to_vec())
+		.collect())
+}
+
+/// The transpose of a matrix with `columns` columns
+pub(crate) fn transpose(matrix: &[Vec<VasValue>], columns: usize) -> Vec<Vec<VasValue>> {
+	(0..columns)
+		.map(|column| matrix.iter().map(|row| row[column]).collect())
+		.collect()
+}
@@ -0,0 +1,4 @@
+pub mod linear;
+pub mod p_invariant;
+pub mod reduction;
+pub mod t_invariant;
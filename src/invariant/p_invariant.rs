@@ -0,0 +1,218 @@
+use crate::{
+	invariant::linear::{
+		integer_null_space, integer_row_echelon, semi_positive_solutions, transpose,
+	},
+	logging::messages::*,
+	model::vas_model::{AbstractVas, VasStateVector, VasValue},
+};
+
+/// The most candidates the Farkas algorithm may keep while computing the moieties
+const MOIETY_CANDIDATE_LIMIT: usize = 10000;
+
+/// The stoichiometry matrix of a model: one row per species and one column per transition,
+/// holding the net change in the species when the transition fires
+pub(crate) fn stoichiometry_matrix(model: &AbstractVas) -> Vec<Vec<VasValue>> {
+	(0..model.variable_names.len())
+		.map(|species| {
+			model
+				.transitions
+				.iter()
+				.map(|transition| transition.update_vector[species])
+				.collect()
+		})
+		.collect()
+}
+
+/// Writes a weighted sum of species as, e.g., `E + 2 ES - P`
+pub(crate) fn weighted_sum_string(weights: &[VasValue], variable_names: &[String]) -> String {
+	let mut output = String::new();
+	for (weight, name) in weights.iter().zip(variable_names.iter()) {
+		if *weight == 0 {
+			continue;
+		}
+		let sign = match (output.is_empty(), *weight < 0) {
+			(true, false) => "",
+			(true, true) => "-",
+			(false, false) => " + ",
+			(false, true) => " - ",
+		};
+		match weight.abs() {
+			1 => output.push_str(&format!("{}{}", sign, name)),
+			factor => output.push_str(&format!("{}{} {}", sign, factor, name)),
+		}
+	}
+	if output.is_empty() {
+		output.push('0');
+	}
+	output
+}
+
+/// A P-invariant (place invariant) of a model: weights for the species such that the
+/// weighted sum of the species counts is the same in every reachable state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PInvariant {
+	pub(crate) weights: Vec<VasValue>,
+}
+
+impl PInvariant {
+	/// The weighted sum of the species counts in a state
+	pub fn value(&self, vector: &VasStateVector) -> VasValue {
+		self.weights
+			.iter()
+			.zip(vector.iter())
+			.map(|(weight, count)| weight * count)
+			.sum()
+	}
+
+	/// The conserved value from each of the initial states of a model
+	pub fn initial_values(&self, model: &AbstractVas) -> Vec<VasValue> {
+		model
+			.initial_states
+			.iter()
+			.map(|state| self.value(&state.vector))
+			.collect()
+	}
+
+	/// Whether every weight is nonnegative, i.e., the invariant is a conserved moiety
+	pub fn is_semi_positive(&self) -> bool {
+		self.weights.iter().all(|weight| *weight >= 0)
+	}
+
+	/// Writes the invariant with its conserved value(s), e.g., `E + ES = 10`
+	pub fn to_string_with_values(&self, model: &AbstractVas) -> String {
+		let mut values = self.initial_values(model);
+		values.sort();
+		values.dedup();
+		let sum = weighted_sum_string(&self.weights, &model.variable_names);
+		match &values[..] {
+			[value] => format!("{} = {}", sum, value),
+			_ => format!(
+				"{} in {{{}}}",
+				sum,
+				values
+					.iter()
+					.map(|value| value.to_string())
+					.collect::<Vec<_>>()
+					.join(", ")
+			),
+		}
+	}
+}
+
+/// The conservation laws of a model
+pub(crate) struct PInvariants {
+	/// A basis of the left null space of the stoichiometry matrix, in reduced row echelon
+	/// form, so each invariant has a species of its own (its pivot)
+	pub(crate) basis: Vec<PInvariant>,
+	/// The pivot species of each invariant in the basis, which the invariant determines
+	/// from the species that are not pivots
+	pub(crate) pivots: Vec<usize>,
+	/// The minimal semi-positive invariants (conserved moieties), unless there were too
+	/// many candidates to compute them
+	pub(crate) moieties: Option<Vec<PInvariant>>,
+}
+
+impl PInvariants {
+	/// Computes the P-invariants of a model. The species in the target are the last to be
+	/// chosen as pivots, so that eliminating the pivots keeps the target when possible.
+	pub fn from_model(model: &AbstractVas) -> Result<Self, String> {
+		let species_count = model.variable_names.len();
+		let stoichiometry = stoichiometry_matrix(model);
+		let mut basis = integer_null_space(
+			&transpose(&stoichiometry, model.transitions.len()),
+			species_count,
+		)?;
+		let target_species = model
+			.target
+			.atoms()
+			.iter()
+			.map(|(index, _, _)| *index)
+			.collect::<Vec<_>>();
+		let (targets, others): (Vec<usize>, Vec<usize>) =
+			(0..species_count).partition(|species| target_species.contains(species));
+		let pivots = integer_row_echelon(&mut basis, &[others, targets].concat())?;
+		let moieties: Option<Vec<PInvariant>> =
+			match semi_positive_solutions(&stoichiometry, MOIETY_CANDIDATE_LIMIT) {
+				Ok(moieties) => Some(
+					moieties
+						.into_iter()
+						.map(|weights| PInvariant { weights })
+						.collect(),
+				),
+				Err(e) => {
+					warning!("Could not compute the conserved moieties: {}", e);
+					None
+				}
+			};
+		debug_message!(
+			"Found {} P-invariants and {} conserved moieties",
+			basis.len(),
+			moieties.as_ref().map_or(0, |moieties| moieties.len())
+		);
+		Ok(Self {
+			basis: basis
+				.into_iter()
+				.map(|weights| PInvariant { weights })
+				.collect(),
+			pivots,
+			moieties,
+		})
+	}
+
+	/// The upper bound on each species that the conserved moieties imply: a species with
+	/// weight `w` in a moiety with value `c` never exceeds `c / w`. Species that no moiety
+	/// covers have no bound.
+	pub fn upper_bounds(&self, model: &AbstractVas) -> Vec<Option<VasValue>> {
+		let mut bounds = vec![None::<VasValue>; model.variable_names.len()];
+		for moiety in self.moieties.iter().flatten() {
+			let value = moiety.initial_values(model).into_iter().max().unwrap_or(0);
+			for (species, weight) in moiety.weights.iter().enumerate() {
+				if *weight > 0 {
+					let bound = value / weight;
+					bounds[species] = Some(bounds[species].map_or(bound, |b| b.min(bound)));
+				}
+			}
+		}
+		bounds
+	}
+
+	/// A human-readable report of the invariants and the bounds they imply
+	pub fn report(&self, model: &AbstractVas) -> String {
+		let mut output = String::new();
+		output.push_str("==========================================\n");
+		output.push_str("              P-INVARIANTS                \n");
+		output.push_str("==========================================\n");
+		output.push_str(&format!(
+			"Conservation laws ({} independent):\n",
+			self.basis.len()
+		));
+		for (invariant, pivot) in self.basis.iter().zip(self.pivots.iter()) {
+			output.push_str(&format!(
+				"\t{}\t(determines {})\n",
+				invariant.to_string_with_values(model),
+				model.variable_names[*pivot]
+			));
+		}
+		match &self.moieties {
+			Some(moieties) => {
+				output.push_str(&format!("Conserved moieties ({}):\n", moieties.len()));
+				for moiety in moieties.iter() {
+					output.push_str(&format!("\t{}\n", moiety.to_string_with_values(model)));
+				}
+			}
+			None => output.push_str("Conserved moieties: too many to compute\n"),
+		}
+		output.push_str("Upper bounds from the moieties:\n");
+		for (name, bound) in model
+			.variable_names
+			.iter()
+			.zip(self.upper_bounds(model).iter())
+		{
+			match bound {
+				Some(bound) => output.push_str(&format!("\t{} <= {}\n", name, bound)),
+				None => output.push_str(&format!("\t{} unbounded\n", name)),
+			}
+		}
+		output
+	}
+}
@@ -0,0 +1,229 @@
+use std::{collections::HashMap, sync::Arc};
+
+use nalgebra::DVector;
+
+use crate::{
+	invariant::p_invariant::{weighted_sum_string, PInvariant, PInvariants},
+	logging::messages::*,
+	model::{
+		model::Transition,
+		vas_model::{variable_index, AbstractVas, LinearGuard, VasState, VasStateVector, VasValue},
+	},
+};
+
+/// A species that a conservation law determines: its count is
+/// `(value - Σ w_j x_j) / w` where `w` is its own weight and the sum is over the other species
+#[derive(Clone, Debug)]
+pub(crate) struct Elimination {
+	pub(crate) species: usize,
+	pub(crate) invariant: PInvariant,
+	pub(crate) value: VasValue,
+}
+
+/// How the species of a reduced model map back onto the species of the original model
+#[derive(Clone, Debug)]
+pub(crate) struct SpeciesReduction {
+	/// The species of the original model that are kept, in their original order
+	pub(crate) kept: Vec<usize>,
+	/// The species that are eliminated
+	pub(crate) eliminated: Vec<Elimination>,
+	species_count: usize,
+}
+
+impl SpeciesReduction {
+	/// The state of the original model that a state of the reduced model stands for
+	pub fn full_vector(&self, reduced: &VasStateVector) -> VasStateVector {
+		let mut full = DVector::zeros(self.species_count);
+		for (index, species) in self.kept.iter().enumerate() {
+			full[*species] = reduced[index];
+		}
+		// The eliminated species only depend on the kept ones, since the invariants are in
+		// reduced row echelon form
+		for elimination in self.eliminated.iter() {
+			let weight = elimination.invariant.weights[elimination.species];
+			let rest: VasValue =
+				elimination.invariant.value(&full) - weight * full[elimination.species];
+			full[elimination.species] = (elimination.value - rest) / weight;
+		}
+		full
+	}
+
+	/// The guard that an eliminated species is at least `bound`, over the kept species.
+	/// With `w` its weight and `S` the weighted sum of the kept species, the species is
+	/// `(value - S) / w`, so the guard is `-S >= w bound - value` for positive `w` and
+	/// `S >= value - w bound` for negative `w`.
+	pub fn guard(&self, elimination: &Elimination, bound: VasValue) -> LinearGuard {
+		let weight = elimination.invariant.weights[elimination.species];
+		let sign = weight.signum();
+		LinearGuard {
+			weights: DVector::from_iterator(
+				self.kept.len(),
+				self.kept
+					.iter()
+					.map(|&species| -sign * elimination.invariant.weights[species]),
+			),
+			bound: sign * (weight * bound - elimination.value),
+		}
+	}
+
+	/// Lists the eliminated species and how they are computed, e.g., `E = 10 - (ES)`
+	pub fn report(&self, model: &AbstractVas) -> String {
+		let mut output = String::new();
+		output.push_str(&format!(
+			"Eliminated {} of {} species:\n",
+			self.eliminated.len(),
+			self.species_count
+		));
+		for elimination in self.eliminated.iter() {
+			let weight = elimination.invariant.weights[elimination.species];
+			let mut rest = elimination.invariant.weights.clone();
+			rest[elimination.species] = 0;
+			let mut expression = if rest.iter().all(|w| *w == 0) {
+				elimination.value.to_string()
+			} else {
+				format!(
+					"{} - ({})",
+					elimination.value,
+					weighted_sum_string(&rest, &model.variable_names)
+				)
+			};
+			if weight != 1 {
+				expression = format!("({}) / {}", expression, weight);
+			}
+			output.push_str(&format!(
+				"\t{} = {}\n",
+				model.variable_names[elimination.species], expression
+			));
+		}
+		output
+	}
+}
+
+/// Removes the species that the P-invariants determine from a model. Each invariant of
+/// the basis eliminates its pivot species, unless the species is in the target or the
+/// initial states do not agree on the conserved value. The transitions of the reduced
+/// model compute their rates on the original state, and guard the bounds they had on the
+/// eliminated species, so they are enabled in exactly the same states. The properties and
+/// labels are renumbered, dropping those that use an eliminated species.
+pub fn reduce_model(
+	model: &AbstractVas,
+	invariants: &PInvariants,
+) -> (AbstractVas, Arc<SpeciesReduction>) {
+	let target_species = model
+		.target
+		.atoms()
+		.iter()
+		.map(|(index, _, _)| *index)
+		.collect::<Vec<_>>();
+	let mut eliminated = Vec::<Elimination>::new();
+	for (invariant, pivot) in invariants.basis.iter().zip(invariants.pivots.iter()) {
+		let name = &model.variable_names[*pivot];
+		if target_species.contains(pivot) {
+			message!("Keeping {}, which is in the target.", name);
+			continue;
+		}
+		let values = invariant.initial_values(model);
+		if values.iter().any(|value| *value != values[0]) {
+			message!(
+				"Keeping {}, since the initial states have different values of {}.",
+				name,
+				weighted_sum_string(&invariant.weights, &model.variable_names)
+			);
+			continue;
+		}
+		eliminated.push(Elimination {
+			species: *pivot,
+			invariant: invariant.clone(),
+			value: values[0],
+		});
+	}
+	let kept = (0..model.variable_names.len())
+		.filter(|species| !eliminated.iter().any(|e| e.species == *species))
+		.collect::<Vec<_>>();
+	let reduction = Arc::new(SpeciesReduction {
+		kept: kept.clone(),
+		eliminated,
+		species_count: model.variable_names.len(),
+	});
+	let project = |vector: &VasStateVector| {
+		DVector::from_iterator(kept.len(), kept.iter().map(|&species| vector[species]))
+	};
+	// Each transition keeps its update on the kept species and takes its rate from the
+	// original transition in the original state
+	let original_index = model.variable_index.clone();
+	let mut transitions = Vec::with_capacity(model.transitions.len());
+	for original in model.transitions.iter() {
+		let mut transition = original.clone();
+		transition.update_vector = project(&original.update_vector);
+		transition.enabled_bounds = project(&original.enabled_bounds);
+		transition.guards = reduction
+			.eliminated
+			.iter()
+			.filter(|elimination| original.enabled_bounds[elimination.species] > 0)
+			.map(|elimination| {
+				reduction.guard(elimination, original.enabled_bounds[elimination.species])
+			})
+			.collect();
+		let (original, reduction, original_index) =
+			(original.clone(), reduction.clone(), original_index.clone());
+		transition.set_custom_rate_fn(Arc::new(move |state: &VasState| {
+			let full_state =
+				VasState::with_names(reduction.full_vector(&state.vector), original_index.clone());
+			original.rate_probability_at(&full_state).unwrap_or(0.0)
+		}));
+		transitions.push(transition);
+	}
+	let variable_names = kept
+		.iter()
+		.map(|&species| model.variable_names[species].clone())
+		.collect::<Vec<_>>();
+	// The target only uses kept species
+	let renumber = |species| kept.iter().position(|&k| k == species);
+	let target = model.target.map_variables(&renumber).unwrap();
+	let properties = model
+		.properties
+		.iter()
+		.filter_map(|query| {
+			let reduced = query.map_variables(&renumber);
+			if reduced.is_none() {
+				warning!("Dropping {}, which uses an eliminated species.", query);
+			}
+			reduced
+		})
+		.collect::<Vec<_>>();
+	let labels = model
+		.labels
+		.iter()
+		.filter_map(|(name, formula)| {
+			let reduced = formula.map_variables(&renumber);
+			if reduced.is_none() {
+				warning!(
+					"Dropping label \"{}\", which uses an eliminated species.",
+					name
+				);
+			}
+			Some((name.clone(), reduced?))
+		})
+		.collect::<HashMap<_, _>>();
+	// Project the initial states onto the kept species. They agree on every eliminated
+	// species, so no two of them become one.
+	let variable_index = variable_index(&variable_names);
+	let initial_states = model
+		.initial_states
+		.iter()
+		.map(|state| VasState::with_names(project(&state.vector), variable_index.clone()))
+		.collect::<Vec<_>>();
+	let reduced_model = AbstractVas {
+		variable_names: variable_names.into_boxed_slice(),
+		variable_index,
+		initial_states,
+		initial_probabilities: model.initial_probabilities.clone(),
+		transitions,
+		m_type: model.m_type,
+		target,
+		properties,
+		labels,
+		z3_context: None,
+	};
+	(reduced_model, reduction)
+}
@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+
+use crate::{
+	invariant::{
+		linear::{integer_null_space, semi_positive_solutions, transpose},
+		p_invariant::{stoichiometry_matrix, weighted_sum_string},
+	},
+	logging::messages::*,
+	model::vas_model::{AbstractVas, VasStateVector, VasValue},
+};
+
+/// The most candidates the Farkas algorithm may keep while enumerating the T-invariants
+const T_INVARIANT_CANDIDATE_LIMIT: usize = 10000;
+
+/// A T-invariant (transition invariant) of a model: how many times each transition fires
+/// in a sequence of transitions that leads back to the state it started from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TInvariant {
+	pub(crate) counts: Vec<VasValue>,
+}
+
+impl TInvariant {
+	/// The total number of transitions that fire, i.e., the length of the cycle
+	pub fn length(&self) -> usize {
+		self.counts.iter().sum::<VasValue>() as usize
+	}
+
+	/// The indices of the transitions that fire at least once
+	pub fn support(&self) -> Vec<usize> {
+		(0..self.counts.len())
+			.filter(|&transition| self.counts[transition] != 0)
+			.collect()
+	}
+
+	/// Writes the invariant as a multiset of transitions, e.g., `R1 + 2 R2`
+	pub fn to_string_with_names(&self, model: &AbstractVas) -> String {
+		let names = model
+			.transitions
+			.iter()
+			.map(|transition| transition.transition_name.clone())
+			.collect::<Vec<_>>();
+		weighted_sum_string(&self.counts, &names)
+	}
+
+	/// The orders (as transition indices) in which the transitions of this invariant can
+	/// all fire from `start`, which they lead back to. Stops after `limit` orders.
+	pub fn firing_sequences(
+		&self,
+		model: &AbstractVas,
+		start: &VasStateVector,
+		limit: usize,
+	) -> Vec<Vec<usize>> {
+		// The current state only depends on the transitions that are left to fire, so the
+		// remaining counts that cannot complete the cycle are dead ends from any order
+		fn search(
+			model: &AbstractVas,
+			current: &VasStateVector,
+			remaining: &mut Vec<VasValue>,
+			sequence: &mut Vec<usize>,
+			dead_ends: &mut HashSet<Vec<VasValue>>,
+			sequences: &mut Vec<Vec<usize>>,
+			limit: usize,
+		) {
+			if remaining.iter().all(|count| *count == 0) {
+				sequences.push(sequence.clone());
+				return;
+			}
+			let found = sequences.len();
+			for transition in 0..remaining.len() {
+				if sequences.len() >= limit {
+					return;
+				}
+				if remaining[transition] == 0
+					|| !model.transitions[transition].enabled_vector(current)
+				{
+					continue;
+				}
+				let next = current + &model.transitions[transition].update_vector;
+				if next.iter().any(|count| *count < 0) {
+					continue;
+				}
+				remaining[transition] -= 1;
+				if dead_ends.contains(remaining) {
+					remaining[transition] += 1;
+					continue;
+				}
+				sequence.push(transition);
+				search(
+					model, &next, remaining, sequence, dead_ends, sequences, limit,
+				);
+				sequence.pop();
+				remaining[transition] += 1;
+			}
+			if sequences.len() == found {
+				dead_ends.insert(remaining.clone());
+			}
+		}
+		let mut sequences = Vec::new();
+		search(
+			model,
+			start,
+			&mut self.counts.clone(),
+			&mut Vec::with_capacity(self.length()),
+			&mut HashSet::new(),
+			&mut sequences,
+			limit,
+		);
+		sequences
+	}
+}
+
+/// The T-invariants of a model
+pub(crate) struct TInvariants {
+	/// The dimension of the right null space of the stoichiometry matrix, i.e., how many
+	/// independent (possibly negative) combinations of transitions have no net effect
+	pub(crate) dimension: usize,
+	/// The minimal semi-positive T-invariants. Since every transition of a VAS is
+	/// irreversible, these are its elementary flux modes: each one is a cycle that cannot be
+	/// split into smaller cycles, and every cycle is a sum of them.
+	pub(crate) minimal: Vec<TInvariant>,
+}
+
+impl TInvariants {
+	/// Computes the T-invariants of a model, or an error if there are too many candidates
+	/// to enumerate them
+	pub fn from_model(model: &AbstractVas) -> Result<Self, String> {
+		let stoichiometry = stoichiometry_matrix(model);
+		let dimension = integer_null_space(&stoichiometry, model.transitions.len())?.len();
+		// The solutions `x >= 0` of `S x = 0` are those of `x^T S^T = 0`
+		let minimal = semi_positive_solutions(
+			&transpose(&stoichiometry, model.transitions.len()),
+			T_INVARIANT_CANDIDATE_LIMIT,
+		)?
+		.into_iter()
+		.map(|counts| TInvariant { counts })
+		.collect::<Vec<_>>();
+		debug_message!(
+			"Found {} minimal T-invariants ({} independent)",
+			minimal.len(),
+			dimension
+		);
+		Ok(Self { dimension, minimal })
+	}
+
+	/// The transitions that are not in any T-invariant. In a model with finitely many
+	/// reachable states, each of them can only fire finitely often on any path.
+	pub fn uncovered_transitions(&self, model: &AbstractVas) -> Vec<usize> {
+		(0..model.transitions.len())
+			.filter(|&transition| {
+				self.minimal
+					.iter()
+					.all(|invariant| invariant.counts[transition] == 0)
+			})
+			.collect()
+	}
+
+	/// A human-readable report of the T-invariants
+	pub fn report(&self, model: &AbstractVas) -> String {
+		let mut output = String::new();
+		output.push_str("==========================================\n");
+		output.push_str("              T-INVARIANTS                \n");
+		output.push_str("==========================================\n");
+		output.push_str(&format!(
+			"Minimal cycles ({}, {} independent):\n",
+			self.minimal.len(),
+			self.dimension
+		));
+		for invariant in self.minimal.iter() {
+			output.push_str(&format!(
+				"\t{}\t(length {})\n",
+				invariant.to_string_with_names(model),
+				invariant.length()
+			));
+		}
+		let uncovered = self.uncovered_transitions(model);
+		if !uncovered.is_empty() {
+			output.push_str("Transitions in no cycle:\n");
+			for transition in uncovered {
+				output.push_str(&format!(
+					"\t{}\n",
+					model.transitions[transition].transition_name
+				));
+			}
+		}
+		output
+	}
+}
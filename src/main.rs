@@ -1,31 +1,313 @@
 #![allow(dead_code)]
 
 mod bmc;
+mod checker;
 mod cycle_commute;
 mod demos;
 mod dependency;
+mod invariant;
 mod logging;
 mod model;
 mod parser;
 mod property;
 // mod ragtimer;
 mod builder;
+mod solver;
 mod trace;
 mod util;
 mod validator;
 
-use clap::{Arg, Command};
-use dependency::graph::make_dependency_graph;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use dependency::{graph::make_dependency_graph, trimmer::trim_model};
+use invariant::{p_invariant::PInvariants, reduction::reduce_model, t_invariant::TInvariants};
 use model::vas_model::AbstractVas;
-use std::{default, path::Path};
+use std::{collections::HashMap, default, path::Path, time::Duration};
 
 use crate::{
-	builder::{builder::Builder, ragtimer::ragtimer::RagtimerBuilder},
-	model::{model::ExplicitModel, vas_model::PrismVasModel},
+	builder::{ragtimer::ragtimer::RagtimerBuilder, stamina::StaminaBuilder},
+	checker::{
+		checker::{model_queries, Budget},
+		report::Report,
+		vas_checker::{RagtimerChecker, StaminaChecker},
+	},
+	model::{
+		model::ExplicitModel,
+		propensity::Kinetics,
+		serialization::Persistent,
+		vas_mdp::PrismVasMdp,
+		vas_model::{DtmcDerivation, PrismVasModel},
+	},
+	parser::prism_file_writer,
+	solver::iterative::{Convergence, IterativeMethod, SolverOptions},
 };
 
 // use crate::ragtimer::rl_traces::print_traces_to_file;
 const TIMEOUT_MINUTES: &str = "10"; //
+const MDP_MAX_STATES: &str = "100000";
+
+/// The `--param` argument of the subcommands that read a model, see `parse_params`
+fn param_arg() -> Arg {
+	Arg::new("param")
+		.short('p')
+		.long("param")
+		.value_name("NAME=VALUE")
+		.action(ArgAction::Append)
+		.help("Overrides a parameter or constant declared in the model file (repeatable)")
+}
+
+/// The `--kinetics` argument of the subcommands that read a model, see `parse_kinetics`
+fn kinetics_arg() -> Arg {
+	Arg::new("kinetics")
+		.short('k')
+		.long("kinetics")
+		.value_name("KINETICS")
+		.help("Mass-action convention: stochastic (default), falling-factorial, deterministic or constant")
+}
+
+/// The value of an argument of a subcommand, or `None` if it is not given or the
+/// subcommand does not take it
+fn arg_value<'a>(sub_m: &'a ArgMatches, id: &str) -> Option<&'a String> {
+	sub_m.try_get_one::<String>(id).ok().flatten()
+}
+
+/// Whether a flag of a subcommand is set, or `false` if the subcommand does not take it
+fn arg_flag(sub_m: &ArgMatches, id: &str) -> bool {
+	sub_m
+		.try_get_one::<bool>(id)
+		.ok()
+		.flatten()
+		.copied()
+		.unwrap_or(false)
+}
+
+/// Reads the `--param NAME=VALUE` overrides of a subcommand
+fn parse_params(sub_m: &ArgMatches) -> Result<HashMap<String, f64>, String> {
+	let mut parameters = HashMap::new();
+	for param in sub_m
+		.try_get_many::<String>("param")
+		.ok()
+		.flatten()
+		.into_iter()
+		.flatten()
+	{
+		let (name, value) = param
+			.split_once('=')
+			.ok_or(format!("Expected NAME=VALUE, got `{}`", param))?;
+		let value = value
+			.trim()
+			.parse::<f64>()
+			.map_err(|_| format!("Expected a number for {}, got `{}`", name, value))?;
+		parameters.insert(name.trim().to_string(), value);
+	}
+	Ok(parameters)
+}
+
+/// Reads the `--kinetics` convention of a subcommand, if one is given. Without one, each
+/// model keeps its own (stochastic for CTMCs, constant probabilities for DTMCs).
+fn parse_kinetics(sub_m: &ArgMatches) -> Result<Option<Kinetics>, String> {
+	let Some(name) = arg_value(sub_m, "kinetics") else {
+		return Ok(None);
+	};
+	Kinetics::from_name(name).map(Some).ok_or(format!(
+		"Unknown kinetics `{}`, expected one of: {}",
+		name,
+		Kinetics::ALL.map(|k| k.to_string()).join(", ")
+	))
+}
+
+/// The arguments of the iterative solvers, see `parse_solver_options`
+fn solver_args() -> [Arg; 4] {
+	[
+		Arg::new("method")
+			.long("method")
+			.value_name("METHOD")
+			.help("Iterative method for unbounded and steady-state queries: gauss-seidel (default), jacobi or power"),
+		Arg::new("tolerance")
+			.long("tolerance")
+			.value_name("EPSILON")
+			.help("Convergence tolerance of the iterative method, and the widest gap between the bounds of interval iteration (default 1e-6)"),
+		Arg::new("max_iterations")
+			.long("max-iterations")
+			.value_name("N")
+			.help("Most iterations of the iterative method (default 10000)"),
+		Arg::new("absolute")
+			.long("absolute")
+			.action(ArgAction::SetTrue)
+			.help("Stops when no value changes by more than the tolerance, rather than by more than the tolerance relative to the value"),
+	]
+}
+
+/// The `--to-dtmc` argument of the subcommands that check a model, see `parse_to_dtmc`
+fn to_dtmc_arg() -> Arg {
+	Arg::new("to_dtmc")
+		.long("to-dtmc")
+		.value_name("DERIVATION")
+		.help("Checks a DTMC derived from the CTMC: embedded, or uniformized:Q with uniformization rate Q")
+}
+
+/// Reads the `--to-dtmc` derivation of a subcommand, if one is given
+fn parse_to_dtmc(sub_m: &ArgMatches) -> Result<Option<DtmcDerivation>, String> {
+	let Some(name) = arg_value(sub_m, "to_dtmc") else {
+		return Ok(None);
+	};
+	DtmcDerivation::from_name(name).map(Some).ok_or(format!(
+		"Unknown derivation `{}`, expected embedded or uniformized:Q",
+		name
+	))
+}
+
+/// The `--reduce` flag of the subcommands that can analyze a reduced model, see `reduce`
+fn reduce_arg() -> Arg {
+	Arg::new("reduce")
+		.long("reduce")
+		.action(ArgAction::SetTrue)
+		.help("Eliminates the species that the conservation laws (P-invariants) determine before the analysis")
+}
+
+/// The model without the species its P-invariants determine, if the `--reduce` flag of a
+/// subcommand is set
+fn reduce(sub_m: &ArgMatches, model: AbstractVas) -> Result<AbstractVas, String> {
+	if !arg_flag(sub_m, "reduce") {
+		return Ok(model);
+	}
+	let invariants = PInvariants::from_model(&model)
+		.map_err(|e| format!("Error computing the P-invariants: {}", e))?;
+	let (reduced_model, reduction) = reduce_model(&model, &invariants);
+	message!("{}", reduction.report(&model));
+	Ok(reduced_model)
+}
+
+/// Reads the settings of the iterative solvers of a subcommand, starting from the defaults
+fn parse_solver_options(sub_m: &ArgMatches) -> Result<SolverOptions, String> {
+	let mut options = SolverOptions::default();
+	if let Some(name) = arg_value(sub_m, "method") {
+		options.method = IterativeMethod::from_name(name).ok_or(format!(
+			"Unknown method `{}`, expected one of: {}",
+			name,
+			IterativeMethod::ALL.map(|m| m.to_string()).join(", ")
+		))?;
+	}
+	if let Some(tolerance) = arg_value(sub_m, "tolerance") {
+		options.tolerance = tolerance
+			.parse::<f64>()
+			.map_err(|_| format!("Expected a number for the tolerance, got `{}`", tolerance))?;
+	}
+	if let Some(max_iterations) = arg_value(sub_m, "max_iterations") {
+		options.max_iterations = max_iterations.parse::<usize>().map_err(|_| {
+			format!("Expected a whole number of iterations, got `{}`", max_iterations)
+		})?;
+	}
+	if arg_flag(sub_m, "absolute") {
+		options.convergence = Convergence::Absolute;
+	}
+	Ok(options)
+}
+
+/// Reads the `--model` of a subcommand with its `--param` overrides and applies its
+/// `--kinetics`, `--to-dtmc` and `--reduce` options, along with its solver settings. The
+/// options that the subcommand does not take are left out.
+fn read_model(sub_m: &ArgMatches) -> Result<(AbstractVas, SolverOptions), String> {
+	let model_file = sub_m.get_one::<String>("model").unwrap();
+	let parameters = parse_params(sub_m)?;
+	let kinetics = parse_kinetics(sub_m)?;
+	let to_dtmc = parse_to_dtmc(sub_m)?;
+	let solver_options = parse_solver_options(sub_m)?;
+	let mut model = AbstractVas::from_file_with_params(model_file, &parameters)
+		.map_err(|_| format!("Error parsing model file: {}", model_file))?;
+	if let Some(kinetics) = kinetics {
+		model.set_kinetics(kinetics);
+	}
+	if let Some(derivation) = to_dtmc {
+		model = model.to_dtmc(derivation)?;
+	}
+	Ok((reduce(sub_m, model)?, solver_options))
+}
+
+/// Like `read_model`, but reports the error and gives `None`
+fn load_model(sub_m: &ArgMatches) -> Option<(AbstractVas, SolverOptions)> {
+	match read_model(sub_m) {
+		Ok(loaded) => Some(loaded),
+		Err(e) => {
+			error!("{}", e);
+			None
+		}
+	}
+}
+
+/// Reads the STAMINA settings of a subcommand into `builder`, which keeps its defaults for
+/// the settings that are not given
+fn parse_stamina_settings(sub_m: &ArgMatches, builder: &mut StaminaBuilder) -> Result<(), String> {
+	let number = |name: &str| -> Result<Option<f64>, String> {
+		sub_m
+			.get_one::<String>(name)
+			.map(|value| {
+				value
+					.parse::<f64>()
+					.map_err(|_| format!("Expected a number for the {}, got `{}`", name, value))
+			})
+			.transpose()
+	};
+	if let Some(kappa) = number("kappa")? {
+		builder.kappa = kappa;
+	}
+	if let Some(reduction) = number("reduce_kappa")? {
+		if !(reduction > 1.0) {
+			return Err(format!("The kappa reduction {} must be above 1", reduction));
+		}
+		builder.kappa_reduction = reduction;
+	}
+	if let Some(window) = number("window")? {
+		builder.window = window;
+	}
+	if let Some(max_refinements) = sub_m.get_one::<String>("max_refinements") {
+		builder.max_iters = max_refinements.parse::<u16>().map_err(|_| {
+			format!(
+				"Expected a whole number of refinements, got `{}`",
+				max_refinements
+			)
+		})?;
+	}
+	Ok(())
+}
+
+/// The `--save-explicit` argument of the subcommands that build a state space
+fn save_explicit_arg() -> Arg {
+	Arg::new("save_explicit")
+		.short('s')
+		.long("save-explicit")
+		.value_name("FILE")
+		.help("Saves the explored state space to FILE (JSON if it ends in .json, binary otherwise)")
+}
+
+/// Saves the explored state space to the `--save-explicit` file of a subcommand, if one is
+/// given
+fn save_explicit(sub_m: &ArgMatches, explicit_model: &PrismVasModel) {
+	if let Some(filename) = sub_m.get_one::<String>("save_explicit") {
+		match explicit_model.save(filename) {
+			Ok(()) => message!("Saved the explored state space to {}", filename),
+			Err(e) => error!("{}", e),
+		}
+	}
+}
+
+/// The `--report` argument of the subcommands that check a model, see `write_report`
+fn report_arg() -> Arg {
+	Arg::new("report")
+		.long("report")
+		.value_name("FILE")
+		.help("Saves the results to FILE, to compare with other methods (JSON if it ends in .json, binary otherwise)")
+}
+
+/// Prints a report and saves it to the `--report` file of a subcommand, if one is given
+fn write_report(sub_m: &ArgMatches, report: &Report) {
+	message!("{}", report);
+	if let Some(filename) = sub_m.get_one::<String>("report") {
+		match report.save(filename) {
+			Ok(()) => message!("Saved the report to {}", filename),
+			Err(e) => error!("{}", e),
+		}
+	}
+}
 
 fn main() {
 	let matches = Command::new("practice")
@@ -55,6 +337,28 @@ fn main() {
 						.help("Sets the model file")
 						.required(true),
 				)
+				.arg(param_arg())
+				.arg(kinetics_arg())
+				.arg(
+					Arg::new("prism_output")
+						.short('o')
+						.long("prism-output")
+						.value_name("PREFIX")
+						.help("Writes the original and trimmed models to PREFIX.sm and PREFIX_trimmed.sm"),
+				)
+		)
+		.subcommand(
+			Command::new("invariants")
+				.about("Find the conservation laws (P-invariants) and cycles (T-invariants) of a model")
+				.arg(
+					Arg::new("model")
+						.short('m')
+						.long("model")
+						.value_name("FILE")
+						.help("Sets the model file (crn, PRISM .sm or SBML .xml format)")
+						.required(true),
+				)
+				.arg(param_arg())
 		)
 		.subcommand(
 			Command::new("ragtimer")
@@ -64,9 +368,13 @@ fn main() {
 						.short('d')
 						.long("model")
 						.value_name("MODEL")
-						.help("Sets the model file (crn format)")
+						.help("Sets the model file (crn, PRISM .sm or SBML .xml format)")
 						.required(true),
 				)
+				.arg(param_arg())
+				.arg(kinetics_arg())
+				.arg(to_dtmc_arg())
+				.arg(reduce_arg())
 				.arg(
 					Arg::new("qty")
 						.short('q')
@@ -83,6 +391,16 @@ fn main() {
 						.help("Timeout in minutes for get_bounds")
 						.default_value(TIMEOUT_MINUTES),
 				)
+				.args(solver_args())
+				.arg(
+					Arg::new("rewards")
+						.short('r')
+						.long("rewards")
+						.value_name("FILE")
+						.help("Starts from the rewards learned in FILE, if it exists, and saves the learned rewards to it"),
+				)
+				.arg(save_explicit_arg())
+				.arg(report_arg())
 		)
 		.subcommand(
 			Command::new("cycle-commute")
@@ -92,9 +410,12 @@ fn main() {
 						.short('d')
 						.long("model-file")
 						.value_name("MODEL")
-						.help("Sets the model file (crn format)")
+						.help("Sets the model file (crn, PRISM .sm or SBML .xml format)")
 						.required(true),
 				)
+				.arg(param_arg())
+				.arg(kinetics_arg())
+				.arg(to_dtmc_arg())
 				.arg(
 					Arg::new("trace")
 						.short('t')
@@ -108,30 +429,103 @@ fn main() {
 						.short('o')
 						.long("output-file")
 						.value_name("OUTPUT")
-						.help("File to write the output to WITHOUT A FILE EXTENSION")
-						.default_value("cycle_commute"),
+						.help("Also writes the state space to the .sta, .tra and .dot files of OUTPUT (given WITHOUT A FILE EXTENSION)"),
 				)
+				.args(solver_args())
+				.arg(report_arg())
 		)
 		.subcommand(
 			Command::new("stamina")
 				.about("Run the stamina tool")
 				.arg(
-					Arg::new("models_dir")
-						.required(true)
+					Arg::new("model")
 						.short('d')
-						.long("models-dir")
-						.value_name("DIR")
-						.help("Sets the directory containing model folders")
-						.default_value("models"),
+						.long("model")
+						.value_name("MODEL")
+						.help("Sets the model file (crn, PRISM .sm or SBML .xml format)")
+						.required(true),
+				)
+				.arg(param_arg())
+				.arg(kinetics_arg())
+				.arg(to_dtmc_arg())
+				.arg(
+					Arg::new("kappa")
+						.long("kappa")
+						.value_name("KAPPA")
+						.help("Reachability threshold of the first round (default 1)"),
+				)
+				.arg(
+					Arg::new("reduce_kappa")
+						.long("reduce-kappa")
+						.value_name("FACTOR")
+						.help("Factor the reachability threshold is divided by after each round (default 1000)"),
+				)
+				.arg(
+					Arg::new("window")
+						.short('w')
+						.long("window")
+						.value_name("WIDTH")
+						.help("Stops once Pmax - Pmin is at most WIDTH (default 1e-3)"),
+				)
+				.arg(
+					Arg::new("max_refinements")
+						.long("max-refinements")
+						.value_name("N")
+						.help("Most rounds of refinement (default 10)"),
 				)
 				.arg(
 					Arg::new("timeout")
 						.short('t')
 						.long("timeout")
 						.value_name("MINUTES")
-						.help("Timeout in minutes for get_bounds")
+						.help("Stops refining after this many minutes")
 						.default_value(TIMEOUT_MINUTES),
 				)
+				.args(solver_args())
+				.arg(save_explicit_arg())
+				.arg(report_arg())
+		)
+		.subcommand(
+			Command::new("mdp")
+				.about("Explore an MDP or CTMDP and bound its Pmin and Pmax by value iteration")
+				.arg(
+					Arg::new("model")
+						.short('d')
+						.long("model")
+						.value_name("MODEL")
+						.help("Sets the model file (crn, PRISM .sm or SBML .xml format)")
+						.required(true),
+				)
+				.arg(param_arg())
+				.arg(kinetics_arg())
+				.arg(
+					Arg::new("max_states")
+						.long("max-states")
+						.value_name("N")
+						.help("Most states to explore, after which the rest lead to an absorbing sink")
+						.default_value(MDP_MAX_STATES),
+				)
+				// Value iteration has a single method
+				.args(
+					solver_args()
+						.into_iter()
+						.filter(|arg| arg.get_id().as_str() != "method"),
+				)
+				.arg(report_arg())
+		)
+		.subcommand(
+			Command::new("compare")
+				.about("Merge the reports of different methods on a model into the tightest bounds, checking that they agree")
+				.arg(
+					Arg::new("reports")
+						.value_name("FILE")
+						.num_args(1..)
+						.help("The reports to merge, saved with --report")
+						.required(true),
+				)
+				.arg(report_arg().help(
+					"Saves the merged report to FILE (JSON if it ends in .json, binary otherwise)",
+				))
 		)
 		.subcommand(
 			Command::new("wayfarer")
@@ -167,12 +561,9 @@ fn main() {
 			// TODO: Move this whole thing to a demo
 			let model_file = sub_m.get_one::<String>("model").unwrap();
 			message!("Running ragtimer with models: {}", model_file);
-			let parsed_model = AbstractVas::from_file(model_file);
-			if !parsed_model.is_ok() {
-				error!("Error parsing model file: {}", model_file);
+			let Some((parsed_model, _)) = load_model(sub_m) else {
 				return;
-			}
-			let parsed_model = parsed_model.unwrap();
+			};
 			message!("MODEL PARSED\n\n");
 			message!("{}", parsed_model.nice_print());
 			let dg = make_dependency_graph(&parsed_model);
@@ -180,10 +571,37 @@ fn main() {
 				dependency_graph.pretty_print(&parsed_model);
 				dependency_graph.simple_print(&parsed_model);
 				dependency_graph.original_print(&parsed_model);
+				if let Some(prefix) = sub_m.get_one::<String>("prism_output") {
+					let trimmed_model = trim_model(&parsed_model, dependency_graph.clone());
+					prism_file_writer::write_model(&parsed_model, &format!("{}.sm", prefix));
+					prism_file_writer::write_model(&trimmed_model, &format!("{}_trimmed.sm", prefix));
+				}
 			} else {
 				error!("Error creating dependency graph.");
 			}
 		}
+		Some(("invariants", sub_m)) => {
+			let model_file = sub_m.get_one::<String>("model").unwrap();
+			message!("Finding the invariants of model: {}", model_file);
+			let Some((parsed_model, _)) = load_model(sub_m) else {
+				return;
+			};
+			let invariants = match PInvariants::from_model(&parsed_model) {
+				Ok(invariants) => invariants,
+				Err(e) => {
+					error!("Error computing the P-invariants: {}", e);
+					return;
+				}
+			};
+			message!("{}", invariants.report(&parsed_model));
+			let (reduced_model, reduction) = reduce_model(&parsed_model, &invariants);
+			message!("{}", reduction.report(&parsed_model));
+			message!("{}", reduced_model.nice_print());
+			match TInvariants::from_model(&parsed_model) {
+				Ok(cycles) => message!("{}", cycles.report(&parsed_model)),
+				Err(e) => warning!("Could not compute the T-invariants: {}", e),
+			}
+		}
 		Some(("ragtimer", sub_m)) => {
 			message!("Ragtimer under development...");
 			let num_traces = sub_m
@@ -192,12 +610,16 @@ fn main() {
 				.unwrap();
 			let model_file = sub_m.get_one::<String>("model").unwrap();
 			message!("Running ragtimer with models: {}", model_file);
-			let parsed_model = AbstractVas::from_file(model_file);
-			if !parsed_model.is_ok() {
-				error!("Error parsing model file: {}", model_file);
+			let Some((parsed_model, solver_options)) = load_model(sub_m) else {
+				return;
+			};
+			if parsed_model.m_type.is_nondeterministic() {
+				error!(
+					"Ragtimer needs a Markov chain, but {} is a {}. Check it with the `mdp` subcommand instead.",
+					model_file, parsed_model.m_type
+				);
 				return;
 			}
-			let parsed_model = parsed_model.unwrap();
 			message!("MODEL PARSED\n\n");
 			message!("{}", parsed_model.nice_print());
 			let dg = make_dependency_graph(&parsed_model);
@@ -205,7 +627,24 @@ fn main() {
 				dependency_graph.pretty_print(&parsed_model);
 				let mut explicit_model = PrismVasModel::from_abstract_model(&parsed_model);
 				let mut ragtimer_builder = RagtimerBuilder::new(&parsed_model, None);
-				ragtimer_builder.build(&mut explicit_model);
+				ragtimer_builder.rewards_file = sub_m.get_one::<String>("rewards").cloned();
+				// Check the queries on the explored state space
+				let mut checker = match RagtimerChecker::new(
+					ragtimer_builder,
+					model_queries(&parsed_model),
+					solver_options,
+				) {
+					Ok(checker) => checker,
+					Err(e) => {
+						error!("{}", e);
+						return;
+					}
+				};
+				let results = checker.build_and_check_all(&mut explicit_model);
+				let mut report = Report::new(model_file);
+				report.add_results("ragtimer", &results);
+				write_report(sub_m, &report);
+				save_explicit(sub_m, &explicit_model);
 			} else {
 				error!("Error creating dependency graph.");
 				return;
@@ -214,23 +653,124 @@ fn main() {
 		Some(("cycle-commute", sub_m)) => {
 			let model = sub_m.get_one::<String>("model").unwrap();
 			let trace = sub_m.get_one::<String>("trace").unwrap();
-			let output_file = sub_m.get_one::<String>("output_file").unwrap();
+			let output_file = sub_m.get_one::<String>("output_file");
+			let Some((parsed_model, solver_options)) = load_model(sub_m) else {
+				return;
+			};
 			message!(
 				"Running cycle-commute with model: {} and trace: {}",
 				model,
 				trace
 			);
-			demos::cycle_commute_demo::cycle_commute_demo(model, trace, output_file);
+			if let Some(report) = demos::cycle_commute_demo::cycle_commute_demo(
+				model,
+				&parsed_model,
+				trace,
+				output_file.map(String::as_str),
+				&solver_options,
+			) {
+				write_report(sub_m, &report);
+			}
 		}
 		Some(("stamina", sub_m)) => {
-			let models_dir = sub_m.get_one::<String>("models_dir").unwrap();
+			let model_file = sub_m.get_one::<String>("model").unwrap();
+			let Some((parsed_model, solver_options)) = load_model(sub_m) else {
+				return;
+			};
+			if parsed_model.m_type.is_nondeterministic() {
+				error!(
+					"STAMINA needs a Markov chain, but {} is a {}. Check it with the `mdp` subcommand instead.",
+					model_file, parsed_model.m_type
+				);
+				return;
+			}
+			let queries = model_queries(&parsed_model);
+			let mut stamina_builder = StaminaBuilder::new(&parsed_model);
+			// The states that decide one query need not decide the others, so only a single
+			// query lets STAMINA preterminate them
+			if let [query] = queries.as_slice() {
+				stamina_builder.set_property(query);
+			} else {
+				message!(
+					"Exploring without pretermination, since there are {} queries.",
+					queries.len()
+				);
+			}
+			if let Err(e) = parse_stamina_settings(sub_m, &mut stamina_builder) {
+				error!("{}", e);
+				return;
+			}
 			let timeout = sub_m.get_one::<String>("timeout").unwrap();
-			message!(
-				"Running stamina with models_dir: {} and timeout: {}",
-				models_dir,
-				timeout
-			);
-			unimplemented!();
+			let Ok(minutes) = timeout.parse::<f64>() else {
+				error!(
+					"Expected a number of minutes for the timeout, got `{}`",
+					timeout
+				);
+				return;
+			};
+			message!("Running stamina with model: {}", model_file);
+			let mut explicit_model = PrismVasModel::from_abstract_model(&parsed_model);
+			let mut checker = match StaminaChecker::new(stamina_builder, queries, solver_options) {
+				Ok(checker) => checker,
+				Err(e) => {
+					error!("{}", e);
+					return;
+				}
+			};
+			checker.set_budget(Budget {
+				time_limit: Some(Duration::from_secs_f64(minutes * 60.0)),
+				..Budget::default()
+			});
+			let results = checker.build_and_check_all(&mut explicit_model);
+			let mut report = Report::new(model_file);
+			report.add_results("stamina", &results);
+			write_report(sub_m, &report);
+			save_explicit(sub_m, &explicit_model);
+		}
+		Some(("mdp", sub_m)) => {
+			let model_file = sub_m.get_one::<String>("model").unwrap();
+			let max_states = sub_m.get_one::<String>("max_states").unwrap();
+			let Ok(max_states) = max_states.parse::<usize>() else {
+				error!("Expected a whole number of states, got `{}`", max_states);
+				return;
+			};
+			let Some((parsed_model, solver_options)) = load_model(sub_m) else {
+				return;
+			};
+			message!("Exploring {} as a {}", model_file, parsed_model.m_type);
+			let mdp = PrismVasMdp::from_abstract_model(&parsed_model, max_states);
+			let results = model_queries(&parsed_model)
+				.into_iter()
+				.map(|query| {
+					let result = mdp.check(&parsed_model, &query, &solver_options);
+					(query, result)
+				})
+				.collect::<Vec<_>>();
+			let mut report = Report::new(model_file);
+			report.add_results("mdp", &results);
+			write_report(sub_m, &report);
+		}
+		Some(("compare", sub_m)) => {
+			let mut merged: Option<Report> = None;
+			for filename in sub_m.get_many::<String>("reports").into_iter().flatten() {
+				let report = match Report::load(filename) {
+					Ok(report) => report,
+					Err(e) => {
+						error!("{}", e);
+						return;
+					}
+				};
+				let merged = merged.get_or_insert_with(|| Report::new(&report.model));
+				if let Err(e) = merged.merge(&report) {
+					error!("{}", e);
+					return;
+				}
+			}
+			let merged = merged.unwrap();
+			write_report(sub_m, &merged);
+			if !merged.consistent() {
+				error!("The methods disagree on {}", merged.model);
+			}
 		}
 		Some(("wayfarer", sub_m)) => {
 			let models_dir = sub_m.get_one::<String>("models_dir").unwrap();
@@ -1,4 +1,8 @@
 // pub mod parser;
 pub mod model;
+pub mod propensity;
+pub mod serialization;
+pub mod sparse_matrix;
+pub mod vas_mdp;
 pub mod vas_model;
 pub mod vas_trie;
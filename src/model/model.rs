@@ -4,8 +4,6 @@ use crate::property::property::Labeled;
 
 pub type ProbabilityOrRate = f64;
 
-// TODO: should we include the skeleton code for nondeterministic actions?
-
 /// A trait representing a state object. Generally these will need
 /// to have some global context so implementing structs are recommended
 /// to use lifetime parameters and contain a reference to the state
@@ -49,6 +47,13 @@ pub(crate) trait Transition: Clone + PartialEq {
 		self.next_state(state).is_some()
 	}
 
+	/// The action this transition belongs to in a nondeterministic model. A scheduler picks
+	/// an action, then the transitions of that action are taken as in a Markov chain.
+	/// Transitions without an action are an action of their own.
+	fn action(&self) -> Option<&str> {
+		None
+	}
+
 	/// Gets the next state and its rate probability if it exist given a
 	/// current state. Provides a default implementation if not.
 	fn next(
@@ -71,11 +76,47 @@ pub(crate) enum ModelType {
 	ContinuousTime,
 	/// There are discrete time steps and the transitions are probabilities, rather than rates.
 	DiscreteTime,
+	/// Like `DiscreteTime`, but a scheduler chooses one of the enabled actions in each
+	/// state (an MDP)
+	NondeterministicDiscreteTime,
+	/// Like `ContinuousTime`, but a scheduler chooses one of the enabled actions in each
+	/// state (a CTMDP)
+	NondeterministicContinuousTime,
+}
+
+impl ModelType {
+	/// Whether or not the transitions are probabilities of discrete time steps
+	pub fn is_discrete_time(&self) -> bool {
+		matches!(
+			self,
+			ModelType::DiscreteTime | ModelType::NondeterministicDiscreteTime
+		)
+	}
+
+	/// Whether or not a scheduler chooses between actions
+	pub fn is_nondeterministic(&self) -> bool {
+		matches!(
+			self,
+			ModelType::NondeterministicDiscreteTime | ModelType::NondeterministicContinuousTime
+		)
+	}
+}
+
+impl std::fmt::Display for ModelType {
+	/// The PRISM keyword for the model type
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ModelType::ContinuousTime => write!(f, "ctmc"),
+			ModelType::DiscreteTime => write!(f, "dtmc"),
+			ModelType::NondeterministicDiscreteTime => write!(f, "mdp"),
+			ModelType::NondeterministicContinuousTime => write!(f, "ctmdp"),
+		}
+	}
 }
 
 pub(crate) trait AbstractModel {
 	type StateType: State;
-	type TransitionType: Transition;
+	type TransitionType: Transition<StateType = Self::StateType>;
 
 	// Functions for which no default implementation is provided
 	// and must be provided by derived types
@@ -89,28 +130,82 @@ pub(crate) trait AbstractModel {
 
 	// Functions for which we can provide a default implementation
 
-	// /// Finds all next states for a certain state.
-	// fn next_states(&self, state: &Self::StateType)
-	// 	-> impl Iterator<Item=(<Self::TransitionType as Transition>::RateOrProbabilityType, <<Self as AbstractModel>::TransitionType as Transition>::StateType)> {
-	// 	self.transitions().filter_map(|t| t.next(state))
-	// }
-
-	// /// Finds the exit rate (or exit probability) for a state. If a discrete time model,
-	// /// this will always return `1.0` and can be used to check if implementations are correct.
-	// fn exit_rate(&self, state: &Self::StateType) -> <Self::TransitionType as Transition>::RateOrProbabilityType {
-	// 	self.next_states(state).map(|(rate, _state)| rate).sum()
-	// }
-
-	// /// Only finds successors for transitions that pass a certain filter predicate `filter`.
-	// /// This is useful in Wayfarer/ISR, as well as pancake abstraction.
-	// fn next_filtered(&self, state: &Self::StateType, filter: &dyn Fn(Self::TransitionType) -> bool)
-	// -> impl Iterator<Item=(<Self::TransitionType as Transition>::RateOrProbabilityType, Self::StateType)> {
-
-	// self.transitions()
-	// 	.filter(filter) // This filter call applies our filter function
-	// 	.filter_map(|t| t.next(state)) // and this one filters enabledness
-	// unimplemented!();
-	// }
+	/// The transitions that are enabled at `state`
+	fn enabled_transitions(
+		&self,
+		state: &Self::StateType,
+	) -> impl Iterator<Item = Self::TransitionType> {
+		self.transitions().filter(move |t| t.enabled(state))
+	}
+
+	/// Finds all next states for a certain state, along with the rate (or probability)
+	/// of the transition that leads to each.
+	fn next_states(
+		&self,
+		state: &Self::StateType,
+	) -> impl Iterator<
+		Item = (
+			<Self::TransitionType as Transition>::RateOrProbabilityType,
+			Self::StateType,
+		),
+	> {
+		self.transitions().filter_map(move |t| t.next(state))
+	}
+
+	/// Finds the exit rate (or exit probability) for a state. If a discrete time model,
+	/// this will always return `1.0` and can be used to check if implementations are correct.
+	fn exit_rate(
+		&self,
+		state: &Self::StateType,
+	) -> <Self::TransitionType as Transition>::RateOrProbabilityType {
+		self.next_states(state)
+			.fold(num::zero(), |total, (rate, _state)| total + rate)
+	}
+
+	/// The choices of a scheduler at `state`: the enabled transitions grouped by action, in
+	/// the order that the actions first appear. A model without nondeterminism has a single
+	/// choice with every enabled transition, and a deadlocked state has none.
+	fn choices(&self, state: &Self::StateType) -> Vec<Vec<Self::TransitionType>> {
+		let enabled = self.enabled_transitions(state);
+		if !self.model_type().is_nondeterministic() {
+			let transitions = enabled.collect::<Vec<_>>();
+			return if transitions.is_empty() {
+				Vec::new()
+			} else {
+				vec![transitions]
+			};
+		}
+		let mut choices: Vec<Vec<Self::TransitionType>> = Vec::new();
+		for transition in enabled {
+			let existing = transition.action().and_then(|action| {
+				choices
+					.iter()
+					.position(|choice| choice[0].action() == Some(action))
+			});
+			match existing {
+				Some(index) => choices[index].push(transition),
+				None => choices.push(vec![transition]),
+			}
+		}
+		choices
+	}
+
+	/// Only finds successors for transitions that pass a certain filter predicate `filter`.
+	/// This is useful in Wayfarer/ISR, as well as pancake abstraction.
+	fn next_filtered<'a>(
+		&'a self,
+		state: &'a Self::StateType,
+		filter: &'a dyn Fn(&Self::TransitionType) -> bool,
+	) -> impl Iterator<
+		Item = (
+			<Self::TransitionType as Transition>::RateOrProbabilityType,
+			Self::StateType,
+		),
+	> + 'a {
+		self.transitions()
+			.filter(move |t| filter(t)) // This filter call applies our filter function
+			.filter_map(move |t| t.next(state)) // and this one filters enabledness
+	}
 }
 
 pub(crate) trait ExplicitModel: Default {
@@ -0,0 +1,121 @@
+use std::fmt;
+
+use crate::model::{
+	model::ProbabilityOrRate,
+	vas_model::{VasStateVector, VasValue},
+};
+
+/// The convention for turning the reactant counts of a reaction into its
+/// propensity. For `2X + Y -> ...` with rate constant `k` these give
+///
+/// - `Stochastic`: `k * C(X, 2) * Y = k * X * (X - 1) / 2 * Y`
+/// - `FallingFactorial`: `k * X * (X - 1) * Y`
+/// - `Deterministic`: `k * X^2 * Y`
+/// - `Constant`: `k`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Kinetics {
+	/// Stochastic mass action, counting the distinct combinations of reactant molecules
+	#[default]
+	Stochastic,
+	/// Stochastic mass action without the `1 / r!` factor, as many SBML exports use
+	FallingFactorial,
+	/// Powers of the reactant counts, as in the deterministic rate law
+	Deterministic,
+	/// The rate constant alone, whatever the reactant counts. The transitions of a DTMC
+	/// use this, so that their weights are the probabilities written in the model.
+	Constant,
+}
+
+impl Kinetics {
+	/// All of the conventions, in the order they are listed to users
+	pub const ALL: [Kinetics; 4] = [
+		Kinetics::Stochastic,
+		Kinetics::FallingFactorial,
+		Kinetics::Deterministic,
+		Kinetics::Constant,
+	];
+
+	/// Reads a convention from its name (as printed by `Display`)
+	pub fn from_name(name: &str) -> Option<Self> {
+		Kinetics::ALL
+			.into_iter()
+			.find(|kinetics| kinetics.to_string() == name)
+	}
+
+	/// The factor that `count` molecules contribute to a reaction that
+	/// consumes `stoichiometry` of them
+	pub fn term(&self, count: VasValue, stoichiometry: VasValue) -> ProbabilityOrRate {
+		if stoichiometry <= 0 {
+			return 1.0;
+		}
+		if count < stoichiometry {
+			return 0.0;
+		}
+		match self {
+			Kinetics::Stochastic => (0..stoichiometry)
+				.map(|i| (count - i) as ProbabilityOrRate / (i + 1) as ProbabilityOrRate)
+				.product(),
+			Kinetics::FallingFactorial => (0..stoichiometry)
+				.map(|i| (count - i) as ProbabilityOrRate)
+				.product(),
+			Kinetics::Deterministic => (count as ProbabilityOrRate).powi(stoichiometry as i32),
+			Kinetics::Constant => 1.0,
+		}
+	}
+
+	/// The mass-action propensity of a reaction with rate constant `rate_const` and
+	/// reactant stoichiometry `reactants` in the state `state`
+	pub fn propensity(
+		&self,
+		rate_const: ProbabilityOrRate,
+		reactants: &VasStateVector,
+		state: &VasStateVector,
+	) -> ProbabilityOrRate {
+		reactants
+			.iter()
+			.zip(state.iter())
+			.fold(rate_const, |rate, (stoichiometry, count)| {
+				rate * self.term(*count, *stoichiometry)
+			})
+	}
+
+	/// The PRISM expression for the propensity, given the name of the rate constant
+	/// and the `(variable, stoichiometry)` pairs of the reactants
+	pub fn prism_expression(&self, rate_name: &str, reactants: &[(String, VasValue)]) -> String {
+		let mut factors = vec![rate_name.to_string()];
+		for (variable, stoichiometry) in reactants.iter().filter(|(_, s)| *s > 0) {
+			let falling = (0..*stoichiometry)
+				.map(|i| match i {
+					0 => variable.clone(),
+					_ => format!("({} - {})", variable, i),
+				})
+				.collect::<Vec<_>>();
+			match self {
+				Kinetics::Constant => {}
+				Kinetics::Deterministic => {
+					factors.extend((0..*stoichiometry).map(|_| variable.clone()))
+				}
+				Kinetics::FallingFactorial => factors.extend(falling),
+				Kinetics::Stochastic if *stoichiometry == 1 => factors.extend(falling),
+				Kinetics::Stochastic => factors.push(format!(
+					"({}) / {}",
+					falling.join(" * "),
+					(1..=*stoichiometry).product::<VasValue>()
+				)),
+			}
+		}
+		factors.join(" * ")
+	}
+}
+
+impl fmt::Display for Kinetics {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			Kinetics::Stochastic => "stochastic",
+			Kinetics::FallingFactorial => "falling-factorial",
+			Kinetics::Deterministic => "deterministic",
+			Kinetics::Constant => "constant",
+		};
+		write!(f, "{}", name)
+	}
+}
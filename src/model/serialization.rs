@@ -0,0 +1,663 @@
+/// Saving and loading models and other expensive results, so that they can be reused across
+/// runs and machines. Every file starts with a header naming the format, the schema version
+/// and the kind of data it holds, and is JSON (if its name ends in `.json`) or a compact
+/// binary encoding. The data goes through plain structs and enums rather than the types
+/// themselves, and enums such as the model type are kept by name, so that the schema only
+/// changes on purpose.
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{BufReader, BufWriter},
+	path::Path,
+};
+
+use nalgebra::DVector;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+	logging::messages::*,
+	model::{
+		model::{ModelType, ProbabilityOrRate},
+		propensity::Kinetics,
+		vas_model::{
+			AbstractVas, AllowedRelation, LinearGuard, PrismVasModel, PrismVasState,
+			PrismVasTransition, VasState, VasTarget, VasTransition, VasValue,
+		},
+	},
+	property::property::{Property, PropertyQuery, StateFormula},
+};
+
+/// The format name in the header of every file written here
+pub(crate) const FORMAT_NAME: &str = "practice";
+
+/// The version of the schema. It must increase whenever the data of a `Persistent` type
+/// changes, so that older files are rejected rather than misread.
+pub(crate) const SCHEMA_VERSION: u32 = 2;
+
+/// What a file holds, written before the data
+#[derive(Serialize, Deserialize)]
+struct Header {
+	format: String,
+	version: u32,
+	kind: String,
+}
+
+impl Header {
+	fn new(kind: &str) -> Self {
+		Self {
+			format: FORMAT_NAME.to_string(),
+			version: SCHEMA_VERSION,
+			kind: kind.to_string(),
+		}
+	}
+
+	/// Checks that a file holds data of the given kind in the current schema
+	fn check(&self, filename: &str, kind: &str) -> Result<(), String> {
+		if self.format != FORMAT_NAME {
+			return Err(format!("{} was not written by this tool", filename));
+		}
+		if self.kind != kind {
+			return Err(format!(
+				"{} holds a {}, not a {}",
+				filename, self.kind, kind
+			));
+		}
+		if self.version != SCHEMA_VERSION {
+			return Err(format!(
+				"{} uses schema version {}, but this version of the tool reads version {}",
+				filename, self.version, SCHEMA_VERSION
+			));
+		}
+		Ok(())
+	}
+}
+
+/// The layout of a JSON file: the header fields followed by the data
+#[derive(Serialize)]
+struct JsonFile<'a, T> {
+	#[serde(flatten)]
+	header: Header,
+	data: &'a T,
+}
+
+/// Whether a file is (or should be) JSON rather than binary
+fn is_json(filename: &str) -> bool {
+	Path::new(filename)
+		.extension()
+		.is_some_and(|extension| extension == "json")
+}
+
+/// Whether a cached result exists and is newer than the file it was computed from
+pub(crate) fn is_fresh(cache: &str, source: &str) -> bool {
+	let modified = |filename: &str| std::fs::metadata(filename).and_then(|m| m.modified()).ok();
+	match (modified(cache), modified(source)) {
+		(Some(cache), Some(source)) => cache >= source,
+		_ => false,
+	}
+}
+
+/// A type that can be saved to a file and loaded back, through a plain data type with a
+/// stable schema
+pub(crate) trait Persistent: Sized {
+	/// The kind of data in the header, e.g., `abstract-vas`
+	const KIND: &'static str;
+	/// The plain data that is written to the file
+	type Data: Serialize + DeserializeOwned;
+
+	/// Converts into the plain data, or an error if some of it cannot be saved
+	fn to_data(&self) -> Result<Self::Data, String>;
+
+	/// Converts back from the plain data, or an error if it is inconsistent
+	fn from_data(data: Self::Data) -> Result<Self, String>;
+
+	/// Saves to a file, as JSON if its name ends in `.json` and in binary otherwise
+	fn save(&self, filename: &str) -> Result<(), String> {
+		let data = self.to_data()?;
+		let file =
+			File::create(filename).map_err(|e| format!("Could not create {}: {}", filename, e))?;
+		let mut writer = BufWriter::new(file);
+		let written = if is_json(filename) {
+			let contents = JsonFile {
+				header: Header::new(Self::KIND),
+				data: &data,
+			};
+			serde_json::to_writer_pretty(&mut writer, &contents).map_err(|e| e.to_string())
+		} else {
+			bincode::serialize_into(&mut writer, &Header::new(Self::KIND))
+				.and_then(|_| bincode::serialize_into(&mut writer, &data))
+				.map_err(|e| e.to_string())
+		};
+		written.map_err(|e| format!("Could not write {}: {}", filename, e))?;
+		debug_message!("Saved {} to {}", Self::KIND, filename);
+		Ok(())
+	}
+
+	/// Loads from a file written by `save`
+	fn load(filename: &str) -> Result<Self, String> {
+		let file =
+			File::open(filename).map_err(|e| format!("Could not open {}: {}", filename, e))?;
+		let mut reader = BufReader::new(file);
+		let invalid = |e: String| format!("Could not read {}: {}", filename, e);
+		let data = if is_json(filename) {
+			let mut contents: serde_json::Value =
+				serde_json::from_reader(reader).map_err(|e| invalid(e.to_string()))?;
+			let header: Header =
+				serde_json::from_value(contents.clone()).map_err(|e| invalid(e.to_string()))?;
+			header.check(filename, Self::KIND)?;
+			serde_json::from_value(contents["data"].take()).map_err(|e| invalid(e.to_string()))?
+		} else {
+			let header: Header =
+				bincode::deserialize_from(&mut reader).map_err(|e| invalid(e.to_string()))?;
+			header.check(filename, Self::KIND)?;
+			bincode::deserialize_from(&mut reader).map_err(|e| invalid(e.to_string()))?
+		};
+		debug_message!("Loaded {} from {}", Self::KIND, filename);
+		Self::from_data(data)
+	}
+}
+
+/// Reads a model type saved by name, e.g., `ctmc`
+fn model_type_from_name(name: &str) -> Result<ModelType, String> {
+	[
+		ModelType::ContinuousTime,
+		ModelType::DiscreteTime,
+		ModelType::NondeterministicDiscreteTime,
+		ModelType::NondeterministicContinuousTime,
+	]
+	.into_iter()
+	.find(|m_type| m_type.to_string() == name)
+	.ok_or(format!("Unknown model type `{}`", name))
+}
+
+/// Reads a relation saved by its symbol, e.g., `>=`
+fn relation_from_symbol(symbol: &str) -> Result<AllowedRelation, String> {
+	AllowedRelation::from_symbol(symbol).ok_or(format!("Unknown relation `{}`", symbol))
+}
+
+/// The data of a `VasTarget`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum VasTargetData {
+	Relation {
+		index: usize,
+		relation: String,
+		value: VasValue,
+	},
+	And(Vec<VasTargetData>),
+	Or(Vec<VasTargetData>),
+}
+
+impl VasTargetData {
+	pub(crate) fn new(target: &VasTarget) -> Self {
+		match target {
+			VasTarget::Relation(index, relation, value) => VasTargetData::Relation {
+				index: *index,
+				relation: relation.to_string(),
+				value: *value,
+			},
+			VasTarget::And(targets) => VasTargetData::And(targets.iter().map(Self::new).collect()),
+			VasTarget::Or(targets) => VasTargetData::Or(targets.iter().map(Self::new).collect()),
+		}
+	}
+
+	pub(crate) fn to_target(&self) -> Result<VasTarget, String> {
+		let all = |targets: &[VasTargetData]| {
+			targets
+				.iter()
+				.map(Self::to_target)
+				.collect::<Result<Vec<_>, _>>()
+		};
+		Ok(match self {
+			VasTargetData::Relation {
+				index,
+				relation,
+				value,
+			} => VasTarget::Relation(*index, relation_from_symbol(relation)?, *value),
+			VasTargetData::And(targets) => VasTarget::And(all(targets)?),
+			VasTargetData::Or(targets) => VasTarget::Or(all(targets)?),
+		})
+	}
+}
+
+/// The data of a `StateFormula`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum StateFormulaData {
+	True,
+	False,
+	Label(String),
+	Relation {
+		variable: String,
+		index: usize,
+		relation: String,
+		value: VasValue,
+	},
+	Not(Box<StateFormulaData>),
+	And(Vec<StateFormulaData>),
+	Or(Vec<StateFormulaData>),
+}
+
+impl StateFormulaData {
+	pub(crate) fn new(formula: &StateFormula) -> Self {
+		match formula {
+			StateFormula::True => StateFormulaData::True,
+			StateFormula::False => StateFormulaData::False,
+			StateFormula::StateLabel(label) => StateFormulaData::Label(label.clone()),
+			StateFormula::Relation {
+				variable,
+				index,
+				relation,
+				value,
+			} => StateFormulaData::Relation {
+				variable: variable.clone(),
+				index: *index,
+				relation: relation.to_string(),
+				value: *value,
+			},
+			StateFormula::Not(formula) => StateFormulaData::Not(Box::new(Self::new(formula))),
+			StateFormula::And(formulae) => {
+				StateFormulaData::And(formulae.iter().map(Self::new).collect())
+			}
+			StateFormula::Or(formulae) => {
+				StateFormulaData::Or(formulae.iter().map(Self::new).collect())
+			}
+		}
+	}
+
+	pub(crate) fn to_formula(&self) -> Result<StateFormula, String> {
+		let all = |formulae: &[StateFormulaData]| {
+			formulae
+				.iter()
+				.map(Self::to_formula)
+				.collect::<Result<Vec<_>, _>>()
+		};
+		Ok(match self {
+			StateFormulaData::True => StateFormula::True,
+			StateFormulaData::False => StateFormula::False,
+			StateFormulaData::Label(label) => StateFormula::StateLabel(label.clone()),
+			StateFormulaData::Relation {
+				variable,
+				index,
+				relation,
+				value,
+			} => StateFormula::Relation {
+				variable: variable.clone(),
+				index: *index,
+				relation: relation_from_symbol(relation)?,
+				value: *value,
+			},
+			StateFormulaData::Not(formula) => StateFormula::Not(Box::new(formula.to_formula()?)),
+			StateFormulaData::And(formulae) => StateFormula::And(all(formulae)?),
+			StateFormulaData::Or(formulae) => StateFormula::Or(all(formulae)?),
+		})
+	}
+}
+
+/// The data of a `Property`, i.e., a path formula
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum PropertyData {
+	Globally(StateFormulaData),
+	Finally(StateFormulaData, Option<f64>),
+	Until(StateFormulaData, StateFormulaData, Option<f64>),
+}
+
+impl PropertyData {
+	pub(crate) fn new(property: &Property) -> Self {
+		match property {
+			Property::Globally(formula) => PropertyData::Globally(StateFormulaData::new(formula)),
+			Property::Finally(formula, bound) => {
+				PropertyData::Finally(StateFormulaData::new(formula), *bound)
+			}
+			Property::Until(safe, goal, bound) => PropertyData::Until(
+				StateFormulaData::new(safe),
+				StateFormulaData::new(goal),
+				*bound,
+			),
+		}
+	}
+
+	pub(crate) fn to_property(&self) -> Result<Property, String> {
+		Ok(match self {
+			PropertyData::Globally(formula) => Property::Globally(formula.to_formula()?),
+			PropertyData::Finally(formula, bound) => {
+				Property::Finally(formula.to_formula()?, *bound)
+			}
+			PropertyData::Until(safe, goal, bound) => {
+				Property::Until(safe.to_formula()?, goal.to_formula()?, *bound)
+			}
+		})
+	}
+}
+
+/// The data of a `PropertyQuery`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum PropertyQueryData {
+	Probability(PropertyData),
+	MaxProbability(PropertyData),
+	MinProbability(PropertyData),
+	SteadyState(StateFormulaData),
+}
+
+impl PropertyQueryData {
+	pub(crate) fn new(query: &PropertyQuery) -> Self {
+		match query {
+			PropertyQuery::Probability(property) => {
+				PropertyQueryData::Probability(PropertyData::new(property))
+			}
+			PropertyQuery::MaxProbability(property) => {
+				PropertyQueryData::MaxProbability(PropertyData::new(property))
+			}
+			PropertyQuery::MinProbability(property) => {
+				PropertyQueryData::MinProbability(PropertyData::new(property))
+			}
+			PropertyQuery::SteadyState(formula) => {
+				PropertyQueryData::SteadyState(StateFormulaData::new(formula))
+			}
+		}
+	}
+
+	pub(crate) fn to_query(&self) -> Result<PropertyQuery, String> {
+		Ok(match self {
+			PropertyQueryData::Probability(property) => {
+				PropertyQuery::Probability(property.to_property()?)
+			}
+			PropertyQueryData::MaxProbability(property) => {
+				PropertyQuery::MaxProbability(property.to_property()?)
+			}
+			PropertyQueryData::MinProbability(property) => {
+				PropertyQuery::MinProbability(property.to_property()?)
+			}
+			PropertyQueryData::SteadyState(formula) => {
+				PropertyQuery::SteadyState(formula.to_formula()?)
+			}
+		})
+	}
+}
+
+/// The data of a `VasTransition`. A rate law is compiled code, so only whether there was
+/// one is kept.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct VasTransitionData {
+	pub(crate) id: usize,
+	pub(crate) name: String,
+	pub(crate) update: Vec<VasValue>,
+	pub(crate) enabled_bounds: Vec<VasValue>,
+	/// The weights and bound of each guard
+	pub(crate) guards: Vec<(Vec<VasValue>, VasValue)>,
+	pub(crate) rate_const: ProbabilityOrRate,
+	/// The name of the kinetics, e.g., `stochastic`
+	pub(crate) kinetics: String,
+	pub(crate) action: Option<String>,
+	pub(crate) rate_law: bool,
+}
+
+impl VasTransitionData {
+	/// The transition this data describes, without its rate law if it had one
+	pub(crate) fn to_transition(&self) -> Result<VasTransition, String> {
+		let kinetics = Kinetics::from_name(&self.kinetics)
+			.ok_or(format!("Unknown kinetics `{}`", self.kinetics))?;
+		Ok(VasTransition {
+			transition_id: self.id,
+			transition_name: self.name.clone(),
+			update_vector: DVector::from_vec(self.update.clone()),
+			enabled_bounds: DVector::from_vec(self.enabled_bounds.clone()),
+			guards: self
+				.guards
+				.iter()
+				.map(|(weights, bound)| LinearGuard {
+					weights: DVector::from_vec(weights.clone()),
+					bound: *bound,
+				})
+				.collect(),
+			rate_const: self.rate_const,
+			kinetics,
+			action: self.action.clone(),
+			custom_rate_fn: None,
+		})
+	}
+}
+
+impl Persistent for VasTransition {
+	const KIND: &'static str = "vas-transition";
+	type Data = VasTransitionData;
+
+	fn to_data(&self) -> Result<Self::Data, String> {
+		Ok(VasTransitionData {
+			id: self.transition_id,
+			name: self.transition_name.clone(),
+			update: self.update_vector.iter().copied().collect(),
+			enabled_bounds: self.enabled_bounds.iter().copied().collect(),
+			guards: self
+				.guards
+				.iter()
+				.map(|guard| (guard.weights.iter().copied().collect(), guard.bound))
+				.collect(),
+			rate_const: self.rate_const,
+			kinetics: self.kinetics.to_string(),
+			action: self.action.clone(),
+			rate_law: self.custom_rate_fn.is_some(),
+		})
+	}
+
+	fn from_data(data: Self::Data) -> Result<Self, String> {
+		if data.rate_law {
+			return Err(format!(
+				"Transition {} has a rate law, which is not saved. Load it from the model file instead.",
+				data.name
+			));
+		}
+		if data.update.len() != data.enabled_bounds.len() {
+			return Err(format!(
+				"Transition {} has {} updates but {} bounds",
+				data.name,
+				data.update.len(),
+				data.enabled_bounds.len()
+			));
+		}
+		data.to_transition()
+	}
+}
+
+/// The data of an `AbstractVas`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct AbstractVasData {
+	pub(crate) variable_names: Vec<String>,
+	pub(crate) initial_states: Vec<Vec<VasValue>>,
+	pub(crate) initial_probabilities: Vec<ProbabilityOrRate>,
+	pub(crate) transitions: Vec<VasTransitionData>,
+	/// The PRISM keyword of the model type, e.g., `ctmc`
+	pub(crate) m_type: String,
+	pub(crate) target: VasTargetData,
+	pub(crate) properties: Vec<PropertyQueryData>,
+	pub(crate) labels: HashMap<String, StateFormulaData>,
+}
+
+impl Persistent for AbstractVas {
+	const KIND: &'static str = "abstract-vas";
+	type Data = AbstractVasData;
+
+	fn to_data(&self) -> Result<Self::Data, String> {
+		let transitions = self
+			.transitions
+			.iter()
+			.map(|transition| transition.to_data())
+			.collect::<Result<Vec<_>, _>>()?;
+		if let Some(transition) = transitions.iter().find(|transition| transition.rate_law) {
+			return Err(format!(
+				"Transition {} has a rate law, which cannot be saved",
+				transition.name
+			));
+		}
+		Ok(AbstractVasData {
+			variable_names: self.variable_names.to_vec(),
+			initial_states: self
+				.initial_states
+				.iter()
+				.map(|state| state.vector.iter().copied().collect())
+				.collect(),
+			initial_probabilities: self.initial_probabilities.clone(),
+			transitions,
+			m_type: self.m_type.to_string(),
+			target: VasTargetData::new(&self.target),
+			properties: self.properties.iter().map(PropertyQueryData::new).collect(),
+			labels: self
+				.labels
+				.iter()
+				.map(|(name, formula)| (name.clone(), StateFormulaData::new(formula)))
+				.collect(),
+		})
+	}
+
+	fn from_data(data: Self::Data) -> Result<Self, String> {
+		let variable_count = data.variable_names.len();
+		if let Some(state) = data
+			.initial_states
+			.iter()
+			.find(|state| state.len() != variable_count)
+		{
+			return Err(format!(
+				"An initial state has {} values but there are {} variables",
+				state.len(),
+				variable_count
+			));
+		}
+		let transitions = data
+			.transitions
+			.into_iter()
+			.map(VasTransition::from_data)
+			.collect::<Result<Vec<_>, _>>()?;
+		if let Some(transition) = transitions
+			.iter()
+			.find(|transition| transition.update_vector.len() != variable_count)
+		{
+			return Err(format!(
+				"Transition {} has {} updates but there are {} variables",
+				transition.transition_name,
+				transition.update_vector.len(),
+				variable_count
+			));
+		}
+		let initial_states = data
+			.initial_states
+			.into_iter()
+			.map(|state| VasState::new(DVector::from_vec(state)))
+			.collect();
+		let mut model = AbstractVas::new(
+			data.variable_names.into_boxed_slice(),
+			initial_states,
+			transitions,
+			data.target.to_target()?,
+			model_type_from_name(&data.m_type)?,
+		);
+		model.set_initial_probabilities(data.initial_probabilities)?;
+		model.properties = data
+			.properties
+			.iter()
+			.map(PropertyQueryData::to_query)
+			.collect::<Result<_, _>>()?;
+		model.labels = data
+			.labels
+			.iter()
+			.map(|(name, formula)| Ok((name.clone(), formula.to_formula()?)))
+			.collect::<Result<_, String>>()?;
+		Ok(model)
+	}
+}
+
+/// The data of a state of a `PrismVasModel`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PrismVasStateData {
+	pub(crate) id: usize,
+	pub(crate) vector: Vec<VasValue>,
+	pub(crate) label: Option<String>,
+	pub(crate) total_outgoing_rate: ProbabilityOrRate,
+}
+
+/// The data of a transition of a `PrismVasModel`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PrismVasTransitionData {
+	pub(crate) id: usize,
+	pub(crate) from: usize,
+	pub(crate) to: usize,
+	pub(crate) rate: ProbabilityOrRate,
+}
+
+/// The data of a `PrismVasModel`, i.e., an explored state space
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PrismVasModelData {
+	pub(crate) variable_names: Vec<String>,
+	/// The PRISM keyword of the model type, e.g., `ctmc`
+	pub(crate) m_type: String,
+	pub(crate) states: Vec<PrismVasStateData>,
+	pub(crate) transitions: Vec<PrismVasTransitionData>,
+	pub(crate) reserved_indices: Vec<usize>,
+	pub(crate) next_index: usize,
+}
+
+impl Persistent for PrismVasModel {
+	const KIND: &'static str = "explicit-vas";
+	type Data = PrismVasModelData;
+
+	fn to_data(&self) -> Result<Self::Data, String> {
+		Ok(PrismVasModelData {
+			variable_names: self.variable_names.clone(),
+			m_type: self.m_type.to_string(),
+			states: self
+				.states
+				.iter()
+				.map(|state| PrismVasStateData {
+					id: state.state_id,
+					vector: state.vector.iter().copied().collect(),
+					label: state.label.clone(),
+					total_outgoing_rate: state.total_outgoing_rate,
+				})
+				.collect(),
+			transitions: self
+				.transitions
+				.iter()
+				.map(|transition| PrismVasTransitionData {
+					id: transition.transition_id,
+					from: transition.from_state,
+					to: transition.to_state,
+					rate: transition.rate,
+				})
+				.collect(),
+			reserved_indices: self.reserved_indices.iter().copied().collect(),
+			next_index: self.next_index,
+		})
+	}
+
+	fn from_data(data: Self::Data) -> Result<Self, String> {
+		let mut model = PrismVasModel {
+			variable_names: data.variable_names,
+			m_type: model_type_from_name(&data.m_type)?,
+			..PrismVasModel::default()
+		};
+		// Adding the states rebuilds the trie, and adding the transitions their lookup
+		for state in data.states {
+			if state.vector.len() != model.variable_names.len() {
+				return Err(format!(
+					"State {} has {} values but there are {} variables",
+					state.id,
+					state.vector.len(),
+					model.variable_names.len()
+				));
+			}
+			model.add_state(PrismVasState {
+				state_id: state.id,
+				vector: DVector::from_vec(state.vector),
+				label: state.label,
+				total_outgoing_rate: state.total_outgoing_rate,
+			});
+		}
+		for transition in data.transitions {
+			model.add_transition(PrismVasTransition {
+				transition_id: transition.id,
+				from_state: transition.from,
+				to_state: transition.to,
+				rate: transition.rate,
+			});
+		}
+		model.reserved_indices = data.reserved_indices.into_iter().collect();
+		model.next_index = model.next_index.max(data.next_index);
+		Ok(model)
+	}
+}
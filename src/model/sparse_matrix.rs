@@ -0,0 +1,154 @@
+use crate::model::model::ProbabilityOrRate;
+
+/// A sparse matrix in compressed sparse row (CSR) form. Row `i` holds the entries
+/// `values[row_offsets[i]..row_offsets[i + 1]]`, in the columns with the same indices
+/// in `column_indices`, sorted by column.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct SparseMatrix {
+	rows: usize,
+	columns: usize,
+	row_offsets: Vec<usize>,
+	column_indices: Vec<usize>,
+	values: Vec<ProbabilityOrRate>,
+}
+
+impl SparseMatrix {
+	/// Builds a matrix from `(row, column, value)` triplets (COO form). Entries with
+	/// the same row and column are summed, and entries that sum to zero are dropped.
+	pub fn from_triplets(
+		rows: usize,
+		columns: usize,
+		triplets: impl IntoIterator<Item = (usize, usize, ProbabilityOrRate)>,
+	) -> Self {
+		let mut triplets: Vec<_> = triplets.into_iter().collect();
+		triplets.sort_by_key(|&(row, column, _)| (row, column));
+		let mut row_offsets = vec![0; rows + 1];
+		let mut column_indices = Vec::with_capacity(triplets.len());
+		let mut values: Vec<ProbabilityOrRate> = Vec::with_capacity(triplets.len());
+		let mut last = None;
+		for (row, column, value) in triplets {
+			assert!(
+				row < rows && column < columns,
+				"Entry ({}, {}) is outside of a {}x{} matrix",
+				row,
+				column,
+				rows,
+				columns
+			);
+			if last == Some((row, column)) {
+				*values.last_mut().unwrap() += value;
+			} else {
+				column_indices.push(column);
+				values.push(value);
+				row_offsets[row + 1] += 1;
+				last = Some((row, column));
+			}
+		}
+		for row in 0..rows {
+			row_offsets[row + 1] += row_offsets[row];
+		}
+		let mut matrix = Self {
+			rows,
+			columns,
+			row_offsets,
+			column_indices,
+			values,
+		};
+		matrix.drop_zeros();
+		matrix
+	}
+
+	/// Removes the entries that are exactly zero
+	fn drop_zeros(&mut self) {
+		if self.values.iter().all(|value| *value != 0.0) {
+			return;
+		}
+		let mut kept = 0;
+		let mut start = 0;
+		for row in 0..self.rows {
+			let end = self.row_offsets[row + 1];
+			for i in start..end {
+				if self.values[i] != 0.0 {
+					self.column_indices[kept] = self.column_indices[i];
+					self.values[kept] = self.values[i];
+					kept += 1;
+				}
+			}
+			start = end;
+			self.row_offsets[row + 1] = kept;
+		}
+		self.column_indices.truncate(kept);
+		self.values.truncate(kept);
+	}
+
+	/// The number of rows
+	pub fn rows(&self) -> usize {
+		self.rows
+	}
+
+	/// The number of columns
+	pub fn columns(&self) -> usize {
+		self.columns
+	}
+
+	/// The number of stored (nonzero) entries
+	pub fn nonzeros(&self) -> usize {
+		self.values.len()
+	}
+
+	/// The `(column, value)` entries of a row
+	pub fn row(&self, row: usize) -> impl Iterator<Item = (usize, ProbabilityOrRate)> + '_ {
+		let range = self.row_offsets[row]..self.row_offsets[row + 1];
+		self.column_indices[range.clone()]
+			.iter()
+			.copied()
+			.zip(self.values[range].iter().copied())
+	}
+
+	/// The entry at `(row, column)`, which is zero if it is not stored
+	pub fn get(&self, row: usize, column: usize) -> ProbabilityOrRate {
+		let range = self.row_offsets[row]..self.row_offsets[row + 1];
+		match self.column_indices[range.clone()].binary_search(&column) {
+			Ok(i) => self.values[range.start + i],
+			Err(_) => 0.0,
+		}
+	}
+
+	/// The sum of each row, e.g., the exit rates of a rate matrix
+	pub fn row_sums(&self) -> Vec<ProbabilityOrRate> {
+		(0..self.rows).map(|row| self.row(row).map(|(_, value)| value).sum()).collect()
+	}
+
+	/// The product `A x` of this matrix with a column vector
+	pub fn multiply(&self, x: &[ProbabilityOrRate]) -> Vec<ProbabilityOrRate> {
+		assert_eq!(x.len(), self.columns);
+		(0..self.rows)
+			.map(|row| self.row(row).map(|(column, value)| value * x[column]).sum())
+			.collect()
+	}
+
+	/// The product `x^T A` of a row vector with this matrix, e.g., one step of a
+	/// distribution over the states of a DTMC
+	pub fn left_multiply(&self, x: &[ProbabilityOrRate]) -> Vec<ProbabilityOrRate> {
+		assert_eq!(x.len(), self.rows);
+		let mut result = vec![0.0; self.columns];
+		for (row, weight) in x.iter().enumerate() {
+			if *weight == 0.0 {
+				continue;
+			}
+			for (column, value) in self.row(row) {
+				result[column] += weight * value;
+			}
+		}
+		result
+	}
+
+	/// The transpose of this matrix
+	pub fn transpose(&self) -> Self {
+		Self::from_triplets(
+			self.columns,
+			self.rows,
+			(0..self.rows).flat_map(|row| self.row(row).map(move |(column, value)| (column, row, value))),
+		)
+	}
+}
@@ -0,0 +1,358 @@
+use std::collections::VecDeque;
+
+use nalgebra::DVector;
+
+use crate::{
+	checker::result::ModelCheckingResult,
+	logging::messages::*,
+	model::{
+		model::{AbstractModel, ModelType, ProbabilityOrRate, Transition},
+		sparse_matrix::SparseMatrix,
+		vas_model::{AbstractVas, VasStateVector},
+		vas_trie::VasTrieNode,
+	},
+	property::property::{Property, PropertyQuery, StateFormula},
+	solver::iterative::SolverOptions,
+};
+
+/// The index of the absorbing state that unexplored states lead to
+pub(crate) const SINK_INDEX: usize = 0;
+
+/// The action of the single choice of a state that was not explored
+const TRUNCATED_ACTION: &str = "truncated";
+
+/// One choice of a state in an explicit MDP: the action that a scheduler picks and the
+/// probabilities (or rates, in a CTMDP) of the successors it leads to
+#[derive(Clone, Debug)]
+pub(crate) struct MdpChoice {
+	pub(crate) action: String,
+	pub(crate) entries: Vec<(usize, ProbabilityOrRate)>,
+}
+
+impl MdpChoice {
+	/// The probability of reaching the goal after taking this choice, given the values of
+	/// the successors. The entries are normalized, so the choices of a CTMDP act as in its
+	/// embedded MDP, and a choice without any outgoing weight stays in `state`.
+	fn value(&self, state: usize, values: &[ProbabilityOrRate]) -> ProbabilityOrRate {
+		let total: ProbabilityOrRate = self.entries.iter().map(|(_, weight)| weight).sum();
+		if total <= 0.0 {
+			return values[state];
+		}
+		self.entries
+			.iter()
+			.map(|(to, weight)| weight / total * values[*to])
+			.sum()
+	}
+}
+
+/// Whether a scheduler minimizes or maximizes the probability
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Objective {
+	Minimize,
+	Maximize,
+}
+
+impl Objective {
+	/// Whether `value` is better than `best` for this objective
+	fn improves(&self, value: ProbabilityOrRate, best: ProbabilityOrRate) -> bool {
+		match self {
+			Objective::Minimize => value < best,
+			Objective::Maximize => value > best,
+		}
+	}
+}
+
+/// The result of value iteration: the optimal probability from each state and the choice
+/// (an index into the choices of the state) of a scheduler that achieves it
+#[derive(Clone, Debug)]
+pub(crate) struct ReachabilityResult {
+	pub(crate) probabilities: Vec<ProbabilityOrRate>,
+	pub(crate) scheduler: Vec<Option<usize>>,
+	pub(crate) iterations: usize,
+	pub(crate) converged: bool,
+}
+
+/// The data for an explicit MDP (or CTMDP) of a VAS, the nondeterministic counterpart of
+/// `PrismVasModel`. Each state has a list of choices; index 0 is an absorbing sink that
+/// the states left unexplored lead to.
+pub(crate) struct PrismVasMdp {
+	pub(crate) variable_names: Vec<String>,
+	pub(crate) m_type: ModelType,
+	pub(crate) states: Vec<VasStateVector>,
+	pub(crate) choices: Vec<Vec<MdpChoice>>,
+	pub(crate) initial_indices: Vec<usize>,
+	pub(crate) state_trie: VasTrieNode,
+}
+
+impl PrismVasMdp {
+	/// Creates an MDP with only the sink state, for the variables and type of a model
+	pub fn new(abstract_model: &AbstractVas) -> Self {
+		let variable_count = abstract_model.variable_names.len();
+		Self {
+			variable_names: abstract_model.variable_names.clone().into_vec(),
+			m_type: abstract_model.m_type,
+			states: vec![DVector::from_element(variable_count, -1)],
+			choices: vec![vec![MdpChoice {
+				action: "sink".to_string(),
+				entries: vec![(SINK_INDEX, 1.0)],
+			}]],
+			initial_indices: Vec::new(),
+			state_trie: VasTrieNode::new(),
+		}
+	}
+
+	/// Explores the states of a model reachable from its initial states, breadth first.
+	/// Once `state_limit` states are explored, the states that are left lead to the sink.
+	pub fn from_abstract_model(abstract_model: &AbstractVas, state_limit: usize) -> Self {
+		let mut mdp = Self::new(abstract_model);
+		let mut queue = VecDeque::new();
+		for state in abstract_model.initial_states.iter() {
+			let (index, added) = mdp.find_or_add_index(&state.vector);
+			mdp.initial_indices.push(index);
+			if added {
+				queue.push_back(index);
+			}
+		}
+		while let Some(index) = queue.pop_front() {
+			if mdp.states.len() > state_limit {
+				mdp.add_choice(index, TRUNCATED_ACTION, vec![(SINK_INDEX, 1.0)]);
+				continue;
+			}
+			let state = abstract_model.state(mdp.states[index].clone());
+			for choice in abstract_model.choices(&state) {
+				// A Markov chain has a single choice per state, which has no action
+				let action = if abstract_model.m_type.is_nondeterministic() {
+					choice[0]
+						.action()
+						.unwrap_or(choice[0].transition_name.as_str())
+						.to_string()
+				} else {
+					String::new()
+				};
+				let mut entries = Vec::with_capacity(choice.len());
+				for (rate, next_state) in choice.iter().filter_map(|t| t.next(&state)) {
+					let (next_index, added) = mdp.find_or_add_index(&next_state.vector);
+					if added {
+						queue.push_back(next_index);
+					}
+					entries.push((next_index, rate));
+				}
+				mdp.add_choice(index, &action, entries);
+			}
+		}
+		debug_message!(
+			"Explored {} states and {} choices",
+			mdp.state_count(),
+			mdp.choice_count()
+		);
+		mdp
+	}
+
+	/// The index of a state vector, adding the state if it is new. Also returns whether
+	/// or not the state was added.
+	pub fn find_or_add_index(&mut self, vector: &VasStateVector) -> (usize, bool) {
+		let index = self.states.len();
+		match self.state_trie.insert_if_not_exists(vector, index) {
+			Some(existing) => (existing, false),
+			None => {
+				self.states.push(vector.clone());
+				self.choices.push(Vec::new());
+				(index, true)
+			}
+		}
+	}
+
+	/// Adds a choice to a state
+	pub fn add_choice(
+		&mut self,
+		state: usize,
+		action: &str,
+		entries: Vec<(usize, ProbabilityOrRate)>,
+	) {
+		self.choices[state].push(MdpChoice {
+			action: action.to_string(),
+			entries,
+		});
+	}
+
+	/// The number of states, including the sink
+	pub fn state_count(&self) -> usize {
+		self.states.len()
+	}
+
+	/// The number of choices over all states
+	pub fn choice_count(&self) -> usize {
+		self.choices.iter().map(|choices| choices.len()).sum()
+	}
+
+	/// Converts this model into a sparse matrix with one row per choice, as in PRISM's
+	/// explicit engine. The choices of state `i` are the rows `offsets[i]..offsets[i + 1]`.
+	pub fn to_matrix(&self) -> (Vec<usize>, SparseMatrix) {
+		let mut offsets = Vec::with_capacity(self.states.len() + 1);
+		offsets.push(0);
+		let mut triplets = Vec::new();
+		let mut row = 0;
+		for choices in self.choices.iter() {
+			for choice in choices.iter() {
+				triplets.extend(
+					choice
+						.entries
+						.iter()
+						.map(|(to, weight)| (row, *to, *weight)),
+				);
+				row += 1;
+			}
+			offsets.push(row);
+		}
+		(
+			offsets,
+			SparseMatrix::from_triplets(row, self.states.len(), triplets),
+		)
+	}
+
+	/// The states of this model that satisfy a state formula over `abstract_model`
+	pub fn satisfying(&self, abstract_model: &AbstractVas, formula: &StateFormula) -> Vec<bool> {
+		self.states
+			.iter()
+			.enumerate()
+			.map(|(index, vector)| {
+				index != SINK_INDEX
+					&& abstract_model.satisfies(formula, &abstract_model.state(vector.clone()))
+			})
+			.collect()
+	}
+
+	/// Whether some states were left unexplored and lead to the sink
+	pub fn is_truncated(&self) -> bool {
+		self.choices
+			.iter()
+			.flatten()
+			.any(|choice| choice.action == TRUNCATED_ACTION)
+	}
+
+	/// The minimum or maximum probability, over all schedulers, of reaching a `goal`
+	/// state through `safe` states only, by value iteration. The values start at zero and
+	/// increase towards the probabilities, stopping once every value has converged by
+	/// `options`. The sink is whatever `goal` makes it, e.g., failure for a lower bound.
+	pub fn reachability(
+		&self,
+		safe: &[bool],
+		goal: &[bool],
+		objective: Objective,
+		options: &SolverOptions,
+	) -> ReachabilityResult {
+		let mut values: Vec<ProbabilityOrRate> = goal
+			.iter()
+			.map(|&reached| if reached { 1.0 } else { 0.0 })
+			.collect();
+		let mut scheduler = vec![None; self.states.len()];
+		let mut iterations = 0;
+		let mut converged = false;
+		while iterations < options.max_iterations && !converged {
+			iterations += 1;
+			let mut next_values = values.clone();
+			converged = true;
+			for state in 0..self.states.len() {
+				if goal[state] || !safe[state] {
+					continue;
+				}
+				let mut best: Option<(usize, ProbabilityOrRate)> = None;
+				for (index, choice) in self.choices[state].iter().enumerate() {
+					let value = choice.value(state, &values);
+					if best.map_or(true, |(_, best)| objective.improves(value, best)) {
+						best = Some((index, value));
+					}
+				}
+				if let Some((index, value)) = best {
+					next_values[state] = value;
+					scheduler[state] = Some(index);
+					converged &= options.converged(values[state], value);
+				}
+			}
+			values = next_values;
+		}
+		if !converged {
+			warning!(
+				"Value iteration did not converge within {} iterations",
+				options.max_iterations
+			);
+		}
+		ReachabilityResult {
+			probabilities: values,
+			scheduler,
+			iterations,
+			converged,
+		}
+	}
+
+	/// Checks an unbounded `Pmin=? [...]` or `Pmax=? [...]` query of `abstract_model`
+	/// (`F φ`, `ψ U φ` or `G φ`) on this model, and `P=? [...]` if it is a Markov chain.
+	/// If some states were left unexplored, the sink as failure gives a lower bound and
+	/// the sink as success an upper bound.
+	pub fn check(
+		&self,
+		abstract_model: &AbstractVas,
+		query: &PropertyQuery,
+		options: &SolverOptions,
+	) -> Result<ModelCheckingResult, String> {
+		let (objective, property) = match query {
+			PropertyQuery::MinProbability(property) => (Objective::Minimize, property),
+			PropertyQuery::MaxProbability(property) => (Objective::Maximize, property),
+			PropertyQuery::Probability(property) if !self.m_type.is_nondeterministic() => {
+				(Objective::Maximize, property)
+			}
+			_ => return Err("Only Pmin=? and Pmax=? queries can be checked on an MDP".to_string()),
+		};
+		// `G φ` holds exactly when `F !φ` does not, under the opposite objective
+		let (objective, safe, goal, complement) = match property {
+			Property::Finally(goal, None) => (
+				objective,
+				vec![true; self.states.len()],
+				self.satisfying(abstract_model, goal),
+				false,
+			),
+			Property::Until(safe, goal, None) => (
+				objective,
+				self.satisfying(abstract_model, safe),
+				self.satisfying(abstract_model, goal),
+				false,
+			),
+			Property::Globally(invariant) => (
+				match objective {
+					Objective::Minimize => Objective::Maximize,
+					Objective::Maximize => Objective::Minimize,
+				},
+				vec![true; self.states.len()],
+				self.satisfying(
+					abstract_model,
+					&StateFormula::Not(Box::new(invariant.clone())),
+				),
+				true,
+			),
+			Property::Finally(_, Some(_)) | Property::Until(_, _, Some(_)) => {
+				return Err("Time-bounded properties cannot be checked on an MDP".to_string())
+			}
+		};
+		let initial = |result: &ReachabilityResult| -> ProbabilityOrRate {
+			self.initial_indices
+				.iter()
+				.zip(abstract_model.initial_probabilities.iter())
+				.map(|(index, probability)| probability * result.probabilities[*index])
+				.sum()
+		};
+		let lower = initial(&self.reachability(&safe, &goal, objective, options));
+		let upper = if self.is_truncated() {
+			let mut goal = goal;
+			goal[SINK_INDEX] = true;
+			initial(&self.reachability(&safe, &goal, objective, options))
+		} else {
+			lower
+		};
+		let (lower, upper) = if complement {
+			(1.0 - upper, 1.0 - lower)
+		} else {
+			(lower, upper)
+		};
+		Ok(ModelCheckingResult::from_bounds(Some(lower), Some(upper)))
+	}
+}
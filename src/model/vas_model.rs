@@ -1,12 +1,20 @@
 use std::{
 	collections::{BTreeSet, HashMap},
 	fmt,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, OnceLock,
+	},
 };
 
 use crate::{
+	invariant::p_invariant::weighted_sum_string,
 	logging::messages::*,
-	model::{model::ExplicitModel, vas_trie::VasTrieNode},
-	parser::vas_file_reader,
+	model::{
+		model::ExplicitModel, propensity::Kinetics, sparse_matrix::SparseMatrix,
+		vas_trie::VasTrieNode,
+	},
+	parser::{prism_file_reader, prop_file_reader, vas_file_reader},
 	property::property,
 	validator::vas_validator::validate_vas,
 };
@@ -25,12 +33,31 @@ struct StateLabel {
 	// Add fields as needed
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Maps the variable names of a model to their index in the state vector. It is
+/// shared by all of the states of a model, so naming a state is only a pointer copy.
+pub(crate) type VariableIndex = Arc<HashMap<String, usize>>;
+
+#[derive(Debug, Clone)]
 pub(crate) struct VasState {
 	// The state values
 	pub(crate) vector: VasStateVector,
 	// The labelset for this state
 	labels: Option<BTreeSet<property::StateFormula>>,
+	// The names of the variables, used to evaluate expressions on the state
+	names: Option<VariableIndex>,
+	// The values as evalexpr sees them, built on the first lookup by name
+	values: OnceLock<Box<[evalexpr::Value<evalexpr::DefaultNumericTypes>]>>,
+}
+
+/// Builds the name-to-index map for the variables of a model
+pub(crate) fn variable_index(variable_names: &[String]) -> VariableIndex {
+	Arc::new(
+		variable_names
+			.iter()
+			.enumerate()
+			.map(|(index, name)| (name.clone(), index))
+			.collect(),
+	)
 }
 
 impl VasState {
@@ -40,10 +67,42 @@ impl VasState {
 		Self {
 			vector,
 			labels: None,
+			names: None,
+			values: OnceLock::new(),
+		}
+	}
+
+	/// A state whose variables can be looked up by name
+	pub fn with_names(vector: VasStateVector, names: VariableIndex) -> Self {
+		Self {
+			names: Some(names),
+			..Self::new(vector)
+		}
+	}
+
+	/// The index of the variable `var_name` in the state vector
+	pub fn index_of(&self, var_name: &str) -> Option<usize> {
+		self.names.as_ref()?.get(var_name).copied()
+	}
+
+	/// A successor of this state, with the same labels and variable names
+	pub(crate) fn successor(&self, vector: VasStateVector) -> Self {
+		Self {
+			vector,
+			labels: self.labels.clone(),
+			names: self.names.clone(),
+			values: OnceLock::new(),
 		}
 	}
 }
 
+// The names and cached values are derived data, so only the values and labels count
+impl PartialEq for VasState {
+	fn eq(&self, other: &Self) -> bool {
+		self.vector == other.vector && self.labels == other.labels
+	}
+}
+
 impl property::Labeled for VasState {
 	type LabelType = property::StateFormula;
 
@@ -62,39 +121,61 @@ impl property::Labeled for VasState {
 	}
 }
 
+/// Variables of the state can be used in evalexpr expressions, e.g., the rate of a
+/// transition or a guard. Builtin functions (`math::pow`, `min`, ...) are available.
 impl evalexpr::Context for VasState {
 	type NumericTypes = evalexpr::DefaultNumericTypes; // Use the default numeric types provided by evalexpr
 
 	fn get_value(&self, identifier: &str) -> Option<&evalexpr::Value<Self::NumericTypes>> {
-		todo!()
+		let index = self.index_of(identifier)?;
+		let values = self.values.get_or_init(|| {
+			self.vector
+				.iter()
+				.map(|value| match i64::try_from(*value) {
+					Ok(value) => evalexpr::Value::Int(value),
+					Err(_) => evalexpr::Value::Float(*value as f64),
+				})
+				.collect()
+		});
+		values.get(index)
 	}
 
 	fn call_function(
 		&self,
 		identifier: &str,
-		argument: &evalexpr::Value<Self::NumericTypes>,
+		_argument: &evalexpr::Value<Self::NumericTypes>,
 	) -> evalexpr::error::EvalexprResultValue<Self::NumericTypes> {
-		todo!()
+		Err(evalexpr::EvalexprError::FunctionIdentifierNotFound(
+			identifier.to_string(),
+		))
 	}
 
 	fn are_builtin_functions_disabled(&self) -> bool {
-		todo!()
+		false
 	}
 
 	fn set_builtin_functions_disabled(
 		&mut self,
 		disabled: bool,
 	) -> evalexpr::EvalexprResult<(), Self::NumericTypes> {
-		todo!()
+		if disabled {
+			Err(evalexpr::EvalexprError::BuiltinFunctionsCannotBeDisabled)
+		} else {
+			Ok(())
+		}
 	}
-	// Implement required methods for evalexpr::Context
 }
 
 impl State for VasState {
 	type VariableValueType = u64;
 
+	/// The count of a species. Panics if the state has no variable `var_name`,
+	/// and negative counts (which a VAS should never reach) valuate to zero.
 	fn valuate(&self, var_name: &str) -> Self::VariableValueType {
-		todo!()
+		let index = self
+			.index_of(var_name)
+			.unwrap_or_else(|| panic!("State has no variable named {}", var_name));
+		u64::try_from(self.vector[index]).unwrap_or(0)
 	}
 }
 
@@ -106,8 +187,15 @@ pub(crate) struct VasTransition {
 	pub(crate) update_vector: VasStateVector,
 	// The minimum elementwise count for a transition to be enabled
 	pub(crate) enabled_bounds: VasStateVector,
+	// Linear conditions the state must also meet for the transition to be enabled,
+	// e.g., the bounds on species that a reduction eliminated
+	pub(crate) guards: Vec<LinearGuard>,
 	// The rate constant used in CRNs
 	pub(crate) rate_const: ProbabilityOrRate,
+	// The mass-action convention used with the rate constant
+	pub(crate) kinetics: Kinetics,
+	// The action this transition belongs to in an MDP or CTMDP
+	pub(crate) action: Option<String>,
 	// An override function to find the rate probability
 	// (when this is not provided defaults to the implemenation in
 	// rate_probability_at). The override must be stored in static
@@ -115,6 +203,25 @@ pub(crate) struct VasTransition {
 	pub(crate) custom_rate_fn: Option<CustomRateFn>,
 }
 
+/// A linear condition `Σ weights_i x_i >= bound` on the state
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LinearGuard {
+	pub(crate) weights: VasStateVector,
+	pub(crate) bound: VasValue,
+}
+
+impl LinearGuard {
+	/// Whether or not the state meets the condition
+	pub fn holds(&self, state: &VasStateVector) -> bool {
+		self.weights
+			.iter()
+			.zip(state.iter())
+			.map(|(weight, count)| weight * count)
+			.sum::<VasValue>()
+			>= self.bound
+	}
+}
+
 #[derive(Clone)]
 pub(crate) struct CustomRateFn(
 	std::sync::Arc<dyn Fn(&VasState) -> ProbabilityOrRate + Send + Sync + 'static>,
@@ -176,7 +283,10 @@ impl VasTransition {
 					.map(|(inc, dec)| *inc - *dec),
 			),
 			enabled_bounds: DVector::from_iterator(decrement.len(), decrement),
+			guards: Vec::new(),
 			rate_const,
+			kinetics: Kinetics::default(),
+			action: None,
 			custom_rate_fn: None,
 		}
 	}
@@ -200,6 +310,7 @@ impl VasTransition {
 				}
 			})
 			.is_some()
+			&& self.guards.iter().all(|guard| guard.holds(state))
 	}
 }
 
@@ -208,21 +319,14 @@ impl Transition for VasTransition {
 	type RateOrProbabilityType = ProbabilityOrRate;
 
 	/// Check to see if our state is above every bound in the enabled
-	/// bound. We use try-fold to short circuit and return false if we
-	/// encounter at least one value that does not satisfy.
+	/// bound, using the same check as `enabled_vector`.
 
 	fn enabled(&self, state: &VasState) -> bool {
-		self.enabled_bounds
-			.iter()
-			.zip(state.vector.iter())
-			.try_fold(true, |_, (bound, state_val)| {
-				if *state_val >= *bound {
-					Some(true)
-				} else {
-					None
-				}
-			})
-			.is_some()
+		self.enabled_vector(&state.vector)
+	}
+
+	fn action(&self) -> Option<&str> {
+		self.action.as_deref()
 	}
 
 	fn rate_probability_at(&self, state: &VasState) -> Option<ProbabilityOrRate> {
@@ -231,19 +335,9 @@ impl Transition for VasTransition {
 			let rate = if let Some(rate_fn) = &self.custom_rate_fn {
 				(rate_fn.0)(state)
 			} else {
-				// Compute the transition rate using the same equation that
-				// is used for the chemical kinetics equation
-				self.rate_const
-					* self
-						.update_vector
-						.zip_fold(&state.vector, 1.0, |acc, state_i, update_i| {
-							if (update_i as ProbabilityOrRate) <= 0.0 {
-								acc * (state_i as ProbabilityOrRate)
-									.powf(-(update_i as ProbabilityOrRate))
-							} else {
-								acc
-							}
-						})
+				// The reactant stoichiometry is the enabled bound
+				self.kinetics
+					.propensity(self.rate_const, &self.enabled_bounds, &state.vector)
 			};
 			Some(rate)
 		} else {
@@ -254,10 +348,7 @@ impl Transition for VasTransition {
 	fn next_state(&self, state: &VasState) -> Option<Self::StateType> {
 		let enabled = self.enabled(state);
 		if enabled {
-			Some(VasState {
-				vector: &state.vector + &self.update_vector.map(|val| val),
-				labels: state.labels.clone(),
-			})
+			Some(state.successor(&state.vector + &self.update_vector.map(|val| val)))
 		} else {
 			None
 		}
@@ -283,13 +374,156 @@ pub struct VasProperty {
 	pub(crate) target_value: VasValue,
 }
 
+/// The target of a VAS model: a boolean combination of relational atoms
+/// `variable <relation> value`, e.g., `S5 >= 50 & S4 <= 25`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VasTarget {
+	Relation(usize, AllowedRelation, VasValue),
+	And(Vec<VasTarget>),
+	Or(Vec<VasTarget>),
+}
+
+impl From<VasProperty> for VasTarget {
+	fn from(property: VasProperty) -> Self {
+		VasTarget::Relation(
+			property.variable_index,
+			AllowedRelation::Equal,
+			property.target_value,
+		)
+	}
+}
+
+impl VasTarget {
+	/// Whether or not a state vector satisfies the target
+	pub fn is_satisfied(&self, vector: &VasStateVector) -> bool {
+		match self {
+			VasTarget::Relation(index, relation, value) => vector
+				.get(*index)
+				.map_or(false, |current| relation.holds(*current, *value)),
+			VasTarget::And(targets) => targets.iter().all(|t| t.is_satisfied(vector)),
+			VasTarget::Or(targets) => targets.iter().any(|t| t.is_satisfied(vector)),
+		}
+	}
+
+	/// The relational atoms of the target, in the order they appear
+	pub fn atoms(&self) -> Vec<(usize, AllowedRelation, VasValue)> {
+		match self {
+			VasTarget::Relation(index, relation, value) => vec![(*index, *relation, *value)],
+			VasTarget::And(targets) | VasTarget::Or(targets) => {
+				targets.iter().flat_map(|t| t.atoms()).collect()
+			}
+		}
+	}
+
+	/// The target in disjunctive normal form, i.e., a list of alternatives that are
+	/// each a conjunction of atoms.
+	pub fn disjuncts(&self) -> Vec<Vec<(usize, AllowedRelation, VasValue)>> {
+		match self {
+			VasTarget::Relation(index, relation, value) => vec![vec![(*index, *relation, *value)]],
+			VasTarget::Or(targets) => targets.iter().flat_map(|t| t.disjuncts()).collect(),
+			VasTarget::And(targets) => {
+				targets.iter().fold(vec![Vec::new()], |conjunctions, target| {
+					let mut product = Vec::new();
+					for conjunction in conjunctions.iter() {
+						for disjunct in target.disjuncts() {
+							let mut combined = conjunction.clone();
+							combined.extend(disjunct);
+							product.push(combined);
+						}
+					}
+					product
+				})
+			}
+		}
+	}
+
+	/// Renames the variables of the target, e.g., after variables were removed from the
+	/// model. Gives `None` if a variable has no new index.
+	pub fn map_variables(&self, map: &impl Fn(usize) -> Option<usize>) -> Option<VasTarget> {
+		match self {
+			VasTarget::Relation(index, relation, value) => {
+				Some(VasTarget::Relation(map(*index)?, *relation, *value))
+			}
+			VasTarget::And(targets) => Some(VasTarget::And(
+				targets
+					.iter()
+					.map(|t| t.map_variables(map))
+					.collect::<Option<Vec<_>>>()?,
+			)),
+			VasTarget::Or(targets) => Some(VasTarget::Or(
+				targets
+					.iter()
+					.map(|t| t.map_variables(map))
+					.collect::<Option<Vec<_>>>()?,
+			)),
+		}
+	}
+
+	/// Formats the target with variable names, in PRISM syntax
+	pub fn to_string_with_names(&self, variable_names: &[String]) -> String {
+		let join = |targets: &Vec<VasTarget>, operator: &str| {
+			targets
+				.iter()
+				.map(|t| match t {
+					VasTarget::Relation(..) => t.to_string_with_names(variable_names),
+					_ => format!("({})", t.to_string_with_names(variable_names)),
+				})
+				.collect::<Vec<_>>()
+				.join(operator)
+		};
+		match self {
+			VasTarget::Relation(index, relation, value) => format!(
+				"{} {} {}",
+				variable_names
+					.get(*index)
+					.map(|s| s.as_str())
+					.unwrap_or("Unknown"),
+				relation,
+				value
+			),
+			VasTarget::And(targets) => join(targets, " & "),
+			VasTarget::Or(targets) => join(targets, " | "),
+		}
+	}
+}
+
+/// How a DTMC is derived from a CTMC (see `AbstractVas::to_dtmc`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DtmcDerivation {
+	/// The embedded (jump) chain: each transition fires with probability `rate / exit rate`
+	/// and states without any outgoing rate loop on themselves
+	Embedded,
+	/// The uniformized chain with uniformization rate `q`, which should be at least every
+	/// exit rate: each transition fires with probability `rate / q` and the state loops
+	/// on itself with the remaining probability `1 - exit rate / q`. A state whose exit
+	/// rate is above `q` is uniformized at its exit rate instead.
+	Uniformized(ProbabilityOrRate),
+}
+
+impl DtmcDerivation {
+	/// Reads a derivation from its name, `embedded` or `uniformized:Q`
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name.split_once(':') {
+			None if name == "embedded" => Some(DtmcDerivation::Embedded),
+			Some(("uniformized", q)) => q.trim().parse().ok().map(DtmcDerivation::Uniformized),
+			_ => None,
+		}
+	}
+}
+
 /// The data for an abstract Vector Addition System
 pub(crate) struct AbstractVas {
 	pub(crate) variable_names: Box<[String]>,
+	pub(crate) variable_index: VariableIndex,
 	pub(crate) initial_states: Vec<VasState>,
+	/// The probability of starting in each of the initial states
+	pub(crate) initial_probabilities: Vec<ProbabilityOrRate>,
 	pub(crate) transitions: Vec<VasTransition>,
 	pub(crate) m_type: ModelType,
-	pub(crate) target: VasProperty,
+	pub(crate) target: VasTarget,
+	/// The queries and labels from the model's `.prop` file, if any
+	pub(crate) properties: Vec<property::PropertyQuery>,
+	pub(crate) labels: HashMap<String, property::StateFormula>,
 	pub(crate) z3_context: Option<z3::Context>, // Removed because z3::Context and z3::Config do not implement Clone
 }
 
@@ -314,10 +548,38 @@ impl AbstractModel for AbstractVas {
 	}
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum AllowedRelation {
 	Equal,
 	LessThan,
 	GreaterThan,
+	LessOrEqual,
+	GreaterOrEqual,
+}
+
+impl AllowedRelation {
+	/// Reads a relation from its symbol
+	pub fn from_symbol(symbol: &str) -> Option<Self> {
+		match symbol {
+			"=" | "==" => Some(AllowedRelation::Equal),
+			"<" => Some(AllowedRelation::LessThan),
+			">" => Some(AllowedRelation::GreaterThan),
+			"<=" => Some(AllowedRelation::LessOrEqual),
+			">=" => Some(AllowedRelation::GreaterOrEqual),
+			_ => None,
+		}
+	}
+
+	/// Whether or not `lhs <relation> rhs` holds
+	pub fn holds(&self, lhs: VasValue, rhs: VasValue) -> bool {
+		match self {
+			AllowedRelation::Equal => lhs == rhs,
+			AllowedRelation::LessThan => lhs < rhs,
+			AllowedRelation::GreaterThan => lhs > rhs,
+			AllowedRelation::LessOrEqual => lhs <= rhs,
+			AllowedRelation::GreaterOrEqual => lhs >= rhs,
+		}
+	}
 }
 
 impl fmt::Display for AllowedRelation {
@@ -326,49 +588,324 @@ impl fmt::Display for AllowedRelation {
 			AllowedRelation::Equal => "=",
 			AllowedRelation::LessThan => "<",
 			AllowedRelation::GreaterThan => ">",
+			AllowedRelation::LessOrEqual => "<=",
+			AllowedRelation::GreaterOrEqual => ">=",
 		};
 		write!(f, "{}", relation_str)
 	}
 }
 
-// TODO: May need to allow discrete/continuous time models
-// for now we will just use continuous time models
-
 impl AbstractVas {
 	pub fn new(
 		variable_names: Box<[String]>,
 		initial_states: Vec<VasState>,
 		transitions: Vec<VasTransition>,
-		target: VasProperty,
+		target: VasTarget,
+		m_type: ModelType,
 	) -> Self {
+		let variable_index = variable_index(&variable_names);
+		let initial_states: Vec<VasState> = initial_states
+			.into_iter()
+			.map(|mut state| {
+				state.names = Some(variable_index.clone());
+				state
+			})
+			.collect();
+		// Without probabilities, every initial state is equally likely
+		let initial_probabilities =
+			vec![1.0 / initial_states.len() as ProbabilityOrRate; initial_states.len()];
 		Self {
 			variable_names,
+			variable_index,
 			initial_states,
+			initial_probabilities,
 			transitions,
-			m_type: ModelType::ContinuousTime,
+			m_type,
 			target,
+			properties: Vec::new(),
+			labels: HashMap::new(),
 			z3_context: None, // z3_context is not initialized here
 		}
 	}
 
-	/// Calls a parser to get a VAS model from a file
+	/// Calls a parser to get a VAS model from a file. PRISM files (`.sm`, `.prism`)
+	/// go to the PRISM reader, everything else to the .crn/.vas reader.
 
 	pub fn from_file(filename: &str) -> Result<Self, String> {
-		match vas_file_reader::build_model(filename) {
-			Ok(model) => {
+		Self::from_file_with_params(filename, &HashMap::new())
+	}
+
+	/// Like `from_file`, but with values that override the parameters (or constants)
+	/// declared in the model file, e.g., from `--param k1=0.5`.
+	pub fn from_file_with_params(
+		filename: &str,
+		parameters: &HashMap<String, f64>,
+	) -> Result<Self, String> {
+		let extension = std::path::Path::new(filename)
+			.extension()
+			.and_then(|e| e.to_str());
+		let parsed = match extension {
+			Some("sm") | Some("prism") => {
+				prism_file_reader::build_model(filename, parameters).map_err(|e| vec![e])
+			}
+			#[cfg(feature = "sbml")]
+			Some("xml") | Some("sbml") => {
+				crate::parser::sbml_file_reader::build_model(filename, parameters).map_err(|e| vec![e])
+			}
+			#[cfg(not(feature = "sbml"))]
+			Some("xml") | Some("sbml") => {
+				error!("SBML support is not enabled. Rebuild with `--features sbml`.");
+				return Err("SBML support is not enabled".to_string());
+			}
+			_ => vas_file_reader::build_model(filename, parameters),
+		};
+		match parsed {
+			Ok(mut model) => {
 				debug_message!("Parsing gave OK result");
+				if let Some(prop_file) = prop_file_reader::prop_file_for(filename) {
+					model.load_properties(&prop_file, parameters)?;
+				}
 				Ok(model)
 			}
-			Err(err) => {
-				error!("ERROR DURING PARSING: {}", err);
-				Err(err.to_string())
+			Err(errors) => {
+				let errors = errors
+					.into_iter()
+					.map(|e| e.with_source_from_file(filename).to_string())
+					.collect::<Vec<_>>();
+				for err in errors.iter() {
+					error!("ERROR DURING PARSING: {}", err);
+				}
+				if errors.len() > 1 {
+					error!("{} errors found while parsing {}", errors.len(), filename);
+				}
+				Err(errors.join("\n"))
+			}
+		}
+	}
+
+	/// Reads the queries and labels of a PRISM `.prop` file, bound to the variables
+	/// of this model. The goal of the first query becomes the model's target.
+	pub fn load_properties(
+		&mut self,
+		prop_file: &str,
+		parameters: &HashMap<String, f64>,
+	) -> Result<(), String> {
+		let properties =
+			prop_file_reader::build_properties(prop_file, &self.variable_names, parameters)
+				.map_err(|e| {
+					let e = e.with_source_from_file(prop_file).to_string();
+					error!("ERROR DURING PARSING: {}", e);
+					e
+				})?;
+		let goal = properties
+			.queries
+			.first()
+			.and_then(|query| query.goal())
+			.and_then(|goal| goal.to_target(&properties.labels));
+		let deferred = prop_file_reader::is_deferred_target(&self.target);
+		match goal {
+			Some(target) if deferred => {
+				debug_message!("Read target from {}", prop_file);
+				self.target = target;
+			}
+			None if deferred => {
+				let e = format!(
+					"The first query in {} has no goal usable as a target, and the model has no target of its own",
+					prop_file
+				);
+				error!("{}", e);
+				return Err(e);
+			}
+			Some(target) if target != self.target => {
+				warning!(
+					"Target `{}` from {} replaces the model's target `{}`",
+					target.to_string_with_names(&self.variable_names),
+					prop_file,
+					self.target.to_string_with_names(&self.variable_names)
+				);
+				self.target = target;
+			}
+			Some(_) => debug_message!("Target matches the first query in {}", prop_file),
+			None => warning!(
+				"The first query in {} has no goal usable as a target, keeping the model's target",
+				prop_file
+			),
+		}
+		self.properties = properties.queries;
+		self.labels = properties.labels;
+		Ok(())
+	}
+
+	/// Sets the probabilities of starting in each of the initial states, which must sum
+	/// to one
+	pub fn set_initial_probabilities(
+		&mut self,
+		probabilities: Vec<ProbabilityOrRate>,
+	) -> Result<(), String> {
+		if probabilities.len() != self.initial_states.len() {
+			return Err(format!(
+				"Expected {} initial probabilities, got {}",
+				self.initial_states.len(),
+				probabilities.len()
+			));
+		}
+		if let Some(p) = probabilities.iter().find(|p| !(0.0..=1.0).contains(*p)) {
+			return Err(format!("Initial probability {} is not in [0, 1]", p));
+		}
+		let sum: ProbabilityOrRate = probabilities.iter().sum();
+		if (sum - 1.0).abs() > 1e-9 {
+			return Err(format!("Initial probabilities sum to {}, not 1", sum));
+		}
+		self.initial_probabilities = probabilities;
+		Ok(())
+	}
+
+	/// Picks the index of one of the initial states at random, by its probability
+	pub fn sample_initial_index(&self, rng: &mut impl rand::Rng) -> usize {
+		let mut remaining: ProbabilityOrRate = rng.random();
+		for (index, probability) in self.initial_probabilities.iter().enumerate() {
+			if remaining < *probability {
+				return index;
+			}
+			remaining -= probability;
+		}
+		// Rounding can leave a little probability over
+		self.initial_states.len() - 1
+	}
+
+	/// The smallest and largest value of a variable over the initial states
+	pub fn initial_range(&self, index: usize) -> (VasValue, VasValue) {
+		self.initial_states
+			.iter()
+			.map(|state| state.vector[index])
+			.fold((VasValue::MAX, VasValue::MIN), |(low, high), value| {
+				(low.min(value), high.max(value))
+			})
+	}
+
+	/// Sets the mass-action convention of every transition in the model
+	pub fn set_kinetics(&mut self, kinetics: Kinetics) {
+		for transition in self.transitions.iter_mut() {
+			transition.kinetics = kinetics;
+		}
+	}
+
+	/// The rate of a transition at a plain state vector, zero if it is not enabled.
+	/// Tools that work on vectors rather than `VasState`s should use this (and
+	/// `exit_rate_at`) so that every tool agrees on the rates.
+	pub fn rate_at(&self, transition: &VasTransition, vector: &VasStateVector) -> ProbabilityOrRate {
+		transition
+			.rate_probability_at(&self.state(vector.clone()))
+			.unwrap_or(0.0)
+	}
+
+	/// The total outgoing rate of a plain state vector
+	pub fn exit_rate_at(&self, vector: &VasStateVector) -> ProbabilityOrRate {
+		self.exit_rate(&self.state(vector.clone()))
+	}
+
+	/// Derives a DTMC from this CTMC. Every transition keeps its update and its enabled
+	/// bounds but gets the probability from `derivation`, and a `self_loop` transition
+	/// with no update takes the remaining probability of each state.
+	pub fn to_dtmc(&self, derivation: DtmcDerivation) -> Result<AbstractVas, String> {
+		if self.m_type != ModelType::ContinuousTime {
+			return Err("Only a CTMC can be turned into a DTMC".to_string());
+		}
+		if let DtmcDerivation::Uniformized(q) = derivation {
+			if !(q > 0.0 && q.is_finite()) {
+				return Err(format!("Invalid uniformization rate {}", q));
+			}
+		}
+		let rates = Arc::new(self.transitions.clone());
+		fn exit_rate(rates: &[VasTransition], state: &VasState) -> ProbabilityOrRate {
+			rates
+				.iter()
+				.filter_map(|transition| transition.rate_probability_at(state))
+				.sum()
+		}
+		let mut transitions = Vec::with_capacity(self.transitions.len() + 1);
+		for (i, transition) in self.transitions.iter().enumerate() {
+			let rates = rates.clone();
+			let mut transition = transition.clone();
+			transition.set_custom_rate_fn(Arc::new(move |state: &VasState| {
+				let rate = rates[i].rate_probability_at(state).unwrap_or(0.0);
+				match derivation {
+					DtmcDerivation::Embedded => match exit_rate(&rates, state) {
+						exit if exit > 0.0 => rate / exit,
+						_ => 0.0,
+					},
+					DtmcDerivation::Uniformized(q) => rate / q.max(exit_rate(&rates, state)),
+				}
+			}));
+			transitions.push(transition);
+		}
+		let variable_count = self.variable_names.len();
+		let mut self_loop = VasTransition::new(
+			self.transitions.len(),
+			"self_loop".to_string(),
+			vec![0; variable_count].into_boxed_slice(),
+			vec![0; variable_count].into_boxed_slice(),
+			1.0,
+		);
+		let warned = AtomicBool::new(false);
+		self_loop.set_custom_rate_fn(Arc::new(move |state: &VasState| {
+			let exit = exit_rate(&rates, state);
+			match derivation {
+				DtmcDerivation::Embedded if exit > 0.0 => 0.0,
+				DtmcDerivation::Embedded => 1.0,
+				DtmcDerivation::Uniformized(q) if exit > q => {
+					if !warned.swap(true, Ordering::Relaxed) {
+						warning!(
+							"A state has exit rate {} above the uniformization rate {}, so it is uniformized at its exit rate",
+							exit,
+							q
+						);
+					}
+					0.0
+				}
+				DtmcDerivation::Uniformized(q) => 1.0 - exit / q,
+			}
+		}));
+		transitions.push(self_loop);
+		let mut dtmc = AbstractVas::new(
+			self.variable_names.clone(),
+			self.initial_states.clone(),
+			transitions,
+			self.target.clone(),
+			ModelType::DiscreteTime,
+		);
+		dtmc.initial_probabilities = self.initial_probabilities.clone();
+		dtmc.properties = self.properties.clone();
+		dtmc.labels = self.labels.clone();
+		Ok(dtmc)
+	}
+
+	/// A state of this model, whose variables can be looked up by name
+	pub fn state(&self, vector: VasStateVector) -> VasState {
+		VasState::with_names(vector, self.variable_index.clone())
+	}
+
+	/// Whether or not a state satisfies a state formula over this model. Labels come
+	/// from the `.prop` file, and `"target"` is the model's target unless redefined.
+	pub fn satisfies(&self, formula: &property::StateFormula, state: &VasState) -> bool {
+		formula.holds(state, &|name, state| self.label_holds(name, state))
+	}
+
+	/// Whether or not a state has the label `name`
+	pub fn label_holds(&self, name: &str, state: &VasState) -> bool {
+		match self.labels.get(name) {
+			Some(formula) => self.satisfies(formula, state),
+			None if name == "target" => self.target.is_satisfied(&state.vector),
+			None => {
+				debug_message!("Unknown label \"{}\"", name);
+				false
 			}
 		}
 	}
 
 	/// Runs the validator on the model and its property
 
-	pub fn validate_model(&self, property: VasProperty) -> Result<String, String> {
+	pub fn validate_model(&self, property: VasTarget) -> Result<String, String> {
 		let result = validate_vas(self, &property);
 		result
 	}
@@ -413,13 +950,20 @@ impl AbstractVas {
 			.for_each(|name| output.push_str(&format!("\t{}", name)));
 		output.push_str("\n");
 		output.push_str("Initial States:\n");
-		for state in self.initial_states.clone() {
+		for (state, probability) in self
+			.initial_states
+			.iter()
+			.zip(self.initial_probabilities.iter())
+		{
 			state
 				.vector
 				.iter()
 				.for_each(|name| output.push_str(&format!("\t{}", name)));
+			if self.initial_states.len() > 1 {
+				output.push_str(&format!("\t(probability {})", probability));
+			}
+			output.push_str("\n");
 		}
-		output.push_str("\n");
 		output.push_str("Transitions:\n");
 		for transition in self.transitions.clone() {
 			output.push_str(&format!(
@@ -436,17 +980,22 @@ impl AbstractVas {
 				.enabled_bounds
 				.iter()
 				.for_each(|name| output.push_str(&format!("\t{}", name)));
-			output.push_str(&format!("\t]\n\t\tRate:\t{}\n", transition.rate_const));
+			output.push_str("\t]\n");
+			for guard in transition.guards.iter() {
+				let weights = guard.weights.iter().copied().collect::<Vec<_>>();
+				output.push_str(&format!(
+					"\t\tGuard:\t{} >= {}\n",
+					weighted_sum_string(&weights, &self.variable_names),
+					guard.bound
+				));
+			}
+			output.push_str(&format!("\t\tRate:\t{}\n", transition.rate_const));
 		}
 		output.push_str("Target:\n");
 		output.push_str(&format!(
-			"\tVariable: {}\n",
-			self.variable_names
-				.get(self.target.variable_index)
-				.map(|s| s.as_str())
-				.unwrap_or("Unknown")
+			"\t{}\n",
+			self.target.to_string_with_names(&self.variable_names)
 		));
-		output.push_str(&format!("\tTarget Value: {}\n", self.target.target_value));
 		output.push_str("==========================================\n");
 		output.push_str("               END VAS MODEL              \n");
 		output.push_str("==========================================\n");
@@ -479,6 +1028,8 @@ pub(crate) struct PrismVasModel {
 	pub(crate) m_type: ModelType,
 	pub(crate) state_trie: VasTrieNode, // Optional trie for storing traces, if needed
 	pub(crate) transition_map: HashMap<usize, Vec<(usize, usize)>>, // Quick transition from-(to, transitions list index) lookup
+	pub(crate) reserved_indices: BTreeSet<usize>, // Indices reserved for states that are not added yet
+	pub(crate) next_index: usize, // One more than the largest index in use
 }
 
 /// Default implementation for PrismVasModel
@@ -491,6 +1042,8 @@ impl Default for PrismVasModel {
 			m_type: ModelType::ContinuousTime,
 			state_trie: VasTrieNode::new(), // No trie by default
 			transition_map: HashMap::new(), // No transitions by default
+			reserved_indices: BTreeSet::new(),
+			next_index: 0,
 		}
 	}
 }
@@ -498,65 +1051,85 @@ impl Default for PrismVasModel {
 impl ExplicitModel for PrismVasModel {
 	type StateType = VasState;
 	type TransitionType = VasTransition;
-	type MatrixType = (); // TODO: There is no matrix type for PrismVasModel, using this placeholder
+	type MatrixType = SparseMatrix;
 
 	/// Maps the state to a state index (in our case just a usize)
 	fn state_to_index(&self, state: &Self::StateType) -> Option<usize> {
-		for my_state in self.states.iter() {
-			if my_state.vector == state.vector.map(|v| v as i128) {
-				return Some(my_state.state_id);
-			}
-		}
-		None
+		self.state_trie.get(&state.vector)
 	}
 
 	/// Like `state_to_index` but if the state is not present adds it and
 	/// assigns it a new index
 	fn find_or_add_index(&mut self, state: &Self::StateType) -> usize {
-		let index = self.state_to_index(state);
-		if let Some(idx) = index {
+		if let Some(idx) = self.state_to_index(state) {
 			return idx; // State already exists, return its index
-		} else {
-			let new_index = self.states.len();
-			self.states.push(PrismVasState {
-				state_id: new_index,
-				vector: state.vector.map(|v| v as i128),
-				label: None,              // No label by default
-				total_outgoing_rate: 0.0, // No outgoing rate by default
-			});
-			return new_index; // Return the newly added index
 		}
+		let mut new_index = self.next_index;
+		while self.reserved_indices.contains(&new_index) {
+			new_index += 1;
+		}
+		self.add_state(PrismVasState {
+			state_id: new_index,
+			vector: state.vector.clone(),
+			label: None,              // No label by default
+			total_outgoing_rate: 0.0, // No outgoing rate by default
+		});
+		new_index // Return the newly added index
 	}
 
 	/// Reserve an index in the explicit model (useful for artificially introduced absorbing
 	/// states). Returns whether or not the index was able to be reserved.
 	fn reserve_index(&mut self, index: usize) -> bool {
-		todo!()
+		if self.states.iter().any(|state| state.state_id == index) {
+			return false;
+		}
+		self.next_index = self.next_index.max(index + 1);
+		self.reserved_indices.insert(index)
 	}
 
-	/// The number of states added to our model so far
+	/// The number of states added to our model so far, including reserved indices
 	fn state_count(&self) -> usize {
-		todo!()
+		self.states.len() + self.reserved_indices.len()
 	}
 
 	/// The type of this model
 	fn model_type(&self) -> ModelType {
-		todo!()
+		self.m_type
 	}
 
-	/// Adds an entry to the sparse matrix
+	/// Adds an entry to the sparse matrix. Entries between the same states are summed.
 	fn add_entry(
 		&mut self,
 		from_idx: usize,
 		to_idx: usize,
 		entry: <Self::TransitionType as Transition>::RateOrProbabilityType,
 	) {
-		todo!()
+		let existing = self
+			.transition_map
+			.get(&from_idx)
+			.and_then(|targets| targets.iter().find(|(to_state, _)| *to_state == to_idx))
+			.map(|(_, transition_index)| *transition_index);
+		match existing {
+			Some(transition_index) => self.transitions[transition_index].rate += entry,
+			None => self.add_transition(PrismVasTransition {
+				transition_id: usize::MAX, // Not tied to a single VAS transition
+				from_state: from_idx,
+				to_state: to_idx,
+				rate: entry,
+			}),
+		}
 	}
 
-	/// Converts this model into a sparse matrix
+	/// Converts this model into a sparse matrix, indexed by state index. For a CTMC
+	/// this is the rate matrix, for a DTMC the transition probability matrix.
 	fn to_matrix(&self) -> Self::MatrixType {
-		todo!()
+		SparseMatrix::from_triplets(
+			self.next_index,
+			self.next_index,
+			self.transitions
+				.iter()
+				.map(|t| (t.from_state, t.to_state, t.rate)),
+		)
 	}
 
 	/// Whether or not this model has not been expanded yet/is empty
@@ -583,13 +1156,47 @@ impl PrismVasModel {
 		model
 	}
 
-	/// Adds a transition to the model
+	/// Adds a transition to the model. Its states take up indices, even before they are
+	/// added, so that the matrix covers them.
 	pub fn add_transition(&mut self, transition: PrismVasTransition) {
+		self.next_index = self
+			.next_index
+			.max(transition.from_state.max(transition.to_state) + 1);
+		self.transition_map
+			.entry(transition.from_state)
+			.or_insert_with(Vec::new)
+			.push((transition.to_state, self.transitions.len()));
 		self.transitions.push(transition);
 	}
 
-	/// Adds a state to the model
+	/// Removes the transition between two states, if there is one, and gives its rate
+	pub fn remove_entry(&mut self, from_idx: usize, to_idx: usize) -> Option<ProbabilityOrRate> {
+		let targets = self.transition_map.get_mut(&from_idx)?;
+		let position = targets
+			.iter()
+			.position(|(to_state, _)| *to_state == to_idx)?;
+		let (_, transition_index) = targets.swap_remove(position);
+		let removed = self.transitions.swap_remove(transition_index);
+		// The last transition takes the place of the removed one
+		if let Some(moved) = self.transitions.get(transition_index) {
+			let last_index = self.transitions.len();
+			if let Some(entry) = self
+				.transition_map
+				.get_mut(&moved.from_state)
+				.and_then(|targets| targets.iter_mut().find(|(_, index)| *index == last_index))
+			{
+				entry.1 = transition_index;
+			}
+		}
+		Some(removed.rate)
+	}
+
+	/// Adds a state to the model (and its trie), filling its index if it was reserved
 	pub fn add_state(&mut self, state: PrismVasState) {
+		self.state_trie
+			.insert_if_not_exists(&state.vector, state.state_id);
+		self.reserved_indices.remove(&state.state_id);
+		self.next_index = self.next_index.max(state.state_id + 1);
 		self.states.push(state);
 	}
 }
@@ -50,6 +50,20 @@ impl VasTrieNode {
 			}
 		}
 	}
+	/// Gets the ID associated with a state, if the state is in the trie.
+	pub fn get(&self, state: &VasStateVector) -> Option<usize> {
+		let mut node = self;
+		for val in state.iter() {
+			match node {
+				VasTrieNode::Node(children) => node = children.get(val)?,
+				VasTrieNode::LeafNode(_) => break,
+			}
+		}
+		match node {
+			VasTrieNode::LeafNode(id) => Some(*id),
+			VasTrieNode::Node(_) => None,
+		}
+	}
 	/// Gets the next available ID for a new state.
 	pub fn next_available_id(&self) -> usize {
 		fn max_id(node: &VasTrieNode) -> usize {
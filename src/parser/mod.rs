@@ -1,3 +1,9 @@
 pub(crate) mod parser;
+pub(crate) mod prism_file_reader;
+pub(crate) mod prism_file_writer;
+pub(crate) mod prop_file_reader;
+pub(crate) mod rate_expression;
+#[cfg(feature = "sbml")]
+pub(crate) mod sbml_file_reader;
 pub(crate) mod vas_file_reader;
 pub(crate) mod vas_parser;
@@ -0,0 +1,1163 @@
+use std::collections::HashMap;
+
+use evalexpr::{build_operator_tree, DefaultNumericTypes, Node};
+use itertools::Itertools;
+use metaverify::trusted;
+use nalgebra::DVector;
+
+use crate::{
+	logging::messages::*,
+	model::{
+		model::{ModelType, ProbabilityOrRate},
+		vas_model::{AbstractVas, AllowedRelation, VasState, VasTarget, VasTransition, VasValue},
+	},
+	parser::{
+		prop_file_reader::deferred_target,
+		rate_expression::{compile_rate_expression, RateFunctions},
+		vas_file_reader::{get_variable_id, warn_unused_parameters, ModelParseError},
+	},
+	util::util::read_lines,
+};
+
+const CONTINUOUS_TIME_TERMS: &[&str] = &["ctmc", "stochastic"];
+const DISCRETE_TIME_TERMS: &[&str] = &["dtmc", "probabilistic"];
+const NONDETERMINISTIC_TERMS: &[(&str, ModelType)] = &[
+	("mdp", ModelType::NondeterministicDiscreteTime),
+	("nondeterministic", ModelType::NondeterministicDiscreteTime),
+	("ctmdp", ModelType::NondeterministicContinuousTime),
+];
+const UNSUPPORTED_MODEL_TERMS: &[&str] = &["pta", "smg"];
+const TYPE_TERMS: &[&str] = &["int", "double", "bool"];
+const RELATION_TERMS: &[&str] = &[">=", "<=", ">", "<", "="];
+/// PRISM built-in functions and the evalexpr functions they translate to
+const PRISM_FUNCTIONS: &[(&str, &str)] = &[
+	("pow", "math::pow"),
+	("log", "math::log"),
+	("min", "min"),
+	("max", "max"),
+	("floor", "floor"),
+	("ceil", "ceil"),
+	("round", "round"),
+];
+
+/// A single lexical token of the PRISM language
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Token {
+	Identifier(String),
+	Number(String),
+	Quoted(String),
+	Symbol(String),
+}
+
+impl ToString for Token {
+	fn to_string(&self) -> String {
+		match self {
+			Token::Identifier(s) | Token::Number(s) | Token::Symbol(s) => s.clone(),
+			Token::Quoted(s) => format!("\"{}\"", s),
+		}
+	}
+}
+
+/// A (possibly probabilistic) branch of a command: `rate : updates`
+struct PrismBranch {
+	rate: Option<Vec<Token>>,
+	updates: Vec<Vec<Token>>,
+}
+
+/// A guarded command `[action] guard -> branches;` inside of a module
+struct PrismCommand {
+	line: usize,
+	module: usize,
+	action: Option<String>,
+	guard: Vec<Token>,
+	branches: Vec<PrismBranch>,
+}
+
+/// Splits a line into PRISM tokens, dropping `//` comments.
+#[trusted]
+pub(crate) fn tokenize(line_num: usize, line: &str) -> Result<Vec<Token>, ModelParseError> {
+	let chars = line.chars().collect::<Vec<char>>();
+	let mut tokens = Vec::new();
+	let mut i = 0;
+	while i < chars.len() {
+		let c = chars[i];
+		if c.is_whitespace() {
+			i += 1;
+		} else if c == '/' && chars.get(i + 1) == Some(&'/') {
+			break;
+		} else if c.is_ascii_alphabetic() || c == '_' {
+			let start = i;
+			while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+				i += 1;
+			}
+			tokens.push(Token::Identifier(chars[start..i].iter().collect()));
+		} else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).map_or(false, |d| d.is_ascii_digit())) {
+			let start = i;
+			while i < chars.len() && chars[i].is_ascii_digit() {
+				i += 1;
+			}
+			// A single dot continues the number, but `..` is a range
+			if i < chars.len() && chars[i] == '.' && chars.get(i + 1) != Some(&'.') {
+				i += 1;
+				while i < chars.len() && chars[i].is_ascii_digit() {
+					i += 1;
+				}
+			}
+			if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+				let mut j = i + 1;
+				if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+					j += 1;
+				}
+				if j < chars.len() && chars[j].is_ascii_digit() {
+					i = j;
+					while i < chars.len() && chars[i].is_ascii_digit() {
+						i += 1;
+					}
+				}
+			}
+			tokens.push(Token::Number(chars[start..i].iter().collect()));
+		} else if c == '"' {
+			let start = i + 1;
+			i += 1;
+			while i < chars.len() && chars[i] != '"' {
+				i += 1;
+			}
+			if i >= chars.len() {
+				return Err(ModelParseError::unexpected_token(line_num, &line));
+			}
+			tokens.push(Token::Quoted(chars[start..i].iter().collect()));
+			i += 1;
+		} else {
+			let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+			if ["<=", ">=", "!=", "->", ".."].contains(&pair.as_str()) {
+				tokens.push(Token::Symbol(pair));
+				i += 2;
+			} else if "+-*/()[]{}<>=&|!:,;?'".contains(c) {
+				tokens.push(Token::Symbol(c.to_string()));
+				i += 1;
+			} else {
+				return Err(ModelParseError::unexpected_token(line_num, &c));
+			}
+		}
+	}
+	Ok(tokens)
+}
+
+/// Joins tokens back into readable text for error messages
+pub(crate) fn tokens_to_string(tokens: &[Token]) -> String {
+	tokens.iter().map(|t| t.to_string()).join(" ")
+}
+
+fn is_symbol(token: &Token, symbol: &str) -> bool {
+	matches!(token, Token::Symbol(s) if s == symbol)
+}
+
+/// Finds the index of the first occurrence of `symbol` outside of any parentheses
+pub(crate) fn find_top_level(tokens: &[Token], symbol: &str) -> Option<usize> {
+	let mut depth = 0;
+	for (i, token) in tokens.iter().enumerate() {
+		match token {
+			Token::Symbol(s) if s == "(" => depth += 1,
+			Token::Symbol(s) if s == ")" => depth -= 1,
+			Token::Symbol(s) if s == symbol && depth == 0 => return Some(i),
+			_ => {}
+		}
+	}
+	None
+}
+
+/// Splits tokens on every occurrence of `symbol` outside of any parentheses
+pub(crate) fn split_top_level<'t>(tokens: &'t [Token], symbol: &str) -> Vec<&'t [Token]> {
+	let mut pieces = Vec::new();
+	let mut rest = tokens;
+	while let Some(index) = find_top_level(rest, symbol) {
+		pieces.push(&rest[..index]);
+		rest = &rest[index + 1..];
+	}
+	pieces.push(rest);
+	pieces
+}
+
+/// Removes parentheses that enclose the entire expression
+pub(crate) fn strip_parens(tokens: &[Token]) -> &[Token] {
+	let mut tokens = tokens;
+	while tokens.len() >= 2
+		&& is_symbol(&tokens[0], "(")
+		&& is_symbol(&tokens[tokens.len() - 1], ")")
+	{
+		// Make sure the opening parenthesis is matched by the last one, not earlier
+		let mut depth = 0;
+		let mut encloses = true;
+		for (i, token) in tokens.iter().enumerate() {
+			if is_symbol(token, "(") {
+				depth += 1;
+			} else if is_symbol(token, ")") {
+				depth -= 1;
+				if depth == 0 && i != tokens.len() - 1 {
+					encloses = false;
+					break;
+				}
+			}
+		}
+		if !encloses {
+			break;
+		}
+		tokens = &tokens[1..tokens.len() - 1];
+	}
+	tokens
+}
+
+/// Replaces formula names by their (parenthesized) definitions
+fn expand_formulas(tokens: &[Token], formulas: &HashMap<String, Vec<Token>>) -> Vec<Token> {
+	let mut expanded = Vec::new();
+	for token in tokens {
+		match token {
+			Token::Identifier(name) if formulas.contains_key(name) => {
+				expanded.push(Token::Symbol("(".to_string()));
+				expanded.extend(formulas[name].iter().cloned());
+				expanded.push(Token::Symbol(")".to_string()));
+			}
+			_ => expanded.push(token.clone()),
+		}
+	}
+	expanded
+}
+
+/// Formats a float so that evalexpr always reads it as a float (PRISM division is never
+/// integer division).
+fn float_literal(value: f64) -> String {
+	let literal = if value.fract() == 0.0 && value.abs() < 1e15 {
+		format!("{:.1}", value.abs())
+	} else {
+		format!("{}", value.abs())
+	};
+	if value < 0.0 {
+		format!("(-{})", literal)
+	} else {
+		literal
+	}
+}
+
+/// Translates a PRISM expression into an evalexpr expression, inlining the values of
+/// any constants.
+#[trusted]
+pub(crate) fn to_evalexpr(tokens: &[Token], constants: &HashMap<String, f64>) -> String {
+	let mut output = Vec::new();
+	for (i, token) in tokens.iter().enumerate() {
+		let is_call = tokens.get(i + 1).map_or(false, |next| is_symbol(next, "("));
+		output.push(match token {
+			Token::Identifier(name) if is_call => PRISM_FUNCTIONS
+				.iter()
+				.find(|(prism_name, _)| prism_name == name)
+				.map_or(name.clone(), |(_, evalexpr_name)| evalexpr_name.to_string()),
+			Token::Identifier(name) => constants
+				.get(name)
+				.map_or(name.clone(), |value| float_literal(*value)),
+			Token::Number(number) => number
+				.parse::<f64>()
+				.map_or(number.clone(), float_literal),
+			Token::Quoted(text) => format!("\"{}\"", text),
+			Token::Symbol(symbol) => match symbol.as_str() {
+				"&" => "&&".to_string(),
+				"|" => "||".to_string(),
+				"=" => "==".to_string(),
+				other => other.to_string(),
+			},
+		});
+	}
+	output.join(" ")
+}
+
+/// Whether or not an expression refers to any of the model variables
+fn mentions_variable(tokens: &[Token], variable_names: &[String]) -> bool {
+	tokens.iter().any(|token| match token {
+		Token::Identifier(name) => variable_names.contains(name),
+		_ => false,
+	})
+}
+
+/// Evaluates an expression that may only depend on constants
+#[trusted]
+pub(crate) fn evaluate_constant(
+	line: usize,
+	tokens: &[Token],
+	constants: &HashMap<String, f64>,
+) -> Result<f64, ModelParseError> {
+	let expression = to_evalexpr(tokens, constants);
+	let node: Node<DefaultNumericTypes> = build_operator_tree(&expression)
+		.map_err(|e| ModelParseError::general(line, &format!("`{}`: {}", expression, e)))?;
+	if let Some(name) = node.iter_variable_identifiers().next() {
+		return Err(ModelParseError::unspecified_variable(line, &name));
+	}
+	node.eval_number()
+		.map_err(|e| ModelParseError::general(line, &format!("`{}`: {}", expression, e)))
+}
+
+/// Evaluates an expression that may only depend on constants and must be a whole number
+fn evaluate_integer(
+	line: usize,
+	tokens: &[Token],
+	constants: &HashMap<String, f64>,
+) -> Result<VasValue, ModelParseError> {
+	let value = evaluate_constant(line, tokens, constants)?;
+	if value.fract() != 0.0 {
+		return Err(ModelParseError::expected_integer(line, &value));
+	}
+	Ok(value as VasValue)
+}
+
+/// Reads the initial states from the body of an `init ... endinit` block. PRISM allows any
+/// predicate there, but a VAS needs finitely many initial states, so the block must be a
+/// disjunction of conjunctions `x = v & y = w` that each give a value to every variable.
+#[trusted]
+fn parse_initial_states(
+	line: usize,
+	tokens: &[Token],
+	variable_names: &[String],
+	constants: &HashMap<String, f64>,
+) -> Result<Vec<VasState>, ModelParseError> {
+	let mut initial_states = Vec::<VasState>::new();
+	for conjunction in split_top_level(strip_parens(tokens), "|") {
+		let mut values = vec![None::<VasValue>; variable_names.len()];
+		for atom in split_top_level(strip_parens(conjunction), "&") {
+			let (name, value) = match strip_parens(atom) {
+				[Token::Identifier(name), Token::Symbol(eq), value @ ..] if eq == "=" => {
+					(name, value)
+				}
+				_ => {
+					return Err(ModelParseError::general(
+						line,
+						&format!(
+							"Initial states must be written as `x = v & y = w | ...`, not `{}`.",
+							tokens_to_string(atom)
+						),
+					))
+				}
+			};
+			let index = get_variable_id(variable_names, name)
+				.ok_or_else(|| ModelParseError::unspecified_variable(line, name))?;
+			if values[index].is_some() {
+				return Err(ModelParseError::general(
+					line,
+					&format!("Variable `{}` is given more than one initial value.", name),
+				));
+			}
+			values[index] = Some(evaluate_integer(line, value, constants)?);
+		}
+		if let Some(index) = values.iter().position(|value| value.is_none()) {
+			return Err(ModelParseError::init_unspecified(
+				line,
+				&variable_names[index],
+			));
+		}
+		let vector = DVector::from_vec(values.into_iter().flatten().collect());
+		if initial_states.iter().any(|state| state.vector == vector) {
+			warning!("Ignoring a repeated initial state (line {}).", line);
+			continue;
+		}
+		initial_states.push(VasState::new(vector));
+	}
+	Ok(initial_states)
+}
+
+/// Parses an update of the form `(x' = x + c)` or `(x' = x - c)`, returning the variable
+/// index and the (constant) change.
+#[trusted]
+fn parse_update(
+	line: usize,
+	tokens: &[Token],
+	variable_names: &[String],
+	constants: &HashMap<String, f64>,
+) -> Result<(usize, VasValue), ModelParseError> {
+	let tokens = strip_parens(tokens);
+	let (name, rhs) = match tokens {
+		[Token::Identifier(name), Token::Symbol(prime), Token::Symbol(eq), rhs @ ..]
+			if prime == "'" && eq == "=" =>
+		{
+			(name, strip_parens(rhs))
+		}
+		_ => {
+			return Err(ModelParseError::unexpected_token(
+				line,
+				&tokens_to_string(tokens),
+			))
+		}
+	};
+	let index = get_variable_id(variable_names, name)
+		.ok_or_else(|| ModelParseError::unspecified_variable(line, name))?;
+	let change = match rhs {
+		[Token::Identifier(same)] if same == name => 0,
+		[Token::Identifier(same), Token::Symbol(op), delta @ ..] if same == name && op == "+" => {
+			evaluate_integer(line, delta, constants)?
+		}
+		[Token::Identifier(same), Token::Symbol(op), delta @ ..] if same == name && op == "-" => {
+			-evaluate_integer(line, delta, constants)?
+		}
+		_ => {
+			return Err(ModelParseError::general(
+				line,
+				&format!(
+					"Update `{}` is not a constant increment or decrement, which a VAS cannot represent.",
+					tokens_to_string(tokens)
+				),
+			))
+		}
+	};
+	Ok((index, change))
+}
+
+/// Reads the lower bounds that a guard places on variables into `bounds`. A VAS can only
+/// express guards that are conjunctions of lower bounds, so anything else is an error,
+/// except for the `rate > 0` guards that SBML-to-PRISM converters emit (they are implied by
+/// CTMC semantics). Returns `false` if the guard can never be satisfied.
+#[trusted]
+fn guard_lower_bounds(
+	line: usize,
+	guard: &[Token],
+	rate: Option<&[Token]>,
+	variable_names: &[String],
+	constants: &HashMap<String, f64>,
+	bounds: &mut [VasValue],
+) -> Result<bool, ModelParseError> {
+	for atom in split_top_level(strip_parens(guard), "&") {
+		let atom = strip_parens(atom);
+		if !mentions_variable(atom, variable_names) {
+			let expression = to_evalexpr(atom, constants);
+			let satisfiable = evalexpr::eval_boolean(&expression)
+				.map_err(|e| ModelParseError::general(line, &format!("`{}`: {}", expression, e)))?;
+			if !satisfiable {
+				return Ok(false);
+			}
+			continue;
+		}
+		let relation = RELATION_TERMS
+			.iter()
+			.find_map(|op| find_top_level(atom, op).map(|index| (index, *op)));
+		let unsupported = || {
+			ModelParseError::general(
+				line,
+				&format!(
+					"Unsupported guard `{}`: only lower bounds on variables can be represented in a VAS.",
+					tokens_to_string(atom)
+				),
+			)
+		};
+		let (index, op) = relation.ok_or_else(unsupported)?;
+		let (lhs, rhs) = (strip_parens(&atom[..index]), strip_parens(&atom[index + 1..]));
+		// Normalize `c < x` into `x > c`
+		let (variable, op, bound) = match (lhs, rhs) {
+			([Token::Identifier(name)], bound) if variable_names.contains(name) => (name, op, bound),
+			(bound, [Token::Identifier(name)]) if variable_names.contains(name) => {
+				let flipped = match op {
+					"<" => ">",
+					"<=" => ">=",
+					">" => "<",
+					">=" => "<=",
+					other => other,
+				};
+				(name, flipped, bound)
+			}
+			(lhs, rhs) if op == ">" && Some(lhs) == rate.map(strip_parens) => {
+				if evaluate_constant(line, rhs, constants)? != 0.0 {
+					return Err(unsupported());
+				}
+				continue;
+			}
+			_ => return Err(unsupported()),
+		};
+		if mentions_variable(bound, variable_names) {
+			return Err(unsupported());
+		}
+		let bound = evaluate_constant(line, bound, constants)?;
+		let lower_bound = match op {
+			">" => bound.floor() as VasValue + 1,
+			">=" => bound.ceil() as VasValue,
+			_ => return Err(unsupported()),
+		};
+		let index = get_variable_id(variable_names, variable).unwrap();
+		bounds[index] = bounds[index].max(lower_bound);
+	}
+	Ok(true)
+}
+
+/// Tries to read a rate expression as a mass-action product `k * X * Y * ...`. If it is one,
+/// returns the constant factor `k` and the multiplicity of each variable in the product.
+#[trusted]
+fn mass_action_form(
+	line: usize,
+	tokens: &[Token],
+	variable_names: &[String],
+	constants: &HashMap<String, f64>,
+) -> Result<Option<(ProbabilityOrRate, Vec<VasValue>)>, ModelParseError> {
+	let mut rate_const = 1.0;
+	let mut multiplicity = vec![0; variable_names.len()];
+	let mut stack = vec![strip_parens(tokens)];
+	while let Some(expression) = stack.pop() {
+		let factors = split_top_level(expression, "*");
+		if factors.len() > 1 {
+			stack.extend(factors.into_iter().map(strip_parens));
+			continue;
+		}
+		if !mentions_variable(expression, variable_names) {
+			rate_const *= evaluate_constant(line, expression, constants)?;
+			continue;
+		}
+		match expression {
+			[Token::Identifier(name)] => {
+				multiplicity[get_variable_id(variable_names, name).unwrap()] += 1;
+			}
+			_ => return Ok(None),
+		}
+	}
+	Ok(Some((rate_const, multiplicity)))
+}
+
+/// Builds a transition from its changes, guard bounds and rate expression. Mass-action
+/// rates become a plain `rate_const`, while any other rate expression is compiled into a
+/// custom rate function.
+#[trusted]
+pub(crate) fn build_transition(
+	line: usize,
+	transition_id: usize,
+	transition_name: String,
+	mut increment: Vec<VasValue>,
+	mut decrement: Vec<VasValue>,
+	mut bounds: Vec<VasValue>,
+	rate_tokens: &[Token],
+	variable_names: &[String],
+	constants: &HashMap<String, f64>,
+) -> Result<VasTransition, ModelParseError> {
+	// The transition is enabled when every variable is at least its guard bound and
+	// at least what it consumes.
+	for i in 0..variable_names.len() {
+		bounds[i] = bounds[i].max(decrement[i]);
+	}
+	// A repeated factor (`k * X * X`) keeps its exact form, since the kinetics
+	// conventions only agree on reactants with a stoichiometry of one
+	let mass_action = mass_action_form(line, rate_tokens, variable_names, constants)?.filter(
+		|(_, multiplicity)| {
+			multiplicity
+				.iter()
+				.zip(bounds.iter())
+				.all(|(m, b)| m >= b && *m <= 1)
+		},
+	);
+	let rate_const = if let Some((rate_const, multiplicity)) = &mass_action {
+		// Reactants that are not consumed (catalysts) still bound enabledness
+		bounds.copy_from_slice(multiplicity);
+		*rate_const
+	} else {
+		1.0
+	};
+	// Encode the bounds as a decrement with a matching increment, like a catalyst
+	// in the .crn format (`consume X` and `produce X`)
+	for i in 0..variable_names.len() {
+		increment[i] += bounds[i] - decrement[i];
+		decrement[i] = bounds[i];
+	}
+	let mut transition = VasTransition::new(
+		transition_id,
+		transition_name,
+		increment.into_boxed_slice(),
+		decrement.into_boxed_slice(),
+		rate_const,
+	);
+	if mass_action.is_none() {
+		debug_message!(
+			"Rate of `{}` is not mass-action, compiling `{}`",
+			transition.transition_name,
+			tokens_to_string(rate_tokens)
+		);
+		transition.set_custom_rate_fn(compile_rate_expression(
+			line,
+			rate_tokens,
+			variable_names,
+			constants,
+			&RateFunctions::new(),
+		)?);
+	}
+	Ok(transition)
+}
+
+/// Reads a target from a boolean combination (`&`, `|` and parentheses) of relational
+/// atoms `X op v`, where `op` is one of `=`, `<`, `>`, `<=`, `>=` and `v` is a constant
+/// expression, e.g. `S5 >= 50 & (S4 <= 25 | S4 > 100)`.
+#[trusted]
+pub(crate) fn target_from_tokens(
+	line: usize,
+	tokens: &[Token],
+	variable_names: &[String],
+	constants: &HashMap<String, f64>,
+) -> Result<VasTarget, ModelParseError> {
+	let tokens = strip_parens(tokens);
+	for (symbol, combine) in [
+		("|", VasTarget::Or as fn(Vec<VasTarget>) -> VasTarget),
+		("&", VasTarget::And as fn(Vec<VasTarget>) -> VasTarget),
+	] {
+		let operands = split_top_level(tokens, symbol);
+		if operands.len() > 1 {
+			return Ok(combine(
+				operands
+					.into_iter()
+					.map(|operand| target_from_tokens(line, operand, variable_names, constants))
+					.collect::<Result<Vec<_>, _>>()?,
+			));
+		}
+	}
+	let relation = RELATION_TERMS
+		.iter()
+		.find_map(|op| find_top_level(tokens, op).map(|index| (index, *op)))
+		.ok_or_else(|| ModelParseError::unexpected_token(line, &tokens_to_string(tokens)))?;
+	let (index, op) = relation;
+	let (lhs, rhs) = (strip_parens(&tokens[..index]), strip_parens(&tokens[index + 1..]));
+	// Normalize `v < X` into `X > v`
+	let (name, op, value) = match (lhs, rhs) {
+		([Token::Identifier(name)], value) if variable_names.contains(name) => (name, op, value),
+		(value, [Token::Identifier(name)]) if variable_names.contains(name) => {
+			let flipped = match op {
+				"<" => ">",
+				"<=" => ">=",
+				">" => "<",
+				">=" => "<=",
+				other => other,
+			};
+			(name, flipped, value)
+		}
+		_ => {
+			return Err(ModelParseError::general(
+				line,
+				&format!(
+					"Target atom `{}` must compare a variable with a constant.",
+					tokens_to_string(tokens)
+				),
+			))
+		}
+	};
+	Ok(VasTarget::Relation(
+		get_variable_id(variable_names, name).unwrap(),
+		AllowedRelation::from_symbol(op).unwrap(),
+		evaluate_integer(line, value, constants)?,
+	))
+}
+
+/// Consumes tokens up to (and including) the next `;`
+fn take_statement(
+	tokens: &[(usize, Token)],
+	position: &mut usize,
+) -> Result<(usize, Vec<Token>), ModelParseError> {
+	let line = tokens[*position].0;
+	let mut statement = Vec::new();
+	while *position < tokens.len() {
+		let (_, token) = &tokens[*position];
+		*position += 1;
+		if is_symbol(token, ";") {
+			return Ok((line, statement));
+		}
+		statement.push(token.clone());
+	}
+	Err(ModelParseError::general(
+		line,
+		&format!("Missing `;` after `{}`", tokens_to_string(&statement)),
+	))
+}
+
+/// Parses a command `[action] guard -> rate : updates + rate : updates`
+#[trusted]
+fn parse_command(
+	line: usize,
+	module: usize,
+	statement: &[Token],
+) -> Result<PrismCommand, ModelParseError> {
+	let close = statement
+		.iter()
+		.position(|t| is_symbol(t, "]"))
+		.ok_or_else(|| ModelParseError::unexpected_token(line, &tokens_to_string(statement)))?;
+	let action = match &statement[1..close] {
+		[] => None,
+		[Token::Identifier(name)] => Some(name.clone()),
+		other => return Err(ModelParseError::unexpected_token(line, &tokens_to_string(other))),
+	};
+	let body = &statement[close + 1..];
+	let arrow = find_top_level(body, "->")
+		.ok_or_else(|| ModelParseError::unexpected_token(line, &tokens_to_string(body)))?;
+	let guard = body[..arrow].to_vec();
+	// Branches are separated by a top-level `+` that comes after the branch's `:`
+	let mut branch_tokens = Vec::new();
+	let (mut start, mut depth, mut seen_colon) = (arrow + 1, 0, false);
+	for i in arrow + 1..body.len() {
+		match &body[i] {
+			Token::Symbol(s) if s == "(" => depth += 1,
+			Token::Symbol(s) if s == ")" => depth -= 1,
+			Token::Symbol(s) if s == ":" && depth == 0 => seen_colon = true,
+			Token::Symbol(s) if s == "+" && depth == 0 && seen_colon => {
+				branch_tokens.push(&body[start..i]);
+				start = i + 1;
+				seen_colon = false;
+			}
+			_ => {}
+		}
+	}
+	branch_tokens.push(&body[start..]);
+	let mut branches = Vec::new();
+	for branch in branch_tokens {
+		let (rate, updates) = match find_top_level(branch, ":") {
+			Some(colon) => (Some(branch[..colon].to_vec()), &branch[colon + 1..]),
+			None => (None, branch),
+		};
+		let updates = match updates {
+			[Token::Identifier(t)] if t == "true" => Vec::new(),
+			_ => split_top_level(updates, "&")
+				.into_iter()
+				.map(|u| u.to_vec())
+				.collect(),
+		};
+		branches.push(PrismBranch { rate, updates });
+	}
+	Ok(PrismCommand {
+		line,
+		module,
+		action,
+		guard,
+		branches,
+	})
+}
+
+/// Builds a VAS model from a PRISM `.sm` file. Only the CTMC subset of the language that
+/// maps onto a VAS is supported: constants, formulas, `int` module variables with `init`
+/// (or an `init ... endinit` block listing the initial states),
+/// guarded commands whose guards are lower bounds and whose updates are constant
+/// increments/decrements, and action-label synchronization between modules. Constants
+/// can be overridden (or given a value) through `parameters`.
+#[trusted]
+pub fn build_model(
+	filename: &str,
+	parameters: &HashMap<String, f64>,
+) -> Result<AbstractVas, ModelParseError> {
+	let lines = read_lines(&filename).map_err(|_| {
+		ModelParseError::general(
+			0,
+			&"line-by-line file parsing not Ok. Check your model file.",
+		)
+	})?;
+	let mut tokens = Vec::<(usize, Token)>::new();
+	for (num, line) in lines.flatten().enumerate() {
+		tokens.extend(tokenize(num + 1, &line)?.into_iter().map(|t| (num + 1, t)));
+	}
+
+	let mut constants = HashMap::<String, f64>::new();
+	let mut formulas = HashMap::<String, Vec<Token>>::new();
+	let mut labels = HashMap::<String, (usize, Vec<Token>)>::new();
+	let mut module_names = Vec::<String>::new();
+	let mut variable_names = Vec::<String>::new();
+	let mut initial_state = Vec::<VasValue>::new();
+	let mut initial_block = None::<(usize, Vec<Token>)>;
+	let mut initialized_variable = None::<(usize, String)>;
+	let mut commands = Vec::<PrismCommand>::new();
+	let mut model_type = ModelType::ContinuousTime;
+
+	let mut position = 0;
+	let mut current_module: Option<usize> = None;
+	while position < tokens.len() {
+		let (line, token) = tokens[position].clone();
+		let keyword = match &token {
+			Token::Identifier(word) => word.as_str(),
+			Token::Symbol(s) if s == "[" && current_module.is_some() => "[",
+			_ => return Err(ModelParseError::unexpected_token(line, &token.to_string())),
+		};
+		match keyword {
+			k if CONTINUOUS_TIME_TERMS.contains(&k) => position += 1,
+			// The probabilities of a DTMC take the place of the rates. PRISM chooses
+			// uniformly between commands that are enabled at the same time, which the
+			// validator reports as a state whose probabilities do not sum to one.
+			k if DISCRETE_TIME_TERMS.contains(&k) => {
+				model_type = ModelType::DiscreteTime;
+				position += 1;
+			}
+			// Every combination of synchronizing commands is a choice of its own
+			k if NONDETERMINISTIC_TERMS.iter().any(|(term, _)| *term == k) => {
+				model_type = NONDETERMINISTIC_TERMS
+					.iter()
+					.find(|(term, _)| *term == k)
+					.unwrap()
+					.1;
+				position += 1;
+			}
+			k if UNSUPPORTED_MODEL_TERMS.contains(&k) => {
+				return Err(ModelParseError::general(
+					line,
+					&format!(
+						"Model type `{}` is not supported, only CTMCs, DTMCs, MDPs and CTMDPs are.",
+						k
+					),
+				));
+			}
+			"const" => {
+				position += 1;
+				let (line, statement) = take_statement(&tokens, &mut position)?;
+				let statement = match statement.first() {
+					Some(Token::Identifier(t)) if TYPE_TERMS.contains(&t.as_str()) => &statement[1..],
+					_ => &statement[..],
+				};
+				match statement {
+					// Overrides take the place of the value in the file, like PRISM's `-const`
+					[Token::Identifier(name), ..] if parameters.contains_key(name) => {
+						message!("Using {} = {} (overridden)", name, parameters[name]);
+						constants.insert(name.clone(), parameters[name]);
+					}
+					[Token::Identifier(name), Token::Symbol(eq), value @ ..] if eq == "=" => {
+						let value = evaluate_constant(line, &expand_formulas(value, &formulas), &constants)?;
+						constants.insert(name.clone(), value);
+					}
+					[Token::Identifier(name)] => {
+						return Err(ModelParseError::init_unspecified(line, name));
+					}
+					_ => {
+						return Err(ModelParseError::unexpected_token(
+							line,
+							&tokens_to_string(statement),
+						))
+					}
+				}
+			}
+			"formula" => {
+				position += 1;
+				let (line, statement) = take_statement(&tokens, &mut position)?;
+				match &statement[..] {
+					[Token::Identifier(name), Token::Symbol(eq), value @ ..] if eq == "=" => {
+						let value = expand_formulas(value, &formulas);
+						formulas.insert(name.clone(), value);
+					}
+					_ => {
+						return Err(ModelParseError::unexpected_token(
+							line,
+							&tokens_to_string(&statement),
+						))
+					}
+				}
+			}
+			"label" => {
+				position += 1;
+				let (line, statement) = take_statement(&tokens, &mut position)?;
+				match &statement[..] {
+					[Token::Quoted(name), Token::Symbol(eq), value @ ..] if eq == "=" => {
+						labels.insert(name.clone(), (line, expand_formulas(value, &formulas)));
+					}
+					_ => {
+						return Err(ModelParseError::unexpected_token(
+							line,
+							&tokens_to_string(&statement),
+						))
+					}
+				}
+			}
+			"module" => {
+				if current_module.is_some() {
+					return Err(ModelParseError::general(line, &"Modules cannot be nested."));
+				}
+				match tokens.get(position + 1) {
+					Some((_, Token::Identifier(name))) => {
+						if tokens
+							.get(position + 2)
+							.map_or(false, |(_, t)| is_symbol(t, "="))
+						{
+							return Err(ModelParseError::general(
+								line,
+								&"Module renaming is not supported.",
+							));
+						}
+						current_module = Some(module_names.len());
+						module_names.push(name.clone());
+					}
+					_ => return Err(ModelParseError::unexpected_token(line, &"module")),
+				}
+				position += 2;
+			}
+			"endmodule" => {
+				if current_module.is_none() {
+					return Err(ModelParseError::unexpected_token(line, &"endmodule"));
+				}
+				current_module = None;
+				position += 1;
+			}
+			"init" if current_module.is_none() => {
+				// The tokens up to `endinit`, read once the variables are known
+				position += 1;
+				let start = position;
+				while position < tokens.len()
+					&& tokens[position].1 != Token::Identifier("endinit".to_string())
+				{
+					position += 1;
+				}
+				if position == tokens.len() {
+					return Err(ModelParseError::general(line, &"Missing `endinit`."));
+				}
+				if initial_block.is_some() {
+					return Err(ModelParseError::general(
+						line,
+						&"There can only be one `init ... endinit` block.",
+					));
+				}
+				let block: Vec<Token> = tokens[start..position]
+					.iter()
+					.map(|(_, token)| token.clone())
+					.collect();
+				initial_block = Some((line, expand_formulas(&block, &formulas)));
+				position += 1;
+			}
+			"rewards" => {
+				// Reward structures do not affect the VAS, so skip them
+				while position < tokens.len()
+					&& tokens[position].1 != Token::Identifier("endrewards".to_string())
+				{
+					position += 1;
+				}
+				position += 1;
+			}
+			"[" => {
+				let (line, statement) = take_statement(&tokens, &mut position)?;
+				commands.push(parse_command(line, current_module.unwrap(), &statement)?);
+			}
+			_ if current_module.is_some() => {
+				// A variable declaration `name : int init value` or `name : [lo..hi] init value`
+				let (line, statement) = take_statement(&tokens, &mut position)?;
+				let (name, declaration) = match &statement[..] {
+					[Token::Identifier(name), Token::Symbol(colon), rest @ ..] if colon == ":" => (name, rest),
+					_ => {
+						return Err(ModelParseError::unexpected_token(
+							line,
+							&tokens_to_string(&statement),
+						))
+					}
+				};
+				let init = declaration
+					.iter()
+					.position(|t| *t == Token::Identifier("init".to_string()));
+				let variable_type = &declaration[..init.unwrap_or(declaration.len())];
+				match variable_type {
+					[Token::Identifier(t)] if t == "int" => {}
+					[Token::Symbol(open), .., Token::Symbol(close)] if open == "[" && close == "]" => {
+						warning!(
+							"Ignoring the range of variable `{}` (line {}): VAS variables are unbounded.",
+							name,
+							line
+						);
+					}
+					_ => {
+						return Err(ModelParseError::general(
+							line,
+							&format!(
+								"Variable `{}` has type `{}`, but only integer variables are supported.",
+								name,
+								tokens_to_string(variable_type)
+							),
+						))
+					}
+				}
+				let value = match init {
+					Some(index) => evaluate_integer(
+						line,
+						&expand_formulas(&declaration[index + 1..], &formulas),
+						&constants,
+					)
+					.map_err(|_| {
+						ModelParseError::invalid_init(line, &tokens_to_string(&declaration[index + 1..]))
+					})?,
+					None => 0,
+				};
+				if init.is_some() && initialized_variable.is_none() {
+					initialized_variable = Some((line, name.clone()));
+				}
+				if get_variable_id(&variable_names, name).is_some() {
+					return Err(ModelParseError::general(
+						line,
+						&format!("Variable `{}` is declared more than once.", name),
+					));
+				}
+				variable_names.push(name.clone());
+				initial_state.push(value);
+			}
+			other => {
+				return Err(ModelParseError::general(
+					line,
+					&format!("Unsupported PRISM construct `{}`.", other),
+				));
+			}
+		}
+	}
+	if current_module.is_some() {
+		return Err(ModelParseError::general(
+			tokens.last().map_or(0, |(line, _)| *line),
+			&"Missing `endmodule`.",
+		));
+	}
+
+	// Group commands into transitions: unlabeled commands stand alone, while labeled
+	// commands synchronize with the commands sharing the label in every other module.
+	let mut groups = Vec::<(String, Vec<Vec<&PrismCommand>>)>::new();
+	let mut unlabeled_count = HashMap::<usize, usize>::new();
+	for command in commands.iter() {
+		match &command.action {
+			None => {
+				let count = unlabeled_count.entry(command.module).or_insert(0);
+				*count += 1;
+				groups.push((
+					format!("{}_{}", module_names[command.module], count),
+					vec![vec![command]],
+				));
+			}
+			Some(action) => {
+				if groups.iter().any(|(name, _)| name == action) {
+					continue;
+				}
+				let per_module = (0..module_names.len())
+					.map(|module| {
+						commands
+							.iter()
+							.filter(|c| c.module == module && c.action.as_ref() == Some(action))
+							.collect::<Vec<_>>()
+					})
+					.filter(|module_commands| !module_commands.is_empty())
+					.collect::<Vec<_>>();
+				groups.push((action.clone(), per_module));
+			}
+		}
+	}
+
+	let num_variables = variable_names.len();
+	let mut transitions = Vec::<VasTransition>::new();
+	for (name, per_module) in groups {
+		let combinations = per_module
+			.into_iter()
+			.multi_cartesian_product()
+			.enumerate()
+			.flat_map(|(choice, commands)| {
+				commands
+					.into_iter()
+					.map(|c| c.branches.iter().map(move |b| (c, b)))
+					.multi_cartesian_product()
+					.map(move |combination| (choice, combination))
+					.collect::<Vec<_>>()
+			})
+			.collect::<Vec<_>>();
+		let multiple = combinations.len() > 1;
+		let multiple_choices = combinations.last().is_some_and(|(choice, _)| *choice > 0);
+		for (k, (choice, combination)) in combinations.into_iter().enumerate() {
+			let line = combination[0].0.line;
+			let mut increment = vec![0; num_variables];
+			let mut decrement = vec![0; num_variables];
+			let mut bounds = vec![0; num_variables];
+			let mut updated = vec![false; num_variables];
+			let mut rate_tokens = Vec::<Token>::new();
+			let mut satisfiable = true;
+			for (command, branch) in combination.iter() {
+				let rate = branch.rate.as_ref().map(|r| expand_formulas(r, &formulas));
+				satisfiable &= guard_lower_bounds(
+					command.line,
+					&expand_formulas(&command.guard, &formulas),
+					rate.as_deref(),
+					&variable_names,
+					&constants,
+					&mut bounds,
+				)?;
+				for update in branch.updates.iter() {
+					let (index, change) =
+						parse_update(command.line, update, &variable_names, &constants)?;
+					if updated[index] {
+						return Err(ModelParseError::general(
+							command.line,
+							&format!(
+								"Variable `{}` is updated more than once by `{}`.",
+								variable_names[index], name
+							),
+						));
+					}
+					updated[index] = true;
+					if change > 0 {
+						increment[index] += change;
+					} else {
+						decrement[index] -= change;
+					}
+				}
+				if let Some(rate) = rate {
+					if !rate_tokens.is_empty() {
+						rate_tokens.push(Token::Symbol("*".to_string()));
+					}
+					rate_tokens.push(Token::Symbol("(".to_string()));
+					rate_tokens.extend(rate);
+					rate_tokens.push(Token::Symbol(")".to_string()));
+				}
+			}
+			if !satisfiable {
+				warning!("Transition `{}` (line {}) can never be enabled, skipping it.", name, line);
+				continue;
+			}
+			if rate_tokens.is_empty() {
+				rate_tokens.push(Token::Number("1.0".to_string()));
+			}
+			let transition_name = if multiple {
+				format!("{}_{}", name, k)
+			} else {
+				name.clone()
+			};
+			let mut transition = build_transition(
+				line,
+				transitions.len(),
+				transition_name,
+				increment,
+				decrement,
+				bounds,
+				&rate_tokens,
+				&variable_names,
+				&constants,
+			)?;
+			if model_type.is_nondeterministic() {
+				transition.action = Some(if multiple_choices {
+					format!("{}_c{}", name, choice)
+				} else {
+					name.clone()
+				});
+			}
+			transitions.push(transition);
+		}
+	}
+
+	warn_unused_parameters(parameters, &constants);
+
+	// Read the target from a `target` label, falling back on the property file
+	let target = labels
+		.get("target")
+		.map(|(line, label)| target_from_tokens(*line, label, &variable_names, &constants))
+		.transpose()?
+		.or_else(|| deferred_target(filename))
+		.ok_or_else(|| {
+			ModelParseError::general(
+				0,
+				&"No target found. Add a `label \"target\" = X = v;` or a .prop file next to the model.",
+			)
+		})?;
+
+	// An `init ... endinit` block replaces the initial values of the variables, as in PRISM
+	let initial_states = match initial_block {
+		Some((line, block)) => {
+			if let Some((_, name)) = initialized_variable {
+				return Err(ModelParseError::general(
+					line,
+					&format!(
+						"Variable `{}` has an initial value, which is not allowed with an `init ... endinit` block.",
+						name
+					),
+				));
+			}
+			parse_initial_states(line, &block, &variable_names, &constants)?
+		}
+		None => vec![VasState::new(DVector::from_vec(initial_state))],
+	};
+
+	Ok(AbstractVas::new(
+		variable_names.into_boxed_slice(),
+		initial_states,
+		transitions,
+		target,
+		model_type,
+	))
+}
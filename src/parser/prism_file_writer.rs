@@ -0,0 +1,179 @@
+use std::{fs::File, io::Write};
+
+use crate::{
+	logging::messages::*,
+	model::{
+		model::ModelType,
+		vas_model::{AbstractVas, VasTransition},
+	},
+};
+
+/// Makes a name usable as a PRISM identifier
+fn prism_identifier(name: &str) -> String {
+	let identifier: String = name
+		.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+		.collect();
+	if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+		format!("_{}", identifier)
+	} else {
+		identifier
+	}
+}
+
+/// The name of the rate constant for a transition
+fn rate_constant_name(transition: &VasTransition) -> String {
+	format!("k_{}", prism_identifier(&transition.transition_name))
+}
+
+/// The mass-action rate expression implied by the enabled bounds, i.e.,
+/// `k * (X * (X - 1)) / 2 * Y` for a transition that needs two `X` and one `Y`
+/// under stochastic kinetics.
+fn mass_action_expression(model: &AbstractVas, transition: &VasTransition) -> String {
+	let reactants = transition
+		.enabled_bounds
+		.iter()
+		.enumerate()
+		.map(|(i, bound)| (prism_identifier(&model.variable_names[i]), *bound))
+		.collect::<Vec<_>>();
+	transition
+		.kinetics
+		.prism_expression(&rate_constant_name(transition), &reactants)
+}
+
+/// Outputs a model as a PRISM CTMC (or DTMC or MDP) with one module per species (synchronizing
+/// on the transition names) plus a `reaction_rates` module, in the same layout that the
+/// SBML-to-PRISM converter produces. The target is written as the label `"target"`.
+pub fn model_to_prism(model: &AbstractVas) -> String {
+	if model.m_type == ModelType::DiscreteTime && model.transitions.len() > 1 {
+		warning!(
+			"PRISM chooses uniformly between the commands of a DTMC that are enabled together, so the exported model only matches in states with a single enabled transition."
+		);
+	}
+	if model.m_type.is_nondeterministic() {
+		warning!(
+			"Actions are not exported: every transition of the {} becomes a choice of its own.",
+			model.m_type
+		);
+	}
+	// Several initial states are written as an `init ... endinit` block instead
+	let single_initial_state = model.initial_states.len() == 1;
+	if !single_initial_state
+		&& model
+			.initial_probabilities
+			.iter()
+			.any(|p| (p - model.initial_probabilities[0]).abs() > 1e-12)
+	{
+		warning!(
+			"PRISM has no initial distributions, so the probabilities of the {} initial states are not exported.",
+			model.initial_states.len()
+		);
+	}
+	let mut output = String::new();
+	output.push_str("// File generated by the STAMINA toolset\n\n");
+	output.push_str(&format!("{}\n\n", model.m_type));
+
+	output.push_str("// Rate constants\n");
+	for transition in model.transitions.iter() {
+		output.push_str(&format!(
+			"const double {} = {};\n",
+			rate_constant_name(transition),
+			transition.rate_const
+		));
+	}
+	output.push_str("\n");
+
+	for (i, name) in model.variable_names.iter().enumerate() {
+		let variable = prism_identifier(name);
+		output.push_str(&format!("// Species {}\n", name));
+		output.push_str(&format!("module {}\n\n", variable));
+		if single_initial_state {
+			output.push_str(&format!(
+				"\t{} : int init {};\n\n",
+				variable, model.initial_states[0].vector[i]
+			));
+		} else {
+			output.push_str(&format!("\t{} : int;\n\n", variable));
+		}
+		for transition in model.transitions.iter() {
+			let update = transition.update_vector[i];
+			let bound = transition.enabled_bounds[i];
+			if update == 0 && bound == 0 {
+				continue;
+			}
+			output.push_str(&format!(
+				"\t[{}] {} >= {} -> ({}' = {} {} {});\n",
+				prism_identifier(&transition.transition_name),
+				variable,
+				bound,
+				variable,
+				variable,
+				if update < 0 { "-" } else { "+" },
+				update.abs()
+			));
+		}
+		output.push_str("\nendmodule\n\n");
+	}
+
+	output.push_str("// Reaction rates\n");
+	output.push_str("module reaction_rates\n\n");
+	for transition in model.transitions.iter() {
+		if transition.custom_rate_fn.is_some() {
+			warning!(
+				"Transition {} has a custom rate function, which cannot be exported. Writing its mass-action rate instead.",
+				transition.transition_name
+			);
+			output.push_str("\t// WARNING: custom rate function replaced by mass-action rate\n");
+		}
+		output.push_str(&format!(
+			"\t[{}] true -> ({}) : true;\n",
+			prism_identifier(&transition.transition_name),
+			mass_action_expression(model, transition)
+		));
+	}
+	output.push_str("\nendmodule\n\n");
+
+	let prism_names = model
+		.variable_names
+		.iter()
+		.map(|name| prism_identifier(name))
+		.collect::<Vec<_>>();
+	if !single_initial_state {
+		let initial_states = model
+			.initial_states
+			.iter()
+			.map(|state| {
+				let values = prism_names
+					.iter()
+					.zip(state.vector.iter())
+					.map(|(name, value)| format!("{} = {}", name, value))
+					.collect::<Vec<_>>();
+				format!("({})", values.join(" & "))
+			})
+			.collect::<Vec<_>>();
+		output.push_str("init\n");
+		output.push_str(&format!("\t{}\n", initial_states.join(" | ")));
+		output.push_str("endinit\n\n");
+	}
+	output.push_str(&format!(
+		"label \"target\" = {};\n",
+		model.target.to_string_with_names(&prism_names)
+	));
+	output
+}
+
+/// Writes a model to a PRISM `.sm` file
+pub fn write_model(model: &AbstractVas, filename: &str) {
+	let mut file = match File::create(filename) {
+		Ok(f) => f,
+		Err(e) => {
+			error!("Error creating PRISM file {}: {}", filename, e);
+			return;
+		}
+	};
+	if let Err(e) = file.write_all(model_to_prism(model).as_bytes()) {
+		error!("Error writing PRISM file {}: {}", filename, e);
+		return;
+	}
+	message!("PRISM model written to: {}", filename);
+}
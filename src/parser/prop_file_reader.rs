@@ -0,0 +1,301 @@
+use std::{collections::HashMap, path::Path};
+
+use metaverify::trusted;
+
+use crate::{
+	logging::messages::*,
+	model::vas_model::VasTarget,
+	parser::{
+		prism_file_reader::{
+			evaluate_constant, split_top_level, strip_parens, target_from_tokens,
+			tokenize, tokens_to_string, Token,
+		},
+		vas_file_reader::ModelParseError,
+	},
+	property::property::{Property, PropertyQuery, StateFormula},
+	util::util::read_lines,
+};
+
+/// The properties of a model, as read from a PRISM `.prop` file
+#[derive(Debug, Default)]
+pub(crate) struct PropertyFile {
+	pub(crate) queries: Vec<PropertyQuery>,
+	pub(crate) labels: HashMap<String, StateFormula>,
+}
+
+/// Parses a state formula: relational atoms over model variables, `true`, `false`,
+/// labels (`"name"`), `!`, `&`, `|` and parentheses.
+#[trusted]
+pub(crate) fn parse_state_formula(
+	line: usize,
+	tokens: &[Token],
+	variable_names: &[String],
+	constants: &HashMap<String, f64>,
+) -> Result<StateFormula, ModelParseError> {
+	let tokens = strip_parens(tokens);
+	for (symbol, combine) in [
+		("|", StateFormula::Or as fn(Vec<StateFormula>) -> StateFormula),
+		("&", StateFormula::And as fn(Vec<StateFormula>) -> StateFormula),
+	] {
+		let operands = split_top_level(tokens, symbol);
+		if operands.len() > 1 {
+			return Ok(combine(
+				operands
+					.into_iter()
+					.map(|operand| parse_state_formula(line, operand, variable_names, constants))
+					.collect::<Result<Vec<_>, _>>()?,
+			));
+		}
+	}
+	match tokens {
+		[] => Err(ModelParseError::general(line, &"Empty state formula.")),
+		[Token::Symbol(not), rest @ ..] if not == "!" => Ok(StateFormula::Not(Box::new(
+			parse_state_formula(line, rest, variable_names, constants)?,
+		))),
+		[Token::Identifier(value)] if value == "true" => Ok(StateFormula::True),
+		[Token::Identifier(value)] if value == "false" => Ok(StateFormula::False),
+		[Token::Quoted(label)] => Ok(StateFormula::StateLabel(label.clone())),
+		_ => match target_from_tokens(line, tokens, variable_names, constants)? {
+			VasTarget::Relation(index, relation, value) => Ok(StateFormula::Relation {
+				variable: variable_names[index].clone(),
+				index,
+				relation,
+				value,
+			}),
+			_ => Err(ModelParseError::unexpected_token(
+				line,
+				&tokens_to_string(tokens),
+			)),
+		},
+	}
+}
+
+/// Reads an optional time bound `<=T` after `F` or `U`, returning the bound and the rest
+fn parse_time_bound<'t>(
+	line: usize,
+	tokens: &'t [Token],
+	constants: &HashMap<String, f64>,
+) -> Result<(Option<f64>, &'t [Token]), ModelParseError> {
+	match tokens {
+		[Token::Symbol(le), bound, rest @ ..] if le == "<=" => Ok((
+			Some(evaluate_constant(line, std::slice::from_ref(bound), constants)?),
+			rest,
+		)),
+		[Token::Symbol(op), ..] if ["<", ">", ">=", "["].contains(&op.as_str()) => {
+			Err(ModelParseError::general(
+				line,
+				&format!("Only upper time bounds `<=T` are supported, got `{}`.", op),
+			))
+		}
+		_ => Ok((None, tokens)),
+	}
+}
+
+/// Parses the path formula inside of `P=? [...]`: `F φ`, `F<=T φ`, `G φ` or `φ U<=T ψ`
+#[trusted]
+fn parse_path_formula(
+	line: usize,
+	tokens: &[Token],
+	variable_names: &[String],
+	constants: &HashMap<String, f64>,
+) -> Result<Property, ModelParseError> {
+	// `U` outside of any parentheses splits the two sides of an until
+	let mut depth = 0;
+	let mut until = None;
+	for (i, token) in tokens.iter().enumerate() {
+		match token {
+			Token::Symbol(s) if s == "(" => depth += 1,
+			Token::Symbol(s) if s == ")" => depth -= 1,
+			Token::Identifier(u) if u == "U" && depth == 0 => {
+				until = Some(i);
+				break;
+			}
+			_ => {}
+		}
+	}
+	match (tokens, until) {
+		(_, Some(index)) => {
+			let (bound, rest) = parse_time_bound(line, &tokens[index + 1..], constants)?;
+			Ok(Property::Until(
+				parse_state_formula(line, &tokens[..index], variable_names, constants)?,
+				parse_state_formula(line, rest, variable_names, constants)?,
+				bound,
+			))
+		}
+		([Token::Identifier(f), rest @ ..], None) if f == "F" => {
+			let (bound, rest) = parse_time_bound(line, rest, constants)?;
+			Ok(Property::Finally(
+				parse_state_formula(line, rest, variable_names, constants)?,
+				bound,
+			))
+		}
+		([Token::Identifier(g), rest @ ..], None) if g == "G" => {
+			let (bound, rest) = parse_time_bound(line, rest, constants)?;
+			if bound.is_some() {
+				return Err(ModelParseError::general(
+					line,
+					&"Time bounds on `G` are not supported.",
+				));
+			}
+			Ok(Property::Globally(parse_state_formula(
+				line,
+				rest,
+				variable_names,
+				constants,
+			)?))
+		}
+		_ => Err(ModelParseError::general(
+			line,
+			&format!(
+				"Unsupported path formula `{}`, expected `F`, `G` or `U`.",
+				tokens_to_string(tokens)
+			),
+		)),
+	}
+}
+
+/// Parses a query: `P=? [path]`, `Pmax=? [path]`, `Pmin=? [path]` or `S=? [φ]`
+#[trusted]
+pub(crate) fn parse_query(
+	line: usize,
+	tokens: &[Token],
+	variable_names: &[String],
+	constants: &HashMap<String, f64>,
+) -> Result<PropertyQuery, ModelParseError> {
+	let (operator, body) = match tokens {
+		[Token::Identifier(operator), Token::Symbol(eq), Token::Symbol(question), Token::Symbol(open), body @ .., Token::Symbol(close)]
+			if eq == "=" && question == "?" && open == "[" && close == "]" =>
+		{
+			(operator.as_str(), body)
+		}
+		_ => {
+			return Err(ModelParseError::general(
+				line,
+				&format!(
+					"Unsupported property `{}`, expected `P=? [...]`, `Pmax=? [...]`, `Pmin=? [...]` or `S=? [...]`.",
+					tokens_to_string(tokens)
+				),
+			))
+		}
+	};
+	match operator {
+		"P" => Ok(PropertyQuery::Probability(parse_path_formula(
+			line,
+			body,
+			variable_names,
+			constants,
+		)?)),
+		"Pmax" => Ok(PropertyQuery::MaxProbability(parse_path_formula(
+			line,
+			body,
+			variable_names,
+			constants,
+		)?)),
+		"Pmin" => Ok(PropertyQuery::MinProbability(parse_path_formula(
+			line,
+			body,
+			variable_names,
+			constants,
+		)?)),
+		"S" => Ok(PropertyQuery::SteadyState(parse_state_formula(
+			line,
+			body,
+			variable_names,
+			constants,
+		)?)),
+		other => Err(ModelParseError::unexpected_token(line, &other)),
+	}
+}
+
+/// Builds the properties from a PRISM `.prop` file, bound to the variables of a model.
+/// Besides the queries, the file may declare `const` values and `label`s; statements
+/// end with a `;` or at the end of the line.
+#[trusted]
+pub fn build_properties(
+	filename: &str,
+	variable_names: &[String],
+	parameters: &HashMap<String, f64>,
+) -> Result<PropertyFile, ModelParseError> {
+	let lines = read_lines(&filename).map_err(|_| {
+		ModelParseError::general(
+			0,
+			&"line-by-line file parsing not Ok. Check your property file.",
+		)
+	})?;
+	let mut constants = parameters.clone();
+	let mut properties = PropertyFile::default();
+	for (num, line) in lines.flatten().enumerate() {
+		let line_num = num + 1;
+		let tokens = tokenize(line_num, &line)?;
+		for statement in split_top_level(&tokens, ";") {
+			match statement {
+				[] => {}
+				[Token::Identifier(keyword), rest @ ..] if keyword == "const" => {
+					let rest = match rest {
+						[Token::Identifier(t), rest @ ..] if ["int", "double"].contains(&t.as_str()) => rest,
+						_ => rest,
+					};
+					match rest {
+						[Token::Identifier(name), ..] if parameters.contains_key(name) => {}
+						[Token::Identifier(name), Token::Symbol(eq), value @ ..] if eq == "=" => {
+							let value = evaluate_constant(line_num, value, &constants)?;
+							constants.insert(name.clone(), value);
+						}
+						_ => {
+							return Err(ModelParseError::unexpected_token(
+								line_num,
+								&tokens_to_string(statement),
+							))
+						}
+					}
+				}
+				[Token::Identifier(keyword), Token::Quoted(name), Token::Symbol(eq), formula @ ..]
+					if keyword == "label" && eq == "=" =>
+				{
+					let formula = parse_state_formula(line_num, formula, variable_names, &constants)?;
+					properties.labels.insert(name.clone(), formula);
+				}
+				// Named properties, `"name": P=? [...]`
+				[Token::Quoted(_), Token::Symbol(colon), query @ ..] if colon == ":" => {
+					properties
+						.queries
+						.push(parse_query(line_num, query, variable_names, &constants)?);
+				}
+				query => {
+					properties
+						.queries
+						.push(parse_query(line_num, query, variable_names, &constants)?);
+				}
+			}
+		}
+	}
+	debug_message!(
+		"Read {} properties and {} labels from {}",
+		properties.queries.len(),
+		properties.labels.len(),
+		filename
+	);
+	Ok(properties)
+}
+
+/// The path of the property file that belongs to a model file
+pub(crate) fn prop_file_for(filename: &str) -> Option<String> {
+	let prop_file = Path::new(filename).with_extension("prop");
+	if prop_file.exists() {
+		Some(prop_file.display().to_string())
+	} else {
+		None
+	}
+}
+
+/// The target of a model file without one of its own, if it has a `.prop` file next to
+/// it. It stands in until the model is loaded, when the goal of the first query in the
+/// `.prop` file replaces it (see `AbstractVas::load_properties`).
+pub(crate) fn deferred_target(filename: &str) -> Option<VasTarget> {
+	prop_file_for(filename).map(|_| VasTarget::And(Vec::new()))
+}
+
+/// Whether a target is the stand-in from `deferred_target`
+pub(crate) fn is_deferred_target(target: &VasTarget) -> bool {
+	matches!(target, VasTarget::And(targets) if targets.is_empty())
+}
@@ -0,0 +1,306 @@
+use std::{collections::HashMap, sync::Arc};
+
+use evalexpr::*;
+use itertools::Itertools;
+use metaverify::trusted;
+
+use crate::{
+	model::{model::ProbabilityOrRate, vas_model::VasState},
+	parser::{
+		prism_file_reader::{split_top_level, to_evalexpr, tokens_to_string, Token},
+		vas_file_reader::{get_variable_id, ModelParseError},
+	},
+};
+
+/// Rate laws that are always available, named and ordered as in Catalyst:
+/// `hill(X, v, K, n)`, `hillr(X, v, K, n)`, `mm(X, v, K)` and `mmr(X, v, K)`
+const RATE_LAWS: &[(&str, usize)] = &[("hill", 4), ("hillr", 4), ("mm", 3), ("mmr", 3)];
+
+/// Evaluates one of the `RATE_LAWS`
+fn rate_law(name: &str, args: &[f64]) -> Option<f64> {
+	match (name, args) {
+		("hill", [x, v, k, n]) => Some(v * x.powf(*n) / (k.powf(*n) + x.powf(*n))),
+		("hillr", [x, v, k, n]) => Some(v * k.powf(*n) / (k.powf(*n) + x.powf(*n))),
+		("mm", [x, v, k]) => Some(v * x / (k + x)),
+		("mmr", [x, v, k]) => Some(v * k / (k + x)),
+		_ => None,
+	}
+}
+
+/// A user-defined function of a model, e.g. `function dtet(x) = x^2 / (K^2 + x^2)`
+#[derive(Clone, Debug)]
+pub(crate) struct RateFunction {
+	parameters: Vec<String>,
+	body: Node<DefaultNumericTypes>,
+}
+
+/// The user-defined functions of a model, by name
+pub(crate) type RateFunctions = HashMap<String, RateFunction>;
+
+/// The evalexpr context a rate expression is evaluated in: the species it uses (or the
+/// arguments of the function being evaluated) plus the functions of the model.
+struct RateContext<'a> {
+	values: Vec<(String, Value<DefaultNumericTypes>)>,
+	functions: &'a RateFunctions,
+}
+
+impl<'a> Context for RateContext<'a> {
+	type NumericTypes = DefaultNumericTypes;
+
+	fn get_value(&self, identifier: &str) -> Option<&Value<Self::NumericTypes>> {
+		self.values
+			.iter()
+			.find(|(name, _)| name == identifier)
+			.map(|(_, value)| value)
+	}
+
+	fn call_function(
+		&self,
+		identifier: &str,
+		argument: &Value<Self::NumericTypes>,
+	) -> EvalexprResultValue<Self::NumericTypes> {
+		let args = match argument {
+			Value::Tuple(values) => values
+				.iter()
+				.map(|value| value.as_number())
+				.collect::<Result<Vec<_>, _>>()?,
+			Value::Empty => Vec::new(),
+			value => vec![value.as_number()?],
+		};
+		if let Some(function) = self.functions.get(identifier) {
+			if args.len() != function.parameters.len() {
+				return Err(EvalexprError::CustomMessage(format!(
+					"{} takes {} arguments, got {}",
+					identifier,
+					function.parameters.len(),
+					args.len()
+				)));
+			}
+			// Arguments shadow the species of the calling expression
+			let mut values = function
+				.parameters
+				.iter()
+				.cloned()
+				.zip(args.into_iter().map(Value::Float))
+				.collect::<Vec<_>>();
+			values.extend(self.values.iter().cloned());
+			let context = RateContext {
+				values,
+				functions: self.functions,
+			};
+			return function.body.eval_with_context(&context);
+		}
+		match rate_law(identifier, &args) {
+			Some(value) => Ok(Value::Float(value)),
+			None => Err(EvalexprError::FunctionIdentifierNotFound(
+				identifier.to_string(),
+			)),
+		}
+	}
+
+	fn are_builtin_functions_disabled(&self) -> bool {
+		false
+	}
+
+	fn set_builtin_functions_disabled(
+		&mut self,
+		disabled: bool,
+	) -> EvalexprResult<(), Self::NumericTypes> {
+		if disabled {
+			Err(EvalexprError::BuiltinFunctionsCannotBeDisabled)
+		} else {
+			Ok(())
+		}
+	}
+}
+
+/// Checks the functions an expression calls: they must be declared before it (so that
+/// functions cannot recurse), be one of the `RATE_LAWS` or be an evalexpr builtin.
+fn check_function_calls(
+	line: usize,
+	node: &Node<DefaultNumericTypes>,
+	functions: &RateFunctions,
+) -> Result<(), ModelParseError> {
+	for name in node.iter_function_identifiers().unique() {
+		let known = functions.contains_key(name)
+			|| RATE_LAWS.iter().any(|(law, _)| *law == name)
+			|| name.contains("::")
+			|| ["min", "max", "floor", "ceil", "round", "if"].contains(&name);
+		// evalexpr builtins check their own arguments on evaluation
+		if !known {
+			return Err(ModelParseError::general(
+				line,
+				&format!(
+					"Unknown function `{}`. Functions must be declared before they are used.",
+					name
+				),
+			));
+		}
+	}
+	Ok(())
+}
+
+/// Collects the species that an expression uses, directly or through the functions it
+/// calls, along with their index
+fn used_species(
+	node: &Node<DefaultNumericTypes>,
+	arguments: &[String],
+	functions: &RateFunctions,
+	variable_names: &[String],
+	used: &mut Vec<(String, usize)>,
+) {
+	for name in node.iter_variable_identifiers() {
+		if arguments.iter().any(|a| a == name) || used.iter().any(|(u, _)| u == name) {
+			continue;
+		}
+		if let Some(index) = get_variable_id(variable_names, name) {
+			used.push((name.to_string(), index));
+		}
+	}
+	for name in node.iter_function_identifiers() {
+		if let Some(function) = functions.get(name) {
+			used_species(
+				&function.body,
+				&function.parameters,
+				functions,
+				variable_names,
+				used,
+			);
+		}
+	}
+}
+
+/// Whether or not a rate is a rate law (depends on the species or calls a rate function),
+/// rather than a constant for mass-action kinetics
+pub(crate) fn is_rate_law(
+	tokens: &[Token],
+	variable_names: &[String],
+	functions: &RateFunctions,
+) -> bool {
+	tokens.iter().any(|token| match token {
+		Token::Identifier(name) => {
+			variable_names.contains(name)
+				|| functions.contains_key(name)
+				|| RATE_LAWS.iter().any(|(law, _)| law == name)
+		}
+		_ => false,
+	})
+}
+
+/// Parses a function declaration (without the keyword), `name(x, y) = expression`. The
+/// body may use its arguments, the species, the parameters and earlier functions.
+#[trusted]
+pub(crate) fn parse_function(
+	line: usize,
+	tokens: &[Token],
+	variable_names: &[String],
+	parameters: &HashMap<String, f64>,
+	functions: &RateFunctions,
+) -> Result<(String, RateFunction), ModelParseError> {
+	let (name, arguments, body) = match tokens {
+		[Token::Identifier(name), Token::Symbol(open), rest @ ..] if open == "(" => {
+			let close = rest
+				.iter()
+				.position(|token| matches!(token, Token::Symbol(s) if s == ")"))
+				.ok_or_else(|| {
+					ModelParseError::unexpected_token(line, &tokens_to_string(tokens))
+				})?;
+			match &rest[close + 1..] {
+				[Token::Symbol(eq), body @ ..] if eq == "=" && !body.is_empty() => {
+					(name, &rest[..close], body)
+				}
+				_ => {
+					return Err(ModelParseError::unexpected_token(
+						line,
+						&tokens_to_string(tokens),
+					))
+				}
+			}
+		}
+		_ => {
+			return Err(ModelParseError::unexpected_token(
+				line,
+				&tokens_to_string(tokens),
+			))
+		}
+	};
+	let mut argument_names = Vec::new();
+	if !arguments.is_empty() {
+		for argument in split_top_level(arguments, ",") {
+			match argument {
+				[Token::Identifier(argument)] => argument_names.push(argument.clone()),
+				_ => {
+					return Err(ModelParseError::unexpected_token(
+						line,
+						&tokens_to_string(argument),
+					))
+				}
+			}
+		}
+	}
+	if functions.contains_key(name) || RATE_LAWS.iter().any(|(law, _)| law == name) {
+		return Err(ModelParseError::general(
+			line,
+			&format!("Function `{}` is declared more than once.", name),
+		));
+	}
+	// Arguments shadow the parameters with the same name
+	let mut constants = parameters.clone();
+	for argument in argument_names.iter() {
+		constants.remove(argument);
+	}
+	let expression = to_evalexpr(body, &constants);
+	let node: Node<DefaultNumericTypes> = build_operator_tree(&expression)
+		.map_err(|e| ModelParseError::general(line, &format!("`{}`: {}", expression, e)))?;
+	for identifier in node.iter_variable_identifiers() {
+		if !argument_names.iter().any(|a| a == identifier)
+			&& get_variable_id(variable_names, identifier).is_none()
+		{
+			return Err(ModelParseError::unspecified_variable(line, &identifier));
+		}
+	}
+	check_function_calls(line, &node, functions)?;
+	Ok((
+		name.clone(),
+		RateFunction {
+			parameters: argument_names,
+			body: node,
+		},
+	))
+}
+
+/// Compiles a rate expression over the species, parameters and functions of a model into
+/// a rate function. An expression that cannot be evaluated at a state has the rate `NaN`,
+/// which the validator reports.
+#[trusted]
+pub(crate) fn compile_rate_expression(
+	line: usize,
+	tokens: &[Token],
+	variable_names: &[String],
+	parameters: &HashMap<String, f64>,
+	functions: &RateFunctions,
+) -> Result<Arc<dyn Fn(&VasState) -> ProbabilityOrRate + Send + Sync + 'static>, ModelParseError> {
+	let expression = to_evalexpr(tokens, parameters);
+	let node: Node<DefaultNumericTypes> = build_operator_tree(&expression)
+		.map_err(|e| ModelParseError::general(line, &format!("`{}`: {}", expression, e)))?;
+	for name in node.iter_variable_identifiers() {
+		if get_variable_id(variable_names, name).is_none() {
+			return Err(ModelParseError::unspecified_variable(line, &name));
+		}
+	}
+	check_function_calls(line, &node, functions)?;
+	let mut used_variables = Vec::new();
+	used_species(&node, &[], functions, variable_names, &mut used_variables);
+	let functions = functions.clone();
+	Ok(Arc::new(move |state: &VasState| {
+		let context = RateContext {
+			values: used_variables
+				.iter()
+				.map(|(name, index)| (name.clone(), Value::Float(state.vector[*index] as f64)))
+				.collect(),
+			functions: &functions,
+		};
+		node.eval_number_with_context(&context)
+			.unwrap_or(ProbabilityOrRate::NAN)
+	}))
+}
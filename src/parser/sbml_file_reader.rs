@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+
+use metaverify::trusted;
+use nalgebra::DVector;
+use roxmltree::{Document, Node};
+
+use crate::{
+	logging::messages::*,
+	model::{
+		model::ModelType,
+		vas_model::{AbstractVas, VasState, VasTransition, VasValue},
+	},
+	parser::{
+		prism_file_reader::{
+			build_transition, split_top_level, strip_parens, tokens_to_string, Token,
+		},
+		prop_file_reader::deferred_target,
+		vas_file_reader::{get_variable_id, warn_unused_parameters, ModelParseError},
+	},
+};
+
+/// MathML operators that translate to an infix PRISM operator
+const INFIX_OPERATORS: &[(&str, &str)] = &[
+	("plus", "+"),
+	("times", "*"),
+	("divide", "/"),
+	("minus", "-"),
+	("and", "&"),
+	("or", "|"),
+	("eq", "="),
+	("neq", "!="),
+	("gt", ">"),
+	("lt", "<"),
+	("geq", ">="),
+	("leq", "<="),
+];
+/// MathML functions and the (PRISM or evalexpr) functions they translate to
+const FUNCTION_OPERATORS: &[(&str, &str)] = &[
+	("power", "pow"),
+	("exp", "math::exp"),
+	("ln", "math::ln"),
+	("floor", "floor"),
+	("ceiling", "ceil"),
+	("abs", "math::abs"),
+];
+
+/// The line in the SBML document where a node starts
+fn line_of(doc: &Document, node: Node) -> usize {
+	doc.text_pos_at(node.range().start).row as usize
+}
+
+/// The first child element with the tag `name`
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+	node.children()
+		.find(|c| c.is_element() && c.tag_name().name() == name)
+}
+
+/// The elements `item` inside of the list element `list`, e.g., the `species` in
+/// `listOfSpecies`
+fn list_of<'a, 'input>(
+	node: Node<'a, 'input>,
+	list: &str,
+	item: &'static str,
+) -> Vec<Node<'a, 'input>> {
+	child(node, list)
+		.map(|l| {
+			l.children()
+				.filter(|c| c.is_element() && c.tag_name().name() == item)
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+/// Reads a required attribute, pointing at the element if it is missing
+fn required_attribute<'a>(
+	doc: &Document,
+	node: Node<'a, '_>,
+	name: &str,
+) -> Result<&'a str, ModelParseError> {
+	node.attribute(name).ok_or_else(|| {
+		ModelParseError::general(
+			line_of(doc, node),
+			&format!(
+				"<{}> is missing the attribute `{}`.",
+				node.tag_name().name(),
+				name
+			),
+		)
+	})
+}
+
+/// Reads a numeric attribute, pointing at the element if it is not a number
+fn float_attribute(
+	doc: &Document,
+	node: Node,
+	name: &str,
+) -> Result<Option<f64>, ModelParseError> {
+	match node.attribute(name) {
+		Some(value) => value
+			.trim()
+			.parse::<f64>()
+			.map(Some)
+			.map_err(|_| ModelParseError::expected_float(line_of(doc, node), &value)),
+		None => Ok(None),
+	}
+}
+
+/// Converts a float that must be a whole number into a VAS value
+fn whole_number(doc: &Document, node: Node, value: f64) -> Result<VasValue, ModelParseError> {
+	if value.fract() != 0.0 {
+		return Err(ModelParseError::expected_integer(line_of(doc, node), &value));
+	}
+	Ok(value as VasValue)
+}
+
+fn parenthesized(tokens: Vec<Token>) -> Vec<Token> {
+	let mut output = vec![Token::Symbol("(".to_string())];
+	output.extend(tokens);
+	output.push(Token::Symbol(")".to_string()));
+	output
+}
+
+/// Translates a content MathML element into PRISM expression tokens, so that it can go
+/// through the same rate handling as PRISM models.
+#[trusted]
+fn mathml_to_tokens(doc: &Document, node: Node) -> Result<Vec<Token>, ModelParseError> {
+	let line = line_of(doc, node);
+	let unsupported = |what: &str| {
+		ModelParseError::general(
+			line,
+			&format!("Unsupported MathML element <{}> in kinetic law.", what),
+		)
+	};
+	let elements = node.children().filter(|c| c.is_element()).collect::<Vec<_>>();
+	match node.tag_name().name() {
+		"math" => match elements.as_slice() {
+			[expression] => mathml_to_tokens(doc, *expression),
+			_ => Err(ModelParseError::general(line, &"<math> must contain exactly one expression.")),
+		},
+		"ci" => Ok(vec![Token::Identifier(
+			node.text().unwrap_or("").trim().to_string(),
+		)]),
+		"cn" => {
+			// e-notation numbers are written `<cn type="e-notation"> 1 <sep/> -3 </cn>`
+			let parts = node
+				.children()
+				.filter(|c| c.is_text())
+				.filter_map(|c| c.text())
+				.map(|t| t.trim())
+				.filter(|t| !t.is_empty())
+				.collect::<Vec<_>>();
+			let number = match (node.attribute("type"), parts.as_slice()) {
+				(Some("e-notation"), [mantissa, exponent]) => format!("{}e{}", mantissa, exponent),
+				(Some("rational"), [numerator, denominator]) => {
+					return Ok(parenthesized(vec![
+						Token::Number(numerator.to_string()),
+						Token::Symbol("/".to_string()),
+						Token::Number(denominator.to_string()),
+					]))
+				}
+				(_, [value]) => value.to_string(),
+				_ => return Err(ModelParseError::expected_float(line, &parts.join(" "))),
+			};
+			Ok(vec![Token::Number(number)])
+		}
+		"true" | "false" => Ok(vec![Token::Identifier(node.tag_name().name().to_string())]),
+		"apply" => {
+			let (operator, arguments) = elements
+				.split_first()
+				.ok_or_else(|| unsupported("apply"))?;
+			let mut operands = Vec::new();
+			for argument in arguments {
+				operands.push(parenthesized(mathml_to_tokens(doc, *argument)?));
+			}
+			let name = operator.tag_name().name();
+			if let Some((_, symbol)) = INFIX_OPERATORS.iter().find(|(op, _)| *op == name) {
+				if operands.len() == 1 && name == "minus" {
+					let mut tokens = vec![Token::Symbol("-".to_string())];
+					tokens.extend(operands.remove(0));
+					return Ok(tokens);
+				}
+				if operands.is_empty() {
+					return Err(unsupported(name));
+				}
+				let mut tokens = Vec::new();
+				for (i, operand) in operands.into_iter().enumerate() {
+					if i > 0 {
+						tokens.push(Token::Symbol(symbol.to_string()));
+					}
+					tokens.extend(operand);
+				}
+				Ok(tokens)
+			} else if name == "ci" {
+				Err(ModelParseError::general(
+					line,
+					&format!(
+						"Call to function `{}`: SBML function definitions are not supported.",
+						operator.text().unwrap_or("").trim()
+					),
+				))
+			} else if let Some((_, function)) = FUNCTION_OPERATORS.iter().find(|(op, _)| *op == name) {
+				let mut tokens = vec![
+					Token::Identifier(function.to_string()),
+					Token::Symbol("(".to_string()),
+				];
+				for (i, operand) in operands.into_iter().enumerate() {
+					if i > 0 {
+						tokens.push(Token::Symbol(",".to_string()));
+					}
+					tokens.extend(operand);
+				}
+				tokens.push(Token::Symbol(")".to_string()));
+				Ok(tokens)
+			} else {
+				Err(unsupported(name))
+			}
+		}
+		other => Err(unsupported(other)),
+	}
+}
+
+/// Reads the species and stoichiometry of a `speciesReference`
+fn species_reference(
+	doc: &Document,
+	reference: Node,
+	variable_names: &[String],
+) -> Result<(usize, VasValue), ModelParseError> {
+	let species = required_attribute(doc, reference, "species")?;
+	let index = get_variable_id(variable_names, species)
+		.ok_or_else(|| ModelParseError::unspecified_variable(line_of(doc, reference), &species))?;
+	let stoichiometry = float_attribute(doc, reference, "stoichiometry")?.unwrap_or(1.0);
+	Ok((index, whole_number(doc, reference, stoichiometry)?))
+}
+
+/// Builds a VAS model from an SBML (Level 3, or Level 2 without rules/events) file.
+/// Species become variables, reactions become transitions, and mass-action kinetic laws
+/// become rate constants. Other kinetic laws are compiled into custom rate functions.
+/// Global parameters can be overridden through `parameters`.
+#[trusted]
+pub fn build_model(
+	filename: &str,
+	parameters: &HashMap<String, f64>,
+) -> Result<AbstractVas, ModelParseError> {
+	let text = std::fs::read_to_string(filename).map_err(|_| {
+		ModelParseError::general(0, &"Unable to read the SBML file. Check your model file.")
+	})?;
+	let doc = Document::parse(&text)
+		.map_err(|e| ModelParseError::general(e.pos().row as usize, &e))?;
+	let sbml = doc.root_element();
+	if sbml.tag_name().name() != "sbml" {
+		return Err(ModelParseError::unexpected_token(
+			line_of(&doc, sbml),
+			&sbml.tag_name().name(),
+		));
+	}
+	let model = child(sbml, "model")
+		.ok_or_else(|| ModelParseError::general(line_of(&doc, sbml), &"<sbml> has no <model>."))?;
+
+	// Rules and events change species outside of reactions, which a VAS cannot represent
+	for unsupported in ["listOfRules", "listOfEvents", "listOfFunctionDefinitions"] {
+		if let Some(list) = child(model, unsupported) {
+			if list.children().any(|c| c.is_element()) {
+				return Err(ModelParseError::general(
+					line_of(&doc, list),
+					&format!("<{}> is not supported.", unsupported),
+				));
+			}
+		}
+	}
+
+	// Compartment sizes and global parameters are constants
+	let mut constants = HashMap::<String, f64>::new();
+	for compartment in list_of(model, "listOfCompartments", "compartment") {
+		let id = required_attribute(&doc, compartment, "id")?;
+		constants.insert(
+			id.to_string(),
+			float_attribute(&doc, compartment, "size")?.unwrap_or(1.0),
+		);
+	}
+	for parameter in list_of(model, "listOfParameters", "parameter") {
+		let id = required_attribute(&doc, parameter, "id")?;
+		let value = match parameters.get(id) {
+			Some(value) => *value,
+			None => float_attribute(&doc, parameter, "value")?.ok_or_else(|| {
+				ModelParseError::init_unspecified(line_of(&doc, parameter), &id)
+			})?,
+		};
+		constants.insert(id.to_string(), value);
+	}
+	warn_unused_parameters(parameters, &constants);
+
+	// Species are the variables
+	let mut variable_names = Vec::<String>::new();
+	let mut initial_state = Vec::<VasValue>::new();
+	let mut boundary = Vec::<bool>::new();
+	for species in list_of(model, "listOfSpecies", "species") {
+		let id = required_attribute(&doc, species, "id")?;
+		let amount = match float_attribute(&doc, species, "initialAmount")? {
+			Some(amount) => amount,
+			None => {
+				let concentration = float_attribute(&doc, species, "initialConcentration")?
+					.ok_or_else(|| ModelParseError::init_unspecified(line_of(&doc, species), &id))?;
+				warning!(
+					"Species {} has an initial concentration, interpreting it as a molecule count.",
+					id
+				);
+				concentration
+			}
+		};
+		variable_names.push(id.to_string());
+		initial_state.push(
+			whole_number(&doc, species, amount)
+				.map_err(|_| ModelParseError::invalid_init(line_of(&doc, species), &amount))?,
+		);
+		boundary.push(species.attribute("boundaryCondition") == Some("true"));
+	}
+
+	let num_variables = variable_names.len();
+	let mut transitions = Vec::<VasTransition>::new();
+	for reaction in list_of(model, "listOfReactions", "reaction") {
+		let line = line_of(&doc, reaction);
+		let id = required_attribute(&doc, reaction, "id")?;
+		let mut increment = vec![0; num_variables];
+		let mut decrement = vec![0; num_variables];
+		for reference in list_of(reaction, "listOfReactants", "speciesReference") {
+			let (index, stoichiometry) = species_reference(&doc, reference, &variable_names)?;
+			decrement[index] += stoichiometry;
+			// Boundary species are needed, but never used up
+			if boundary[index] {
+				increment[index] += stoichiometry;
+			}
+		}
+		for reference in list_of(reaction, "listOfProducts", "speciesReference") {
+			let (index, stoichiometry) = species_reference(&doc, reference, &variable_names)?;
+			if !boundary[index] {
+				increment[index] += stoichiometry;
+			}
+		}
+
+		let law = child(reaction, "kineticLaw").ok_or_else(|| {
+			ModelParseError::general(line, &format!("Reaction {} has no <kineticLaw>.", id))
+		})?;
+		// Local parameters shadow the global ones
+		let mut local_constants = constants.clone();
+		for parameter in list_of(law, "listOfLocalParameters", "localParameter")
+			.into_iter()
+			.chain(list_of(law, "listOfParameters", "parameter"))
+		{
+			let parameter_id = required_attribute(&doc, parameter, "id")?;
+			let value = float_attribute(&doc, parameter, "value")?.ok_or_else(|| {
+				ModelParseError::init_unspecified(line_of(&doc, parameter), &parameter_id)
+			})?;
+			local_constants.insert(parameter_id.to_string(), value);
+		}
+		let math = child(law, "math").ok_or_else(|| {
+			ModelParseError::general(line_of(&doc, law), &"<kineticLaw> has no <math>.")
+		})?;
+		let rate_tokens = mathml_to_tokens(&doc, math)?;
+		let line = line_of(&doc, math);
+
+		// A reversible reaction with a law `forward - backward` becomes two transitions
+		if reaction.attribute("reversible") == Some("true") {
+			let parts = split_top_level(strip_parens(&rate_tokens), "-");
+			if let [forward, backward] = parts.as_slice() {
+				if !forward.is_empty() {
+					let forward_transition = build_transition(
+						line,
+						transitions.len(),
+						id.to_string(),
+						increment.clone(),
+						decrement.clone(),
+						vec![0; num_variables],
+						forward,
+						&variable_names,
+						&local_constants,
+					)?;
+					let backward_transition = build_transition(
+						line,
+						transitions.len() + 1,
+						format!("{}_rev", id),
+						decrement.clone(),
+						increment.clone(),
+						vec![0; num_variables],
+						backward,
+						&variable_names,
+						&local_constants,
+					)?;
+					if forward_transition.custom_rate_fn.is_none()
+						&& backward_transition.custom_rate_fn.is_none()
+					{
+						transitions.push(forward_transition);
+						transitions.push(backward_transition);
+						continue;
+					}
+				}
+			}
+			warning!(
+				"Reversible reaction {} (line {}) does not have a mass-action `forward - backward` law, importing it as a single transition with the net rate.",
+				id,
+				line
+			);
+		}
+
+		let transition = build_transition(
+			line,
+			transitions.len(),
+			id.to_string(),
+			increment,
+			decrement,
+			vec![0; num_variables],
+			&rate_tokens,
+			&variable_names,
+			&local_constants,
+		)?;
+		if transition.custom_rate_fn.is_some() {
+			warning!(
+				"Reaction {} (line {}) does not follow mass-action kinetics, using the rate law `{}` as a custom rate function.",
+				id,
+				line,
+				tokens_to_string(&rate_tokens)
+			);
+		}
+		transitions.push(transition);
+	}
+
+	// SBML has no notion of a target, so it comes from the property file
+	let target = deferred_target(filename).ok_or_else(|| {
+		ModelParseError::general(0, &"No target found. Add a .prop file next to the model.")
+	})?;
+	debug_message!(
+		"Imported {} species and {} transitions from SBML",
+		variable_names.len(),
+		transitions.len()
+	);
+
+	Ok(AbstractVas::new(
+		variable_names.into_boxed_slice(),
+		vec![VasState::new(DVector::from_vec(initial_state))],
+		transitions,
+		target,
+		ModelType::ContinuousTime,
+	))
+}
@@ -1,4 +1,8 @@
-use std::fmt;
+use std::{
+	collections::HashMap,
+	fmt,
+	path::{Path, PathBuf},
+};
 
 use metaverify::trusted;
 use nalgebra::DVector;
@@ -6,8 +10,16 @@ use nalgebra::DVector;
 use crate::{
 	logging::messages::*,
 	model::{
-		model::{AbstractModel, ProbabilityOrRate},
-		vas_model::{AbstractVas, VasProperty, VasState, VasTransition, VasValue},
+		model::{AbstractModel, ModelType, ProbabilityOrRate},
+		propensity::Kinetics,
+		vas_model::{AbstractVas, VasState, VasTarget, VasTransition, VasValue},
+	},
+	parser::{
+		prism_file_reader::{
+			evaluate_constant, split_top_level, target_from_tokens, tokenize, Token,
+		},
+		prop_file_reader::deferred_target,
+		rate_expression::{compile_rate_expression, is_rate_law, parse_function, RateFunctions},
 	},
 	util::util::read_lines,
 };
@@ -19,6 +31,17 @@ const DECREASE_TERMS: &[&str] = &["consume", "decrease", "decrement"];
 const INCREASE_TERMS: &[&str] = &["produce", "increase", "increment"];
 const RATE_TERMS: &[&str] = &["rate", "const"];
 const TARGET_TERMS: &[&str] = &["target", "goal", "prop", "check"];
+const PARAMETER_TERMS: &[&str] = &["param", "parameter"];
+const FUNCTION_TERMS: &[&str] = &["function", "func"];
+const ACTION_TERMS: &[&str] = &["action"];
+const ARROW_TERMS: &[&str] = &["<->", "->"];
+const EMPTY_TERMS: &[&str] = &["0", "∅"];
+const MODEL_TYPE_TERMS: &[(&str, ModelType)] = &[
+	("ctmc", ModelType::ContinuousTime),
+	("dtmc", ModelType::DiscreteTime),
+	("mdp", ModelType::NondeterministicDiscreteTime),
+	("ctmdp", ModelType::NondeterministicContinuousTime),
+];
 
 #[trusted]
 #[derive(Clone, Debug)]
@@ -55,112 +78,286 @@ impl ToString for ModelParseErrorType {
 	}
 }
 #[trusted]
+impl ModelParseErrorType {
+	/// The offending text, if the error has one, used to find the error in its line
+	#[trusted]
+	fn payload(&self) -> Option<&str> {
+		match self {
+			Self::InvalidInitialVariableCount(text)
+			| Self::InitUnspecified(text)
+			| Self::UnexpextedTokenError(text)
+			| Self::ExpectedInteger(text)
+			| Self::ExpectedFloat(text)
+			| Self::UnspecifiedTransitionError(text)
+			| Self::UnspecifiedVariableError(text) => Some(text),
+			Self::GeneralParseError(_) => None,
+		}
+	}
+}
+#[trusted]
 #[derive(Clone, Debug)]
 pub struct ModelParseError {
 	line: usize,
 	etype: ModelParseErrorType,
+	file: Option<String>,
+	source_line: Option<String>,
 }
 #[trusted]
 impl fmt::Display for ModelParseError {
 	#[trusted]
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		let (line_num, line_content) = self.line();
-		let col = self.column();
-		let err_str = self.to_string();
-		let marker = if col.is_some() {
-			let col_n = col.unwrap();
-			format!(
-				"{}^{}",
-				" ".repeat(col_n as usize),
-				"-".repeat(line_content.len() - col_n as usize - 1)
+		let file = self.file.clone().unwrap_or("<model>".to_string());
+		let err_str = self.etype.to_string();
+		if line_num == 0 || line_content.is_empty() {
+			return write!(
+				f,
+				"[Parse Error] Error in model parsing. Unable to parse model!\n{}:{}\n{}",
+				file, line_num, err_str
+			);
+		}
+		// Keep tabs in the indentation of the marker, so it lines up with the source
+		let indent = |n: usize| -> String {
+			line_content
+				.chars()
+				.take(n)
+				.map(|c| if c == '\t' { '\t' } else { ' ' })
+				.collect()
+		};
+		let (location, marker) = if let Some((col_n, len)) = self.column() {
+			(
+				format!("{}:{}:{}", file, line_num, col_n + 1),
+				format!("{}{}", indent(col_n), "^".repeat(len.max(1))),
 			)
 		} else {
-			"^".repeat(line_content.len())
+			// No column, so mark the whole line
+			let leading = line_content.chars().count() - line_content.trim_start().chars().count();
+			(
+				format!("{}:{}", file, line_num),
+				format!(
+					"{}{}",
+					indent(leading),
+					"^".repeat(line_content.trim().chars().count().max(1))
+				),
+			)
 		};
 		write!(
 			f,
-			"[Parse Error] Error in model parsing. Unable to parse model!\n{}: {}\n{}\n{}",
-			line_num, line_content, marker, err_str
+			"[Parse Error] Error in model parsing. Unable to parse model!\n{}\n{}\n{}\n{}",
+			location, line_content, marker, err_str
 		)
 	}
 }
 #[trusted]
 impl ModelParseError {
 	#[trusted]
-	fn line(&self) -> (usize, String) {
-		(self.line, self.etype.to_string())
+	fn new(line: usize, etype: ModelParseErrorType) -> Self {
+		Self {
+			line,
+			etype,
+			file: None,
+			source_line: None,
+		}
 	}
+	/// The line number and the content of that line in the source
 	#[trusted]
-	fn column(&self) -> Option<u32> {
-		None
+	fn line(&self) -> (usize, String) {
+		(self.line, self.source_line.clone().unwrap_or_default())
 	}
+	/// The (0-based, in characters) column and length of the offending text in the
+	/// source line, if it can be found there
 	#[trusted]
-	fn invalid_init(line: usize, count: &dyn ToString) -> Self {
-		Self {
-			line,
-			etype: ModelParseErrorType::InvalidInitialVariableCount(count.to_string()),
+	fn column(&self) -> Option<(usize, usize)> {
+		let source_line = self.source_line.as_ref()?;
+		let payload = self.etype.payload()?.trim();
+		if payload.is_empty() {
+			return None;
 		}
+		let start = source_line.find(payload)?;
+		Some((
+			source_line[..start].chars().count(),
+			payload.chars().count(),
+		))
 	}
+	/// Attaches the location of the error in the source
 	#[trusted]
-	fn init_unspecified(line: usize, name: &dyn ToString) -> Self {
-		Self {
-			line,
-			etype: ModelParseErrorType::InitUnspecified(name.to_string()),
-		}
+	pub(crate) fn located(mut self, file: &str, line: usize, source_line: &str) -> Self {
+		self.file = Some(file.to_string());
+		self.line = line;
+		self.source_line = Some(source_line.to_string());
+		self
 	}
+	/// Attaches the file of the error and, if the error has a (1-based) line, the
+	/// content of that line. Errors that are already located are unchanged.
 	#[trusted]
-	fn unexpected_token(line: usize, token: &dyn ToString) -> Self {
-		Self {
-			line,
-			etype: ModelParseErrorType::UnexpextedTokenError(token.to_string()),
+	pub(crate) fn with_source_from_file(mut self, filename: &str) -> Self {
+		if self.file.is_some() {
+			return self;
+		}
+		self.file = Some(filename.to_string());
+		if self.line > 0 {
+			self.source_line = read_lines(filename)
+				.ok()
+				.and_then(|mut lines| lines.nth(self.line - 1))
+				.and_then(|line| line.ok());
 		}
+		self
 	}
 	#[trusted]
-	fn expected_integer(line: usize, value: &dyn ToString) -> Self {
-		Self {
+	pub(crate) fn invalid_init(line: usize, count: &dyn ToString) -> Self {
+		Self::new(
 			line,
-			etype: ModelParseErrorType::ExpectedInteger(value.to_string()),
-		}
+			ModelParseErrorType::InvalidInitialVariableCount(count.to_string()),
+		)
 	}
 	#[trusted]
-	fn expected_float(line: usize, value: &dyn ToString) -> Self {
-		Self {
-			line,
-			etype: ModelParseErrorType::ExpectedFloat(value.to_string()),
-		}
+	pub(crate) fn init_unspecified(line: usize, name: &dyn ToString) -> Self {
+		Self::new(line, ModelParseErrorType::InitUnspecified(name.to_string()))
 	}
 	#[trusted]
-	fn unspecified_transition(line: usize, tname: &dyn ToString) -> Self {
-		Self {
+	pub(crate) fn unexpected_token(line: usize, token: &dyn ToString) -> Self {
+		Self::new(
 			line,
-			etype: ModelParseErrorType::UnspecifiedTransitionError(tname.to_string()),
-		}
+			ModelParseErrorType::UnexpextedTokenError(token.to_string()),
+		)
 	}
 	#[trusted]
-	fn unspecified_variable(line: usize, vname: &dyn ToString) -> Self {
-		Self {
+	pub(crate) fn expected_integer(line: usize, value: &dyn ToString) -> Self {
+		Self::new(line, ModelParseErrorType::ExpectedInteger(value.to_string()))
+	}
+	#[trusted]
+	pub(crate) fn expected_float(line: usize, value: &dyn ToString) -> Self {
+		Self::new(line, ModelParseErrorType::ExpectedFloat(value.to_string()))
+	}
+	#[trusted]
+	pub(crate) fn unspecified_transition(line: usize, tname: &dyn ToString) -> Self {
+		Self::new(
 			line,
-			etype: ModelParseErrorType::UnspecifiedVariableError(vname.to_string()),
-		}
+			ModelParseErrorType::UnspecifiedTransitionError(tname.to_string()),
+		)
 	}
 	#[trusted]
-	fn general(line: usize, desc: &dyn ToString) -> Self {
-		Self {
+	pub(crate) fn unspecified_variable(line: usize, vname: &dyn ToString) -> Self {
+		Self::new(
 			line,
-			etype: ModelParseErrorType::GeneralParseError(desc.to_string()),
-		}
+			ModelParseErrorType::UnspecifiedVariableError(vname.to_string()),
+		)
+	}
+	#[trusted]
+	pub(crate) fn general(line: usize, desc: &dyn ToString) -> Self {
+		Self::new(line, ModelParseErrorType::GeneralParseError(desc.to_string()))
 	}
 }
 #[trusted]
-fn get_variable_id(v: &[String], name: &str) -> Option<usize> {
+pub(crate) fn get_variable_id(v: &[String], name: &str) -> Option<usize> {
 	v.iter().position(|r| r == name)
 }
 
+/// Evaluates an arithmetic expression over the parameters (e.g., `k1*Cell/2`)
+#[trusted]
+fn evaluate_expression(
+	line: usize,
+	expression: &str,
+	parameters: &HashMap<String, f64>,
+) -> Result<f64, ModelParseError> {
+	evaluate_constant(line, &tokenize(line, expression)?, parameters)
+}
+
+/// Evaluates an arithmetic expression over the parameters that must be a whole number
+#[trusted]
+fn evaluate_count(
+	line: usize,
+	expression: &str,
+	parameters: &HashMap<String, f64>,
+) -> Result<VasValue, ModelParseError> {
+	let value = evaluate_expression(line, expression, parameters)?;
+	if value.fract() != 0.0 {
+		return Err(ModelParseError::expected_integer(line, &value));
+	}
+	Ok(value as VasValue)
+}
+
+/// Warns about overridden parameters that the model never declares, which are most
+/// likely typos on the command line.
+pub(crate) fn warn_unused_parameters(
+	overrides: &HashMap<String, f64>,
+	declared: &HashMap<String, f64>,
+) {
+	for name in overrides.keys() {
+		if !declared.contains_key(name) {
+			warning!("Parameter {} is not declared in the model, ignoring it.", name);
+		}
+	}
+}
+
+/// Splits a declaration such as `const k1 = 0.5` (or `const k1=0.5`) into its keyword,
+/// name and value expression, which is `None` for a declaration without a value
+fn split_declaration(line: &str) -> Option<(&str, &str, Option<&str>)> {
+	let (head, value) = match line.split_once('=') {
+		// `==` compares in a rate expression, rather than declaring a value
+		Some((_, value)) if value.starts_with('=') => return None,
+		Some((head, value)) => (head, Some(value.trim())),
+		None => (line, None),
+	};
+	match head.split_whitespace().collect::<Vec<&str>>()[..] {
+		[keyword, name] => Some((keyword, name, value)),
+		_ => None,
+	}
+}
+
+#[trusted]
+// Build the parameters (`param k1 = 0.5` or `const k2 = k1 * 2`) in the order they are
+// declared, so that each one may use the ones before it. Overrides replace the declared value.
+fn build_parameters(
+	raw_data: Vec<(usize, String)>,
+	overrides: &HashMap<String, f64>,
+	errors: &mut Vec<ModelParseError>,
+) -> HashMap<String, f64> {
+	let mut parameters = HashMap::<String, f64>::new();
+	for declaration in raw_data.iter() {
+		let (parameter_name, expression) = match split_declaration(&declaration.1) {
+			Some((_, name, Some(expression))) if !expression.is_empty() => {
+				(name.to_string(), expression)
+			}
+			Some((_, name, None)) if overrides.contains_key(name) => (name.to_string(), ""),
+			Some((_, name, None)) => {
+				errors.push(ModelParseError::init_unspecified(declaration.0, name));
+				continue;
+			}
+			_ => {
+				errors.push(ModelParseError::unexpected_token(
+					declaration.0,
+					&declaration.1,
+				));
+				continue;
+			}
+		};
+		let value = match overrides.get(&parameter_name) {
+			Some(value) => {
+				message!("Using {} = {} (overridden)", parameter_name, value);
+				*value
+			}
+			None => match evaluate_expression(declaration.0, expression, &parameters) {
+				Ok(value) => value,
+				Err(e) => {
+					errors.push(e);
+					continue;
+				}
+			},
+		};
+		parameters.insert(parameter_name, value);
+	}
+	warn_unused_parameters(overrides, &parameters);
+	parameters
+}
+
 #[trusted]
 // Build two variable objects (names and initial values)
 fn build_variables(
 	raw_data: Vec<(usize, String)>,
-) -> Result<(Box<[String]>, Box<[VasValue]>), ModelParseError> {
+	parameters: &HashMap<String, f64>,
+	errors: &mut Vec<ModelParseError>,
+) -> (Box<[String]>, Box<[VasValue]>) {
 	let mut variable_names = Vec::<String>::new();
 	let mut initial_state = Vec::<VasValue>::new();
 	for declaration in raw_data.iter() {
@@ -173,47 +370,215 @@ fn build_variables(
 				variable_name = words[1].to_string();
 				variable_init = 0;
 			}
-			4 => {
+			n if n >= 4 => {
 				// Handle case with initialization (i.e., initial value follows word "init")
 				if INITIAL_TERMS.contains(&words[2]) {
 					variable_name = words[1].to_string();
-					if let Ok(count) = words[3].parse::<VasValue>() {
-						variable_init = count;
-					} else {
-						return Err(ModelParseError::invalid_init(
-							declaration.0.try_into().unwrap(),
-							&words[3],
-						));
-					}
+					// Keep the variable on a bad initial value, so its uses are not errors too
+					variable_init =
+						match evaluate_count(declaration.0, &words[3..].join(" "), parameters) {
+							Ok(count) => count,
+							Err(e) => {
+								errors.push(e);
+								0
+							}
+						};
 				} else {
-					return Err(ModelParseError::init_unspecified(
-						declaration.0.try_into().unwrap(),
+					errors.push(ModelParseError::init_unspecified(
+						declaration.0,
 						&words[1],
 					));
+					continue;
 				}
 			}
 			_ => {
-				return Err(ModelParseError::unexpected_token(
-					declaration.0.try_into().unwrap(),
+				errors.push(ModelParseError::unexpected_token(
+					declaration.0,
 					&declaration.1,
 				));
+				continue;
 			}
 		}
 		variable_names.push(variable_name);
 		initial_state.push(variable_init);
 	}
-	Ok((
+	(
 		variable_names.into_boxed_slice(),
 		initial_state.into_boxed_slice(),
-	))
+	)
+}
+
+#[trusted]
+// Build the initial states from `init X = 5, Y = 2` lines, optionally with a probability
+// (`init 0.3: X = 5, Y = 2`). Species that a line does not mention keep the value from
+// their declaration. Without any such lines, the declarations give the only initial state.
+fn build_initial_states(
+	raw_data: Vec<(usize, String)>,
+	variable_names: &[String],
+	declared_state: &[VasValue],
+	parameters: &HashMap<String, f64>,
+	errors: &mut Vec<ModelParseError>,
+) -> (Vec<VasState>, Option<Vec<ProbabilityOrRate>>) {
+	if raw_data.is_empty() {
+		return (
+			vec![VasState::new(DVector::from_vec(declared_state.to_vec()))],
+			None,
+		);
+	}
+	let mut initial_states = Vec::<VasState>::new();
+	let mut probabilities = Vec::<Option<ProbabilityOrRate>>::new();
+	for (line_num, line) in raw_data.iter() {
+		let declaration = line
+			.trim_start()
+			.split_once(char::is_whitespace)
+			.map(|(_, declaration)| declaration)
+			.unwrap_or("");
+		let (probability, assignments) = match declaration.split_once(':') {
+			Some((probability, assignments)) => {
+				match evaluate_expression(*line_num, probability, parameters) {
+					Ok(probability) => (Some(probability), assignments),
+					Err(e) => {
+						errors.push(e);
+						continue;
+					}
+				}
+			}
+			None => (None, declaration),
+		};
+		let mut state = declared_state.to_vec();
+		let mut assigned = vec![false; variable_names.len()];
+		for assignment in assignments.split(',') {
+			let Some((name, value)) = assignment.split_once('=') else {
+				errors.push(ModelParseError::unexpected_token(
+					*line_num,
+					&assignment.trim(),
+				));
+				continue;
+			};
+			let name = name.trim();
+			let Some(index) = get_variable_id(variable_names, name) else {
+				errors.push(ModelParseError::unspecified_variable(*line_num, &name));
+				continue;
+			};
+			if assigned[index] {
+				errors.push(ModelParseError::general(
+					*line_num,
+					&format!("Species `{}` is given more than one initial value.", name),
+				));
+				continue;
+			}
+			assigned[index] = true;
+			match evaluate_count(*line_num, value, parameters) {
+				Ok(count) => state[index] = count,
+				Err(e) => errors.push(e),
+			}
+		}
+		let vector = DVector::from_vec(state);
+		if initial_states.iter().any(|s| s.vector == vector) {
+			errors.push(ModelParseError::general(
+				*line_num,
+				&"This initial state is declared more than once.",
+			));
+			continue;
+		}
+		initial_states.push(VasState::new(vector));
+		probabilities.push(probability);
+	}
+	// Either every initial state has a probability, or they are all equally likely
+	if probabilities.iter().all(|p| p.is_none()) {
+		return (initial_states, None);
+	}
+	if let Some(index) = probabilities.iter().position(|p| p.is_none()) {
+		errors.push(ModelParseError::general(
+			raw_data[index].0,
+			&"Either every initial state has a probability or none do.",
+		));
+	}
+	(
+		initial_states,
+		Some(probabilities.into_iter().flatten().collect()),
+	)
 }
 
 // Build the transition objects
+/// A rate written in the model: either a rate constant for mass-action kinetics, or a
+/// rate law over the species (e.g., `hill(TetR, 1.5, K, 2)`) compiled into a rate function
+enum Rate {
+	Constant(ProbabilityOrRate),
+	Law(std::sync::Arc<dyn Fn(&VasState) -> ProbabilityOrRate + Send + Sync + 'static>),
+}
+
+impl Rate {
+	/// Reads a rate from its tokens
+	fn parse(
+		line: usize,
+		tokens: &[Token],
+		variable_names: &[String],
+		parameters: &HashMap<String, f64>,
+		functions: &RateFunctions,
+	) -> Result<Self, ModelParseError> {
+		if is_rate_law(tokens, variable_names, functions) {
+			Ok(Rate::Law(compile_rate_expression(
+				line,
+				tokens,
+				variable_names,
+				parameters,
+				functions,
+			)?))
+		} else {
+			Ok(Rate::Constant(evaluate_constant(line, tokens, parameters)?))
+		}
+	}
+
+	/// Sets the rate of a transition
+	fn apply(self, transition: &mut VasTransition) {
+		match self {
+			Rate::Constant(rate_const) => transition.rate_const = rate_const,
+			Rate::Law(rate_fn) => {
+				transition.rate_const = 1.0;
+				transition.set_custom_rate_fn(rate_fn);
+			}
+		}
+	}
+}
+
+#[trusted]
+// Build the functions (`function dtet(x) = x^2 / (K^2 + x^2)`) in the order they are
+// declared, so that each one may use the ones before it.
+fn build_functions(
+	raw_data: Vec<(usize, String)>,
+	variable_names: &[String],
+	parameters: &HashMap<String, f64>,
+	errors: &mut Vec<ModelParseError>,
+) -> RateFunctions {
+	let mut functions = RateFunctions::new();
+	for (line_num, line) in raw_data {
+		let declaration = line
+			.trim_start()
+			.split_once(char::is_whitespace)
+			.map(|(_, declaration)| declaration)
+			.unwrap_or("");
+		let parsed = tokenize(line_num, declaration).and_then(|tokens| {
+			parse_function(line_num, &tokens, variable_names, parameters, &functions)
+		});
+		match parsed {
+			Ok((name, function)) => {
+				functions.insert(name, function);
+			}
+			Err(e) => errors.push(e),
+		}
+	}
+	functions
+}
+
 #[trusted]
 fn build_transitions(
 	raw_data: Vec<Vec<(usize, std::string::String)>>,
 	variable_names: &Box<[String]>,
-) -> Result<Vec<<AbstractVas as AbstractModel>::TransitionType>, ModelParseError> {
+	parameters: &HashMap<String, f64>,
+	functions: &RateFunctions,
+	errors: &mut Vec<ModelParseError>,
+) -> Vec<<AbstractVas as AbstractModel>::TransitionType> {
 	let mut transitions = Vec::<<AbstractVas as AbstractModel>::TransitionType>::new();
 	let num_variables = variable_names.len();
 	let mut transition_id: usize = 0;
@@ -222,7 +587,7 @@ fn build_transitions(
 		let mut transition_name = String::new();
 		let mut increment = vec![VasValue::from(0); num_variables].into_boxed_slice();
 		let mut decrement = vec![VasValue::from(0); num_variables].into_boxed_slice();
-		let mut rate_const: ProbabilityOrRate = 0.0;
+		let mut rate = Rate::Constant(0.0);
 
 		for line in declaration.iter() {
 			let words: &[&str] = &line.1.split_whitespace().collect::<Vec<&str>>()[..];
@@ -231,10 +596,8 @@ fn build_transitions(
 				if words.len() == 2 {
 					transition_name = words[1].to_string();
 				} else {
-					return Err(ModelParseError::unexpected_token(
-						line.0.try_into().unwrap(),
-						&line.1,
-					));
+					errors.push(ModelParseError::unexpected_token(line.0, &line.1));
+					continue;
 				}
 			} else if DECREASE_TERMS.contains(first_word) {
 				let variable_name;
@@ -248,37 +611,31 @@ fn build_transitions(
 						variable_name = words[1].to_string();
 						if let Ok(count) = words[2].parse::<VasValue>() {
 							if count < 0 {
-								// TODO: This error, and presumably others, just cause a stack overflow. Need to fix this.
-								return Err(ModelParseError::unexpected_token(
-									line.0.try_into().unwrap(),
-									&line.1,
-								));
+								errors.push(ModelParseError::expected_integer(line.0, &words[2]));
+								continue;
 							} else {
 								decrease_count = count;
 							};
 						} else {
-							return Err(ModelParseError::unexpected_token(
-								line.0.try_into().unwrap(),
-								&line.1,
-							));
+							errors.push(ModelParseError::expected_integer(line.0, &words[2]));
+							continue;
 						}
 					}
 					_ => {
-						return Err(ModelParseError::unexpected_token(
-							line.0.try_into().unwrap(),
-							&line.1,
-						));
+						errors.push(ModelParseError::unexpected_token(line.0, &line.1));
+						continue;
 					}
 				}
 				// update the transition
 				if let Some(index) = get_variable_id(&variable_names, &variable_name) {
 					if decrement[index] != 0 {
-						return Err(ModelParseError::general(line.0.try_into().unwrap(), &format!("Model parsing error: variable {} decreases by multiple declared values in the same transition.", variable_name)));
+						errors.push(ModelParseError::general(line.0, &format!("Model parsing error: variable {} decreases by multiple declared values in the same transition.", variable_name)));
+						continue;
 					}
 					decrement[index] = decrease_count;
 				} else {
-					return Err(ModelParseError::unspecified_variable(
-						line.0.try_into().unwrap(),
+					errors.push(ModelParseError::unspecified_variable(
+						line.0,
 						&variable_name,
 					));
 				}
@@ -294,203 +651,580 @@ fn build_transitions(
 						variable_name = words[1].to_string();
 						if let Ok(count) = words[2].parse::<VasValue>() {
 							increase_count = if count < 0 {
-								return Err(ModelParseError::unexpected_token(
-									line.0.try_into().unwrap(),
-									&line.1,
-								));
+								errors.push(ModelParseError::expected_integer(line.0, &words[2]));
+								continue;
 							} else {
 								count
 							};
 						} else {
-							return Err(ModelParseError::unexpected_token(
-								line.0.try_into().unwrap(),
-								&line.1,
-							));
+							errors.push(ModelParseError::expected_integer(line.0, &words[2]));
+							continue;
 						}
 					}
 					_ => {
-						return Err(ModelParseError::unexpected_token(
-							line.0.try_into().unwrap(),
-							&line.1,
-						));
+						errors.push(ModelParseError::unexpected_token(line.0, &line.1));
+						continue;
 					}
 				}
 				// update the transition
 				if let Some(index) = get_variable_id(&variable_names.clone(), &variable_name) {
 					if increment[index] != 0 {
-						return Err(ModelParseError::general(line.0.try_into().unwrap(), &format!("Model parsing error: variable {} increases by multiple declared values in the same transition.", variable_name)));
+						errors.push(ModelParseError::general(line.0, &format!("Model parsing error: variable {} increases by multiple declared values in the same transition.", variable_name)));
+						continue;
 					}
 					increment[index] = increase_count;
 				} else {
-					return Err(ModelParseError::unspecified_variable(
-						line.0.try_into().unwrap(),
+					errors.push(ModelParseError::unspecified_variable(
+						line.0,
 						&variable_name,
 					));
 				}
 			} else if RATE_TERMS.contains(first_word) {
-				if words.len() == 2 {
-					if let Ok(rate) = words[1].parse::<ProbabilityOrRate>() {
-						rate_const = rate;
-					} else {
-						return Err(ModelParseError::expected_float(
-							line.0.try_into().unwrap(),
-							&words[1],
-						));
+				if words.len() >= 2 {
+					let parsed = tokenize(line.0, &words[1..].join(" ")).and_then(|tokens| {
+						Rate::parse(line.0, &tokens, variable_names, parameters, functions)
+					});
+					match parsed {
+						Ok(parsed) => rate = parsed,
+						Err(e) => errors.push(e),
 					}
 				} else {
-					return Err(ModelParseError::unexpected_token(
-						line.0.try_into().unwrap(),
-						&line.1,
-					));
+					errors.push(ModelParseError::unexpected_token(line.0, &line.1));
 				}
 			} else {
-				return Err(ModelParseError::unexpected_token(
-					line.0.try_into().unwrap(),
-					&line.1,
-				));
+				errors.push(ModelParseError::unexpected_token(line.0, &line.1));
 			}
 		}
 
-		let transition = VasTransition::new(
-			transition_id,
-			transition_name,
-			increment,
-			decrement,
-			rate_const,
-		);
+		let mut transition =
+			VasTransition::new(transition_id, transition_name, increment, decrement, 0.0);
+		rate.apply(&mut transition);
 
 		transitions.push(transition);
 
 		transition_id += 1;
 	}
 
-	// For now, we'll return an error as a placeholder
-	Ok(transitions)
+	transitions
 }
 
-fn build_property(
-	raw_data: Vec<(usize, String)>,
-	variable_names: Box<[String]>,
-) -> Result<VasProperty, ModelParseError> {
-	if raw_data.len() != 1 {
+/// Reads one side of a reaction arrow (e.g., `A + 2B` or `2 A + B` or `0`) into the
+/// stoichiometry of each variable.
+#[trusted]
+fn parse_reaction_side(
+	line: usize,
+	side: &str,
+	variable_names: &Box<[String]>,
+) -> Result<Box<[VasValue]>, ModelParseError> {
+	let mut counts = vec![VasValue::from(0); variable_names.len()].into_boxed_slice();
+	let side = side.trim();
+	if EMPTY_TERMS.contains(&side) {
+		return Ok(counts);
+	}
+	for term in side.split('+') {
+		let words: &[&str] = &term.split_whitespace().collect::<Vec<&str>>()[..];
+		let (count, variable_name) = match words {
+			// Coefficient separated by a space, e.g., `2 B`
+			[count, name] => match count.parse::<VasValue>() {
+				Ok(count) => (count, name.to_string()),
+				Err(_) => return Err(ModelParseError::expected_integer(line, count)),
+			},
+			// Coefficient written directly before the name, e.g., `2B`
+			[word] => {
+				let digits = word.chars().take_while(|c| c.is_ascii_digit()).count();
+				if digits == 0 {
+					(1, word.to_string())
+				} else if digits < word.len() {
+					match word[..digits].parse::<VasValue>() {
+						Ok(count) => (count, word[digits..].to_string()),
+						Err(_) => return Err(ModelParseError::expected_integer(line, word)),
+					}
+				} else {
+					return Err(ModelParseError::unexpected_token(line, word));
+				}
+			}
+			_ => return Err(ModelParseError::unexpected_token(line, &term.trim())),
+		};
+		if let Some(index) = get_variable_id(variable_names, &variable_name) {
+			counts[index] += count;
+		} else {
+			return Err(ModelParseError::unspecified_variable(
+				line,
+				&variable_name,
+			));
+		}
+	}
+	Ok(counts)
+}
+
+/// Builds the transition(s) of a line with a reaction arrow, i.e., `[name:] A + 2B -> C @ k`,
+/// or `[name:] A <-> B @ kf, kr` for a reversible reaction. Reversible reactions become two
+/// transitions, the second one named `<name>_rev`.
+#[trusted]
+fn build_arrow_transition(
+	line_num: usize,
+	line: &str,
+	variable_names: &Box<[String]>,
+	parameters: &HashMap<String, f64>,
+	functions: &RateFunctions,
+	transition_id: usize,
+) -> Result<Vec<<AbstractVas as AbstractModel>::TransitionType>, ModelParseError> {
+	// Split off the optional name label
+	let (transition_name, reaction) = match line.split_once(':') {
+		Some((name, reaction)) if !name.contains("->") => {
+			let name = name.trim();
+			if name.is_empty() || name.contains(char::is_whitespace) {
+				return Err(ModelParseError::unexpected_token(line_num, &name));
+			}
+			(name.to_string(), reaction)
+		}
+		_ => (format!("R{}", transition_id), line),
+	};
+	// Split off the rate constant(s)
+	let (reaction, rates) = match reaction.split_once('@') {
+		Some((reaction, rates)) => (reaction, rates),
+		None => {
+			return Err(ModelParseError::general(
+				line_num,
+				&format!("Reaction `{}` has no rate constant (`@ k`).", reaction.trim()),
+			))
+		}
+	};
+	let mut rate_consts = Vec::<Rate>::new();
+	let rate_tokens = tokenize(line_num, rates)?;
+	for rate in split_top_level(&rate_tokens, ",") {
+		rate_consts.push(Rate::parse(
+			line_num,
+			rate,
+			variable_names,
+			parameters,
+			functions,
+		)?);
+	}
+	// Split the reactants from the products
+	let arrow = ARROW_TERMS
+		.iter()
+		.find(|arrow| reaction.contains(*arrow))
+		.unwrap();
+	let (reactants, products) = reaction.split_once(arrow).unwrap();
+	let reversible = *arrow == "<->";
+	if rate_consts.len() != if reversible { 2 } else { 1 } {
 		return Err(ModelParseError::general(
-			0,
-			&"Model parsing error: property must be a single line.",
+			line_num,
+			&format!(
+				"Expected {} rate constant(s) for `{}`.",
+				if reversible { 2 } else { 1 },
+				reaction.trim()
+			),
 		));
 	}
-	let words: &[&str] = &raw_data[0].1.split_whitespace().collect::<Vec<&str>>()[..];
-	let variable_name = words.get(1).unwrap_or(&"");
-	let variable_index = get_variable_id(&*variable_names, variable_name);
-	if variable_index.is_none() {
-		return Err(ModelParseError::unspecified_variable(
-			raw_data[0].0.try_into().unwrap(),
-			&variable_name,
-		));
+	let decrement = parse_reaction_side(line_num, reactants, variable_names)?;
+	let increment = parse_reaction_side(line_num, products, variable_names)?;
+
+	let mut rates = rate_consts.into_iter();
+	let mut forward = VasTransition::new(
+		transition_id,
+		transition_name.clone(),
+		increment.clone(),
+		decrement.clone(),
+		0.0,
+	);
+	rates.next().unwrap().apply(&mut forward);
+	let mut transitions = vec![forward];
+	if reversible {
+		let mut backward = VasTransition::new(
+			transition_id + 1,
+			format!("{}_rev", transition_name),
+			decrement,
+			increment,
+			0.0,
+		);
+		rates.next().unwrap().apply(&mut backward);
+		transitions.push(backward);
 	}
-	let variable_index = variable_index.unwrap();
-	let target_value = if words.len() == 4 {
-		if let Ok(value) = words[3].parse::<VasValue>() {
-			value
-		} else {
-			return Err(ModelParseError::expected_integer(
-				raw_data[0].0.try_into().unwrap(),
-				&words[2],
+	Ok(transitions)
+}
+
+/// Assigns transitions to actions from `action name: t1, t2, ...` lines. Only MDPs and
+/// CTMDPs have actions, and each transition belongs to at most one.
+#[trusted]
+fn build_actions(
+	raw_data: Vec<(usize, String)>,
+	model_type: ModelType,
+	transitions: &mut [VasTransition],
+	errors: &mut Vec<ModelParseError>,
+) {
+	for (line_num, line) in raw_data {
+		if !model_type.is_nondeterministic() {
+			errors.push(ModelParseError::general(
+				line_num,
+				&format!(
+					"Actions are only allowed in an mdp or ctmdp model, not a {}.",
+					model_type
+				),
 			));
+			continue;
 		}
-	} else {
-		return Err(ModelParseError::unexpected_token(
-			raw_data[0].0.try_into().unwrap(),
-			&raw_data[0].1,
-		));
-	};
+		let declaration = line
+			.trim_start()
+			.split_once(char::is_whitespace)
+			.map(|(_, declaration)| declaration)
+			.unwrap_or("");
+		let Some((action, members)) = declaration.split_once(':') else {
+			errors.push(ModelParseError::unexpected_token(line_num, &line.trim()));
+			continue;
+		};
+		let action = action.trim();
+		if action.is_empty() || action.contains(char::is_whitespace) {
+			errors.push(ModelParseError::unexpected_token(line_num, &action));
+			continue;
+		}
+		for member in members.split(',').map(str::trim) {
+			match transitions.iter_mut().find(|t| t.transition_name == member) {
+				Some(transition) if transition.action.is_some() => {
+					errors.push(ModelParseError::general(
+						line_num,
+						&format!(
+							"Transition `{}` is already in action `{}`.",
+							member,
+							transition.action.as_ref().unwrap()
+						),
+					))
+				}
+				Some(transition) => transition.action = Some(action.to_string()),
+				None => errors.push(ModelParseError::general(
+					line_num,
+					&format!("Unknown transition `{}` in action `{}`.", member, action),
+				)),
+			}
+		}
+	}
+}
 
-	let property = VasProperty {
-		variable_index,
-		target_value,
-	};
+/// Build the transitions written with a reaction arrow
+#[trusted]
+fn build_arrow_transitions(
+	raw_data: Vec<(usize, String)>,
+	variable_names: &Box<[String]>,
+	parameters: &HashMap<String, f64>,
+	functions: &RateFunctions,
+	first_transition_id: usize,
+	errors: &mut Vec<ModelParseError>,
+) -> Vec<<AbstractVas as AbstractModel>::TransitionType> {
+	let mut transitions = Vec::<<AbstractVas as AbstractModel>::TransitionType>::new();
+	for (line_num, line) in raw_data {
+		match build_arrow_transition(
+			line_num,
+			&line,
+			variable_names,
+			parameters,
+			functions,
+			first_transition_id + transitions.len(),
+		) {
+			Ok(new_transitions) => transitions.extend(new_transitions),
+			Err(e) => errors.push(e),
+		}
+	}
+	transitions
+}
+
+fn build_property(
+	raw_data: Vec<(usize, String)>,
+	variable_names: Box<[String]>,
+	parameters: &HashMap<String, f64>,
+) -> Result<VasTarget, ModelParseError> {
+	match raw_data.len() {
+		0 => {
+			return Err(ModelParseError::general(
+				0,
+				&"Model parsing error: no target specified.",
+			))
+		}
+		1 => {}
+		_ => {
+			return Err(ModelParseError::general(
+				raw_data[1].0,
+				&"Model parsing error: property must be a single line.",
+			))
+		}
+	}
+	// Everything after the keyword is the target, e.g. `target S5 >= 50 & S4 <= 25`
+	let (line_num, line) = &raw_data[0];
+	let target = line
+		.trim_start()
+		.split_once(char::is_whitespace)
+		.map(|(_, target)| target)
+		.unwrap_or("");
+	let tokens = tokenize(*line_num, target)?;
+	if tokens.is_empty() {
+		return Err(ModelParseError::unexpected_token(*line_num, &line.trim()));
+	}
+	target_from_tokens(*line_num, &tokens, &variable_names, parameters)
+}
 
-	Ok(property)
+/// A line of the model, after includes have been expanded
+struct SourceLine {
+	file: String,
+	line: usize,
+	text: String,
 }
 
-pub fn build_model(filename: &str) -> Result<AbstractVas, ModelParseError> {
-	// Initialize everything
-	let lines = read_lines(&filename).map_err(|_| {
+/// Removes a `#` or `//` comment from a line
+fn strip_comment(line: &str) -> &str {
+	let end = [line.find('#'), line.find("//")]
+		.into_iter()
+		.flatten()
+		.min()
+		.unwrap_or(line.len());
+	&line[..end]
+}
+
+/// Reads the lines of a model file into `sources`, replacing each `include "file.crn"` by
+/// the lines of that file (relative to the including file). Errors in include directives
+/// are pushed to `errors`, already located.
+#[trusted]
+fn read_source(
+	filename: &Path,
+	include_stack: &mut Vec<PathBuf>,
+	sources: &mut Vec<SourceLine>,
+	errors: &mut Vec<ModelParseError>,
+) -> Result<(), ModelParseError> {
+	let lines = read_lines(filename).map_err(|_| {
 		ModelParseError::general(
 			0,
-			&"line-by-line file parsing not Ok. Check your model file.",
+			&format!(
+				"line-by-line file parsing not Ok. Check your model file {}.",
+				filename.display()
+			),
 		)
 	})?;
+	include_stack.push(filename.canonicalize().unwrap_or(filename.to_path_buf()));
+	let file = filename.display().to_string();
+	for (num, text) in lines.flatten().enumerate() {
+		let words: &[&str] = &strip_comment(&text).split_whitespace().collect::<Vec<&str>>()[..];
+		if words.first() != Some(&"include") {
+			sources.push(SourceLine {
+				file: file.clone(),
+				line: num + 1,
+				text,
+			});
+			continue;
+		}
+		let included = match words {
+			[_, name] if name.len() >= 2 && name.starts_with('"') && name.ends_with('"') => {
+				filename
+					.parent()
+					.unwrap_or(Path::new(""))
+					.join(&name[1..name.len() - 1])
+			}
+			_ => {
+				errors.push(
+					ModelParseError::unexpected_token(0, &strip_comment(&text).trim())
+						.located(&file, num + 1, &text),
+				);
+				continue;
+			}
+		};
+		if include_stack.contains(&included.canonicalize().unwrap_or(included.clone())) {
+			errors.push(
+				ModelParseError::general(
+					0,
+					&format!("{} is included in itself.", included.display()),
+				)
+				.located(&file, num + 1, &text),
+			);
+			continue;
+		}
+		if let Err(e) = read_source(&included, include_stack, sources, errors) {
+			errors.push(e.located(&file, num + 1, &text));
+		}
+	}
+	include_stack.pop();
+	Ok(())
+}
+
+/// Builds a VAS model from a `.crn` file. Parameters declared in the file can be
+/// overridden through `parameters`. Parsing does not stop at the first error: all errors
+/// found are returned, each one with its file, line and (where possible) column.
+///
+/// A `ctmc` (the default), `dtmc`, `mdp` or `ctmdp` line sets the model type. The rates of
+/// a DTMC or MDP are the probabilities of its transitions, so they are not scaled by the
+/// species counts. The transitions of an MDP or CTMDP are grouped into actions with
+/// `action name: t1, t2, ...` lines.
+///
+/// Several initial states are declared with `init X = 5, Y = 2` lines, where the species
+/// not mentioned take the value from their declaration. Each line may start with a
+/// probability (`init 0.3: X = 5, Y = 2`), in which case every line must, and the
+/// probabilities must sum to one. Otherwise the initial states are equally likely.
+pub fn build_model(
+	filename: &str,
+	parameters: &HashMap<String, f64>,
+) -> Result<AbstractVas, Vec<ModelParseError>> {
+	// Initialize everything
+	let mut sources = Vec::<SourceLine>::new();
+	let mut located_errors = Vec::<ModelParseError>::new();
+	read_source(
+		Path::new(filename),
+		&mut Vec::new(),
+		&mut sources,
+		&mut located_errors,
+	)
+	.map_err(|e| vec![e.with_source_from_file(filename)])?;
 
-	// Setup strings for the various things
+	// Setup strings for the various things. Lines are numbered by their (1-based) index
+	// in `sources`, which is resolved to the real file and line for errors at the end.
 	let mut variable_lines = Vec::<(usize, String)>::new();
 	let mut transition_lines = Vec::<Vec<(usize, String)>>::new();
 	let mut property_lines = Vec::<(usize, String)>::new();
+	let mut reaction_lines = Vec::<(usize, String)>::new();
+	let mut parameter_lines = Vec::<(usize, String)>::new();
+	let mut function_lines = Vec::<(usize, String)>::new();
+	let mut action_lines = Vec::<(usize, String)>::new();
+	let mut initial_lines = Vec::<(usize, String)>::new();
 	let mut current_transition = Vec::<(usize, String)>::new();
+	let mut model_type = None::<ModelType>;
+	let mut errors = Vec::<ModelParseError>::new();
 
-	for (num, line) in lines.flatten().enumerate() {
+	for (index, source) in sources.iter().enumerate() {
+		let num = index + 1;
+		let line = strip_comment(&source.text).trim_end().to_string();
 		// Split the line into words and convert to a slice, then sort the line by first words
 		let words: &[&str] = &line.split_whitespace().collect::<Vec<&str>>()[..];
 		let first_word = words.get(0).unwrap_or(&"");
 
-		// VARIABLE_TERMS
-		// INITIAL_TERMS
-		// TRANSITION_TERMS
-		// DECREASE_TERMS
-		// INCREASE_TERMS
-		// RATE_TERMS
-		// TARGET_TERMS
-
 		// Check the first word against the keywords
-		if VARIABLE_TERMS.contains(first_word) {
+		if let Some((_, m_type)) = MODEL_TYPE_TERMS.iter().find(|(term, _)| term == first_word) {
+			if words.len() > 1 {
+				errors.push(ModelParseError::unexpected_token(
+					num,
+					&words[1..].join(" "),
+				));
+			} else if let Some(declared) = model_type.filter(|declared| declared != m_type) {
+				errors.push(ModelParseError::general(
+					num,
+					&format!(
+						"The model is declared as both a {} and a {}.",
+						declared, m_type
+					),
+				));
+			}
+			model_type = Some(*m_type);
+		} else if PARAMETER_TERMS.contains(first_word)
+			|| (RATE_TERMS.contains(first_word)
+				&& matches!(split_declaration(&line), Some((_, _, Some(_)))))
+		{
+			// `const k1 = ...` declares a parameter, while `const k1` is a transition rate
+			parameter_lines.push((num, line));
+		} else if FUNCTION_TERMS.contains(first_word) {
+			function_lines.push((num, line));
+		} else if ACTION_TERMS.contains(first_word) {
+			action_lines.push((num, line));
+		} else if INITIAL_TERMS.contains(first_word) {
+			initial_lines.push((num, line));
+		} else if VARIABLE_TERMS.contains(first_word) {
 			variable_lines.push((num, line));
 		} else if TRANSITION_TERMS.contains(first_word) {
-			if current_transition.is_empty() {
-				current_transition = vec![(num, line)];
-			} else {
+			if !current_transition.is_empty() {
 				transition_lines.push(current_transition);
-				current_transition = vec![(num, line)];
 			}
+			current_transition = vec![(num, line)];
 		} else if DECREASE_TERMS.contains(first_word) || INCREASE_TERMS.contains(first_word) {
 			current_transition.push((num, line));
 		} else if RATE_TERMS.contains(first_word) {
 			current_transition.push((num, line));
 		} else if TARGET_TERMS.contains(first_word) {
 			property_lines.push((num, line));
-		} else if line != "" {
-			return Err(ModelParseError::unexpected_token(
-				num.try_into().unwrap(),
-				&line,
-			));
+		} else if ARROW_TERMS.iter().any(|arrow| line.contains(arrow)) {
+			reaction_lines.push((num, line));
+		} else if line.trim() != "" {
+			errors.push(ModelParseError::unexpected_token(num, &line.trim()));
 		}
-		// TODO: Implement better error handling at initial parse.
 	}
-	transition_lines.push(current_transition);
+	if !current_transition.is_empty() {
+		transition_lines.push(current_transition);
+	}
+
+	// Resolve the parameters first, everything else may use them
+	let parameters = build_parameters(parameter_lines, parameters, &mut errors);
 
 	// Parse the variables and initial states
-	let (variable_names, initial_states) = match build_variables(variable_lines) {
-		Ok(result) => result,
-		Err(e) => return Err(e),
-	};
+	let (variable_names, declared_state) =
+		build_variables(variable_lines, &parameters, &mut errors);
+	let (initial_states, initial_probabilities) = build_initial_states(
+		initial_lines,
+		&variable_names,
+		&declared_state,
+		&parameters,
+		&mut errors,
+	);
 
-	// Read the property
-	let target = build_property(property_lines, variable_names.clone())?;
+	// Functions may use the parameters and species
+	let functions = build_functions(function_lines, &variable_names, &parameters, &mut errors);
+
+	// Read the property, falling back to the `.prop` file next to the model
+	let prop_target = if property_lines.is_empty() {
+		deferred_target(filename)
+	} else {
+		None
+	};
+	let target = match prop_target {
+		Some(target) => Some(target),
+		None => build_property(property_lines, variable_names.clone(), &parameters)
+			.map_err(|e| errors.push(e))
+			.ok(),
+	};
 
 	// Read the transitions
-	let transitions = match build_transitions(transition_lines, &variable_names) {
-		Ok(result) => result,
-		Err(e) => {
-			error!("ERROR DURING TRANSITION PARSING:\n{}", e.to_string());
-			return Err(e);
+	let mut transitions = build_transitions(
+		transition_lines,
+		&variable_names,
+		&parameters,
+		&functions,
+		&mut errors,
+	);
+	let arrow_transitions = build_arrow_transitions(
+		reaction_lines,
+		&variable_names,
+		&parameters,
+		&functions,
+		transitions.len(),
+		&mut errors,
+	);
+	transitions.extend(arrow_transitions);
+
+	// Group the transitions into actions
+	let model_type = model_type.unwrap_or(ModelType::ContinuousTime);
+	build_actions(action_lines, model_type, &mut transitions, &mut errors);
+
+	// Point each error at the file and line it came from
+	located_errors.extend(errors.into_iter().map(|e| {
+		if e.line > 0 && e.line <= sources.len() {
+			let source = &sources[e.line - 1];
+			e.located(&source.file, source.line, &source.text)
+		} else {
+			e.with_source_from_file(filename)
 		}
-	};
+	}));
+	if !located_errors.is_empty() {
+		return Err(located_errors);
+	}
 
 	// Return the model
-	let model = AbstractVas::new(
+	let mut model = AbstractVas::new(
 		variable_names,
-		vec![VasState::new(DVector::from_vec(initial_states.to_vec()))],
+		initial_states,
 		transitions,
-		target,
+		target.unwrap(),
+		model_type,
 	);
+	if model_type.is_discrete_time() {
+		model.set_kinetics(Kinetics::Constant);
+	}
+	if let Some(probabilities) = initial_probabilities {
+		model
+			.set_initial_probabilities(probabilities)
+			.map_err(|e| vec![ModelParseError::general(0, &e).with_source_from_file(filename)])?;
+	}
 
 	Ok(model)
 }
@@ -1,7 +1,12 @@
 use evalexpr::*;
 use metaverify::trusted;
 
-use std::fmt::{Display, Error, Formatter};
+use std::{
+	collections::HashMap,
+	fmt::{Display, Error, Formatter},
+};
+
+use crate::model::vas_model::{AllowedRelation, VasState, VasTarget, VasValue};
 
 /// A trait representing a label on a labeled type
 #[trusted]
@@ -12,7 +17,7 @@ pub(crate) trait Label: ToString + Clone {
 	/// E.g., a label containing `"A > 5 & B < 3"` would be a subset
 	/// of the label `"A > 5"`.
 	#[trusted]
-	fn contains(&self, label: &Self);
+	fn contains(&self, label: &Self) -> bool;
 	/// Composes two labels to create a label that represents both
 	#[trusted]
 	fn compose(&self, label: &Self) -> Self;
@@ -44,7 +49,7 @@ pub(crate) enum PropertyClass {
 }
 
 #[trusted]
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) enum Property {
 	/// Where the state formula holds for all
 	Globally(StateFormula),
@@ -53,13 +58,71 @@ pub(crate) enum Property {
 }
 
 #[trusted]
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) enum PropertyQuery {
 	/// We are computing the probability of something.
 	Probability(Property), // TODO: should have Option<(evalexpr::Operator, f64)> for specific
 	// bounds? Or just leave this as is?
+	/// The largest probability over the schedulers of an MDP or CTMDP
 	MaxProbability(Property),
-	SteadyState(Property),
+	/// The smallest probability over the schedulers of an MDP or CTMDP
+	MinProbability(Property),
+	/// The long-run probability of being in a state satisfying the formula
+	SteadyState(StateFormula),
+}
+
+#[trusted]
+impl PropertyQuery {
+	/// The states the query is about reaching (the right side of `F` or `U`), or being in
+	/// for steady-state queries. `G` properties have no goal to reach.
+	#[trusted]
+	pub(crate) fn goal(&self) -> Option<&StateFormula> {
+		match self {
+			PropertyQuery::Probability(property)
+			| PropertyQuery::MaxProbability(property)
+			| PropertyQuery::MinProbability(property) => match property {
+				Property::Finally(goal, _) | Property::Until(_, goal, _) => Some(goal),
+				Property::Globally(_) => None,
+			},
+			PropertyQuery::SteadyState(goal) => Some(goal),
+		}
+	}
+
+	/// The same query over renumbered variables, as in `VasTarget::map_variables`
+	#[trusted]
+	pub(crate) fn map_variables(&self, map: &impl Fn(usize) -> Option<usize>) -> Option<Self> {
+		Some(match self {
+			PropertyQuery::Probability(property) => {
+				PropertyQuery::Probability(property.map_variables(map)?)
+			}
+			PropertyQuery::MaxProbability(property) => {
+				PropertyQuery::MaxProbability(property.map_variables(map)?)
+			}
+			PropertyQuery::MinProbability(property) => {
+				PropertyQuery::MinProbability(property.map_variables(map)?)
+			}
+			PropertyQuery::SteadyState(formula) => {
+				PropertyQuery::SteadyState(formula.map_variables(map)?)
+			}
+		})
+	}
+}
+
+#[trusted]
+impl Property {
+	/// The same property over renumbered variables, as in `VasTarget::map_variables`
+	#[trusted]
+	pub(crate) fn map_variables(&self, map: &impl Fn(usize) -> Option<usize>) -> Option<Self> {
+		Some(match self {
+			Property::Globally(formula) => Property::Globally(formula.map_variables(map)?),
+			Property::Finally(formula, bound) => {
+				Property::Finally(formula.map_variables(map)?, *bound)
+			}
+			Property::Until(left, right, bound) => {
+				Property::Until(left.map_variables(map)?, right.map_variables(map)?, *bound)
+			}
+		})
+	}
 }
 
 /// A trait representing any type of CSL, PCTL, or LTL property
@@ -70,26 +133,377 @@ pub(crate) enum PropertyQuery {
 #[trusted]
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
 pub(crate) enum StateFormula {
+	True,
+	False,
+	/// A named label, e.g. `"target"`
 	StateLabel(String),
-	Expression(Box<crate::property::property::StateFormula>),
+	/// A relational atom `variable <relation> value` over a model variable, which is
+	/// kept by name (for printing) and by index (for evaluation)
+	Relation {
+		variable: String,
+		index: usize,
+		relation: AllowedRelation,
+		value: VasValue,
+	},
+	Not(Box<StateFormula>),
+	And(Vec<StateFormula>),
+	Or(Vec<StateFormula>),
+}
+
+#[trusted]
+impl StateFormula {
+	/// Converts the formula into a VAS target, resolving labels through `labels`.
+	/// Negations are pushed into the relational atoms. Gives `None` for formulas that a
+	/// target cannot represent (`true`, `false` or unknown labels).
+	#[trusted]
+	pub(crate) fn to_target(&self, labels: &HashMap<String, StateFormula>) -> Option<VasTarget> {
+		self.to_target_negated(labels, false)
+	}
+
+	/// The formula of a VAS target, naming its variables with `variable_names`
+	#[trusted]
+	pub(crate) fn from_target(target: &VasTarget, variable_names: &[String]) -> Self {
+		match target {
+			VasTarget::Relation(index, relation, value) => StateFormula::Relation {
+				variable: variable_names.get(*index).cloned().unwrap_or_default(),
+				index: *index,
+				relation: *relation,
+				value: *value,
+			},
+			VasTarget::And(targets) => StateFormula::And(
+				targets
+					.iter()
+					.map(|target| Self::from_target(target, variable_names))
+					.collect(),
+			),
+			VasTarget::Or(targets) => StateFormula::Or(
+				targets
+					.iter()
+					.map(|target| Self::from_target(target, variable_names))
+					.collect(),
+			),
+		}
+	}
+
+	#[trusted]
+	fn to_target_negated(
+		&self,
+		labels: &HashMap<String, StateFormula>,
+		negated: bool,
+	) -> Option<VasTarget> {
+		match self {
+			StateFormula::True | StateFormula::False => None,
+			StateFormula::StateLabel(name) => labels.get(name)?.to_target_negated(labels, negated),
+			StateFormula::Relation {
+				index,
+				relation,
+				value,
+				..
+			} => Some(if !negated {
+				VasTarget::Relation(*index, *relation, *value)
+			} else {
+				match relation {
+					AllowedRelation::Equal => VasTarget::Or(vec![
+						VasTarget::Relation(*index, AllowedRelation::LessThan, *value),
+						VasTarget::Relation(*index, AllowedRelation::GreaterThan, *value),
+					]),
+					AllowedRelation::LessThan => {
+						VasTarget::Relation(*index, AllowedRelation::GreaterOrEqual, *value)
+					}
+					AllowedRelation::GreaterThan => {
+						VasTarget::Relation(*index, AllowedRelation::LessOrEqual, *value)
+					}
+					AllowedRelation::LessOrEqual => {
+						VasTarget::Relation(*index, AllowedRelation::GreaterThan, *value)
+					}
+					AllowedRelation::GreaterOrEqual => {
+						VasTarget::Relation(*index, AllowedRelation::LessThan, *value)
+					}
+				}
+			}),
+			StateFormula::Not(formula) => formula.to_target_negated(labels, !negated),
+			StateFormula::And(formulas) | StateFormula::Or(formulas) => {
+				let targets = formulas
+					.iter()
+					.map(|f| f.to_target_negated(labels, negated))
+					.collect::<Option<Vec<_>>>()?;
+				// De Morgan: a negated conjunction is a disjunction and vice versa
+				if matches!(self, StateFormula::And(_)) != negated {
+					Some(VasTarget::And(targets))
+				} else {
+					Some(VasTarget::Or(targets))
+				}
+			}
+		}
+	}
+
+	/// The same formula with each variable index `i` replaced by `map(i)`, or `None` if
+	/// the formula uses a variable that `map` drops. Labels are kept by name.
+	#[trusted]
+	pub(crate) fn map_variables(&self, map: &impl Fn(usize) -> Option<usize>) -> Option<Self> {
+		Some(match self {
+			StateFormula::True | StateFormula::False | StateFormula::StateLabel(_) => self.clone(),
+			StateFormula::Relation {
+				variable,
+				index,
+				relation,
+				value,
+			} => StateFormula::Relation {
+				variable: variable.clone(),
+				index: map(*index)?,
+				relation: *relation,
+				value: *value,
+			},
+			StateFormula::Not(formula) => StateFormula::Not(Box::new(formula.map_variables(map)?)),
+			StateFormula::And(formulas) => StateFormula::And(
+				formulas
+					.iter()
+					.map(|f| f.map_variables(map))
+					.collect::<Option<Vec<_>>>()?,
+			),
+			StateFormula::Or(formulas) => StateFormula::Or(
+				formulas
+					.iter()
+					.map(|f| f.map_variables(map))
+					.collect::<Option<Vec<_>>>()?,
+			),
+		})
+	}
+
+	/// Whether or not a state satisfies the formula. Labels are resolved through
+	/// `label`, which gets the name of the label and the state.
+	#[trusted]
+	pub(crate) fn holds(&self, state: &VasState, label: &dyn Fn(&str, &VasState) -> bool) -> bool {
+		match self {
+			StateFormula::True => true,
+			StateFormula::False => false,
+			StateFormula::StateLabel(name) => label(name, state),
+			StateFormula::Relation {
+				index,
+				relation,
+				value,
+				..
+			} => state
+				.vector
+				.get(*index)
+				.map_or(false, |current| relation.holds(*current, *value)),
+			StateFormula::Not(formula) => !formula.holds(state, label),
+			StateFormula::And(formulas) => formulas.iter().all(|f| f.holds(state, label)),
+			StateFormula::Or(formulas) => formulas.iter().any(|f| f.holds(state, label)),
+		}
+	}
+
+	/// The formula in disjunctive normal form, as a list of cubes. Negations are pushed
+	/// into the atoms, and cubes that no state satisfies are dropped, so a formula is
+	/// unsatisfiable exactly when the list is empty. Labels are treated as opaque atoms.
+	#[trusted]
+	fn cubes(&self, negated: bool) -> Vec<Cube> {
+		match self {
+			StateFormula::True | StateFormula::False => {
+				if matches!(self, StateFormula::True) != negated {
+					vec![Cube::default()]
+				} else {
+					vec![]
+				}
+			}
+			StateFormula::StateLabel(name) => {
+				let mut cube = Cube::default();
+				cube.labels.insert(name.clone(), !negated);
+				vec![cube]
+			}
+			StateFormula::Relation {
+				index,
+				relation,
+				value,
+				..
+			} => relation_intervals(*relation, *value, negated)
+				.into_iter()
+				.filter(|(low, high)| low <= high)
+				.map(|bounds| {
+					let mut cube = Cube::default();
+					cube.bounds.insert(*index, bounds);
+					cube
+				})
+				.collect(),
+			StateFormula::Not(formula) => formula.cubes(!negated),
+			StateFormula::And(formulas) | StateFormula::Or(formulas) => {
+				if matches!(self, StateFormula::And(_)) != negated {
+					// A conjunction: intersect every cube of each operand
+					formulas.iter().fold(vec![Cube::default()], |cubes, f| {
+						let other = f.cubes(negated);
+						cubes
+							.iter()
+							.flat_map(|cube| other.iter().filter_map(|o| cube.intersect(o)))
+							.collect()
+					})
+				} else {
+					formulas.iter().flat_map(|f| f.cubes(negated)).collect()
+				}
+			}
+		}
+	}
+}
+
+/// A conjunction of bounds on variables and (possibly negated) labels
+#[derive(Clone, Debug, Default)]
+struct Cube {
+	/// Inclusive bounds on the value of the variable with the given index
+	bounds: HashMap<usize, (VasValue, VasValue)>,
+	/// Labels that must hold (`true`) or must not hold (`false`)
+	labels: HashMap<String, bool>,
+}
+
+impl Cube {
+	/// The intersection of two cubes, or `None` if it is empty
+	fn intersect(&self, other: &Cube) -> Option<Cube> {
+		let mut cube = self.clone();
+		for (index, (low, high)) in other.bounds.iter() {
+			let bounds = cube
+				.bounds
+				.entry(*index)
+				.or_insert((VasValue::MIN, VasValue::MAX));
+			*bounds = (bounds.0.max(*low), bounds.1.min(*high));
+			if bounds.0 > bounds.1 {
+				return None;
+			}
+		}
+		for (name, holds) in other.labels.iter() {
+			if *cube.labels.entry(name.clone()).or_insert(*holds) != *holds {
+				return None;
+			}
+		}
+		Some(cube)
+	}
+}
+
+/// The inclusive intervals of values `x` for which `x <relation> value` holds (or,
+/// if `negated`, does not hold)
+fn relation_intervals(
+	relation: AllowedRelation,
+	value: VasValue,
+	negated: bool,
+) -> Vec<(VasValue, VasValue)> {
+	let below = (VasValue::MIN, value.saturating_sub(1));
+	let above = (value.saturating_add(1), VasValue::MAX);
+	match (relation, negated) {
+		(AllowedRelation::Equal, false) => vec![(value, value)],
+		(AllowedRelation::Equal, true) => vec![below, above],
+		(AllowedRelation::LessThan, false) | (AllowedRelation::GreaterOrEqual, true) => vec![below],
+		(AllowedRelation::LessThan, true) | (AllowedRelation::GreaterOrEqual, false) => {
+			vec![(value, VasValue::MAX)]
+		}
+		(AllowedRelation::GreaterThan, false) | (AllowedRelation::LessOrEqual, true) => vec![above],
+		(AllowedRelation::GreaterThan, true) | (AllowedRelation::LessOrEqual, false) => {
+			vec![(VasValue::MIN, value)]
+		}
+	}
 }
 
 #[trusted]
 impl Label for StateFormula {
 	type LabeledType = StateFormula;
+
+	/// `label` is a subset of `self` when no state satisfies `label & !self`
 	#[trusted]
-	fn contains(&self, label: &Self) {
-		todo!()
+	fn contains(&self, label: &Self) -> bool {
+		StateFormula::And(vec![label.clone(), StateFormula::Not(Box::new(self.clone()))])
+			.cubes(false)
+			.is_empty()
 	}
 
 	#[trusted]
 	fn compose(&self, label: &Self) -> Self {
-		todo!()
+		if self.contains(label) {
+			return label.clone();
+		}
+		if label.contains(self) {
+			return self.clone();
+		}
+		let mut formulas = Vec::new();
+		for formula in [self, label] {
+			match formula {
+				StateFormula::And(operands) => formulas.extend(operands.iter().cloned()),
+				other => formulas.push(other.clone()),
+			}
+		}
+		StateFormula::And(formulas)
 	}
 }
 
+/// Prints the formula in the syntax of PRISM `.prop` files, so that the output
+/// parses back into the same formula
 impl Display for StateFormula {
 	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-		todo!()
+		// Nested connectives are parenthesized so they keep their structure
+		let operand = |formula: &StateFormula| match formula {
+			StateFormula::And(_) | StateFormula::Or(_) => format!("({})", formula),
+			_ => formula.to_string(),
+		};
+		match self {
+			StateFormula::True => write!(f, "true"),
+			StateFormula::False => write!(f, "false"),
+			StateFormula::StateLabel(name) => write!(f, "\"{}\"", name),
+			StateFormula::Relation {
+				variable,
+				relation,
+				value,
+				..
+			} => write!(f, "{} {} {}", variable, relation, value),
+			StateFormula::Not(formula) => write!(f, "!({})", formula),
+			StateFormula::And(formulas) | StateFormula::Or(formulas) => {
+				let (symbol, empty) = match self {
+					StateFormula::And(_) => (" & ", "true"),
+					_ => (" | ", "false"),
+				};
+				match formulas.as_slice() {
+					[] => write!(f, "{}", empty),
+					[formula] => write!(f, "{}", formula),
+					_ => write!(
+						f,
+						"{}",
+						formulas.iter().map(operand).collect::<Vec<_>>().join(symbol)
+					),
+				}
+			}
+		}
+	}
+}
+
+/// Prints the path formula in the syntax of PRISM `.prop` files
+impl Display for Property {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+		let bound = |time_bound: &Option<f64>| match time_bound {
+			Some(time) => format!("<={}", time),
+			None => String::new(),
+		};
+		let operand = |formula: &StateFormula| match formula {
+			StateFormula::And(_) | StateFormula::Or(_) => format!("({})", formula),
+			_ => formula.to_string(),
+		};
+		match self {
+			Property::Globally(invariant) => write!(f, "G {}", operand(invariant)),
+			Property::Finally(goal, time_bound) => {
+				write!(f, "F{} {}", bound(time_bound), operand(goal))
+			}
+			Property::Until(safe, goal, time_bound) => write!(
+				f,
+				"{} U{} {}",
+				operand(safe),
+				bound(time_bound),
+				operand(goal)
+			),
+		}
+	}
+}
+
+/// Prints the query in the syntax of PRISM `.prop` files
+impl Display for PropertyQuery {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+		match self {
+			PropertyQuery::Probability(property) => write!(f, "P=? [{}]", property),
+			PropertyQuery::MaxProbability(property) => write!(f, "Pmax=? [{}]", property),
+			PropertyQuery::MinProbability(property) => write!(f, "Pmin=? [{}]", property),
+			PropertyQuery::SteadyState(formula) => write!(f, "S=? [{}]", formula),
+		}
 	}
 }
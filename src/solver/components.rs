@@ -0,0 +1,78 @@
+use crate::model::sparse_matrix::SparseMatrix;
+
+/// The bottom strongly connected components (BSCCs) of the graph of a matrix among the
+/// states reachable from `initial`: the sets of states that reach each other and cannot
+/// be left. Found with an iterative version of Tarjan's algorithm.
+pub(crate) fn bottom_components(matrix: &SparseMatrix, initial: &[usize]) -> Vec<Vec<usize>> {
+	const UNVISITED: usize = usize::MAX;
+	let size = matrix.rows();
+	let mut index = vec![UNVISITED; size];
+	let mut low_link = vec![0; size];
+	let mut on_stack = vec![false; size];
+	let mut stack = Vec::new();
+	let mut component_of = vec![UNVISITED; size];
+	let mut components: Vec<Vec<usize>> = Vec::new();
+	let mut next_index = 0;
+	for &root in initial.iter() {
+		if index[root] != UNVISITED {
+			continue;
+		}
+		// Each frame is a state and the position of the next successor to visit
+		let mut frames = vec![(root, 0)];
+		index[root] = next_index;
+		low_link[root] = next_index;
+		next_index += 1;
+		stack.push(root);
+		on_stack[root] = true;
+		while let Some(&mut (state, ref mut position)) = frames.last_mut() {
+			let successor = matrix.row(state).nth(*position).map(|(column, _)| column);
+			*position += 1;
+			match successor {
+				Some(next) if index[next] == UNVISITED => {
+					index[next] = next_index;
+					low_link[next] = next_index;
+					next_index += 1;
+					stack.push(next);
+					on_stack[next] = true;
+					frames.push((next, 0));
+				}
+				Some(next) => {
+					if on_stack[next] {
+						low_link[state] = low_link[state].min(index[next]);
+					}
+				}
+				None => {
+					frames.pop();
+					if let Some(&(parent, _)) = frames.last() {
+						low_link[parent] = low_link[parent].min(low_link[state]);
+					}
+					if low_link[state] == index[state] {
+						let mut component = Vec::new();
+						while let Some(member) = stack.pop() {
+							on_stack[member] = false;
+							component_of[member] = components.len();
+							component.push(member);
+							if member == state {
+								break;
+							}
+						}
+						components.push(component);
+					}
+				}
+			}
+		}
+	}
+	// A component is at the bottom if no transition leaves it
+	components
+		.iter()
+		.enumerate()
+		.filter(|(number, component)| {
+			component.iter().all(|&state| {
+				matrix
+					.row(state)
+					.all(|(next, _)| component_of[next] == *number)
+			})
+		})
+		.map(|(_, component)| component.clone())
+		.collect()
+}
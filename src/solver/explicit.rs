@@ -0,0 +1,118 @@
+use crate::{
+	model::{
+		model::ProbabilityOrRate,
+		vas_model::{AbstractVas, PrismVasModel, PrismVasState},
+	},
+	property::property::{Property, StateFormula},
+};
+
+/// The label of the absorbing state that the unexplored states of a model lead to
+pub(crate) const SINK_LABEL: &str = "sink";
+
+/// Whether a state of an explicit model is the sink
+pub(crate) fn is_sink(state: &PrismVasState) -> bool {
+	state.label.as_deref() == Some(SINK_LABEL)
+}
+
+/// Which indices of an explicit model are the sink. Indices that were reserved but never
+/// filled are not.
+pub(crate) fn sink_states(explicit_model: &PrismVasModel) -> Vec<bool> {
+	let mut sink = vec![false; explicit_model.next_index];
+	for state in explicit_model.states.iter().filter(|state| is_sink(state)) {
+		sink[state.state_id] = true;
+	}
+	sink
+}
+
+/// Which indices of an explicit model satisfy a state formula of the model it was built
+/// from. The sink and the reserved indices satisfy nothing.
+pub(crate) fn satisfying(
+	abstract_model: &AbstractVas,
+	explicit_model: &PrismVasModel,
+	formula: &StateFormula,
+) -> Vec<bool> {
+	let mut satisfied = vec![false; explicit_model.next_index];
+	for state in explicit_model.states.iter().filter(|state| !is_sink(state)) {
+		satisfied[state.state_id] =
+			abstract_model.satisfies(formula, &abstract_model.state(state.vector.clone()));
+	}
+	satisfied
+}
+
+/// The index and probability of each initial state of the abstract model in the explicit
+/// model, or an error if one of them was not added to it
+pub(crate) fn initial_distribution(
+	abstract_model: &AbstractVas,
+	explicit_model: &PrismVasModel,
+) -> Result<Vec<(usize, ProbabilityOrRate)>, String> {
+	abstract_model
+		.initial_states
+		.iter()
+		.zip(abstract_model.initial_probabilities.iter())
+		.map(|(state, probability)| {
+			explicit_model
+				.state_trie
+				.get(&state.vector)
+				.map(|index| (index, *probability))
+				.ok_or(format!(
+					"The initial state {:?} is not in the explicit model",
+					state.vector.as_slice()
+				))
+		})
+		.collect()
+}
+
+/// The weighted sum of a vector over a distribution of states
+pub(crate) fn expectation(
+	distribution: &[(usize, ProbabilityOrRate)],
+	values: &[ProbabilityOrRate],
+) -> ProbabilityOrRate {
+	distribution
+		.iter()
+		.map(|(index, probability)| probability * values[*index])
+		.sum()
+}
+
+/// The states of a path formula, which asks for the probability of reaching `goal` through
+/// `safe` states only. A `G φ` property is the complement of `F !φ`.
+pub(crate) struct PathStates {
+	pub(crate) safe: Vec<bool>,
+	pub(crate) goal: Vec<bool>,
+	pub(crate) time_bound: Option<f64>,
+	pub(crate) complement: bool,
+}
+
+impl PathStates {
+	/// Finds the states of a property in an explicit model
+	pub fn new(
+		abstract_model: &AbstractVas,
+		explicit_model: &PrismVasModel,
+		property: &Property,
+	) -> Self {
+		let everywhere = vec![true; explicit_model.next_index];
+		match property {
+			Property::Finally(goal, time_bound) => Self {
+				safe: everywhere,
+				goal: satisfying(abstract_model, explicit_model, goal),
+				time_bound: *time_bound,
+				complement: false,
+			},
+			Property::Until(safe, goal, time_bound) => Self {
+				safe: satisfying(abstract_model, explicit_model, safe),
+				goal: satisfying(abstract_model, explicit_model, goal),
+				time_bound: *time_bound,
+				complement: false,
+			},
+			Property::Globally(invariant) => Self {
+				safe: everywhere,
+				goal: satisfying(
+					abstract_model,
+					explicit_model,
+					&StateFormula::Not(Box::new(invariant.clone())),
+				),
+				time_bound: None,
+				complement: true,
+			},
+		}
+	}
+}
@@ -0,0 +1,89 @@
+use crate::model::model::ProbabilityOrRate;
+
+/// The Poisson probabilities `e^-λ λ^k / k!` for the steps `left..=right` of uniformization,
+/// after Fox and Glynn: the weights start at the mode and follow the recurrence between
+/// neighbouring terms outwards, so they neither underflow nor overflow, and the range is
+/// cut off once a geometric bound on each tail is below `epsilon / 2`. The weights are
+/// normalized, so they sum to one and differ from the true probabilities by at most
+/// `epsilon` in total.
+#[derive(Clone, Debug)]
+pub(crate) struct FoxGlynn {
+	pub(crate) left: usize,
+	pub(crate) right: usize,
+	pub(crate) weights: Vec<ProbabilityOrRate>,
+}
+
+impl FoxGlynn {
+	/// Computes the truncated Poisson probabilities for the rate `lambda`
+	pub fn new(lambda: f64, epsilon: f64) -> Result<Self, String> {
+		if !(lambda >= 0.0) || !lambda.is_finite() {
+			return Err(format!("Invalid Poisson rate {}", lambda));
+		}
+		if !(epsilon > 0.0 && epsilon < 1.0) {
+			return Err(format!("The accuracy {} must be between 0 and 1", epsilon));
+		}
+		if lambda == 0.0 {
+			return Ok(Self {
+				left: 0,
+				right: 0,
+				weights: vec![1.0],
+			});
+		}
+		let mode = lambda.floor() as usize;
+		// The weights relative to the mode, with their running total
+		let mut below = Vec::new();
+		let mut above = Vec::new();
+		let mut total = 1.0;
+		// The left tail: each term below `k` is at most `k / λ` times the one above it
+		let mut weight = 1.0;
+		let mut k = mode;
+		while k > 0 {
+			let ratio = k as f64 / lambda;
+			if ratio < 1.0 && weight * ratio / (1.0 - ratio) <= total * epsilon / 2.0 {
+				break;
+			}
+			weight *= ratio;
+			k -= 1;
+			below.push(weight);
+			total += weight;
+		}
+		let left = k;
+		// The right tail: the term above `k` is `λ / (k + 1)` times the one at `k`, and each
+		// term after that is at most `λ / (k + 2)` times the one below it
+		let mut weight = 1.0;
+		let mut k = mode;
+		loop {
+			let ratio = lambda / (k + 2) as f64;
+			let next = weight * lambda / (k + 1) as f64;
+			if ratio < 1.0 && next / (1.0 - ratio) <= total * epsilon / 2.0 {
+				break;
+			}
+			weight = next;
+			k += 1;
+			above.push(weight);
+			total += weight;
+		}
+		let right = k;
+		let weights = below
+			.into_iter()
+			.rev()
+			.chain(std::iter::once(1.0))
+			.chain(above)
+			.map(|weight| weight / total)
+			.collect();
+		Ok(Self {
+			left,
+			right,
+			weights,
+		})
+	}
+
+	/// The probability of `k` steps, which is zero outside of `left..=right`
+	pub fn weight(&self, k: usize) -> ProbabilityOrRate {
+		if k < self.left || k > self.right {
+			0.0
+		} else {
+			self.weights[k - self.left]
+		}
+	}
+}
@@ -0,0 +1,157 @@
+use crate::{
+	checker::result::ModelCheckingResult,
+	logging::messages::*,
+	model::{
+		model::{ExplicitModel, ModelType, ProbabilityOrRate},
+		sparse_matrix::SparseMatrix,
+		vas_model::{AbstractVas, PrismVasModel},
+	},
+	property::property::PropertyQuery,
+	solver::{
+		explicit::{expectation, initial_distribution, sink_states, PathStates},
+		iterative::{can_reach, embedded_matrix, iterate_value, IterativeMethod, SolverOptions},
+	},
+};
+
+/// The bounds from interval iteration: the true probability of each state lies between
+/// `lower` and `upper`, even if the iteration stopped before it converged
+#[derive(Clone, Debug)]
+pub(crate) struct IntervalResult {
+	pub(crate) lower: Vec<ProbabilityOrRate>,
+	pub(crate) upper: Vec<ProbabilityOrRate>,
+	pub(crate) iterations: usize,
+	pub(crate) converged: bool,
+}
+
+/// The probability from each state of eventually reaching a `goal` state through `safe`
+/// states only, bounded from both sides by interval iteration. The lower values start at
+/// zero and the upper values at one, and both are iterated with the same method until
+/// they are within the tolerance of each other. The states that cannot reach the goal
+/// are set to zero first, which makes the fixed point unique, so the upper values
+/// converge as well. Each value only ever moves towards the other bound. The arithmetic
+/// is ordinary floating point, so the bounds are only as sound as its rounding.
+pub(crate) fn interval_reachability(
+	matrix: &SparseMatrix,
+	m_type: ModelType,
+	safe: &[bool],
+	goal: &[bool],
+	options: &SolverOptions,
+) -> Result<IntervalResult, String> {
+	if m_type.is_nondeterministic() {
+		return Err(format!(
+			"Interval iteration needs a Markov chain, not a {}",
+			m_type
+		));
+	}
+	let probabilities = embedded_matrix(matrix, m_type);
+	let reaching = can_reach(&probabilities.transpose(), safe, goal);
+	let states: Vec<usize> = (0..goal.len())
+		.filter(|&state| reaching[state] && !goal[state])
+		.collect();
+	let mut lower: Vec<ProbabilityOrRate> = goal
+		.iter()
+		.map(|&reached| if reached { 1.0 } else { 0.0 })
+		.collect();
+	let mut upper: Vec<ProbabilityOrRate> = (0..goal.len())
+		.map(|state| if reaching[state] { 1.0 } else { 0.0 })
+		.collect();
+	let mut iterations = 0;
+	let mut converged = states
+		.iter()
+		.all(|&state| options.converged(lower[state], upper[state]));
+	while !converged && iterations < options.max_iterations {
+		iterations += 1;
+		if options.method == IterativeMethod::GaussSeidel {
+			for &state in states.iter() {
+				let value = iterate_value(&probabilities, state, &lower, options.method);
+				lower[state] = lower[state].max(value);
+				let value = iterate_value(&probabilities, state, &upper, options.method);
+				upper[state] = upper[state].min(value);
+			}
+		} else {
+			let mut next_lower = lower.clone();
+			let mut next_upper = upper.clone();
+			for &state in states.iter() {
+				let value = iterate_value(&probabilities, state, &lower, options.method);
+				next_lower[state] = lower[state].max(value);
+				let value = iterate_value(&probabilities, state, &upper, options.method);
+				next_upper[state] = upper[state].min(value);
+			}
+			lower = next_lower;
+			upper = next_upper;
+		}
+		converged = states
+			.iter()
+			.all(|&state| options.converged(lower[state], upper[state]));
+	}
+	if !converged {
+		warning!(
+			"Interval iteration with the {} method did not converge within {} iterations, so the bounds are wider than the tolerance",
+			options.method,
+			options.max_iterations
+		);
+	}
+	Ok(IntervalResult {
+		lower,
+		upper,
+		iterations,
+		converged,
+	})
+}
+
+/// Checks an unbounded `F φ`, `ψ U φ` or `G φ` query of `abstract_model` on an explicit
+/// model built from it, by interval iteration on the embedded DTMC. The sink counts as
+/// failure for the lower bound (Pmin) and as success for the upper bound (Pmax), and
+/// each side is taken from the sound end of its interval, so the range contains the
+/// probability up to floating-point rounding. Its width is that of the sink plus at most twice the
+/// tolerance. On a Markov chain, `Pmin=?` and `Pmax=?` ask the same as `P=?`.
+pub(crate) fn check_interval(
+	abstract_model: &AbstractVas,
+	explicit_model: &PrismVasModel,
+	query: &PropertyQuery,
+	options: &SolverOptions,
+) -> Result<ModelCheckingResult, String> {
+	let property = match query {
+		PropertyQuery::Probability(property)
+		| PropertyQuery::MinProbability(property)
+		| PropertyQuery::MaxProbability(property) => property,
+		_ => {
+			return Err(
+				"Only P=?, Pmin=? and Pmax=? queries are checked by reachability".to_string(),
+			)
+		}
+	};
+	let states = PathStates::new(abstract_model, explicit_model, property);
+	if states.time_bound.is_some() {
+		return Err("The query has a time bound".to_string());
+	}
+	let matrix = explicit_model.to_matrix();
+	let initial = initial_distribution(abstract_model, explicit_model)?;
+	let bounds = |goal: &[bool]| -> Result<(ProbabilityOrRate, ProbabilityOrRate), String> {
+		let solution =
+			interval_reachability(&matrix, explicit_model.m_type, &states.safe, goal, options)?;
+		debug_message!("Interval iteration took {} iterations", solution.iterations);
+		Ok((
+			expectation(&initial, &solution.lower).clamp(0.0, 1.0),
+			expectation(&initial, &solution.upper).clamp(0.0, 1.0),
+		))
+	};
+	let sink = sink_states(explicit_model);
+	let (lower, upper) = if sink.iter().any(|&is_sink| is_sink) {
+		let goal_or_sink: Vec<bool> = (0..sink.len())
+			.map(|state| states.goal[state] || sink[state])
+			.collect();
+		let (lower, _) = bounds(&states.goal)?;
+		let (_, upper) = bounds(&goal_or_sink)?;
+		(lower, upper)
+	} else {
+		bounds(&states.goal)?
+	};
+	// `G φ` holds on the paths that never reach `!φ`
+	let (lower, upper) = if states.complement {
+		(1.0 - upper, 1.0 - lower)
+	} else {
+		(lower, upper)
+	};
+	Ok(ModelCheckingResult::ProbabilityRange(lower, upper))
+}
@@ -0,0 +1,418 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	fmt,
+};
+
+use crate::{
+	checker::result::ModelCheckingResult,
+	logging::messages::*,
+	model::{
+		model::{ExplicitModel, ModelType, ProbabilityOrRate},
+		sparse_matrix::SparseMatrix,
+		vas_model::{AbstractVas, PrismVasModel},
+	},
+	property::property::PropertyQuery,
+	solver::{
+		components::bottom_components,
+		explicit::{initial_distribution, satisfying, sink_states},
+	},
+};
+
+/// The iterative method that solves the linear equations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum IterativeMethod {
+	/// Every value of an iteration comes from the values of the previous one
+	Jacobi,
+	/// Like `Jacobi`, but the values already updated in an iteration are used right away,
+	/// which usually converges in fewer iterations
+	GaussSeidel,
+	/// Applies the matrix to the values as it is, without solving for the self-loops
+	Power,
+}
+
+impl IterativeMethod {
+	/// All of the methods, in the order they are listed to users
+	pub const ALL: [IterativeMethod; 3] = [
+		IterativeMethod::GaussSeidel,
+		IterativeMethod::Jacobi,
+		IterativeMethod::Power,
+	];
+
+	/// Reads a method from its name, e.g., on the command line
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL
+			.into_iter()
+			.find(|method| method.to_string() == name.to_lowercase())
+	}
+}
+
+impl fmt::Display for IterativeMethod {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			IterativeMethod::Jacobi => write!(f, "jacobi"),
+			IterativeMethod::GaussSeidel => write!(f, "gauss-seidel"),
+			IterativeMethod::Power => write!(f, "power"),
+		}
+	}
+}
+
+/// When the values of two iterations are close enough to stop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Convergence {
+	/// No value changes by more than the tolerance
+	Absolute,
+	/// No value changes by more than the tolerance times its size
+	Relative,
+}
+
+/// The settings of the iterative solvers
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SolverOptions {
+	pub(crate) method: IterativeMethod,
+	pub(crate) tolerance: f64,
+	pub(crate) max_iterations: usize,
+	pub(crate) convergence: Convergence,
+}
+
+impl Default for SolverOptions {
+	/// The defaults of PRISM's explicit engine
+	fn default() -> Self {
+		Self {
+			method: IterativeMethod::GaussSeidel,
+			tolerance: 1e-6,
+			max_iterations: 10000,
+			convergence: Convergence::Relative,
+		}
+	}
+}
+
+impl SolverOptions {
+	/// Whether a value has converged
+	pub(crate) fn converged(&self, old: f64, new: f64) -> bool {
+		let difference = (new - old).abs();
+		match self.convergence {
+			Convergence::Absolute => difference <= self.tolerance,
+			Convergence::Relative => difference <= self.tolerance * new.abs(),
+		}
+	}
+}
+
+/// The values an iterative solver ended with
+#[derive(Clone, Debug)]
+pub(crate) struct IterationResult {
+	pub(crate) values: Vec<ProbabilityOrRate>,
+	pub(crate) iterations: usize,
+	pub(crate) converged: bool,
+}
+
+/// The transition probabilities of a DTMC, or of the embedded DTMC (jump chain) of a CTMC,
+/// whose self-loops are dropped since they do not change the state
+pub(crate) fn embedded_matrix(matrix: &SparseMatrix, m_type: ModelType) -> SparseMatrix {
+	if m_type.is_discrete_time() {
+		return matrix.clone();
+	}
+	let exit_rates: Vec<ProbabilityOrRate> = (0..matrix.rows())
+		.map(|row| {
+			matrix
+				.row(row)
+				.filter(|(column, _)| *column != row)
+				.map(|(_, rate)| rate)
+				.sum()
+		})
+		.collect();
+	SparseMatrix::from_triplets(
+		matrix.rows(),
+		matrix.columns(),
+		(0..matrix.rows()).flat_map(|row| {
+			let exit_rate = exit_rates[row];
+			matrix
+				.row(row)
+				.filter(move |(column, _)| *column != row)
+				.map(move |(column, rate)| (row, column, rate / exit_rate))
+		}),
+	)
+}
+
+/// The next value of one state in an iteration of `x = A x`. With Jacobi and Gauss-Seidel,
+/// the self-loop of the state is solved for, i.e., `x_i = (Σ_{j≠i} A_ij x_j) / (1 - A_ii)`.
+/// Either way, the value is monotone in the values of the other states.
+pub(crate) fn iterate_value(
+	matrix: &SparseMatrix,
+	state: usize,
+	values: &[ProbabilityOrRate],
+	method: IterativeMethod,
+) -> ProbabilityOrRate {
+	match method {
+		IterativeMethod::Power => matrix
+			.row(state)
+			.map(|(next, probability)| probability * values[next])
+			.sum(),
+		IterativeMethod::Jacobi | IterativeMethod::GaussSeidel => {
+			let (sum, self_loop) =
+				matrix
+					.row(state)
+					.fold((0.0, 0.0), |(sum, self_loop), (next, probability)| {
+						if next == state {
+							(sum, self_loop + probability)
+						} else {
+							(sum + probability * values[next], self_loop)
+						}
+					});
+			if self_loop < 1.0 {
+				sum / (1.0 - self_loop)
+			} else {
+				values[state]
+			}
+		}
+	}
+}
+
+/// Solves `x = A x` for the `unknown` entries of `x`, where the other entries keep the
+/// values they start with.
+pub(crate) fn solve_fixed_point(
+	matrix: &SparseMatrix,
+	unknown: &[bool],
+	mut values: Vec<ProbabilityOrRate>,
+	options: &SolverOptions,
+) -> IterationResult {
+	let states: Vec<usize> = (0..values.len()).filter(|&i| unknown[i]).collect();
+	let update = |state: usize, values: &[ProbabilityOrRate]| {
+		iterate_value(matrix, state, values, options.method)
+	};
+	let mut iterations = 0;
+	let mut converged = false;
+	while !converged && iterations < options.max_iterations {
+		iterations += 1;
+		converged = true;
+		if options.method == IterativeMethod::GaussSeidel {
+			for &state in states.iter() {
+				let value = update(state, &values);
+				converged &= options.converged(values[state], value);
+				values[state] = value;
+			}
+		} else {
+			let mut next_values = values.clone();
+			for &state in states.iter() {
+				next_values[state] = update(state, &values);
+				converged &= options.converged(values[state], next_values[state]);
+			}
+			values = next_values;
+		}
+	}
+	if !converged {
+		warning!(
+			"The {} method did not converge within {} iterations",
+			options.method,
+			options.max_iterations
+		);
+	}
+	IterationResult {
+		values,
+		iterations,
+		converged,
+	}
+}
+
+/// The states that can reach a `goal` state through `safe` states only
+pub(crate) fn can_reach(transposed: &SparseMatrix, safe: &[bool], goal: &[bool]) -> Vec<bool> {
+	let mut reached = goal.to_vec();
+	let mut queue: VecDeque<usize> = (0..goal.len()).filter(|&i| goal[i]).collect();
+	while let Some(state) = queue.pop_front() {
+		for (previous, _) in transposed.row(state) {
+			if !reached[previous] && safe[previous] {
+				reached[previous] = true;
+				queue.push_back(previous);
+			}
+		}
+	}
+	reached
+}
+
+/// The probability from each state of eventually reaching a `goal` state through `safe`
+/// states only. The states that cannot reach the goal at all are found first, so that the
+/// equations of the others have a unique solution.
+pub(crate) fn unbounded_reachability(
+	matrix: &SparseMatrix,
+	m_type: ModelType,
+	safe: &[bool],
+	goal: &[bool],
+	options: &SolverOptions,
+) -> Result<IterationResult, String> {
+	if m_type.is_nondeterministic() {
+		return Err(format!(
+			"The linear solvers need a Markov chain, not a {}",
+			m_type
+		));
+	}
+	let probabilities = embedded_matrix(matrix, m_type);
+	let reaching = can_reach(&probabilities.transpose(), safe, goal);
+	let unknown: Vec<bool> = (0..goal.len())
+		.map(|state| reaching[state] && !goal[state])
+		.collect();
+	let values = goal
+		.iter()
+		.map(|&reached| if reached { 1.0 } else { 0.0 })
+		.collect();
+	Ok(solve_fixed_point(&probabilities, &unknown, values, options))
+}
+
+/// The stationary distribution within a bottom strongly connected component, from the
+/// balance equations `π_j Σ_{k≠j} w_jk = Σ_{i≠j} π_i w_ij` over the rates (or
+/// probabilities) `w`, normalized after each iteration
+fn component_distribution(
+	matrix: &SparseMatrix,
+	component: &[usize],
+	options: &SolverOptions,
+) -> IterationResult {
+	if component.len() == 1 {
+		return IterationResult {
+			values: vec![1.0],
+			iterations: 0,
+			converged: true,
+		};
+	}
+	// The matrix restricted to the component, with local indices and without self-loops
+	let local: HashMap<usize, usize> = component
+		.iter()
+		.enumerate()
+		.map(|(local, state)| (*state, local))
+		.collect();
+	let weights = SparseMatrix::from_triplets(
+		component.len(),
+		component.len(),
+		component.iter().enumerate().flat_map(|(from, &state)| {
+			let local = &local;
+			matrix.row(state).filter_map(move |(next, weight)| {
+				local
+					.get(&next)
+					.filter(|&&to| to != from)
+					.map(|&to| (from, to, weight))
+			})
+		}),
+	);
+	let outgoing = weights.row_sums();
+	let incoming = weights.transpose();
+	// The power method works on the uniformized chain, which is aperiodic
+	let rate = outgoing.iter().copied().fold(0.0, f64::max) * 1.02;
+	let mut values = vec![1.0 / component.len() as f64; component.len()];
+	let mut iterations = 0;
+	let mut converged = false;
+	while !converged && iterations < options.max_iterations {
+		iterations += 1;
+		let previous = values.clone();
+		for state in 0..component.len() {
+			let inflow: f64 = {
+				// Gauss-Seidel uses the values of this iteration as soon as they are known
+				let source = if options.method == IterativeMethod::GaussSeidel {
+					&values
+				} else {
+					&previous
+				};
+				incoming
+					.row(state)
+					.map(|(from, weight)| source[from] * weight)
+					.sum()
+			};
+			values[state] = match options.method {
+				IterativeMethod::Power => {
+					previous[state] + (inflow - previous[state] * outgoing[state]) / rate
+				}
+				IterativeMethod::Jacobi | IterativeMethod::GaussSeidel => inflow / outgoing[state],
+			};
+		}
+		let total: f64 = values.iter().sum();
+		values.iter_mut().for_each(|value| *value /= total);
+		converged = values
+			.iter()
+			.zip(previous.iter())
+			.all(|(new, old)| options.converged(*old, *new));
+	}
+	if !converged {
+		warning!(
+			"The {} method did not converge to a stationary distribution within {} iterations",
+			options.method,
+			options.max_iterations
+		);
+	}
+	IterationResult {
+		values,
+		iterations,
+		converged,
+	}
+}
+
+/// The long-run probability of being in each state, starting from `initial`. Each bottom
+/// strongly connected component gets the probability of reaching it, spread over its
+/// states by its stationary distribution.
+pub(crate) fn steady_state(
+	matrix: &SparseMatrix,
+	m_type: ModelType,
+	initial: &[(usize, ProbabilityOrRate)],
+	options: &SolverOptions,
+) -> Result<Vec<ProbabilityOrRate>, String> {
+	if m_type.is_nondeterministic() {
+		return Err(format!(
+			"Steady-state analysis needs a Markov chain, not a {}",
+			m_type
+		));
+	}
+	let initial_states: Vec<usize> = initial.iter().map(|(state, _)| *state).collect();
+	let components = bottom_components(matrix, &initial_states);
+	debug_message!("Found {} bottom components", components.len());
+	let everywhere = vec![true; matrix.rows()];
+	let mut probabilities = vec![0.0; matrix.rows()];
+	for component in components.iter() {
+		let mut in_component = vec![false; matrix.rows()];
+		component
+			.iter()
+			.for_each(|&state| in_component[state] = true);
+		let reach = if components.len() == 1 {
+			1.0
+		} else {
+			let reach =
+				unbounded_reachability(matrix, m_type, &everywhere, &in_component, options)?;
+			initial
+				.iter()
+				.map(|(state, probability)| probability * reach.values[*state])
+				.sum()
+		};
+		let distribution = component_distribution(matrix, component, options);
+		for (state, probability) in component.iter().zip(distribution.values.iter()) {
+			probabilities[*state] = reach * probability;
+		}
+	}
+	Ok(probabilities)
+}
+
+/// Checks an `S=? [φ]` query of `abstract_model` on an explicit model built from it. The
+/// long-run probability in the sink could belong to any state, so with a sink the result
+/// is a range.
+pub(crate) fn check_steady_state(
+	abstract_model: &AbstractVas,
+	explicit_model: &PrismVasModel,
+	query: &PropertyQuery,
+	options: &SolverOptions,
+) -> Result<ModelCheckingResult, String> {
+	let PropertyQuery::SteadyState(formula) = query else {
+		return Err("Only S=? queries have a steady-state solution".to_string());
+	};
+	let matrix = explicit_model.to_matrix();
+	let initial = initial_distribution(abstract_model, explicit_model)?;
+	let long_run = steady_state(&matrix, explicit_model.m_type, &initial, options)?;
+	let satisfied = satisfying(abstract_model, explicit_model, formula);
+	let sink = sink_states(explicit_model);
+	let in_states = |states: &[bool]| -> ProbabilityOrRate {
+		(0..states.len())
+			.filter(|&state| states[state])
+			.map(|state| long_run[state])
+			.sum::<ProbabilityOrRate>()
+			.clamp(0.0, 1.0)
+	};
+	let lower = in_states(&satisfied);
+	let lost = in_states(&sink);
+	Ok(if lost > 0.0 {
+		ModelCheckingResult::ProbabilityRange(lower, (lower + lost).min(1.0))
+	} else {
+		ModelCheckingResult::ExactProbability(lower)
+	})
+}
@@ -0,0 +1,6 @@
+pub mod components;
+pub mod explicit;
+pub mod fox_glynn;
+pub mod interval;
+pub mod iterative;
+pub mod transient;
@@ -0,0 +1,217 @@
+use crate::{
+	checker::result::ModelCheckingResult,
+	logging::messages::*,
+	model::{
+		model::{ExplicitModel, ModelType, ProbabilityOrRate},
+		sparse_matrix::SparseMatrix,
+		vas_model::{AbstractVas, PrismVasModel},
+	},
+	property::property::{Property, PropertyQuery},
+	solver::{
+		explicit::{expectation, initial_distribution, sink_states, PathStates},
+		fox_glynn::FoxGlynn,
+	},
+};
+
+/// The default accuracy of the Poisson probabilities in uniformization
+pub(crate) const DEFAULT_EPSILON: ProbabilityOrRate = 1e-6;
+
+/// How much larger than the largest exit rate the uniformization rate is, as in PRISM, so
+/// that no state of the uniformized chain is without a self-loop
+const UNIFORMIZATION_FACTOR: ProbabilityOrRate = 1.02;
+
+/// The probabilities from a time-bounded reachability computation
+#[derive(Clone, Debug)]
+pub(crate) struct TransientSolution {
+	/// The probability from each state of the model
+	pub(crate) probabilities: Vec<ProbabilityOrRate>,
+	/// The uniformization rate, which is zero for a DTMC
+	pub(crate) uniformization_rate: ProbabilityOrRate,
+	/// How many matrix-vector products were needed
+	pub(crate) steps: usize,
+	/// A bound on the error of each probability from cutting off the Poisson
+	/// probabilities. The steps of a DTMC are exact.
+	pub(crate) error_bound: ProbabilityOrRate,
+}
+
+/// The result of checking a time-bounded query, with how it was computed
+#[derive(Clone, Debug)]
+pub(crate) struct TransientResult {
+	pub(crate) result: ModelCheckingResult,
+	pub(crate) error_bound: ProbabilityOrRate,
+	pub(crate) uniformization_rate: ProbabilityOrRate,
+	pub(crate) steps: usize,
+}
+
+/// One step of the uniformized chain `P = I + R / q` applied to the values of the successors.
+/// The values of the absorbing states do not change.
+fn uniformized_step(
+	matrix: &SparseMatrix,
+	exit_rates: &[ProbabilityOrRate],
+	absorbing: &[bool],
+	rate: ProbabilityOrRate,
+	values: &[ProbabilityOrRate],
+) -> Vec<ProbabilityOrRate> {
+	let successors = matrix.multiply(values);
+	(0..values.len())
+		.map(|state| {
+			if absorbing[state] {
+				values[state]
+			} else {
+				values[state] + (successors[state] - exit_rates[state] * values[state]) / rate
+			}
+		})
+		.collect()
+}
+
+/// The probability from each state of reaching a `goal` state through `safe` states only
+/// within `time` time units (or steps, in a DTMC). The goal and unsafe states are made
+/// absorbing. A CTMC is solved by uniformization, with the Poisson probabilities from
+/// Fox–Glynn at accuracy `epsilon`.
+pub(crate) fn bounded_reachability(
+	matrix: &SparseMatrix,
+	m_type: ModelType,
+	safe: &[bool],
+	goal: &[bool],
+	time: f64,
+	epsilon: ProbabilityOrRate,
+) -> Result<TransientSolution, String> {
+	if m_type.is_nondeterministic() {
+		return Err(format!(
+			"Transient analysis needs a Markov chain, not a {}",
+			m_type
+		));
+	}
+	if !(time >= 0.0) {
+		return Err(format!("Invalid time bound {}", time));
+	}
+	let absorbing: Vec<bool> = (0..goal.len()).map(|i| goal[i] || !safe[i]).collect();
+	let mut values: Vec<ProbabilityOrRate> = goal
+		.iter()
+		.map(|&reached| if reached { 1.0 } else { 0.0 })
+		.collect();
+	if m_type.is_discrete_time() {
+		let steps = time.floor() as usize;
+		for _ in 0..steps {
+			let successors = matrix.multiply(&values);
+			values = (0..values.len())
+				.map(|state| {
+					if absorbing[state] {
+						values[state]
+					} else {
+						successors[state]
+					}
+				})
+				.collect();
+		}
+		return Ok(TransientSolution {
+			probabilities: values,
+			uniformization_rate: 0.0,
+			steps,
+			error_bound: 0.0,
+		});
+	}
+	let exit_rates = matrix.row_sums();
+	let largest_exit_rate = (0..exit_rates.len())
+		.filter(|&state| !absorbing[state])
+		.map(|state| exit_rates[state])
+		.fold(0.0, ProbabilityOrRate::max);
+	if largest_exit_rate == 0.0 {
+		// Nothing can move, so only the goal states have reached the goal
+		return Ok(TransientSolution {
+			probabilities: values,
+			uniformization_rate: 0.0,
+			steps: 0,
+			error_bound: 0.0,
+		});
+	}
+	let rate = largest_exit_rate * UNIFORMIZATION_FACTOR;
+	let poisson = FoxGlynn::new(rate * time, epsilon)?;
+	debug_message!(
+		"Uniformization rate {}, Poisson steps {} to {}",
+		rate,
+		poisson.left,
+		poisson.right
+	);
+	let mut probabilities = vec![0.0; values.len()];
+	for step in 0..=poisson.right {
+		if step >= poisson.left {
+			let weight = poisson.weight(step);
+			for (probability, value) in probabilities.iter_mut().zip(values.iter()) {
+				*probability += weight * value;
+			}
+		}
+		if step < poisson.right {
+			values = uniformized_step(matrix, &exit_rates, &absorbing, rate, &values);
+		}
+	}
+	Ok(TransientSolution {
+		probabilities,
+		uniformization_rate: rate,
+		steps: poisson.right,
+		error_bound: epsilon,
+	})
+}
+
+/// Checks a time-bounded `P=? [F<=T φ]` or `P=? [ψ U<=T φ]` query of `abstract_model` on an
+/// explicit model built from it. Without a sink, the result is the probability, up to the
+/// error bound. With a sink, the paths that reach the sink within the time bound might
+/// still reach the goal, so the result is a range that contains the probability (widened
+/// by the error bound).
+pub(crate) fn check_bounded(
+	abstract_model: &AbstractVas,
+	explicit_model: &PrismVasModel,
+	query: &PropertyQuery,
+	epsilon: ProbabilityOrRate,
+) -> Result<TransientResult, String> {
+	let property = match query {
+		PropertyQuery::Probability(property) => property,
+		_ => return Err("Only P=? queries have a transient solution".to_string()),
+	};
+	if matches!(property, Property::Globally(_)) {
+		return Err("`G` properties have no time bound to check".to_string());
+	}
+	let states = PathStates::new(abstract_model, explicit_model, property);
+	let Some(time) = states.time_bound else {
+		return Err("The query has no time bound".to_string());
+	};
+	let matrix = explicit_model.to_matrix();
+	let initial = initial_distribution(abstract_model, explicit_model)?;
+	let solution = bounded_reachability(
+		&matrix,
+		explicit_model.m_type,
+		&states.safe,
+		&states.goal,
+		time,
+		epsilon,
+	)?;
+	let probability = expectation(&initial, &solution.probabilities).clamp(0.0, 1.0);
+	let sink = sink_states(explicit_model);
+	let result = if sink.iter().any(|&is_sink| is_sink) {
+		// The probability of being lost to the sink before reaching the goal
+		let undecided: Vec<bool> = (0..sink.len())
+			.map(|state| states.safe[state] && !states.goal[state])
+			.collect();
+		let lost = bounded_reachability(
+			&matrix,
+			explicit_model.m_type,
+			&undecided,
+			&sink,
+			time,
+			epsilon,
+		)?;
+		let lost = expectation(&initial, &lost.probabilities).clamp(0.0, 1.0);
+		ModelCheckingResult::ProbabilityRange(
+			(probability - solution.error_bound).max(0.0),
+			(probability + lost + 2.0 * solution.error_bound).min(1.0),
+		)
+	} else {
+		ModelCheckingResult::ExactProbability(probability)
+	};
+	Ok(TransientResult {
+		result,
+		error_bound: solution.error_bound,
+		uniformization_rate: solution.uniformization_rate,
+		steps: solution.steps,
+	})
+}
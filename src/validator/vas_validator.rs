@@ -1,7 +1,15 @@
 use metaverify::*;
 
-use crate::model::vas_model::{AbstractVas, VasProperty, VasTransition, VasValue};
-use ::std::collections::HashMap;
+use crate::{
+	invariant::t_invariant::TInvariants,
+	logging::messages::*,
+	model::{
+		model::{AbstractModel, ModelType, ProbabilityOrRate, Transition},
+		propensity::Kinetics,
+		vas_model::{AbstractVas, VasProperty, VasState, VasTarget, VasTransition, VasValue},
+	},
+};
+use ::std::collections::{HashMap, HashSet, VecDeque};
 use colored::{ColoredString, Colorize};
 
 #[trusted]
@@ -36,18 +44,20 @@ fn check_variable_names(variable_names: &Box<[String]>) -> Vec<String> {
 }
 
 #[trusted]
-fn initial_state_neq_target(initial_state: Box<[VasValue]>, property: &VasProperty) -> Vec<String> {
+fn initial_state_neq_target(
+	initial_states: &[VasState],
+	property: &VasTarget,
+	variable_names: &[String],
+) -> Vec<String> {
 	let mut errors = Vec::new();
-	if initial_state[property.variable_index] == property.target_value {
-		errors.push(format!(
-			"Initial state [ {} ] satisfies target with value {}",
-			initial_state
-				.iter()
-				.map(|x| format!("{}", x))
-				.collect::<Vec<String>>()
-				.join(" "),
-			property.target_value
-		));
+	for initial_state in initial_states.iter() {
+		if property.is_satisfied(&initial_state.vector) {
+			errors.push(format!(
+				"Initial state [ {} ] satisfies target {}",
+				state_string(initial_state),
+				property.to_string_with_names(variable_names)
+			));
+		}
 	}
 	errors
 }
@@ -77,14 +87,146 @@ fn check_sck_assumption(transitions: Vec<VasTransition>) -> Vec<String> {
 }
 
 #[trusted]
-fn check_rate_constant(transitions: Vec<VasTransition>) -> Vec<String> {
+fn check_rate_constant(transitions: Vec<VasTransition>, model_type: ModelType) -> Vec<String> {
 	let mut errors = Vec::new();
-	for transition in transitions {
+	// Transitions with a rate law do not use their rate constant
+	for transition in transitions.into_iter().filter(|t| t.custom_rate_fn.is_none()) {
 		if transition.rate_const <= 0.0 {
 			errors.push(format!(
 				"Transition {} has a non-positive rate constant {}",
 				transition.transition_name, transition.rate_const
 			));
+		} else if model_type.is_discrete_time()
+			&& transition.kinetics == Kinetics::Constant
+			&& transition.rate_const > 1.0
+		{
+			errors.push(format!(
+				"Transition {} has a probability {} greater than 1",
+				transition.transition_name, transition.rate_const
+			));
+		}
+	}
+	errors
+}
+
+#[trusted]
+// A transition that changes nothing is a T-invariant on its own, and only adds self-loops.
+fn check_trivial_cycles(model: &AbstractVas) -> Vec<String> {
+	match TInvariants::from_model(model) {
+		Ok(invariants) => invariants
+			.minimal
+			.iter()
+			.filter(|invariant| invariant.length() == 1)
+			.map(|invariant| {
+				format!(
+					"Transition {} does not change any variable",
+					invariant.to_string_with_names(model)
+				)
+			})
+			.collect(),
+		Err(e) => {
+			// Not a problem with the model, so the check is skipped rather than failed
+			warning!("Could not compute the T-invariants: {}", e);
+			Vec::new()
+		}
+	}
+}
+
+/// How many reachable states the rate laws and probabilities are checked on
+const RATE_CHECK_STATE_LIMIT: usize = 10_000;
+
+/// Tolerance for the outgoing probabilities of a DTMC state to sum to one
+const PROBABILITY_SUM_TOLERANCE: f64 = 1e-9;
+
+#[trusted]
+// Explores the states reachable from the initial states, breadth first, up to `limit` states.
+fn reachable_states(model: &AbstractVas, limit: usize) -> Vec<VasState> {
+	let mut states = Vec::new();
+	let mut visited = HashSet::new();
+	let mut queue = VecDeque::new();
+	for state in model.initial_states.iter() {
+		if visited.insert(state.vector.iter().copied().collect::<Vec<_>>()) {
+			queue.push_back(model.state(state.vector.clone()));
+		}
+	}
+	while let Some(state) = queue.pop_front() {
+		for next_state in model.next_states(&state).map(|(_, next_state)| next_state) {
+			if visited.len() < limit
+				&& visited.insert(next_state.vector.iter().copied().collect::<Vec<_>>())
+			{
+				queue.push_back(next_state);
+			}
+		}
+		states.push(state);
+	}
+	states
+}
+
+/// Formats a state vector for the validator's messages
+fn state_string(state: &VasState) -> String {
+	state
+		.vector
+		.iter()
+		.map(|x| format!("{}", x))
+		.collect::<Vec<String>>()
+		.join(" ")
+}
+
+#[trusted]
+// Evaluates the rate laws (custom rate functions) on the reachable states and reports any
+// that are negative or undefined (NaN or infinite).
+fn check_rate_expressions(model: &AbstractVas, states: &[VasState]) -> Vec<String> {
+	let mut errors = Vec::new();
+	let mut flagged = HashSet::new();
+	for state in states.iter() {
+		for transition in model.transitions.iter() {
+			let Some(rate) = transition.rate_probability_at(state) else {
+				continue;
+			};
+			if transition.custom_rate_fn.is_some()
+				&& (rate < 0.0 || !rate.is_finite())
+				&& flagged.insert(transition.transition_id)
+			{
+				errors.push(format!(
+					"Transition {} has rate {} in reachable state [ {} ]",
+					transition.transition_name,
+					rate,
+					state_string(state)
+				));
+			}
+		}
+	}
+	errors
+}
+
+#[trusted]
+// Checks that the outgoing probabilities of every reachable state of a DTMC sum to one, or
+// those of every action for an MDP. States without enabled transitions are absorbing and
+// are not reported.
+fn check_probability_sums(model: &AbstractVas, states: &[VasState]) -> Vec<String> {
+	let mut errors = Vec::new();
+	for state in states.iter() {
+		for choice in model.choices(state) {
+			let sum: ProbabilityOrRate = choice
+				.iter()
+				.filter_map(|transition| transition.rate_probability_at(state))
+				.sum();
+			if (sum - 1.0).abs() <= PROBABILITY_SUM_TOLERANCE {
+				continue;
+			}
+			match choice[0].action() {
+				Some(action) if model.m_type.is_nondeterministic() => errors.push(format!(
+					"Probabilities of action {} sum to {} in reachable state [ {} ]",
+					action,
+					sum,
+					state_string(state)
+				)),
+				_ => errors.push(format!(
+					"Outgoing probabilities sum to {} in reachable state [ {} ]",
+					sum,
+					state_string(state)
+				)),
+			}
 		}
 	}
 	errors
@@ -112,7 +254,7 @@ pub fn write_outcome(test_name: &str, errors: Vec<String>) -> String {
 }
 
 #[trusted]
-pub fn validate_vas(model: &AbstractVas, property: &VasProperty) -> Result<String, String> {
+pub fn validate_vas(model: &AbstractVas, property: &VasTarget) -> Result<String, String> {
 	let mut result = String::new();
 
 	result.push_str("===============================================\n");
@@ -124,15 +266,9 @@ pub fn validate_vas(model: &AbstractVas, property: &VasProperty) -> Result<Strin
 		"Check Variable Names",
 		check_variable_names(&model.variable_names),
 	));
-	let initial_state: Box<[VasValue]> = model.initial_states[0]
-		.vector
-		.iter()
-		.map(|&val| val)
-		.collect::<Vec<VasValue>>()
-		.into_boxed_slice();
 	result.push_str(&write_outcome(
-		"Check Initial State != Target",
-		initial_state_neq_target(initial_state, &property),
+		"Check Initial States != Target",
+		initial_state_neq_target(&model.initial_states, &property, &model.variable_names),
 	));
 	result.push_str(&write_outcome(
 		"Check SCK Assumption (CRNs Only)",
@@ -140,8 +276,35 @@ pub fn validate_vas(model: &AbstractVas, property: &VasProperty) -> Result<Strin
 	));
 	result.push_str(&write_outcome(
 		"Check Rate Constant",
-		check_rate_constant(model.transitions.clone()),
+		check_rate_constant(model.transitions.clone(), model.m_type),
 	));
+	result.push_str(&write_outcome(
+		"Check Transitions Change the State",
+		check_trivial_cycles(model),
+	));
+	let needs_states = model.m_type.is_discrete_time()
+		|| model.transitions.iter().any(|t| t.custom_rate_fn.is_some());
+	let states = if needs_states {
+		reachable_states(model, RATE_CHECK_STATE_LIMIT)
+	} else {
+		Vec::new()
+	};
+	result.push_str(&write_outcome(
+		&format!(
+			"Check Rate Laws (first {} reachable states)",
+			RATE_CHECK_STATE_LIMIT
+		),
+		check_rate_expressions(model, &states),
+	));
+	if model.m_type.is_discrete_time() {
+		result.push_str(&write_outcome(
+			&format!(
+				"Check Probabilities Sum to 1 (first {} reachable states)",
+				RATE_CHECK_STATE_LIMIT
+			),
+			check_probability_sums(model, &states),
+		));
+	}
 
 	Ok(result)
 }